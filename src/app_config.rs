@@ -15,7 +15,71 @@ pub enum AppConfig {
     MasterReplOffset(u128),
     RDSDir(String),
     RDSFileName(String),
+    Databases(usize),
+    MaxMemory(usize),
+    MaxMemoryPolicy(EvictionPolicy),
+    MaxMemorySamples(usize),
 }
+
+/// What happens once the keys take more than `maxmemory`, named as in Redis
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Writes that could add memory are refused instead
+    NoEviction,
+    AllKeysLru,
+    AllKeysLfu,
+    AllKeysRandom,
+    /// The same policies, limited to keys that have an expiry
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    /// Keys closest to expiring go first
+    VolatileTtl,
+}
+
+impl EvictionPolicy {
+    const ALL: [EvictionPolicy; 8] = [
+        EvictionPolicy::NoEviction,
+        EvictionPolicy::AllKeysLru,
+        EvictionPolicy::AllKeysLfu,
+        EvictionPolicy::AllKeysRandom,
+        EvictionPolicy::VolatileLru,
+        EvictionPolicy::VolatileLfu,
+        EvictionPolicy::VolatileRandom,
+        EvictionPolicy::VolatileTtl,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EvictionPolicy::NoEviction => "noeviction",
+            EvictionPolicy::AllKeysLru => "allkeys-lru",
+            EvictionPolicy::AllKeysLfu => "allkeys-lfu",
+            EvictionPolicy::AllKeysRandom => "allkeys-random",
+            EvictionPolicy::VolatileLru => "volatile-lru",
+            EvictionPolicy::VolatileLfu => "volatile-lfu",
+            EvictionPolicy::VolatileRandom => "volatile-random",
+            EvictionPolicy::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    /// Whether only keys with an expiry can be evicted
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            EvictionPolicy::VolatileLru
+                | EvictionPolicy::VolatileLfu
+                | EvictionPolicy::VolatileRandom
+                | EvictionPolicy::VolatileTtl
+        )
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|policy| policy.name().eq_ignore_ascii_case(name))
+    }
+}
+
 impl AppConfig {
     pub(crate) fn get_rds_dir() -> String {
         let args = APP_CONFIGS.get_or_init(|| Self::init().unwrap());
@@ -44,6 +108,45 @@ impl AppConfig {
             })
             .unwrap_or(6_379_u16)
     }
+    /// Number of logical databases, 16 unless `--databases` says otherwise
+    pub(crate) fn get_databases() -> usize {
+        let args = APP_CONFIGS.get_or_init(|| Self::init().unwrap());
+        args.get("--databases")
+            .map(|v| match v {
+                AppConfig::Databases(databases) => *databases,
+                _ => 16,
+            })
+            .unwrap_or(16)
+    }
+    /// Bytes the keys may take before eviction kicks in, 0 meaning no limit
+    pub(crate) fn get_maxmemory() -> usize {
+        let args = APP_CONFIGS.get_or_init(|| Self::init().unwrap());
+        args.get("--maxmemory")
+            .map(|v| match v {
+                AppConfig::MaxMemory(maxmemory) => *maxmemory,
+                _ => 0,
+            })
+            .unwrap_or(0)
+    }
+    pub(crate) fn get_maxmemory_policy() -> EvictionPolicy {
+        let args = APP_CONFIGS.get_or_init(|| Self::init().unwrap());
+        args.get("--maxmemory-policy")
+            .map(|v| match v {
+                AppConfig::MaxMemoryPolicy(policy) => *policy,
+                _ => EvictionPolicy::NoEviction,
+            })
+            .unwrap_or(EvictionPolicy::NoEviction)
+    }
+    /// Keys sampled per database when looking for one to evict
+    pub(crate) fn get_maxmemory_samples() -> usize {
+        let args = APP_CONFIGS.get_or_init(|| Self::init().unwrap());
+        args.get("--maxmemory-samples")
+            .map(|v| match v {
+                AppConfig::MaxMemorySamples(samples) => *samples,
+                _ => 5,
+            })
+            .unwrap_or(5)
+    }
     pub(crate) fn is_master() -> bool {
         let args = APP_CONFIGS.get_or_init(|| Self::init().unwrap());
         args.get("--replicaof").is_none()
@@ -92,7 +195,7 @@ impl AppConfig {
                         Err(anyhow!("replicaof host and port not provided"))?
                     };
                     let split = replica_of.split(" ").collect::<Vec<&str>>();
-                    let host = split.get(0).expect("host not provided");
+                    let host = split.first().expect("host not provided");
                     let port = split.get(1).expect("port not provided");
                     AppConfig::ReplicaOf(host.to_string(), port.to_string())
                 }
@@ -110,31 +213,74 @@ impl AppConfig {
                     };
                     AppConfig::RDSFileName(db_file_name)
                 }
+                "--databases" => match args.next() {
+                    Some(databases) => match databases.parse::<usize>()? {
+                        0 => Err(anyhow!("There must be at least one database"))?,
+                        databases => AppConfig::Databases(databases),
+                    },
+                    None => Err(anyhow!("Number of databases not provided"))?,
+                },
+                "--maxmemory" => match args.next() {
+                    Some(maxmemory) => AppConfig::MaxMemory(parse_memory(&maxmemory)?),
+                    None => Err(anyhow!("maxmemory is not provided"))?,
+                },
+                "--maxmemory-policy" => match args.next() {
+                    Some(policy) => match EvictionPolicy::from_name(&policy) {
+                        Some(policy) => AppConfig::MaxMemoryPolicy(policy),
+                        None => Err(anyhow!("Unknown maxmemory policy {policy}"))?,
+                    },
+                    None => Err(anyhow!("maxmemory policy is not provided"))?,
+                },
+                "--maxmemory-samples" => match args.next() {
+                    Some(samples) => match samples.parse::<usize>()? {
+                        0 => Err(anyhow!("maxmemory samples must be at least 1"))?,
+                        samples => AppConfig::MaxMemorySamples(samples),
+                    },
+                    None => Err(anyhow!("maxmemory samples are not provided"))?,
+                },
                 _ => Err(anyhow!("Unknown argument"))?,
             };
             map.insert(arg, cli_arg);
         }
 
         // Setup matser replication id and offset
-        match map.get("--replicaof") {
-            None => {
-                let alpha_numeric = b"abcdefghijklmnopqrstuvwxyz0123456789";
-                let hash = (0..40)
-                    .map(|_| {
-                        let idx = rand::random::<usize>() % alpha_numeric.len();
-                        *alpha_numeric.get(idx).unwrap()
-                    })
-                    .collect::<Vec<u8>>();
-                let hash = String::from_utf8(hash).unwrap();
-                map.insert("$$master_replid".to_string(), AppConfig::MasterReplId(hash));
-                map.insert(
-                    "$$master_repl_offset".to_string(),
-                    AppConfig::MasterReplOffset(0),
-                );
-            }
-            _ => {}
+        if !map.contains_key("--replicaof") {
+            let alpha_numeric = b"abcdefghijklmnopqrstuvwxyz0123456789";
+            let hash = (0..40)
+                .map(|_| {
+                    let idx = rand::random::<usize>() % alpha_numeric.len();
+                    *alpha_numeric.get(idx).unwrap()
+                })
+                .collect::<Vec<u8>>();
+            let hash = String::from_utf8(hash).unwrap();
+            map.insert("$$master_replid".to_string(), AppConfig::MasterReplId(hash));
+            map.insert(
+                "$$master_repl_offset".to_string(),
+                AppConfig::MasterReplOffset(0),
+            );
         }
         debug!("AppConfigs ➡️  {map:?}");
         Ok(map)
     }
 }
+
+/// Parses a memory amount the way redis.conf writes them: a plain number of bytes or one
+/// followed by `k`, `kb`, `m`, `mb`, `g` or `gb`, where the `b` forms are powers of 1024
+fn parse_memory(value: &str) -> anyhow::Result<usize> {
+    let lower = value.to_ascii_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1_000,
+        "kb" => 1 << 10,
+        "m" => 1_000_000,
+        "mb" => 1 << 20,
+        "g" => 1_000_000_000,
+        "gb" => 1 << 30,
+        _ => Err(anyhow!("Invalid memory unit in {value}"))?,
+    };
+    digits
+        .parse::<usize>()?
+        .checked_mul(unit)
+        .ok_or_else(|| anyhow!("Memory amount {value} is too large"))
+}
@@ -1,23 +1,44 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, time::Duration};
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
+use bytes::Bytes;
+use thiserror::Error;
 use tracing::debug;
 
-use crate::{fdbg, resp_type::RESPType};
+use crate::{
+    database::{
+        db_event::{
+            Aggregate, BitFieldOp, BitFieldOverflow, BitFieldType, BitOperation, BitRange, BitUnit,
+            ExpireOptions, Expiry, GeoOrigin, GeoQuery, GeoShape, LexBound, ListEnd,
+            RestoreOptions, ScanOptions, ScoreBound, SetOp, SetOptions, SortOptions, StreamId,
+            StreamRange, StreamTrim, TrimStrategy, XAddOptions, ZAddOptions, ZRangeBy, ZRangeSpec,
+            MAX_STREAM_ID,
+        },
+        geo, next_stream_id, now_ms, parse_float, parse_i64, parse_stream_id, prev_stream_id,
+        INVALID_STREAM_ID,
+    },
+    fdbg,
+    resp_type::RESPType,
+};
 
 type R = anyhow::Result<ServerCommand>;
 
+/// Strings can't grow past 512MB, the same limit as a bulk string on the wire
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+/// Most entries `~` trimming drops when no LIMIT is given, a hundred stream nodes' worth
+const DEFAULT_STREAM_TRIM_LIMIT: usize = 10_000;
+
 #[derive(Debug, Clone)]
 pub enum ServerCommand {
     Ping,
-    Echo(String),
+    Echo(Bytes),
     Get {
-        key: String,
+        key: Bytes,
     },
     Set {
-        key: String,
-        value: String,
-        flags: HashMap<String, String>,
+        key: Bytes,
+        value: Bytes,
+        options: SetOptions,
     },
     Info {
         #[allow(dead_code)]
@@ -41,23 +62,491 @@ pub enum ServerCommand {
         cmd: String,
         key: String,
     },
-    Keys(String),
-    Type(String),
+    Keys(Bytes),
+    Type(Bytes),
     XAdd {
-        stream_key: String,
+        stream_key: Bytes,
         stream_id: String,
-        key: String,
-        value: String,
+        fields: Vec<(Bytes, Bytes)>,
+        options: XAddOptions,
+    },
+    XTrim {
+        key: Bytes,
+        trim: StreamTrim,
     },
+    XDel {
+        key: Bytes,
+        ids: Vec<StreamId>,
+    },
+    XLen {
+        key: Bytes,
+    },
+    /// XRANGE and XREVRANGE
     XRange {
-        stream_key: String,
-        start: String,
-        end: String,
+        stream_key: Bytes,
+        range: StreamRange,
     },
-    XRead(Vec<(String, String)>, Option<u64>),
-    Incr {
-        key: String,
+    /// Streams with the ID to read after, None for `$`, and how long to block for
+    XRead(Vec<(Bytes, Option<StreamId>)>, Option<u64>),
+    /// INCR, DECR, INCRBY and DECRBY
+    IncrBy {
+        key: Bytes,
+        increment: i64,
+    },
+    IncrByFloat {
+        key: Bytes,
+        increment: f64,
+    },
+    HSet {
+        key: Bytes,
+        fields: Vec<(Bytes, Bytes)>,
+    },
+    HSetNx {
+        key: Bytes,
+        field: Bytes,
+        value: Bytes,
+    },
+    HGet {
+        key: Bytes,
+        field: Bytes,
+    },
+    HMGet {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    HGetAll {
+        key: Bytes,
+    },
+    HDel {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    HExists {
+        key: Bytes,
+        field: Bytes,
+    },
+    HLen {
+        key: Bytes,
+    },
+    HKeys {
+        key: Bytes,
+    },
+    HVals {
+        key: Bytes,
+    },
+    HIncrBy {
+        key: Bytes,
+        field: Bytes,
+        increment: i64,
+    },
+    HIncrByFloat {
+        key: Bytes,
+        field: Bytes,
+        increment: f64,
+    },
+    HStrLen {
+        key: Bytes,
+        field: Bytes,
+    },
+    HRandField {
+        key: Bytes,
+        count: Option<i64>,
+        with_values: bool,
+    },
+    ListPush {
+        key: Bytes,
+        elements: Vec<Bytes>,
+        end: ListEnd,
+        only_if_exists: bool,
+    },
+    ListPop {
+        key: Bytes,
+        end: ListEnd,
+        count: Option<usize>,
+    },
+    LRange {
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    LIndex {
+        key: Bytes,
+        index: i64,
+    },
+    LLen {
+        key: Bytes,
+    },
+    LSet {
+        key: Bytes,
+        index: i64,
+        element: Bytes,
+    },
+    LInsert {
+        key: Bytes,
+        before: bool,
+        pivot: Bytes,
+        element: Bytes,
+    },
+    LRem {
+        key: Bytes,
+        count: i64,
+        element: Bytes,
+    },
+    LTrim {
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    LPos {
+        key: Bytes,
+        element: Bytes,
+        rank: i64,
+        count: Option<usize>,
+        max_len: usize,
+    },
+    LMove {
+        source: Bytes,
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    },
+    BPop {
+        keys: Vec<Bytes>,
+        end: ListEnd,
+        timeout: Option<Duration>,
+    },
+    BLMove {
+        source: Bytes,
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+        timeout: Option<Duration>,
+    },
+    BLMPop {
+        keys: Vec<Bytes>,
+        end: ListEnd,
+        count: usize,
+        timeout: Option<Duration>,
+    },
+    SAdd {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SRem {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SMembers {
+        key: Bytes,
+    },
+    SIsMember {
+        key: Bytes,
+        member: Bytes,
+    },
+    SMIsMember {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SCard {
+        key: Bytes,
+    },
+    SPop {
+        key: Bytes,
+        count: Option<usize>,
+    },
+    SRandMember {
+        key: Bytes,
+        count: Option<i64>,
+    },
+    SMove {
+        source: Bytes,
+        destination: Bytes,
+        member: Bytes,
+    },
+    SetOperation {
+        op: SetOp,
+        keys: Vec<Bytes>,
+    },
+    SetOperationStore {
+        op: SetOp,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    SInterCard {
+        keys: Vec<Bytes>,
+        limit: usize,
+    },
+    ZAdd {
+        key: Bytes,
+        members: Vec<(f64, Bytes)>,
+        options: ZAddOptions,
+        incr: bool,
+    },
+    ZIncrBy {
+        key: Bytes,
+        increment: f64,
+        member: Bytes,
+    },
+    ZRem {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    ZScore {
+        key: Bytes,
+        member: Bytes,
+    },
+    ZMScore {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    ZRank {
+        key: Bytes,
+        member: Bytes,
+        rev: bool,
+        with_score: bool,
+    },
+    ZCard {
+        key: Bytes,
+    },
+    ZCount {
+        key: Bytes,
+        min: ScoreBound,
+        max: ScoreBound,
+    },
+    ZRange {
+        key: Bytes,
+        spec: ZRangeSpec,
+        with_scores: bool,
+    },
+    ZRangeStore {
+        destination: Bytes,
+        source: Bytes,
+        spec: ZRangeSpec,
+    },
+    ZPop {
+        key: Bytes,
+        count: usize,
+        max: bool,
+    },
+    ZSetOperationStore {
+        op: SetOp,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+        weights: Vec<f64>,
+        aggregate: Aggregate,
     },
+    GeoAdd {
+        key: Bytes,
+        members: Vec<(f64, f64, Bytes)>,
+        options: ZAddOptions,
+    },
+    GeoPos {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    GeoDist {
+        key: Bytes,
+        from: Bytes,
+        to: Bytes,
+        unit: f64,
+    },
+    GeoHash {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    GeoSearch {
+        key: Bytes,
+        query: GeoQuery,
+        with_coord: bool,
+        with_dist: bool,
+        with_hash: bool,
+    },
+    GeoSearchStore {
+        destination: Bytes,
+        source: Bytes,
+        query: GeoQuery,
+        store_dist: bool,
+    },
+    PfAdd {
+        key: Bytes,
+        elements: Vec<Bytes>,
+    },
+    PfCount {
+        keys: Vec<Bytes>,
+    },
+    PfMerge {
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    SetBit {
+        key: Bytes,
+        offset: u64,
+        value: bool,
+    },
+    GetBit {
+        key: Bytes,
+        offset: u64,
+    },
+    BitCount {
+        key: Bytes,
+        range: Option<BitRange>,
+    },
+    BitPos {
+        key: Bytes,
+        bit: bool,
+        range: Option<BitRange>,
+    },
+    BitOp {
+        op: BitOperation,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    BitField {
+        key: Bytes,
+        ops: Vec<BitFieldOp>,
+    },
+    Append {
+        key: Bytes,
+        value: Bytes,
+    },
+    StrLen {
+        key: Bytes,
+    },
+    GetRange {
+        key: Bytes,
+        start: i64,
+        end: i64,
+    },
+    SetRange {
+        key: Bytes,
+        offset: usize,
+        value: Bytes,
+    },
+    MGet {
+        keys: Vec<Bytes>,
+    },
+    /// MSET, or MSETNX and SETNX when `only_new` is set
+    MSet {
+        pairs: Vec<(Bytes, Bytes)>,
+        only_new: bool,
+    },
+    GetDel {
+        key: Bytes,
+    },
+    GetEx {
+        key: Bytes,
+        expiry: Option<Expiry>,
+    },
+    Lcs {
+        key1: Bytes,
+        key2: Bytes,
+        len: bool,
+        idx: bool,
+        min_match_len: usize,
+        with_match_len: bool,
+    },
+    /// DEL, or UNLINK when `lazy` is set
+    Del {
+        keys: Vec<Bytes>,
+        lazy: bool,
+    },
+    Exists {
+        keys: Vec<Bytes>,
+    },
+    /// RENAME, or RENAMENX when `only_new` is set
+    Rename {
+        key: Bytes,
+        new_key: Bytes,
+        only_new: bool,
+    },
+    Copy {
+        source: Bytes,
+        destination: Bytes,
+        db: Option<usize>,
+        replace: bool,
+    },
+    Touch {
+        keys: Vec<Bytes>,
+    },
+    /// SORT and SORT_RO
+    Sort {
+        key: Bytes,
+        options: SortOptions,
+    },
+    SortStore {
+        destination: Bytes,
+        key: Bytes,
+        options: SortOptions,
+    },
+    Dump {
+        key: Bytes,
+    },
+    Restore {
+        key: Bytes,
+        payload: Bytes,
+        options: RestoreOptions,
+    },
+    RandomKey,
+    DbSize,
+    /// FLUSHDB, or FLUSHALL when `all` is set
+    Flush {
+        all: bool,
+        lazy: bool,
+    },
+    Select(usize),
+    Move {
+        key: Bytes,
+        db: usize,
+    },
+    SwapDb {
+        first: usize,
+        second: usize,
+    },
+    /// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT
+    Expire {
+        key: Bytes,
+        expiry: Expiry,
+        options: ExpireOptions,
+    },
+    /// TTL and PTTL, or EXPIRETIME and PEXPIRETIME when `absolute` is set
+    Ttl {
+        key: Bytes,
+        millis: bool,
+        absolute: bool,
+    },
+    Persist {
+        key: Bytes,
+    },
+    Scan {
+        cursor: u64,
+        options: ScanOptions,
+    },
+    HScan {
+        key: Bytes,
+        cursor: u64,
+        options: ScanOptions,
+        no_values: bool,
+    },
+    SScan {
+        key: Bytes,
+        cursor: u64,
+        options: ScanOptions,
+    },
+    ZScan {
+        key: Bytes,
+        cursor: u64,
+        options: ScanOptions,
+    },
+    MemoryUsage {
+        key: Bytes,
+        /// Elements to size collections from, 0 for all of them and `None` for the default
+        samples: Option<usize>,
+    },
+    MemoryStats,
+    ObjectEncoding(Bytes),
+    ObjectIdleTime(Bytes),
+    ObjectFreq(Bytes),
+    ObjectRefCount(Bytes),
     Multi,
     Exec,
     Discard,
@@ -68,23 +557,110 @@ pub enum ServerCommand {
 impl ServerCommand {
     pub fn from(resp_type: &RESPType) -> anyhow::Result<Self> {
         match resp_type {
-            RESPType::Array(items) => parse_client_cmd(&items),
+            RESPType::Array(items) => parse_client_cmd(items),
             RESPType::CustomNewLine => Ok(ServerCommand::CustomNewLine),
-            RESPType::EOF => Ok(ServerCommand::ExitConn),
+            RESPType::Eof => Ok(ServerCommand::ExitConn),
             _ => bail!("Client command must be of type array"),
         }
     }
+
+    /// Commands that can park the connection until another client writes
+    pub fn is_blocking(&self) -> bool {
+        matches!(
+            self,
+            ServerCommand::BPop { .. }
+                | ServerCommand::BLMove { .. }
+                | ServerCommand::BLMPop { .. }
+                | ServerCommand::XRead(_, Some(_))
+        )
+    }
+
+    /// The command as EXEC runs it. Blocking commands can't wait inside a transaction, so
+    /// like in Redis they time out right away when there is nothing to serve them.
+    pub fn without_blocking(self) -> ServerCommand {
+        match self {
+            ServerCommand::BPop { keys, end, .. } => ServerCommand::BPop {
+                keys,
+                end,
+                timeout: Some(Duration::ZERO),
+            },
+            ServerCommand::BLMove {
+                source,
+                destination,
+                from,
+                to,
+                ..
+            } => ServerCommand::BLMove {
+                source,
+                destination,
+                from,
+                to,
+                timeout: Some(Duration::ZERO),
+            },
+            ServerCommand::BLMPop {
+                keys, end, count, ..
+            } => ServerCommand::BLMPop {
+                keys,
+                end,
+                count,
+                timeout: Some(Duration::ZERO),
+            },
+            ServerCommand::XRead(filters, Some(_)) => ServerCommand::XRead(filters, None),
+            cmd => cmd,
+        }
+    }
+
+    /// Commands that can make the keys take more memory, which are refused once the keys are
+    /// over maxmemory and nothing can be evicted
+    pub fn denies_oom(&self) -> bool {
+        matches!(
+            self,
+            ServerCommand::Set { .. }
+                | ServerCommand::XAdd { .. }
+                | ServerCommand::IncrBy { .. }
+                | ServerCommand::IncrByFloat { .. }
+                | ServerCommand::HSet { .. }
+                | ServerCommand::HSetNx { .. }
+                | ServerCommand::HIncrBy { .. }
+                | ServerCommand::HIncrByFloat { .. }
+                | ServerCommand::ListPush { .. }
+                | ServerCommand::LSet { .. }
+                | ServerCommand::LInsert { .. }
+                | ServerCommand::LMove { .. }
+                | ServerCommand::BLMove { .. }
+                | ServerCommand::SAdd { .. }
+                | ServerCommand::SetOperationStore { .. }
+                | ServerCommand::ZAdd { .. }
+                | ServerCommand::ZIncrBy { .. }
+                | ServerCommand::ZRangeStore { .. }
+                | ServerCommand::ZSetOperationStore { .. }
+                | ServerCommand::GeoAdd { .. }
+                | ServerCommand::GeoSearchStore { .. }
+                | ServerCommand::PfAdd { .. }
+                | ServerCommand::PfMerge { .. }
+                | ServerCommand::SetBit { .. }
+                | ServerCommand::BitOp { .. }
+                | ServerCommand::BitField { .. }
+                | ServerCommand::Append { .. }
+                | ServerCommand::SetRange { .. }
+                | ServerCommand::MSet { .. }
+                | ServerCommand::Copy { .. }
+                | ServerCommand::Restore { .. }
+                | ServerCommand::SortStore { .. }
+        )
+    }
 }
 
 fn parse_client_cmd(items: &[RESPType]) -> R {
     if items.is_empty() {
         bail!("Client command array must have at least one element");
     }
-    let Some(RESPType::BulkString(cmd)) = items.get(0) else {
+    let Some(RESPType::BulkString(cmd)) = items.first() else {
         bail!("First element of client command array must be a bulk string");
     };
-    let cmd = cmd.to_uppercase();
-    match cmd.as_str() {
+    let name = text(cmd);
+    let cmd = name.to_uppercase();
+    let parsed = match cmd.as_str() {
         "PING" => Ok(ServerCommand::Ping),
         "ECHO" => parse_echo_cmd(&items[1..]),
         "SET" => parse_set_cmd(&items[1..]),
@@ -97,16 +673,173 @@ fn parse_client_cmd(items: &[RESPType]) -> R {
         "KEYS" => parse_keys_cmd(&items[1..]),
         "TYPE" => parse_type_cmd(&items[1..]),
         "XADD" => parse_xadd_cmd(&items[1..]),
-        "XRANGE" => parse_xrange_cmd(&items[1..]),
+        "XTRIM" => parse_xtrim_cmd(&items[1..]),
+        "XDEL" => parse_xdel_cmd(&items[1..]),
+        "XLEN" => parse_xlen_cmd(&items[1..]),
+        "XRANGE" => parse_xrange_cmd(&items[1..], false),
+        "XREVRANGE" => parse_xrange_cmd(&items[1..], true),
         "XREAD" => parse_xread_cmd(&items[1..]),
-        "INCR" => parse_incr_cmd(&items[1..]),
+        "INCR" => parse_incr_cmd(&items[1..], 1),
+        "DECR" => parse_incr_cmd(&items[1..], -1),
+        "INCRBY" => parse_incrby_cmd(&items[1..], false),
+        "DECRBY" => parse_incrby_cmd(&items[1..], true),
+        "INCRBYFLOAT" => parse_incrbyfloat_cmd(&items[1..]),
+        "HSET" => parse_hset_cmd(&items[1..]),
+        "HSETNX" => parse_hsetnx_cmd(&items[1..]),
+        "HGET" => parse_hget_cmd(&items[1..]),
+        "HMGET" => parse_hmget_cmd(&items[1..]),
+        "HGETALL" => parse_hgetall_cmd(&items[1..]),
+        "HDEL" => parse_hdel_cmd(&items[1..]),
+        "HEXISTS" => parse_hexists_cmd(&items[1..]),
+        "HLEN" => parse_hlen_cmd(&items[1..]),
+        "HKEYS" => parse_hkeys_cmd(&items[1..]),
+        "HVALS" => parse_hvals_cmd(&items[1..]),
+        "HINCRBY" => parse_hincrby_cmd(&items[1..]),
+        "HINCRBYFLOAT" => parse_hincrbyfloat_cmd(&items[1..]),
+        "HSTRLEN" => parse_hstrlen_cmd(&items[1..]),
+        "HRANDFIELD" => parse_hrandfield_cmd(&items[1..]),
+        "LPUSH" => parse_push_cmd(&items[1..], ListEnd::Left, false),
+        "RPUSH" => parse_push_cmd(&items[1..], ListEnd::Right, false),
+        "LPUSHX" => parse_push_cmd(&items[1..], ListEnd::Left, true),
+        "RPUSHX" => parse_push_cmd(&items[1..], ListEnd::Right, true),
+        "LPOP" => parse_pop_cmd(&items[1..], ListEnd::Left),
+        "RPOP" => parse_pop_cmd(&items[1..], ListEnd::Right),
+        "LRANGE" => parse_lrange_cmd(&items[1..]),
+        "LINDEX" => parse_lindex_cmd(&items[1..]),
+        "LLEN" => parse_llen_cmd(&items[1..]),
+        "LSET" => parse_lset_cmd(&items[1..]),
+        "LINSERT" => parse_linsert_cmd(&items[1..]),
+        "LREM" => parse_lrem_cmd(&items[1..]),
+        "LTRIM" => parse_ltrim_cmd(&items[1..]),
+        "LPOS" => parse_lpos_cmd(&items[1..]),
+        "LMOVE" => parse_lmove_cmd(&items[1..]),
+        "BLPOP" => parse_bpop_cmd(&items[1..], ListEnd::Left),
+        "BRPOP" => parse_bpop_cmd(&items[1..], ListEnd::Right),
+        "BLMOVE" => parse_blmove_cmd(&items[1..]),
+        "BLMPOP" => parse_blmpop_cmd(&items[1..]),
+        "SADD" => parse_sadd_cmd(&items[1..]),
+        "SREM" => parse_srem_cmd(&items[1..]),
+        "SMEMBERS" => parse_smembers_cmd(&items[1..]),
+        "SISMEMBER" => parse_sismember_cmd(&items[1..]),
+        "SMISMEMBER" => parse_smismember_cmd(&items[1..]),
+        "SCARD" => parse_scard_cmd(&items[1..]),
+        "SPOP" => parse_spop_cmd(&items[1..]),
+        "SRANDMEMBER" => parse_srandmember_cmd(&items[1..]),
+        "SMOVE" => parse_smove_cmd(&items[1..]),
+        "SINTER" => parse_set_op_cmd(&items[1..], SetOp::Inter),
+        "SUNION" => parse_set_op_cmd(&items[1..], SetOp::Union),
+        "SDIFF" => parse_set_op_cmd(&items[1..], SetOp::Diff),
+        "SINTERSTORE" => parse_set_op_store_cmd(&items[1..], SetOp::Inter),
+        "SUNIONSTORE" => parse_set_op_store_cmd(&items[1..], SetOp::Union),
+        "SDIFFSTORE" => parse_set_op_store_cmd(&items[1..], SetOp::Diff),
+        "SINTERCARD" => parse_sintercard_cmd(&items[1..]),
+        "ZADD" => parse_zadd_cmd(&items[1..]),
+        "ZINCRBY" => parse_zincrby_cmd(&items[1..]),
+        "ZREM" => parse_zrem_cmd(&items[1..]),
+        "ZSCORE" => parse_zscore_cmd(&items[1..]),
+        "ZMSCORE" => parse_zmscore_cmd(&items[1..]),
+        "ZRANK" => parse_zrank_cmd(&items[1..], false),
+        "ZREVRANK" => parse_zrank_cmd(&items[1..], true),
+        "ZCARD" => parse_zcard_cmd(&items[1..]),
+        "ZCOUNT" => parse_zcount_cmd(&items[1..]),
+        "ZRANGE" => parse_zrange_cmd(&items[1..]),
+        "ZRANGESTORE" => parse_zrangestore_cmd(&items[1..]),
+        "ZPOPMIN" => parse_zpop_cmd(&items[1..], false),
+        "ZPOPMAX" => parse_zpop_cmd(&items[1..], true),
+        "ZUNIONSTORE" => parse_zset_op_store_cmd(&items[1..], SetOp::Union, "zunionstore"),
+        "ZINTERSTORE" => parse_zset_op_store_cmd(&items[1..], SetOp::Inter, "zinterstore"),
+        "ZDIFFSTORE" => parse_zset_op_store_cmd(&items[1..], SetOp::Diff, "zdiffstore"),
+        "GEOADD" => parse_geoadd_cmd(&items[1..]),
+        "GEOPOS" => parse_geopos_cmd(&items[1..]),
+        "GEODIST" => parse_geodist_cmd(&items[1..]),
+        "GEOHASH" => parse_geohash_cmd(&items[1..]),
+        "GEOSEARCH" => parse_geosearch_cmd(&items[1..]),
+        "GEOSEARCHSTORE" => parse_geosearchstore_cmd(&items[1..]),
+        "PFADD" => parse_pfadd_cmd(&items[1..]),
+        "PFCOUNT" => parse_pfcount_cmd(&items[1..]),
+        "PFMERGE" => parse_pfmerge_cmd(&items[1..]),
+        "SETBIT" => parse_setbit_cmd(&items[1..]),
+        "GETBIT" => parse_getbit_cmd(&items[1..]),
+        "BITCOUNT" => parse_bitcount_cmd(&items[1..]),
+        "BITPOS" => parse_bitpos_cmd(&items[1..]),
+        "BITOP" => parse_bitop_cmd(&items[1..]),
+        "BITFIELD" => parse_bitfield_cmd(&items[1..], false),
+        "BITFIELD_RO" => parse_bitfield_cmd(&items[1..], true),
+        "APPEND" => parse_append_cmd(&items[1..]),
+        "STRLEN" => parse_strlen_cmd(&items[1..]),
+        "GETRANGE" => parse_getrange_cmd(&items[1..]),
+        "SETRANGE" => parse_setrange_cmd(&items[1..]),
+        "MGET" => parse_mget_cmd(&items[1..]),
+        "MSET" => parse_mset_cmd(&items[1..], false),
+        "MSETNX" => parse_mset_cmd(&items[1..], true),
+        "SETNX" => parse_setnx_cmd(&items[1..]),
+        "GETDEL" => parse_getdel_cmd(&items[1..]),
+        "GETEX" => parse_getex_cmd(&items[1..]),
+        "LCS" => parse_lcs_cmd(&items[1..]),
+        "DEL" => parse_del_cmd(&items[1..], false),
+        "UNLINK" => parse_del_cmd(&items[1..], true),
+        "EXISTS" => parse_exists_cmd(&items[1..]),
+        "RENAME" => parse_rename_cmd(&items[1..], false),
+        "RENAMENX" => parse_rename_cmd(&items[1..], true),
+        "COPY" => parse_copy_cmd(&items[1..]),
+        "TOUCH" => parse_touch_cmd(&items[1..]),
+        "SORT" => parse_sort_cmd(&items[1..], false),
+        "SORT_RO" => parse_sort_cmd(&items[1..], true),
+        "DUMP" => parse_dump_cmd(&items[1..]),
+        "RESTORE" => parse_restore_cmd(&items[1..]),
+        "RANDOMKEY" => parse_no_args_cmd(&items[1..], ServerCommand::RandomKey),
+        "DBSIZE" => parse_no_args_cmd(&items[1..], ServerCommand::DbSize),
+        "FLUSHDB" => parse_flush_cmd(&items[1..], false),
+        "FLUSHALL" => parse_flush_cmd(&items[1..], true),
+        "SELECT" => parse_select_cmd(&items[1..]),
+        "MOVE" => parse_move_cmd(&items[1..]),
+        "SWAPDB" => parse_swapdb_cmd(&items[1..]),
+        "EXPIRE" => parse_expire_cmd(&items[1..], "expire", false, false),
+        "PEXPIRE" => parse_expire_cmd(&items[1..], "pexpire", true, false),
+        "EXPIREAT" => parse_expire_cmd(&items[1..], "expireat", false, true),
+        "PEXPIREAT" => parse_expire_cmd(&items[1..], "pexpireat", true, true),
+        "TTL" => parse_ttl_cmd(&items[1..], false, false),
+        "PTTL" => parse_ttl_cmd(&items[1..], true, false),
+        "EXPIRETIME" => parse_ttl_cmd(&items[1..], false, true),
+        "PEXPIRETIME" => parse_ttl_cmd(&items[1..], true, true),
+        "PERSIST" => parse_persist_cmd(&items[1..]),
+        "SCAN" => parse_scan_cmd(&items[1..]),
+        "HSCAN" | "SSCAN" | "ZSCAN" => parse_collection_scan_cmd(&items[1..], &cmd),
+        "MEMORY" => parse_memory_cmd(&items[1..]),
+        "OBJECT" => parse_object_cmd(&items[1..]),
         "MULTI" => parse_multi_cmd(),
         "EXEC" => parse_exec_cmd(),
         "DISCARD" => parse_discard_cmd(),
-        _ => bail!("Unknown client command: {}", cmd),
-    }
+        _ => {
+            let args = bulk_strings(&items[1..])?
+                .iter()
+                .map(|arg| format!("'{}' ", text(arg)))
+                .collect::<String>();
+            bail!(
+                "ERR unknown command '{}', with args beginning with: {}",
+                name,
+                args
+            );
+        }
+    };
+    parsed.map_err(|err| match err.downcast_ref::<ArityError>() {
+        Some(ArityError(message)) => {
+            debug!(?message, "Malformed command");
+            anyhow!(
+                "ERR wrong number of arguments for '{}' command",
+                cmd.to_lowercase()
+            )
+        }
+        None => err,
+    })
 }
 
+/// Arguments that don't have the shape the command expects. Clients are told the command got
+/// the wrong number of arguments, while the message says what was off for the logs.
+#[derive(Error, Debug)]
+#[error("{0}")]
+struct ArityError(String);
+
 fn parse_discard_cmd() -> R {
     Ok(ServerCommand::Discard)
 }
@@ -118,219 +851,2470 @@ fn parse_multi_cmd() -> R {
     Ok(ServerCommand::Multi)
 }
 
-fn parse_xread_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(typez)) = items.get(0) else {
-        bail!(fdbg!("XREAD must have type"));
+fn parse_hset_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("HSET command must have key")));
     };
-
-    let mut block_ms: Option<u64> = None;
-    let consumed;
-
-    match typez.to_lowercase().as_str() {
-        "block" => {
-            let Some(RESPType::BulkString(block)) = items.get(1) else {
-                bail!(fdbg!("XREAD must have block"));
-            };
-            block_ms = Some(block.parse::<u64>()?);
-            let Some(RESPType::BulkString(typez)) = items.get(2) else {
-                bail!(fdbg!("XREAD must have type"));
-            };
-            if typez.to_lowercase() != "streams" {
-                bail!(fdbg!("XREAD must have type 'streams'"))
-            }
-            consumed = 3;
-        }
-        "streams" => {
-            consumed = 1;
-        }
-        _ => bail!(fdbg!("XREAD must have type 'streams' or 'block'")),
-    }
-
-    let items = &items[consumed..];
-    let len = items.len() / 2;
-    let mut filter: Vec<(String, String)> = Vec::with_capacity(len);
-    for i in 0..len {
-        let RESPType::BulkString(key) = items[i].clone() else {
-            bail!(fdbg!("XREAD must have key"));
-        };
-        let RESPType::BulkString(stream_id) = items[i + len].clone() else {
-            bail!(fdbg!("XREAD must have stream_id"));
-        };
-        filter.push((key.clone(), stream_id.clone()));
+    let args = bulk_strings(&items[1..])?;
+    if args.is_empty() || args.len() % 2 != 0 {
+        bail!(ArityError(fdbg!(
+            "HSET command must have field value pairs"
+        )));
     }
-    debug!(?filter, ?block_ms, "This is the parsed filter");
-    Ok(ServerCommand::XRead(filter, block_ms))
+    let fields = args
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    Ok(ServerCommand::HSet {
+        key: key.to_owned(),
+        fields,
+    })
 }
 
-fn parse_xrange_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(stream_key)) = items.get(0) else {
-        bail!(fdbg!("XRANGE must have stream_key"));
+fn parse_hsetnx_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("HSETNX command must have key")));
     };
-    let Some(RESPType::BulkString(start)) = items.get(1) else {
-        bail!(fdbg!("XRANGE must have start"));
+    let Some(RESPType::BulkString(field)) = items.get(1) else {
+        bail!(ArityError(fdbg!("HSETNX command must have field")));
     };
-    let Some(RESPType::BulkString(end)) = items.get(2) else {
-        bail!(fdbg!("XRANGE must have end"));
+    let Some(RESPType::BulkString(value)) = items.get(2) else {
+        bail!(ArityError(fdbg!("HSETNX command must have value")));
     };
-    Ok(ServerCommand::XRange {
-        stream_key: stream_key.to_string(),
-        start: start.to_string(),
-        end: end.to_string(),
+    Ok(ServerCommand::HSetNx {
+        key: key.to_owned(),
+        field: field.to_owned(),
+        value: value.to_owned(),
     })
 }
 
-fn parse_xadd_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(stream_key)) = items.get(0) else {
-        bail!(fdbg!("XADD must have stream_key"));
-    };
-    let Some(RESPType::BulkString(stream_id)) = items.get(1) else {
-        bail!(fdbg!("XADD must have stream_id"));
+fn parse_hget_cmd(items: &[RESPType]) -> R {
+    let (key, field) = parse_hash_key_field(items, "HGET")?;
+    Ok(ServerCommand::HGet { key, field })
+}
+
+fn parse_hmget_cmd(items: &[RESPType]) -> R {
+    let (key, fields) = parse_key_with_args(items, "HMGET")?;
+    Ok(ServerCommand::HMGet { key, fields })
+}
+
+fn parse_hgetall_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("HGETALL command must have key")));
     };
-    let Some(RESPType::BulkString(key)) = items.get(2) else {
-        bail!(fdbg!("XADD must have key"));
+    Ok(ServerCommand::HGetAll {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_hdel_cmd(items: &[RESPType]) -> R {
+    let (key, fields) = parse_key_with_args(items, "HDEL")?;
+    Ok(ServerCommand::HDel { key, fields })
+}
+
+fn parse_hexists_cmd(items: &[RESPType]) -> R {
+    let (key, field) = parse_hash_key_field(items, "HEXISTS")?;
+    Ok(ServerCommand::HExists { key, field })
+}
+
+fn parse_hlen_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("HLEN command must have key")));
     };
-    let Some(RESPType::BulkString(value)) = items.get(3) else {
-        bail!(fdbg!("XADD must have value"));
+    Ok(ServerCommand::HLen {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_hkeys_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("HKEYS command must have key")));
     };
-    Ok(ServerCommand::XAdd {
-        stream_key: stream_key.to_string(),
-        stream_id: stream_id.to_string(),
-        key: key.to_string(),
-        value: value.to_string(),
+    Ok(ServerCommand::HKeys {
+        key: key.to_owned(),
     })
 }
 
-fn parse_type_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(value)) = items.get(0) else {
-        bail!(fdbg!("TYPE command must have at least one key"));
+fn parse_hvals_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("HVALS command must have key")));
     };
-    Ok(ServerCommand::Type(value.to_string()))
+    Ok(ServerCommand::HVals {
+        key: key.to_owned(),
+    })
 }
 
-fn parse_keys_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(value)) = items.get(0) else {
-        bail!(fdbg!("KEYS command must have at least one key"));
+fn parse_hincrby_cmd(items: &[RESPType]) -> R {
+    let (key, field) = parse_hash_key_field(items, "HINCRBY")?;
+    let Some(RESPType::BulkString(increment)) = items.get(2) else {
+        bail!(ArityError(fdbg!("HINCRBY command must have increment")));
     };
-    Ok(ServerCommand::Keys(value.to_string()))
+    Ok(ServerCommand::HIncrBy {
+        key,
+        field,
+        increment: parse_integer(increment)?,
+    })
 }
 
-fn parse_config_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(cmd)) = items.get(0) else {
-        bail!(fdbg!("CONFIG command must have associated command"));
+fn parse_hincrbyfloat_cmd(items: &[RESPType]) -> R {
+    let (key, field) = parse_hash_key_field(items, "HINCRBYFLOAT")?;
+    let Some(RESPType::BulkString(increment)) = items.get(2) else {
+        bail!(ArityError(fdbg!(
+            "HINCRBYFLOAT command must have increment"
+        )));
     };
-    let Some(RESPType::BulkString(key)) = items.get(1) else {
-        bail!(fdbg!("CONFIG command must have at key"));
+    let Some(increment) = parse_float(increment) else {
+        bail!("ERR value is not a valid float");
     };
-    Ok(ServerCommand::Config {
-        cmd: cmd.to_string(),
-        key: key.to_string(),
+    Ok(ServerCommand::HIncrByFloat {
+        key,
+        field,
+        increment,
     })
 }
 
-fn parse_wait_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(num_replicas)) = items.get(0) else {
-        bail!(fdbg!("WAIT command must have at least one key"));
+fn parse_hstrlen_cmd(items: &[RESPType]) -> R {
+    let (key, field) = parse_hash_key_field(items, "HSTRLEN")?;
+    Ok(ServerCommand::HStrLen { key, field })
+}
+
+fn parse_hrandfield_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("HRANDFIELD command must have key")));
     };
-    let Some(RESPType::BulkString(value)) = items.get(1) else {
-        bail!(fdbg!("WAIT command must have at least one value"));
+    let count = match items.get(1) {
+        None => None,
+        Some(RESPType::BulkString(count)) => Some(parse_integer(count)?),
+        Some(_) => bail!(ArityError(fdbg!("HRANDFIELD count must be a bulk string"))),
     };
-    let num_replicas = num_replicas.parse::<usize>()?;
-    Ok(ServerCommand::Wait {
-        ack_wanted: num_replicas,
-        timeout_ms: value.parse::<usize>()?,
+    let with_values = match items.get(2) {
+        None => false,
+        Some(RESPType::BulkString(flag)) if flag.eq_ignore_ascii_case(b"withvalues") => true,
+        Some(_) => bail!("ERR syntax error"),
+    };
+    Ok(ServerCommand::HRandField {
+        key: key.to_owned(),
+        count,
+        with_values,
     })
 }
 
-fn parse_psync_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(key)) = items.get(0) else {
-        bail!(fdbg!("PSYNC command must have at least one key"));
+fn parse_push_cmd(items: &[RESPType], end: ListEnd, only_if_exists: bool) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("PUSH command must have key")));
     };
-    let Some(RESPType::BulkString(value)) = items.get(1) else {
-        bail!(fdbg!("PSYNC command must have at least one value"));
+    let elements = bulk_strings(&items[1..])?;
+    if elements.is_empty() {
+        bail!(ArityError(fdbg!(
+            "PUSH command must have at least one element"
+        )));
+    }
+    Ok(ServerCommand::ListPush {
+        key: key.to_owned(),
+        elements,
+        end,
+        only_if_exists,
+    })
+}
+
+fn parse_pop_cmd(items: &[RESPType], end: ListEnd) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("POP command must have key")));
     };
-    Ok(ServerCommand::PSync {
-        key: key.to_string(),
-        value: value.to_string(),
+    let count = match items.get(1) {
+        None => None,
+        Some(RESPType::BulkString(count)) => match text(count).parse::<usize>() {
+            Ok(count) => Some(count),
+            Err(_) => bail!("ERR value is out of range, must be positive"),
+        },
+        Some(_) => bail!(ArityError(fdbg!("POP count must be a bulk string"))),
+    };
+    Ok(ServerCommand::ListPop {
+        key: key.to_owned(),
+        end,
+        count,
     })
 }
-fn parse_replication_conf_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(key)) = items.get(0) else {
-        bail!(fdbg!("REPLCONF command must have at least one key"));
+
+fn parse_lrange_cmd(items: &[RESPType]) -> R {
+    let (key, start, stop) = parse_list_key_range(items, "LRANGE")?;
+    Ok(ServerCommand::LRange { key, start, stop })
+}
+
+fn parse_lindex_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("LINDEX command must have key")));
     };
-    let Some(RESPType::BulkString(value)) = items.get(1) else {
-        bail!(fdbg!("REPLCONF command must have at least one value"));
+    let Some(RESPType::BulkString(index)) = items.get(1) else {
+        bail!(ArityError(fdbg!("LINDEX command must have index")));
     };
-    Ok(ServerCommand::ReplConf {
-        key: key.to_string(),
-        value: value.to_string(),
+    Ok(ServerCommand::LIndex {
+        key: key.to_owned(),
+        index: parse_integer(index)?,
     })
 }
 
-fn parse_info_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(key)) = items.get(0) else {
-        bail!(fdbg!("INFO command must have at least one key"));
+fn parse_llen_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("LLEN command must have key")));
     };
-    Ok(ServerCommand::Info {
-        key: key.to_string(),
+    Ok(ServerCommand::LLen {
+        key: key.to_owned(),
     })
 }
 
-fn parse_get_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(key)) = items.get(0) else {
-        bail!(fdbg!("GEt command must have at least key"));
+fn parse_lset_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("LSET command must have key")));
     };
-    Ok(ServerCommand::Get {
+    let Some(RESPType::BulkString(index)) = items.get(1) else {
+        bail!(ArityError(fdbg!("LSET command must have index")));
+    };
+    let Some(RESPType::BulkString(element)) = items.get(2) else {
+        bail!(ArityError(fdbg!("LSET command must have element")));
+    };
+    Ok(ServerCommand::LSet {
         key: key.to_owned(),
+        index: parse_integer(index)?,
+        element: element.to_owned(),
     })
 }
 
-fn parse_incr_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(key)) = items.get(0) else {
-        bail!(fdbg!("Get command must have at least key"));
+fn parse_linsert_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("LINSERT command must have key")));
+    };
+    let Some(RESPType::BulkString(position)) = items.get(1) else {
+        bail!(ArityError(fdbg!("LINSERT command must have position")));
+    };
+    let before = match text(position).to_lowercase().as_str() {
+        "before" => true,
+        "after" => false,
+        _ => bail!("ERR syntax error"),
+    };
+    let Some(RESPType::BulkString(pivot)) = items.get(2) else {
+        bail!(ArityError(fdbg!("LINSERT command must have pivot")));
     };
-    Ok(ServerCommand::Incr {
+    let Some(RESPType::BulkString(element)) = items.get(3) else {
+        bail!(ArityError(fdbg!("LINSERT command must have element")));
+    };
+    Ok(ServerCommand::LInsert {
         key: key.to_owned(),
+        before,
+        pivot: pivot.to_owned(),
+        element: element.to_owned(),
     })
 }
 
-fn parse_set_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(key)) = items.get(0) else {
-        bail!(fdbg!("SET command must have at least key"));
+fn parse_lrem_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("LREM command must have key")));
     };
-    let Some(RESPType::BulkString(value)) = items.get(1) else {
-        bail!(fdbg!("SET command must have at least value"));
+    let Some(RESPType::BulkString(count)) = items.get(1) else {
+        bail!(ArityError(fdbg!("LREM command must have count")));
+    };
+    let Some(RESPType::BulkString(element)) = items.get(2) else {
+        bail!(ArityError(fdbg!("LREM command must have element")));
+    };
+    Ok(ServerCommand::LRem {
+        key: key.to_owned(),
+        count: parse_integer(count)?,
+        element: element.to_owned(),
+    })
+}
+
+fn parse_ltrim_cmd(items: &[RESPType]) -> R {
+    let (key, start, stop) = parse_list_key_range(items, "LTRIM")?;
+    Ok(ServerCommand::LTrim { key, start, stop })
+}
+
+fn parse_lpos_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("LPOS command must have key")));
+    };
+    let Some(RESPType::BulkString(element)) = items.get(1) else {
+        bail!(ArityError(fdbg!("LPOS command must have element")));
     };
-    let mut remaining = items[2..].iter();
-    let mut flags: HashMap<String, String> = HashMap::new();
-    while let Some(flag) = remaining.next() {
-        let RESPType::BulkString(flag) = flag else {
-            break;
+    let mut rank = 1;
+    let mut count = None;
+    let mut max_len = 0;
+    let mut options = bulk_strings(&items[2..])?.into_iter();
+    while let Some(option) = options.next() {
+        let Some(value) = options.next() else {
+            bail!("ERR syntax error");
         };
-        let flag = flag.to_lowercase();
-        match flag.as_str() {
-            "px" | "ex" => {
-                let Some(RESPType::BulkString(value)) = remaining.next() else {
-                    bail!(fdbg!("Missing value for flag: {}", flag));
-                };
-                flags.insert(flag, value.to_owned());
-            }
-            "get" => {
-                flags.insert(flag, "true".to_owned());
-            }
-            _ => {}
+        let value = parse_integer(&value)?;
+        match text(&option).to_lowercase().as_str() {
+            "rank" if value == 0 => bail!("ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the last match"),
+            "rank" => rank = value,
+            "count" if value < 0 => bail!("ERR COUNT can't be negative"),
+            "count" => count = Some(value as usize),
+            "maxlen" if value < 0 => bail!("ERR MAXLEN can't be negative"),
+            "maxlen" => max_len = value as usize,
+            _ => bail!("ERR syntax error"),
         }
     }
-
-    Ok(ServerCommand::Set {
+    Ok(ServerCommand::LPos {
         key: key.to_owned(),
-        value: value.to_owned(),
-        flags,
+        element: element.to_owned(),
+        rank,
+        count,
+        max_len,
     })
 }
 
-fn parse_echo_cmd(items: &[RESPType]) -> R {
-    let Some(RESPType::BulkString(value)) = items.get(0) else {
-        bail!(fdbg!("ECHO command must have at least one argument"));
+fn parse_lmove_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(source)) = items.first() else {
+        bail!(ArityError(fdbg!("LMOVE command must have source")));
     };
-    Ok(ServerCommand::Echo(value.to_owned()))
+    let Some(RESPType::BulkString(destination)) = items.get(1) else {
+        bail!(ArityError(fdbg!("LMOVE command must have destination")));
+    };
+    let Some(RESPType::BulkString(from)) = items.get(2) else {
+        bail!(ArityError(fdbg!("LMOVE command must have wherefrom")));
+    };
+    let Some(RESPType::BulkString(to)) = items.get(3) else {
+        bail!(ArityError(fdbg!("LMOVE command must have whereto")));
+    };
+    Ok(ServerCommand::LMove {
+        source: source.to_owned(),
+        destination: destination.to_owned(),
+        from: parse_list_end(from)?,
+        to: parse_list_end(to)?,
+    })
+}
+
+fn parse_bpop_cmd(items: &[RESPType], end: ListEnd) -> R {
+    let mut keys = bulk_strings(items)?;
+    let Some(timeout) = keys.pop() else {
+        bail!(ArityError(fdbg!("BLPOP/BRPOP command must have timeout")));
+    };
+    if keys.is_empty() {
+        bail!(ArityError(fdbg!(
+            "BLPOP/BRPOP command must have at least one key"
+        )));
+    }
+    Ok(ServerCommand::BPop {
+        keys,
+        end,
+        timeout: parse_timeout(&timeout)?,
+    })
+}
+
+fn parse_blmove_cmd(items: &[RESPType]) -> R {
+    let ServerCommand::LMove {
+        source,
+        destination,
+        from,
+        to,
+    } = parse_lmove_cmd(items)?
+    else {
+        bail!(ArityError(fdbg!(
+            "BLMOVE command must have LMOVE arguments"
+        )));
+    };
+    let Some(RESPType::BulkString(timeout)) = items.get(4) else {
+        bail!(ArityError(fdbg!("BLMOVE command must have timeout")));
+    };
+    Ok(ServerCommand::BLMove {
+        source,
+        destination,
+        from,
+        to,
+        timeout: parse_timeout(timeout)?,
+    })
+}
+
+fn parse_blmpop_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some(timeout) = args.first() else {
+        bail!(ArityError(fdbg!("BLMPOP command must have timeout")));
+    };
+    let timeout = parse_timeout(timeout)?;
+    let Some(num_keys) = args.get(1) else {
+        bail!(ArityError(fdbg!("BLMPOP command must have numkeys")));
+    };
+    // The keys start at the third argument
+    let keys_end = match text(num_keys).parse::<usize>() {
+        Ok(num_keys) if num_keys > 0 => num_keys.checked_add(2),
+        _ => None,
+    };
+    let Some(keys_end) = keys_end else {
+        bail!("ERR numkeys should be greater than 0");
+    };
+    let Some(keys) = args.get(2..keys_end) else {
+        bail!("ERR syntax error");
+    };
+    let Some(end) = args.get(keys_end) else {
+        bail!("ERR syntax error");
+    };
+    let end = parse_list_end(end)?;
+    let count = match &args[keys_end + 1..] {
+        [] => 1,
+        [option, count] if option.eq_ignore_ascii_case(b"count") => {
+            match text(count).parse::<usize>() {
+                Ok(count) if count > 0 => count,
+                _ => bail!("ERR count should be greater than 0"),
+            }
+        }
+        _ => bail!("ERR syntax error"),
+    };
+    Ok(ServerCommand::BLMPop {
+        keys: keys.to_vec(),
+        end,
+        count,
+        timeout,
+    })
+}
+
+fn parse_sadd_cmd(items: &[RESPType]) -> R {
+    let (key, members) = parse_key_with_args(items, "SADD")?;
+    Ok(ServerCommand::SAdd { key, members })
+}
+
+fn parse_srem_cmd(items: &[RESPType]) -> R {
+    let (key, members) = parse_key_with_args(items, "SREM")?;
+    Ok(ServerCommand::SRem { key, members })
+}
+
+fn parse_smembers_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("SMEMBERS command must have key")));
+    };
+    Ok(ServerCommand::SMembers {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_sismember_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("SISMEMBER command must have key")));
+    };
+    let Some(RESPType::BulkString(member)) = items.get(1) else {
+        bail!(ArityError(fdbg!("SISMEMBER command must have member")));
+    };
+    Ok(ServerCommand::SIsMember {
+        key: key.to_owned(),
+        member: member.to_owned(),
+    })
+}
+
+fn parse_smismember_cmd(items: &[RESPType]) -> R {
+    let (key, members) = parse_key_with_args(items, "SMISMEMBER")?;
+    Ok(ServerCommand::SMIsMember { key, members })
+}
+
+fn parse_scard_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("SCARD command must have key")));
+    };
+    Ok(ServerCommand::SCard {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_spop_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("SPOP command must have key")));
+    };
+    let count = match items.get(1) {
+        None => None,
+        Some(RESPType::BulkString(count)) => match text(count).parse::<usize>() {
+            Ok(count) => Some(count),
+            Err(_) => bail!("ERR value is out of range, must be positive"),
+        },
+        Some(_) => bail!(ArityError(fdbg!("SPOP count must be a bulk string"))),
+    };
+    Ok(ServerCommand::SPop {
+        key: key.to_owned(),
+        count,
+    })
+}
+
+fn parse_srandmember_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("SRANDMEMBER command must have key")));
+    };
+    let count = match items.get(1) {
+        None => None,
+        Some(RESPType::BulkString(count)) => Some(parse_integer(count)?),
+        Some(_) => bail!(ArityError(fdbg!("SRANDMEMBER count must be a bulk string"))),
+    };
+    Ok(ServerCommand::SRandMember {
+        key: key.to_owned(),
+        count,
+    })
+}
+
+fn parse_smove_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(source)) = items.first() else {
+        bail!(ArityError(fdbg!("SMOVE command must have source")));
+    };
+    let Some(RESPType::BulkString(destination)) = items.get(1) else {
+        bail!(ArityError(fdbg!("SMOVE command must have destination")));
+    };
+    let Some(RESPType::BulkString(member)) = items.get(2) else {
+        bail!(ArityError(fdbg!("SMOVE command must have member")));
+    };
+    Ok(ServerCommand::SMove {
+        source: source.to_owned(),
+        destination: destination.to_owned(),
+        member: member.to_owned(),
+    })
+}
+
+fn parse_set_op_cmd(items: &[RESPType], op: SetOp) -> R {
+    let keys = bulk_strings(items)?;
+    if keys.is_empty() {
+        bail!(ArityError(fdbg!(
+            "{:?} command must have at least one key",
+            op
+        )));
+    }
+    Ok(ServerCommand::SetOperation { op, keys })
+}
+
+fn parse_set_op_store_cmd(items: &[RESPType], op: SetOp) -> R {
+    let (destination, keys) = parse_key_with_args(items, "STORE")?;
+    Ok(ServerCommand::SetOperationStore {
+        op,
+        destination,
+        keys,
+    })
+}
+
+fn parse_sintercard_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some(num_keys) = args.first() else {
+        bail!(ArityError(fdbg!("SINTERCARD command must have numkeys")));
+    };
+    // The keys start at the second argument
+    let keys_end = match text(num_keys).parse::<usize>() {
+        Ok(num_keys) if num_keys > 0 => num_keys.checked_add(1),
+        _ => None,
+    };
+    let Some(keys_end) = keys_end else {
+        bail!("ERR numkeys should be greater than 0");
+    };
+    let Some(keys) = args.get(1..keys_end) else {
+        bail!("ERR Number of keys can't be greater than number of args");
+    };
+    let limit = match &args[keys_end..] {
+        [] => 0,
+        [option, limit] if option.eq_ignore_ascii_case(b"limit") => {
+            match text(limit).parse::<usize>() {
+                Ok(limit) => limit,
+                Err(_) => bail!("ERR LIMIT can't be negative"),
+            }
+        }
+        _ => bail!("ERR syntax error"),
+    };
+    Ok(ServerCommand::SInterCard {
+        keys: keys.to_vec(),
+        limit,
+    })
+}
+
+fn parse_zadd_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some((key, mut args)) = args.split_first() else {
+        bail!(ArityError(fdbg!("ZADD command must have key")));
+    };
+    let mut options = ZAddOptions::default();
+    let mut incr = false;
+    while let Some((flag, rest)) = args.split_first() {
+        match text(flag).to_uppercase().as_str() {
+            "NX" => options.nx = true,
+            "XX" => options.xx = true,
+            "GT" => options.gt = true,
+            "LT" => options.lt = true,
+            "CH" => options.ch = true,
+            "INCR" => incr = true,
+            _ => break,
+        }
+        args = rest;
+    }
+    if args.is_empty() || args.len() % 2 != 0 {
+        bail!("ERR syntax error");
+    }
+    if incr && args.len() != 2 {
+        bail!("ERR INCR option supports a single increment-element pair");
+    }
+    if options.nx && options.xx {
+        bail!("ERR XX and NX options at the same time are not compatible");
+    }
+    if (options.gt && options.lt) || ((options.gt || options.lt) && options.nx) {
+        bail!("ERR GT, LT, and/or NX options at the same time are not compatible");
+    }
+    let members = args
+        .chunks(2)
+        .map(|pair| Ok((parse_score(&pair[0])?, pair[1].to_owned())))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(ServerCommand::ZAdd {
+        key: key.to_owned(),
+        members,
+        options,
+        incr,
+    })
+}
+
+fn parse_zincrby_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("ZINCRBY command must have key")));
+    };
+    let Some(RESPType::BulkString(increment)) = items.get(1) else {
+        bail!(ArityError(fdbg!("ZINCRBY command must have increment")));
+    };
+    let Some(RESPType::BulkString(member)) = items.get(2) else {
+        bail!(ArityError(fdbg!("ZINCRBY command must have member")));
+    };
+    Ok(ServerCommand::ZIncrBy {
+        key: key.to_owned(),
+        increment: parse_score(increment)?,
+        member: member.to_owned(),
+    })
+}
+
+fn parse_zrem_cmd(items: &[RESPType]) -> R {
+    let (key, members) = parse_key_with_args(items, "ZREM")?;
+    Ok(ServerCommand::ZRem { key, members })
+}
+
+fn parse_zscore_cmd(items: &[RESPType]) -> R {
+    let (key, member) = parse_hash_key_field(items, "ZSCORE")?;
+    Ok(ServerCommand::ZScore { key, member })
+}
+
+fn parse_zmscore_cmd(items: &[RESPType]) -> R {
+    let (key, members) = parse_key_with_args(items, "ZMSCORE")?;
+    Ok(ServerCommand::ZMScore { key, members })
+}
+
+fn parse_zrank_cmd(items: &[RESPType], rev: bool) -> R {
+    let (key, member) = parse_hash_key_field(items, "ZRANK")?;
+    let with_score = match items.get(2) {
+        None => false,
+        Some(RESPType::BulkString(option)) if option.eq_ignore_ascii_case(b"withscore") => true,
+        Some(_) => bail!("ERR syntax error"),
+    };
+    Ok(ServerCommand::ZRank {
+        key,
+        member,
+        rev,
+        with_score,
+    })
+}
+
+fn parse_zcard_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("ZCARD command must have key")));
+    };
+    Ok(ServerCommand::ZCard {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_zcount_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, min, max] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "ZCOUNT command must have key, min and max"
+        )));
+    };
+    Ok(ServerCommand::ZCount {
+        key: key.to_owned(),
+        min: parse_score_bound(min)?,
+        max: parse_score_bound(max)?,
+    })
+}
+
+fn parse_zrange_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some((key, args)) = args.split_first() else {
+        bail!(ArityError(fdbg!("ZRANGE command must have key")));
+    };
+    let (spec, with_scores) = parse_zrange_spec(args)?;
+    Ok(ServerCommand::ZRange {
+        key: key.to_owned(),
+        spec,
+        with_scores,
+    })
+}
+
+fn parse_zrangestore_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [destination, source, args @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "ZRANGESTORE command must have destination and source"
+        )));
+    };
+    let (spec, with_scores) = parse_zrange_spec(args)?;
+    if with_scores {
+        bail!("ERR syntax error");
+    }
+    Ok(ServerCommand::ZRangeStore {
+        destination: destination.to_owned(),
+        source: source.to_owned(),
+        spec,
+    })
+}
+
+/// Parses `start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]`
+fn parse_zrange_spec(args: &[Bytes]) -> anyhow::Result<(ZRangeSpec, bool)> {
+    let [start, stop, options @ ..] = args else {
+        bail!(ArityError(fdbg!("ZRANGE command must have start and stop")));
+    };
+    let (mut by_score, mut by_lex, mut rev, mut with_scores) = (false, false, false, false);
+    let mut limit = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match text(option).to_uppercase().as_str() {
+            "BYSCORE" => by_score = true,
+            "BYLEX" => by_lex = true,
+            "REV" => rev = true,
+            "WITHSCORES" => with_scores = true,
+            "LIMIT" => {
+                let (Some(offset), Some(count)) = (options.next(), options.next()) else {
+                    bail!("ERR syntax error");
+                };
+                limit = Some((parse_integer(offset)?, parse_integer(count)?));
+            }
+            _ => bail!("ERR syntax error"),
+        }
+    }
+    if by_score && by_lex {
+        bail!("ERR syntax error");
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        bail!(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+        );
+    }
+    if with_scores && by_lex {
+        bail!("ERR syntax error, WITHSCORES not supported in combination with BYLEX");
+    }
+    // Score and lex ranges name the max first when reversed
+    let (min, max) = match rev {
+        true => (stop, start),
+        false => (start, stop),
+    };
+    let by = if by_score {
+        ZRangeBy::Score(parse_score_bound(min)?, parse_score_bound(max)?)
+    } else if by_lex {
+        ZRangeBy::Lex(parse_lex_bound(min)?, parse_lex_bound(max)?)
+    } else {
+        ZRangeBy::Rank(parse_integer(start)?, parse_integer(stop)?)
+    };
+    Ok((ZRangeSpec { by, rev, limit }, with_scores))
+}
+
+fn parse_zpop_cmd(items: &[RESPType], max: bool) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("ZPOP command must have key")));
+    };
+    let count = match items.get(1) {
+        None => 1,
+        Some(RESPType::BulkString(count)) => match text(count).parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => bail!("ERR value is out of range, must be positive"),
+        },
+        Some(_) => bail!(ArityError(fdbg!("ZPOP count must be a bulk string"))),
+    };
+    Ok(ServerCommand::ZPop {
+        key: key.to_owned(),
+        count,
+        max,
+    })
+}
+
+/// Parses `destination numkeys key [key ...] [WEIGHTS weight ...] [AGGREGATE SUM|MIN|MAX]`,
+/// where ZDIFFSTORE takes neither option
+fn parse_zset_op_store_cmd(items: &[RESPType], op: SetOp, cmd: &str) -> R {
+    let args = bulk_strings(items)?;
+    let [destination, num_keys, args @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "{} command must have destination and numkeys",
+            cmd
+        )));
+    };
+    let num_keys = match parse_integer(num_keys)? {
+        num_keys if num_keys > 0 => num_keys as usize,
+        _ => bail!("ERR at least 1 input key is needed for '{}' command", cmd),
+    };
+    let Some(keys) = args.get(..num_keys) else {
+        bail!("ERR syntax error");
+    };
+    let mut weights = vec![1.0; num_keys];
+    let mut aggregate = Aggregate::Sum;
+    let mut options = args[num_keys..].iter();
+    let takes_options = !matches!(op, SetOp::Diff);
+    while let Some(option) = options.next() {
+        match text(option).to_uppercase().as_str() {
+            "WEIGHTS" if takes_options => {
+                for weight in weights.iter_mut() {
+                    let Some(value) = options.next() else {
+                        bail!("ERR syntax error");
+                    };
+                    let Some(value) = parse_float(value) else {
+                        bail!("ERR weight value is not a float");
+                    };
+                    *weight = value;
+                }
+            }
+            "AGGREGATE" if takes_options => {
+                aggregate = match options
+                    .next()
+                    .map(|value| text(value).to_uppercase())
+                    .as_deref()
+                {
+                    Some("SUM") => Aggregate::Sum,
+                    Some("MIN") => Aggregate::Min,
+                    Some("MAX") => Aggregate::Max,
+                    _ => bail!("ERR syntax error"),
+                };
+            }
+            _ => bail!("ERR syntax error"),
+        }
+    }
+    Ok(ServerCommand::ZSetOperationStore {
+        op,
+        destination: destination.to_owned(),
+        keys: keys.to_vec(),
+        weights,
+        aggregate,
+    })
+}
+
+fn parse_geoadd_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some((key, mut args)) = args.split_first() else {
+        bail!(ArityError(fdbg!("GEOADD command must have key")));
+    };
+    let mut options = ZAddOptions::default();
+    while let Some((flag, rest)) = args.split_first() {
+        match text(flag).to_uppercase().as_str() {
+            "NX" => options.nx = true,
+            "XX" => options.xx = true,
+            "CH" => options.ch = true,
+            _ => break,
+        }
+        args = rest;
+    }
+    if args.is_empty() || args.len() % 3 != 0 {
+        bail!("ERR syntax error");
+    }
+    if options.nx && options.xx {
+        bail!("ERR XX and NX options at the same time are not compatible");
+    }
+    let members = args
+        .chunks(3)
+        .map(|triple| {
+            let (longitude, latitude) = parse_coordinates(&triple[0], &triple[1])?;
+            Ok((longitude, latitude, triple[2].to_owned()))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(ServerCommand::GeoAdd {
+        key: key.to_owned(),
+        members,
+        options,
+    })
+}
+
+fn parse_geopos_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("GEOPOS command must have key")));
+    };
+    Ok(ServerCommand::GeoPos {
+        key: key.to_owned(),
+        members: bulk_strings(&items[1..])?,
+    })
+}
+
+fn parse_geodist_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let (key, from, to, unit) = match args.as_slice() {
+        [key, from, to] => (key, from, to, 1.0),
+        [key, from, to, unit] => (key, from, to, parse_geo_unit(unit)?),
+        _ => bail!("ERR syntax error"),
+    };
+    Ok(ServerCommand::GeoDist {
+        key: key.to_owned(),
+        from: from.to_owned(),
+        to: to.to_owned(),
+        unit,
+    })
+}
+
+fn parse_geohash_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("GEOHASH command must have key")));
+    };
+    Ok(ServerCommand::GeoHash {
+        key: key.to_owned(),
+        members: bulk_strings(&items[1..])?,
+    })
+}
+
+fn parse_geosearch_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some((key, args)) = args.split_first() else {
+        bail!(ArityError(fdbg!("GEOSEARCH command must have key")));
+    };
+    let (query, flags) = parse_geo_query(args, &["WITHCOORD", "WITHDIST", "WITHHASH"])?;
+    Ok(ServerCommand::GeoSearch {
+        key: key.to_owned(),
+        query,
+        with_coord: flags.contains(&"WITHCOORD"),
+        with_dist: flags.contains(&"WITHDIST"),
+        with_hash: flags.contains(&"WITHHASH"),
+    })
+}
+
+fn parse_geosearchstore_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [destination, source, args @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "GEOSEARCHSTORE command must have destination and source"
+        )));
+    };
+    let (query, flags) = parse_geo_query(args, &["STOREDIST"])?;
+    Ok(ServerCommand::GeoSearchStore {
+        destination: destination.to_owned(),
+        source: source.to_owned(),
+        query,
+        store_dist: flags.contains(&"STOREDIST"),
+    })
+}
+
+fn parse_pfadd_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("PFADD command must have key")));
+    };
+    Ok(ServerCommand::PfAdd {
+        key: key.to_owned(),
+        elements: bulk_strings(&items[1..])?,
+    })
+}
+
+fn parse_pfcount_cmd(items: &[RESPType]) -> R {
+    let keys = bulk_strings(items)?;
+    if keys.is_empty() {
+        bail!(ArityError(fdbg!(
+            "PFCOUNT command must have at least one key"
+        )));
+    }
+    Ok(ServerCommand::PfCount { keys })
+}
+
+fn parse_pfmerge_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(destination)) = items.first() else {
+        bail!(ArityError(fdbg!("PFMERGE command must have destination")));
+    };
+    Ok(ServerCommand::PfMerge {
+        destination: destination.to_owned(),
+        keys: bulk_strings(&items[1..])?,
+    })
+}
+
+fn parse_setbit_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, offset, value] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "SETBIT command must have key, offset and value"
+        )));
+    };
+    let value = match value.as_ref() {
+        b"0" => false,
+        b"1" => true,
+        _ => bail!("ERR bit is not an integer or out of range"),
+    };
+    Ok(ServerCommand::SetBit {
+        key: key.to_owned(),
+        offset: parse_bit_offset(offset, None)?,
+        value,
+    })
+}
+
+fn parse_getbit_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, offset] = args.as_slice() else {
+        bail!(ArityError(fdbg!("GETBIT command must have key and offset")));
+    };
+    Ok(ServerCommand::GetBit {
+        key: key.to_owned(),
+        offset: parse_bit_offset(offset, None)?,
+    })
+}
+
+fn parse_bitcount_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some((key, args)) = args.split_first() else {
+        bail!(ArityError(fdbg!("BITCOUNT command must have key")));
+    };
+    let range = match args {
+        [] => None,
+        // Unlike BITPOS, a start without an end is not allowed
+        [_] => bail!("ERR syntax error"),
+        [start, end, unit @ ..] => Some(parse_bit_range(start, Some(end), unit)?),
+    };
+    Ok(ServerCommand::BitCount {
+        key: key.to_owned(),
+        range,
+    })
+}
+
+fn parse_bitpos_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, bit, args @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!("BITPOS command must have key and bit")));
+    };
+    let bit = match bit.as_ref() {
+        b"0" => false,
+        b"1" => true,
+        _ => bail!("ERR The bit argument must be 1 or 0."),
+    };
+    let range = match args {
+        [] => None,
+        [start] => Some(parse_bit_range(start, None, &[])?),
+        [start, end, unit @ ..] => Some(parse_bit_range(start, Some(end), unit)?),
+    };
+    Ok(ServerCommand::BitPos {
+        key: key.to_owned(),
+        bit,
+        range,
+    })
+}
+
+fn parse_bit_range(start: &[u8], end: Option<&Bytes>, unit: &[Bytes]) -> anyhow::Result<BitRange> {
+    let unit = match unit {
+        [] => BitUnit::Byte,
+        [unit] => match text(unit).to_uppercase().as_str() {
+            "BYTE" => BitUnit::Byte,
+            "BIT" => BitUnit::Bit,
+            _ => bail!("ERR syntax error"),
+        },
+        _ => bail!("ERR syntax error"),
+    };
+    Ok(BitRange {
+        start: parse_integer(start)?,
+        end: end.map(|end| parse_integer(end)).transpose()?,
+        unit,
+    })
+}
+
+fn parse_bitop_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [op, destination, keys @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "BITOP command must have operation and destination"
+        )));
+    };
+    let op = match text(op).to_uppercase().as_str() {
+        "AND" => BitOperation::And,
+        "OR" => BitOperation::Or,
+        "XOR" => BitOperation::Xor,
+        "NOT" => BitOperation::Not,
+        _ => bail!("ERR syntax error"),
+    };
+    match (op, keys.len()) {
+        (_, 0) => bail!(ArityError(fdbg!(
+            "BITOP command must have at least one key"
+        ))),
+        (BitOperation::Not, 2..) => {
+            bail!("ERR BITOP NOT must be called with a single source key.")
+        }
+        _ => {}
+    }
+    Ok(ServerCommand::BitOp {
+        op,
+        destination: destination.to_owned(),
+        keys: keys.to_vec(),
+    })
+}
+
+fn parse_bitfield_cmd(items: &[RESPType], read_only: bool) -> R {
+    let args = bulk_strings(items)?;
+    let Some((key, args)) = args.split_first() else {
+        bail!(ArityError(fdbg!("BITFIELD command must have key")));
+    };
+    let mut ops = vec![];
+    let mut overflow = BitFieldOverflow::Wrap;
+    let mut args = args.iter();
+    while let Some(subcommand) = args.next() {
+        let subcommand = text(subcommand).to_uppercase();
+        if read_only && subcommand != "GET" {
+            bail!("ERR BITFIELD_RO only supports the GET subcommand");
+        }
+        if subcommand == "OVERFLOW" {
+            overflow = match args
+                .next()
+                .map(|value| text(value).to_uppercase())
+                .as_deref()
+            {
+                Some("WRAP") => BitFieldOverflow::Wrap,
+                Some("SAT") => BitFieldOverflow::Sat,
+                Some("FAIL") => BitFieldOverflow::Fail,
+                Some(_) => bail!("ERR Invalid OVERFLOW type specified"),
+                None => bail!("ERR syntax error"),
+            };
+            continue;
+        }
+        let (Some(field), Some(offset)) = (args.next(), args.next()) else {
+            bail!("ERR syntax error");
+        };
+        let field = parse_bitfield_type(field)?;
+        let offset = parse_bit_offset(offset, Some(field))?;
+        let op = match subcommand.as_str() {
+            "GET" => BitFieldOp::Get { field, offset },
+            "SET" => {
+                let Some(value) = args.next() else {
+                    bail!("ERR syntax error");
+                };
+                BitFieldOp::Set {
+                    field,
+                    offset,
+                    value: parse_integer(value)?,
+                    overflow,
+                }
+            }
+            "INCRBY" => {
+                let Some(increment) = args.next() else {
+                    bail!("ERR syntax error");
+                };
+                BitFieldOp::IncrBy {
+                    field,
+                    offset,
+                    increment: parse_integer(increment)?,
+                    overflow,
+                }
+            }
+            _ => bail!("ERR syntax error"),
+        };
+        ops.push(op);
+    }
+    Ok(ServerCommand::BitField {
+        key: key.to_owned(),
+        ops,
+    })
+}
+
+/// Parses `i1` through `i64` and `u1` through `u63`
+fn parse_bitfield_type(value: &[u8]) -> anyhow::Result<BitFieldType> {
+    let field = match value.split_first() {
+        Some((b'i' | b'I', bits)) => text(bits).parse::<u32>().ok().map(|bits| (true, bits)),
+        Some((b'u' | b'U', bits)) => text(bits).parse::<u32>().ok().map(|bits| (false, bits)),
+        _ => None,
+    };
+    match field {
+        Some((true, bits @ 1..=64)) | Some((false, bits @ 1..=63)) => Ok(BitFieldType {
+            signed: matches!(field, Some((true, _))),
+            bits,
+        }),
+        _ => bail!("ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."),
+    }
+}
+
+/// Bit offsets reach up to the 512MB string limit. BITFIELD offsets prefixed with `#` count
+/// in multiples of the field width.
+fn parse_bit_offset(value: &[u8], field: Option<BitFieldType>) -> anyhow::Result<u64> {
+    let (value, multiplier) = match (value.strip_prefix(b"#"), field) {
+        (Some(value), Some(field)) => (value, field.bits as u64),
+        _ => (value, 1),
+    };
+    match text(value)
+        .parse::<u64>()
+        .ok()
+        .and_then(|offset| offset.checked_mul(multiplier))
+    {
+        Some(offset) if offset < 1 << 32 => Ok(offset),
+        _ => bail!("ERR bit offset is not an integer or out of range"),
+    }
+}
+
+/// Parses the GEOSEARCH options shared with GEOSEARCHSTORE, returning which of `flags` were set
+fn parse_geo_query<'a>(
+    args: &[Bytes],
+    flags: &[&'a str],
+) -> anyhow::Result<(GeoQuery, Vec<&'a str>)> {
+    let mut origins: Vec<GeoOrigin> = vec![];
+    let mut shapes: Vec<GeoShape> = vec![];
+    let mut unit = 1.0;
+    let mut descending = false;
+    let mut count = None;
+    let mut any = false;
+    let mut set_flags = vec![];
+    let mut args = args.iter();
+    let mut next = || match args.next() {
+        Some(arg) => Ok(arg),
+        None => Err(anyhow::anyhow!("ERR syntax error")),
+    };
+    while let Ok(option) = next() {
+        let option = text(option).to_uppercase();
+        match option.as_str() {
+            "FROMMEMBER" => origins.push(GeoOrigin::Member(next()?.to_owned())),
+            "FROMLONLAT" => {
+                let (longitude, latitude) = parse_coordinates(next()?, next()?)?;
+                origins.push(GeoOrigin::Coordinates(longitude, latitude));
+            }
+            "BYRADIUS" => {
+                let Some(radius) = parse_float(next()?) else {
+                    bail!("ERR need numeric radius");
+                };
+                if radius < 0.0 {
+                    bail!("ERR radius cannot be negative");
+                }
+                unit = parse_geo_unit(next()?)?;
+                shapes.push(GeoShape::Radius(radius * unit));
+            }
+            "BYBOX" => {
+                let (Some(width), Some(height)) = (parse_float(next()?), parse_float(next()?))
+                else {
+                    bail!("ERR need numeric width and height");
+                };
+                if width < 0.0 || height < 0.0 {
+                    bail!("ERR height or width cannot be negative");
+                }
+                unit = parse_geo_unit(next()?)?;
+                shapes.push(GeoShape::Box {
+                    width: width * unit,
+                    height: height * unit,
+                });
+            }
+            "ASC" => descending = false,
+            "DESC" => descending = true,
+            "COUNT" => match parse_integer(next()?)? {
+                value if value > 0 => count = Some(value as usize),
+                _ => bail!("ERR COUNT must be > 0"),
+            },
+            "ANY" => any = true,
+            _ => match flags.iter().find(|flag| **flag == option) {
+                Some(flag) => set_flags.push(*flag),
+                None => bail!("ERR syntax error"),
+            },
+        }
+    }
+    let Ok([origin]) = <[GeoOrigin; 1]>::try_from(origins) else {
+        bail!("ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for geosearch");
+    };
+    let Ok([shape]) = <[GeoShape; 1]>::try_from(shapes) else {
+        bail!("ERR exactly one of BYRADIUS and BYBOX can be specified for geosearch");
+    };
+    if any && count.is_none() {
+        bail!("ERR the ANY argument requires COUNT argument");
+    }
+    let query = GeoQuery {
+        origin,
+        shape,
+        unit,
+        descending,
+        count,
+        any,
+    };
+    Ok((query, set_flags))
+}
+
+fn parse_coordinates(longitude: &[u8], latitude: &[u8]) -> anyhow::Result<(f64, f64)> {
+    let (Some(longitude), Some(latitude)) = (parse_float(longitude), parse_float(latitude)) else {
+        bail!("ERR value is not a valid float");
+    };
+    if !geo::valid_coordinates(longitude, latitude) {
+        bail!(
+            "ERR invalid longitude,latitude pair {:.6},{:.6}",
+            longitude,
+            latitude
+        );
+    }
+    Ok((longitude, latitude))
+}
+
+/// Meters per unit for the distance units GEO commands accept
+fn parse_geo_unit(unit: &[u8]) -> anyhow::Result<f64> {
+    let meters = match text(unit).to_lowercase().as_str() {
+        "m" => 1.0,
+        "km" => 1000.0,
+        "ft" => 0.3048,
+        "mi" => 1609.34,
+        _ => bail!("ERR unsupported unit provided. please use M, KM, FT, MI"),
+    };
+    Ok(meters)
+}
+
+fn parse_score(value: &[u8]) -> anyhow::Result<f64> {
+    let Some(score) = parse_float(value) else {
+        bail!("ERR value is not a valid float");
+    };
+    Ok(score)
+}
+
+/// Score bounds are floats or `-inf`/`+inf`, exclusive when prefixed with `(`
+fn parse_score_bound(value: &[u8]) -> anyhow::Result<ScoreBound> {
+    let bound = match value.strip_prefix(b"(") {
+        Some(value) => parse_float(value).map(ScoreBound::Exclusive),
+        None => parse_float(value).map(ScoreBound::Inclusive),
+    };
+    let Some(bound) = bound else {
+        bail!("ERR min or max is not a float");
+    };
+    Ok(bound)
+}
+
+/// Lex bounds are `-`, `+` or a string prefixed with `[` (inclusive) or `(` (exclusive)
+fn parse_lex_bound(value: &Bytes) -> anyhow::Result<LexBound> {
+    let bound = match value.as_ref() {
+        b"-" => LexBound::Min,
+        b"+" => LexBound::Max,
+        _ => match (value.first(), value.slice(1..)) {
+            (Some(b'['), value) => LexBound::Inclusive(value),
+            (Some(b'('), value) => LexBound::Exclusive(value),
+            _ => bail!("ERR min or max not valid string range item"),
+        },
+    };
+    Ok(bound)
+}
+
+/// Blocking timeouts are seconds given as a float, where 0 means wait forever
+fn parse_timeout(value: &[u8]) -> anyhow::Result<Option<Duration>> {
+    let Some(seconds) = parse_float(value).filter(|seconds| seconds.is_finite()) else {
+        bail!("ERR timeout is not a float or out of range");
+    };
+    if seconds < 0.0 {
+        bail!("ERR timeout is negative");
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(timeout) => Ok(Some(timeout)),
+        Err(_) => bail!("ERR timeout is out of range"),
+    }
+}
+
+fn parse_list_end(value: &[u8]) -> anyhow::Result<ListEnd> {
+    match text(value).to_lowercase().as_str() {
+        "left" => Ok(ListEnd::Left),
+        "right" => Ok(ListEnd::Right),
+        _ => bail!("ERR syntax error"),
+    }
+}
+
+fn parse_list_key_range(items: &[RESPType], cmd: &str) -> anyhow::Result<(Bytes, i64, i64)> {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("{} command must have key", cmd)));
+    };
+    let Some(RESPType::BulkString(start)) = items.get(1) else {
+        bail!(ArityError(fdbg!("{} command must have start", cmd)));
+    };
+    let Some(RESPType::BulkString(stop)) = items.get(2) else {
+        bail!(ArityError(fdbg!("{} command must have stop", cmd)));
+    };
+    Ok((key.to_owned(), parse_integer(start)?, parse_integer(stop)?))
+}
+
+fn parse_hash_key_field(items: &[RESPType], cmd: &str) -> anyhow::Result<(Bytes, Bytes)> {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("{} command must have key", cmd)));
+    };
+    let Some(RESPType::BulkString(field)) = items.get(1) else {
+        bail!(ArityError(fdbg!("{} command must have field", cmd)));
+    };
+    Ok((key.to_owned(), field.to_owned()))
+}
+
+/// Parses `key arg [arg ...]`, requiring at least one argument after the key
+fn parse_key_with_args(items: &[RESPType], cmd: &str) -> anyhow::Result<(Bytes, Vec<Bytes>)> {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("{} command must have key", cmd)));
+    };
+    let args = bulk_strings(&items[1..])?;
+    if args.is_empty() {
+        bail!(ArityError(fdbg!(
+            "{} command must have at least one argument",
+            cmd
+        )));
+    }
+    Ok((key.to_owned(), args))
+}
+
+fn parse_xread_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(typez)) = items.first() else {
+        bail!(ArityError(fdbg!("XREAD must have type")));
+    };
+
+    let mut block_ms: Option<u64> = None;
+    let consumed;
+
+    match text(typez).to_lowercase().as_str() {
+        "block" => {
+            let Some(RESPType::BulkString(block)) = items.get(1) else {
+                bail!(ArityError(fdbg!("XREAD must have block")));
+            };
+            block_ms = Some(text(block).parse::<u64>()?);
+            let Some(RESPType::BulkString(typez)) = items.get(2) else {
+                bail!(ArityError(fdbg!("XREAD must have type")));
+            };
+            if text(typez).to_lowercase() != "streams" {
+                bail!(ArityError(fdbg!("XREAD must have type 'streams'")))
+            }
+            consumed = 3;
+        }
+        "streams" => {
+            consumed = 1;
+        }
+        _ => bail!(ArityError(fdbg!(
+            "XREAD must have type 'streams' or 'block'"
+        ))),
+    }
+
+    let items = bulk_strings(&items[consumed..])?;
+    if items.is_empty() {
+        bail!(ArityError(fdbg!("XREAD must have streams")));
+    }
+    if items.len() % 2 != 0 {
+        bail!("ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.");
+    }
+    let (keys, ids) = items.split_at(items.len() / 2);
+    let filter = keys
+        .iter()
+        .zip(ids)
+        .map(|(key, stream_id)| match stream_id.as_ref() {
+            b"$" => Ok((key.to_owned(), None)),
+            _ => match parse_stream_id(&text(stream_id), 0) {
+                Some(stream_id) => Ok((key.to_owned(), Some(stream_id))),
+                None => bail!(INVALID_STREAM_ID),
+            },
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    debug!(?filter, ?block_ms, "This is the parsed filter");
+    Ok(ServerCommand::XRead(filter, block_ms))
+}
+
+/// XRANGE takes the start before the end and XREVRANGE the other way around
+fn parse_xrange_cmd(items: &[RESPType], rev: bool) -> R {
+    let args = bulk_strings(items)?;
+    let [stream_key, first, second, options @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "XRANGE must have stream_key, start and end"
+        )));
+    };
+    let (start, end) = match rev {
+        true => (second, first),
+        false => (first, second),
+    };
+    let (start, exclusive) = parse_stream_range_bound(start, 0, "start")?;
+    let start = match exclusive {
+        true => {
+            next_stream_id(start).ok_or_else(|| anyhow!("ERR invalid start ID for the interval"))?
+        }
+        false => start,
+    };
+    let (end, exclusive) = parse_stream_range_bound(end, usize::MAX, "end")?;
+    let end = match exclusive {
+        true => {
+            prev_stream_id(end).ok_or_else(|| anyhow!("ERR invalid end ID for the interval"))?
+        }
+        false => end,
+    };
+    let count = match options {
+        [] => None,
+        [option, count] if text(option).eq_ignore_ascii_case("COUNT") => {
+            Some(parse_integer(count)?.max(0) as usize)
+        }
+        _ => bail!("ERR syntax error"),
+    };
+    Ok(ServerCommand::XRange {
+        stream_key: stream_key.to_owned(),
+        range: StreamRange {
+            start,
+            end,
+            count,
+            rev,
+        },
+    })
+}
+
+/// Parses `-`, `+` or an ID, where a leading `(` makes an ID exclusive. IDs without a
+/// sequence number get `missing_seq`, so they cover the whole millisecond. `bound` names the
+/// side of the interval in errors.
+fn parse_stream_range_bound(
+    value: &[u8],
+    missing_seq: usize,
+    bound: &str,
+) -> anyhow::Result<(StreamId, bool)> {
+    let (value, exclusive) = match value.strip_prefix(b"(") {
+        Some(value) if !value.is_empty() => (value, true),
+        _ => (value, false),
+    };
+    let id = match value {
+        // The ends of a stream can't be excluded
+        b"-" | b"+" if exclusive => bail!("ERR invalid {bound} ID for the interval"),
+        b"-" => (0, 0),
+        b"+" => MAX_STREAM_ID,
+        _ => match parse_stream_id(&text(value), missing_seq) {
+            Some(id) => id,
+            None => bail!(INVALID_STREAM_ID),
+        },
+    };
+    Ok((id, exclusive))
+}
+
+fn parse_xadd_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(stream_key)) = items.first() else {
+        bail!(ArityError(fdbg!("XADD must have stream_key")));
+    };
+    let args = bulk_strings(&items[1..])?;
+    let (options, consumed) = parse_stream_trim_args(&args, true)?;
+    let Some((stream_id, args)) = args[consumed..].split_first() else {
+        bail!(ArityError(fdbg!("XADD must have stream_id")));
+    };
+    if args.is_empty() || args.len() % 2 != 0 {
+        bail!(ArityError(fdbg!("XADD must have field value pairs")));
+    }
+    let fields = args
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    Ok(ServerCommand::XAdd {
+        stream_key: stream_key.to_owned(),
+        stream_id: text(stream_id).into_owned(),
+        fields,
+        options,
+    })
+}
+
+fn parse_xtrim_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("XTRIM command must have key")));
+    };
+    let args = bulk_strings(&items[1..])?;
+    if args.is_empty() {
+        bail!(ArityError(fdbg!(
+            "XTRIM command must have a trimming strategy"
+        )));
+    }
+    let (options, _) = parse_stream_trim_args(&args, false)?;
+    let Some(trim) = options.trim else {
+        bail!("ERR syntax error, XTRIM must be called with a trimming strategy");
+    };
+    Ok(ServerCommand::XTrim {
+        key: key.to_owned(),
+        trim,
+    })
+}
+
+/// Parses `[MAXLEN|MINID [=|~] threshold] [LIMIT count]`, and NOMKSTREAM for XADD. For XADD
+/// the options end at the entry ID; returns how many arguments the options took.
+fn parse_stream_trim_args(args: &[Bytes], xadd: bool) -> anyhow::Result<(XAddOptions, usize)> {
+    let mut options = XAddOptions::default();
+    let (mut strategy, mut approximate, mut limit) = (None, false, None);
+    let mut i = 0;
+    while let Some(arg) = args.get(i) {
+        let has_value = i + 1 < args.len();
+        match text(arg).to_uppercase().as_str() {
+            name @ ("MAXLEN" | "MINID") if has_value => {
+                if strategy.is_some() {
+                    bail!("ERR syntax error, MAXLEN and MINID options at the same time are not compatible");
+                }
+                approximate = false;
+                if i + 2 < args.len() && matches!(args[i + 1].as_ref(), b"~" | b"=") {
+                    approximate = args[i + 1].as_ref() == b"~";
+                    i += 1;
+                }
+                let threshold = &args[i + 1];
+                strategy = Some(match name {
+                    "MAXLEN" => {
+                        let max_len = parse_integer(threshold)?;
+                        if max_len < 0 {
+                            bail!("ERR The MAXLEN argument must be >= 0.");
+                        }
+                        TrimStrategy::MaxLen(max_len as usize)
+                    }
+                    _ => match parse_stream_id(&text(threshold), 0) {
+                        Some(min_id) => TrimStrategy::MinId(min_id),
+                        None => bail!(INVALID_STREAM_ID),
+                    },
+                });
+                i += 2;
+            }
+            "LIMIT" if has_value => {
+                let count = parse_integer(&args[i + 1])?;
+                if count < 0 {
+                    bail!("ERR The LIMIT argument must be >= 0.");
+                }
+                limit = Some(count as usize);
+                i += 2;
+            }
+            "NOMKSTREAM" if xadd => {
+                options.no_mkstream = true;
+                i += 1;
+            }
+            // The entry ID
+            _ if xadd => break,
+            _ => bail!("ERR syntax error"),
+        }
+    }
+    let Some(strategy) = strategy else {
+        if limit.is_some_and(|limit| limit > 0) {
+            bail!("ERR syntax error, LIMIT cannot be used without specifying a trimming strategy");
+        }
+        return Ok((options, i));
+    };
+    let limit = match (limit, approximate) {
+        (Some(_), false) => {
+            bail!("ERR syntax error, LIMIT cannot be used without the special ~ option")
+        }
+        (Some(limit), true) => limit,
+        (None, true) => DEFAULT_STREAM_TRIM_LIMIT,
+        (None, false) => 0,
+    };
+    options.trim = Some(StreamTrim {
+        strategy,
+        approximate,
+        limit,
+    });
+    Ok((options, i))
+}
+
+fn parse_xdel_cmd(items: &[RESPType]) -> R {
+    let (key, ids) = parse_key_with_args(items, "XDEL")?;
+    let ids = ids
+        .iter()
+        .map(|id| parse_stream_id(&text(id), 0).ok_or_else(|| anyhow!(INVALID_STREAM_ID)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(ServerCommand::XDel { key, ids })
+}
+
+fn parse_xlen_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("XLEN command must have key")));
+    };
+    Ok(ServerCommand::XLen {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_type_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(value)) = items.first() else {
+        bail!(ArityError(fdbg!("TYPE command must have at least one key")));
+    };
+    Ok(ServerCommand::Type(value.to_owned()))
+}
+
+fn parse_keys_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(value)) = items.first() else {
+        bail!(ArityError(fdbg!("KEYS command must have at least one key")));
+    };
+    Ok(ServerCommand::Keys(value.to_owned()))
+}
+
+fn parse_config_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(cmd)) = items.first() else {
+        bail!(ArityError(fdbg!(
+            "CONFIG command must have associated command"
+        )));
+    };
+    let Some(RESPType::BulkString(key)) = items.get(1) else {
+        bail!(ArityError(fdbg!("CONFIG command must have at key")));
+    };
+    Ok(ServerCommand::Config {
+        cmd: text(cmd).into_owned(),
+        key: text(key).into_owned(),
+    })
+}
+
+fn parse_wait_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(num_replicas)) = items.first() else {
+        bail!(ArityError(fdbg!("WAIT command must have at least one key")));
+    };
+    let Some(RESPType::BulkString(value)) = items.get(1) else {
+        bail!(ArityError(fdbg!(
+            "WAIT command must have at least one value"
+        )));
+    };
+    // Asking for no replicas or fewer is answered at once
+    let num_replicas = parse_integer(num_replicas)?.max(0) as usize;
+    let Some(timeout_ms) = parse_i64(value) else {
+        bail!("ERR timeout is not an integer or out of range");
+    };
+    let Ok(timeout_ms) = usize::try_from(timeout_ms) else {
+        bail!("ERR timeout is negative");
+    };
+    Ok(ServerCommand::Wait {
+        ack_wanted: num_replicas,
+        timeout_ms,
+    })
+}
+
+fn parse_psync_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!(
+            "PSYNC command must have at least one key"
+        )));
+    };
+    let Some(RESPType::BulkString(value)) = items.get(1) else {
+        bail!(ArityError(fdbg!(
+            "PSYNC command must have at least one value"
+        )));
+    };
+    Ok(ServerCommand::PSync {
+        key: text(key).into_owned(),
+        value: text(value).into_owned(),
+    })
+}
+fn parse_replication_conf_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!(
+            "REPLCONF command must have at least one key"
+        )));
+    };
+    let Some(RESPType::BulkString(value)) = items.get(1) else {
+        bail!(ArityError(fdbg!(
+            "REPLCONF command must have at least one value"
+        )));
+    };
+    Ok(ServerCommand::ReplConf {
+        key: text(key).into_owned(),
+        value: text(value).into_owned(),
+    })
+}
+
+fn parse_info_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("INFO command must have at least one key")));
+    };
+    Ok(ServerCommand::Info {
+        key: text(key).into_owned(),
+    })
+}
+
+fn parse_get_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("GEt command must have at least key")));
+    };
+    Ok(ServerCommand::Get {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_incr_cmd(items: &[RESPType], increment: i64) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("Get command must have at least key")));
+    };
+    Ok(ServerCommand::IncrBy {
+        key: key.to_owned(),
+        increment,
+    })
+}
+
+fn parse_incrby_cmd(items: &[RESPType], decrement: bool) -> R {
+    let args = bulk_strings(items)?;
+    let [key, increment] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "INCRBY command must have key and increment"
+        )));
+    };
+    let increment = match (decrement, parse_integer(increment)?) {
+        // Negating the smallest i64 has no positive counterpart
+        (true, i64::MIN) => bail!("ERR decrement would overflow"),
+        (true, decrement) => -decrement,
+        (false, increment) => increment,
+    };
+    Ok(ServerCommand::IncrBy {
+        key: key.to_owned(),
+        increment,
+    })
+}
+
+fn parse_incrbyfloat_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, increment] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "INCRBYFLOAT command must have key and increment"
+        )));
+    };
+    let Some(increment) = parse_float(increment) else {
+        bail!("ERR value is not a valid float");
+    };
+    Ok(ServerCommand::IncrByFloat {
+        key: key.to_owned(),
+        increment,
+    })
+}
+
+fn parse_set_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, value, options @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!("SET command must have key and value")));
+    };
+    let mut set_options = SetOptions::default();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let option = text(option).to_uppercase();
+        // NX and XX exclude each other, as do the expiry options and KEEPTTL
+        match option.as_str() {
+            "NX" if !set_options.xx => set_options.nx = true,
+            "XX" if !set_options.nx => set_options.xx = true,
+            "GET" => set_options.get = true,
+            "KEEPTTL" if set_options.expiry.is_none() => set_options.keep_ttl = true,
+            "EX" | "PX" | "EXAT" | "PXAT"
+                if set_options.expiry.is_none() && !set_options.keep_ttl =>
+            {
+                let Some(value) = options.next() else {
+                    bail!("ERR syntax error");
+                };
+                set_options.expiry = Some(parse_expiry(&option, value, "set")?);
+            }
+            _ => bail!("ERR syntax error"),
+        }
+    }
+    Ok(ServerCommand::Set {
+        key: key.to_owned(),
+        value: value.to_owned(),
+        options: set_options,
+    })
+}
+
+fn parse_append_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, value] = args.as_slice() else {
+        bail!(ArityError(fdbg!("APPEND command must have key and value")));
+    };
+    Ok(ServerCommand::Append {
+        key: key.to_owned(),
+        value: value.to_owned(),
+    })
+}
+
+fn parse_strlen_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("STRLEN command must have key")));
+    };
+    Ok(ServerCommand::StrLen {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_getrange_cmd(items: &[RESPType]) -> R {
+    let (key, start, end) = parse_list_key_range(items, "GETRANGE")?;
+    Ok(ServerCommand::GetRange { key, start, end })
+}
+
+fn parse_setrange_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, offset, value] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "SETRANGE command must have key, offset and value"
+        )));
+    };
+    let Ok(offset) = usize::try_from(parse_integer(offset)?) else {
+        bail!("ERR offset is out of range");
+    };
+    // An empty value never grows the string, so only writes are held to the size limit
+    if !value.is_empty() && offset.saturating_add(value.len()) > MAX_STRING_LEN {
+        bail!("ERR string exceeds maximum allowed size (proto-max-bulk-len)");
+    }
+    Ok(ServerCommand::SetRange {
+        key: key.to_owned(),
+        offset,
+        value: value.to_owned(),
+    })
+}
+
+fn parse_mget_cmd(items: &[RESPType]) -> R {
+    let keys = bulk_strings(items)?;
+    if keys.is_empty() {
+        bail!(ArityError(fdbg!("MGET command must have at least one key")));
+    }
+    Ok(ServerCommand::MGet { keys })
+}
+
+fn parse_mset_cmd(items: &[RESPType], only_new: bool) -> R {
+    let args = bulk_strings(items)?;
+    if args.is_empty() || args.len() % 2 != 0 {
+        bail!(ArityError(fdbg!("MSET command must have key value pairs")));
+    }
+    let pairs = args
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    Ok(ServerCommand::MSet { pairs, only_new })
+}
+
+fn parse_setnx_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, value] = args.as_slice() else {
+        bail!(ArityError(fdbg!("SETNX command must have key and value")));
+    };
+    Ok(ServerCommand::MSet {
+        pairs: vec![(key.to_owned(), value.to_owned())],
+        only_new: true,
+    })
+}
+
+fn parse_getdel_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(key)) = items.first() else {
+        bail!(ArityError(fdbg!("GETDEL command must have key")));
+    };
+    Ok(ServerCommand::GetDel {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_getex_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some((key, options)) = args.split_first() else {
+        bail!(ArityError(fdbg!("GETEX command must have key")));
+    };
+    let expiry = match options {
+        [] => None,
+        [option] if option.eq_ignore_ascii_case(b"PERSIST") => Some(Expiry::Persist),
+        [option, value] => {
+            let option = text(option).to_uppercase();
+            match option.as_str() {
+                "EX" | "PX" | "EXAT" | "PXAT" => Some(parse_expiry(&option, value, "getex")?),
+                _ => bail!("ERR syntax error"),
+            }
+        }
+        _ => bail!("ERR syntax error"),
+    };
+    Ok(ServerCommand::GetEx {
+        key: key.to_owned(),
+        expiry,
+    })
+}
+
+/// Parses the value of an EX, PX, EXAT or PXAT option, which must be a positive number of
+/// seconds or milliseconds
+fn parse_expiry(option: &str, value: &[u8], cmd: &str) -> anyhow::Result<Expiry> {
+    let value = parse_integer(value)?;
+    let millis = match option {
+        "EX" | "EXAT" => value.checked_mul(1000),
+        _ => Some(value),
+    };
+    let Some(millis) = millis.filter(|millis| *millis > 0) else {
+        bail!("ERR invalid expire time in '{}' command", cmd);
+    };
+    let expiry = match option {
+        "EX" | "PX" => Expiry::In(Duration::from_millis(millis as u64)),
+        _ => Expiry::At(millis as u64),
+    };
+    Ok(expiry)
+}
+
+fn parse_lcs_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key1, key2, options @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!("LCS command must have two keys")));
+    };
+    let (mut len, mut idx, mut min_match_len, mut with_match_len) = (false, false, 0, false);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match text(option).to_uppercase().as_str() {
+            "LEN" => len = true,
+            "IDX" => idx = true,
+            "WITHMATCHLEN" => with_match_len = true,
+            "MINMATCHLEN" => {
+                let Some(value) = options.next() else {
+                    bail!("ERR syntax error");
+                };
+                min_match_len = parse_integer(value)?.max(0) as usize;
+            }
+            _ => bail!("ERR syntax error"),
+        }
+    }
+    if len && idx {
+        bail!("ERR If you want both the length and indexes, please just use IDX.");
+    }
+    Ok(ServerCommand::Lcs {
+        key1: key1.to_owned(),
+        key2: key2.to_owned(),
+        len,
+        idx,
+        min_match_len,
+        with_match_len,
+    })
+}
+
+fn parse_del_cmd(items: &[RESPType], lazy: bool) -> R {
+    let keys = bulk_strings(items)?;
+    if keys.is_empty() {
+        bail!(ArityError(fdbg!("DEL command must have at least one key")));
+    }
+    Ok(ServerCommand::Del { keys, lazy })
+}
+
+fn parse_exists_cmd(items: &[RESPType]) -> R {
+    let keys = bulk_strings(items)?;
+    if keys.is_empty() {
+        bail!(ArityError(fdbg!(
+            "EXISTS command must have at least one key"
+        )));
+    }
+    Ok(ServerCommand::Exists { keys })
+}
+
+fn parse_rename_cmd(items: &[RESPType], only_new: bool) -> R {
+    let args = bulk_strings(items)?;
+    let [key, new_key] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "RENAME command must have key and new key"
+        )));
+    };
+    Ok(ServerCommand::Rename {
+        key: key.to_owned(),
+        new_key: new_key.to_owned(),
+        only_new,
+    })
+}
+
+fn parse_copy_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [source, destination, options @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "COPY command must have source and destination"
+        )));
+    };
+    let (mut db, mut replace) = (None, false);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match text(option).to_uppercase().as_str() {
+            "REPLACE" => replace = true,
+            "DB" => {
+                let Some(index) = options.next() else {
+                    bail!("ERR syntax error");
+                };
+                db = Some(parse_db_index(index)?);
+            }
+            _ => bail!("ERR syntax error"),
+        }
+    }
+    Ok(ServerCommand::Copy {
+        source: source.to_owned(),
+        destination: destination.to_owned(),
+        db,
+        replace,
+    })
+}
+
+fn parse_touch_cmd(items: &[RESPType]) -> R {
+    let keys = bulk_strings(items)?;
+    if keys.is_empty() {
+        bail!(ArityError(fdbg!(
+            "TOUCH command must have at least one key"
+        )));
+    }
+    Ok(ServerCommand::Touch { keys })
+}
+
+/// SORT_RO is SORT without STORE
+fn parse_sort_cmd(items: &[RESPType], read_only: bool) -> R {
+    let args = bulk_strings(items)?;
+    let [key, args @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!("SORT command must have key")));
+    };
+    let mut options = SortOptions::default();
+    let mut destination = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match text(arg).to_uppercase().as_str() {
+            "ASC" => options.desc = false,
+            "DESC" => options.desc = true,
+            "ALPHA" => options.alpha = true,
+            "BY" | "GET" | "STORE" if args.len() == 0 => bail!("ERR syntax error"),
+            "BY" => options.by = args.next().cloned(),
+            "GET" => options.get.extend(args.next().cloned()),
+            "STORE" if !read_only => destination = args.next().cloned(),
+            "LIMIT" => {
+                let (Some(offset), Some(count)) = (args.next(), args.next()) else {
+                    bail!("ERR syntax error");
+                };
+                options.limit = Some((parse_integer(offset)?, parse_integer(count)?));
+            }
+            _ => bail!("ERR syntax error"),
+        }
+    }
+    let key = key.to_owned();
+    Ok(match destination {
+        Some(destination) => ServerCommand::SortStore {
+            destination,
+            key,
+            options,
+        },
+        None => ServerCommand::Sort { key, options },
+    })
+}
+
+fn parse_dump_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key] = args.as_slice() else {
+        bail!(ArityError(fdbg!("DUMP command must have key")));
+    };
+    Ok(ServerCommand::Dump {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_restore_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, ttl, payload, options @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "RESTORE command must have key, ttl and payload"
+        )));
+    };
+    let ttl = parse_integer(ttl)?;
+    let (mut absolute, mut replace, mut idle_time, mut freq) = (false, false, None, None);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match text(option).to_uppercase().as_str() {
+            "REPLACE" => replace = true,
+            "ABSTTL" => absolute = true,
+            // IDLETIME and FREQ rule each other out
+            "IDLETIME" if freq.is_none() => {
+                let Some(value) = options.next() else {
+                    bail!("ERR syntax error");
+                };
+                let value = parse_integer(value)?;
+                if value < 0 {
+                    bail!("ERR Invalid IDLETIME value, must be >= 0");
+                }
+                idle_time = Some(value as u64);
+            }
+            "FREQ" if idle_time.is_none() => {
+                let Some(value) = options.next() else {
+                    bail!("ERR syntax error");
+                };
+                let value = parse_integer(value)?;
+                if !(0..=255).contains(&value) {
+                    bail!("ERR Invalid FREQ value, must be >= 0 and <= 255");
+                }
+                freq = Some(value as u8);
+            }
+            _ => bail!("ERR syntax error"),
+        }
+    }
+    if ttl < 0 {
+        bail!("ERR Invalid TTL value, must be >= 0");
+    }
+    let expiry = match (ttl, absolute) {
+        (0, _) => Expiry::Persist,
+        (ttl, true) => Expiry::At(ttl as u64),
+        (ttl, false) => Expiry::In(Duration::from_millis(ttl as u64)),
+    };
+    Ok(ServerCommand::Restore {
+        key: key.to_owned(),
+        payload: payload.to_owned(),
+        options: RestoreOptions {
+            expiry,
+            replace,
+            idle_time,
+            freq,
+        },
+    })
+}
+
+fn parse_no_args_cmd(items: &[RESPType], cmd: ServerCommand) -> R {
+    if !items.is_empty() {
+        bail!(ArityError(fdbg!("Command takes no arguments")));
+    }
+    Ok(cmd)
+}
+
+fn parse_flush_cmd(items: &[RESPType], all: bool) -> R {
+    let args = bulk_strings(items)?;
+    let lazy = match args.as_slice() {
+        [] => false,
+        [mode] if mode.eq_ignore_ascii_case(b"ASYNC") => true,
+        [mode] if mode.eq_ignore_ascii_case(b"SYNC") => false,
+        _ => bail!("ERR syntax error"),
+    };
+    Ok(ServerCommand::Flush { all, lazy })
+}
+
+fn parse_select_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [index] = args.as_slice() else {
+        bail!(ArityError(fdbg!("SELECT command must have db index")));
+    };
+    Ok(ServerCommand::Select(parse_db_index(index)?))
+}
+
+fn parse_move_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key, index] = args.as_slice() else {
+        bail!(ArityError(fdbg!("MOVE command must have key and db index")));
+    };
+    Ok(ServerCommand::Move {
+        key: key.to_owned(),
+        db: parse_db_index(index)?,
+    })
+}
+
+fn parse_swapdb_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [first, second] = args.as_slice() else {
+        bail!(ArityError(fdbg!("SWAPDB command must have two db indexes")));
+    };
+    let (Some(first), Some(second)) = (parse_i64(first), parse_i64(second)) else {
+        let which = match parse_i64(first) {
+            None => "first",
+            Some(_) => "second",
+        };
+        bail!("ERR invalid {} DB index", which);
+    };
+    if first < 0 || second < 0 {
+        bail!("ERR DB index is out of range");
+    }
+    Ok(ServerCommand::SwapDb {
+        first: first as usize,
+        second: second as usize,
+    })
+}
+
+/// Indexes past the last database are caught where the databases are known
+fn parse_db_index(index: &[u8]) -> anyhow::Result<usize> {
+    let index = parse_integer(index)?;
+    if index < 0 {
+        bail!("ERR DB index is out of range");
+    }
+    Ok(index as usize)
+}
+
+/// Unlike SET, EXPIRE takes zero and negative times, which delete the key
+fn parse_expire_cmd(items: &[RESPType], cmd: &str, millis: bool, absolute: bool) -> R {
+    let args = bulk_strings(items)?;
+    let [key, time, flags @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!("EXPIRE command must have key and time")));
+    };
+    let time = parse_integer(time)?;
+    let time = match millis {
+        true => Some(time),
+        false => time.checked_mul(1000),
+    };
+    // Relative times are only checked here; the actor adds them to the clock when it runs the
+    // command, which may be much later inside MULTI
+    let Some(time) = time.filter(|time| absolute || time.checked_add(now_ms() as i64).is_some())
+    else {
+        bail!("ERR invalid expire time in '{}' command", cmd);
+    };
+    let expiry = match (absolute, time) {
+        (false, time) if time > 0 => Expiry::In(Duration::from_millis(time as u64)),
+        // Times already past delete the key either way
+        (_, time) => Expiry::At(time.max(0) as u64),
+    };
+    let mut options = ExpireOptions::default();
+    for flag in flags {
+        match text(flag).to_uppercase().as_str() {
+            "NX" => options.nx = true,
+            "XX" => options.xx = true,
+            "GT" => options.gt = true,
+            "LT" => options.lt = true,
+            _ => bail!("ERR Unsupported option {}", text(flag)),
+        }
+    }
+    if options.nx && (options.xx || options.gt || options.lt) {
+        bail!("ERR NX and XX, GT or LT options at the same time are not compatible");
+    }
+    if options.gt && options.lt {
+        bail!("ERR GT and LT options at the same time are not compatible");
+    }
+    Ok(ServerCommand::Expire {
+        key: key.to_owned(),
+        expiry,
+        options,
+    })
+}
+
+fn parse_ttl_cmd(items: &[RESPType], millis: bool, absolute: bool) -> R {
+    let args = bulk_strings(items)?;
+    let [key] = args.as_slice() else {
+        bail!(ArityError(fdbg!("TTL command must have key")));
+    };
+    Ok(ServerCommand::Ttl {
+        key: key.to_owned(),
+        millis,
+        absolute,
+    })
+}
+
+fn parse_persist_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let [key] = args.as_slice() else {
+        bail!(ArityError(fdbg!("PERSIST command must have key")));
+    };
+    Ok(ServerCommand::Persist {
+        key: key.to_owned(),
+    })
+}
+
+fn parse_scan_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some((cursor, options)) = args.split_first() else {
+        bail!(ArityError(fdbg!("SCAN command must have cursor")));
+    };
+    let (options, _) = parse_scan_options(options, true, false)?;
+    Ok(ServerCommand::Scan {
+        cursor: parse_cursor(cursor)?,
+        options,
+    })
+}
+
+fn parse_collection_scan_cmd(items: &[RESPType], cmd: &str) -> R {
+    let args = bulk_strings(items)?;
+    let [key, cursor, options @ ..] = args.as_slice() else {
+        bail!(ArityError(fdbg!(
+            "{} command must have key and cursor",
+            cmd
+        )));
+    };
+    let key = key.to_owned();
+    let cursor = parse_cursor(cursor)?;
+    let (options, no_values) = parse_scan_options(options, false, cmd == "HSCAN")?;
+    let cmd = match cmd {
+        "HSCAN" => ServerCommand::HScan {
+            key,
+            cursor,
+            options,
+            no_values,
+        },
+        "SSCAN" => ServerCommand::SScan {
+            key,
+            cursor,
+            options,
+        },
+        _ => ServerCommand::ZScan {
+            key,
+            cursor,
+            options,
+        },
+    };
+    Ok(cmd)
+}
+
+fn parse_cursor(cursor: &[u8]) -> anyhow::Result<u64> {
+    let Ok(cursor) = text(cursor).parse::<u64>() else {
+        bail!("ERR invalid cursor");
+    };
+    Ok(cursor)
+}
+
+/// MATCH and COUNT, plus TYPE for SCAN and NOVALUES for HSCAN when allowed. The flag tells
+/// whether NOVALUES was given.
+fn parse_scan_options(
+    args: &[Bytes],
+    allow_type: bool,
+    allow_no_values: bool,
+) -> anyhow::Result<(ScanOptions, bool)> {
+    let mut options = ScanOptions::default();
+    let mut no_values = false;
+    let mut args = args.iter();
+    while let Some(option) = args.next() {
+        match text(option).to_uppercase().as_str() {
+            "NOVALUES" if allow_no_values => no_values = true,
+            option @ ("MATCH" | "COUNT" | "TYPE") => {
+                let Some(value) = args.next() else {
+                    bail!("ERR syntax error");
+                };
+                match option {
+                    "MATCH" => options.pattern = Some(value.to_owned()),
+                    "COUNT" => {
+                        let count = parse_integer(value)?;
+                        if count < 1 {
+                            bail!("ERR syntax error");
+                        }
+                        options.count = count as usize;
+                    }
+                    _ if allow_type => options.value_type = Some(text(value).into_owned()),
+                    _ => bail!("ERR syntax error"),
+                }
+            }
+            _ => bail!("ERR syntax error"),
+        }
+    }
+    Ok((options, no_values))
+}
+
+fn parse_memory_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some((subcommand, args)) = args.split_first() else {
+        bail!(ArityError(fdbg!("MEMORY command must have a subcommand")));
+    };
+    match text(subcommand).to_uppercase().as_str() {
+        "USAGE" => {
+            let [key, options @ ..] = args else {
+                bail!(ArityError(fdbg!("MEMORY USAGE must have key")));
+            };
+            let samples = match options {
+                [] => None,
+                [option, samples] if option.eq_ignore_ascii_case(b"SAMPLES") => {
+                    let samples = parse_integer(samples)?;
+                    if samples < 0 {
+                        bail!("ERR syntax error");
+                    }
+                    Some(samples as usize)
+                }
+                _ => bail!("ERR syntax error"),
+            };
+            Ok(ServerCommand::MemoryUsage {
+                key: key.to_owned(),
+                samples,
+            })
+        }
+        "STATS" => parse_no_args_cmd(&items[1..], ServerCommand::MemoryStats),
+        _ => bail!(
+            "ERR unknown subcommand '{}'. Try MEMORY HELP.",
+            text(subcommand)
+        ),
+    }
+}
+
+fn parse_object_cmd(items: &[RESPType]) -> R {
+    let args = bulk_strings(items)?;
+    let Some((subcommand, args)) = args.split_first() else {
+        bail!(ArityError(fdbg!("OBJECT command must have a subcommand")));
+    };
+    let cmd = match text(subcommand).to_uppercase().as_str() {
+        "ENCODING" => ServerCommand::ObjectEncoding,
+        "IDLETIME" => ServerCommand::ObjectIdleTime,
+        "FREQ" => ServerCommand::ObjectFreq,
+        "REFCOUNT" => ServerCommand::ObjectRefCount,
+        _ => bail!(
+            "ERR unknown subcommand '{}'. Try OBJECT HELP.",
+            text(subcommand)
+        ),
+    };
+    let [key] = args else {
+        bail!(ArityError(fdbg!("OBJECT subcommands must have key")));
+    };
+    Ok(cmd(key.to_owned()))
+}
+
+fn parse_echo_cmd(items: &[RESPType]) -> R {
+    let Some(RESPType::BulkString(value)) = items.first() else {
+        bail!(ArityError(fdbg!(
+            "ECHO command must have at least one argument"
+        )));
+    };
+    Ok(ServerCommand::Echo(value.to_owned()))
+}
+
+fn bulk_strings(items: &[RESPType]) -> anyhow::Result<Vec<Bytes>> {
+    items
+        .iter()
+        .map(|item| match item {
+            RESPType::BulkString(value) => Ok(value.to_owned()),
+            _ => bail!(ArityError(fdbg!("Command arguments must be bulk strings"))),
+        })
+        .collect()
+}
+
+fn parse_integer(value: &[u8]) -> anyhow::Result<i64> {
+    let Some(value) = parse_i64(value) else {
+        bail!("ERR value is not an integer or out of range");
+    };
+    Ok(value)
+}
+
+/// Arguments are raw bytes, but keywords and numbers are plain text. Anything that is not valid
+/// UTF-8 simply fails to match or parse.
+fn text(value: &[u8]) -> Cow<'_, str> {
+    String::from_utf8_lossy(value)
 }
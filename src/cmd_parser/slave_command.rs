@@ -1,17 +1,18 @@
-use std::collections::HashMap;
-
 use anyhow::bail;
+use bytes::Bytes;
 
 use super::server_command::ServerCommand;
+use crate::database::db_event::SetOptions;
 
 /// These are commands that are sent by the master to the slave
 #[derive(Debug)]
 pub enum SlaveCommand {
     Ping,
+    Select(usize),
     Set {
-        key: String,
-        value: String,
-        flags: HashMap<String, String>,
+        key: Bytes,
+        value: Bytes,
+        options: SetOptions,
     },
     ReplConf {
         #[allow(dead_code)]
@@ -27,17 +28,22 @@ impl SlaveCommand {
     pub fn from(client_cmd: &ServerCommand) -> anyhow::Result<Self> {
         match client_cmd {
             ServerCommand::Ping => Ok(SlaveCommand::Ping),
-            ServerCommand::Set { key, value, flags } => Ok(SlaveCommand::Set {
+            ServerCommand::Select(index) => Ok(SlaveCommand::Select(*index)),
+            ServerCommand::Set {
+                key,
+                value,
+                options,
+            } => Ok(SlaveCommand::Set {
                 key: key.clone(),
                 value: value.to_owned(),
-                flags: flags.clone(),
+                options: *options,
             }),
             ServerCommand::ReplConf { key, value } => Ok(SlaveCommand::ReplConf {
                 key: key.clone(),
                 value: value.clone(),
             }),
             _ => bail!(
-                "Only PING,SET and REPLCONF command is supported for now = {:?}",
+                "Only PING, SELECT, SET and REPLCONF command is supported for now = {:?}",
                 client_cmd
             ),
         }
@@ -1,9 +1,7 @@
 use anyhow::bail;
 use async_recursion::async_recursion;
-use std::{
-    collections::BTreeMap,
-    time::{Duration, Instant},
-};
+use bytes::Bytes;
+use std::time::{Duration, Instant};
 use tokio::{
     io::AsyncWriteExt,
     net::tcp::WriteHalf,
@@ -11,11 +9,14 @@ use tokio::{
 };
 use tracing::debug;
 
-use crate::database::db_event::DbValueType;
+use crate::database::db_event::{BlockingListOp, DbError, DbValueType, ZAddOptions};
 use crate::{
     app_config::AppConfig,
     cmd_parser::server_command::ServerCommand,
-    database::{db_event::StreamDbValueType, Database},
+    database::{
+        db_event::{MemoryReport, StreamDbValueType, StreamId},
+        format_float, geo, now_ms, Database,
+    },
     replication::ReplicationEvent,
     resp_type::RESPType,
     LINE_ENDING,
@@ -28,35 +29,45 @@ impl ServerCommand {
         &self,
         tx_stack: &mut Vec<Vec<ServerCommand>>,
     ) -> anyhow::Result<Option<RESPType>> {
+        if self.denies_oom() && AppConfig::get_maxmemory() > 0 {
+            if let Err(e) = Database::free_memory().await {
+                return Ok(Some(RESPType::Error(e.to_string())));
+            }
+        }
         let resp = match self {
             Ping => RESPType::SimpleString("PONG".to_string()),
             Echo(value) => RESPType::BulkString(value.clone()),
-            Set { key, value, flags } => {
-                Database::set(key, value, flags).await?;
-                let resp_type = RESPType::SimpleString("OK".to_string());
-                ReplicationEvent::Set {
-                    key: key.to_string(),
-                    value: value.clone(),
-                    flags: flags.clone(),
+            Set {
+                key,
+                value,
+                options,
+            } => match Database::set(key, value, *options).await {
+                Ok((written, previous)) => {
+                    if written {
+                        ReplicationEvent::Set {
+                            db: Database::selected_db(),
+                            key: key.clone(),
+                            value: value.clone(),
+                            options: *options,
+                        }
+                        .emit()
+                        .await?;
+                    }
+                    match (options.get, written) {
+                        (true, _) => bulk_string_or_null(previous),
+                        (false, true) => RESPType::SimpleString("OK".to_string()),
+                        (false, false) => RESPType::NullBulkString,
+                    }
                 }
-                .emit()
-                .await?;
-                resp_type
-            }
-            Get { key } => match Database::get(&key).await? {
-                None => RESPType::NullBulkString,
-                Some(value) => match value {
-                    DbValueType::Integer(value) => RESPType::BulkString(value.to_string()),
-                    DbValueType::String(value) => RESPType::BulkString(value),
-                    DbValueType::Stream(_) => unimplemented!("streams not supported via GET"),
-                },
+                Err(e) => RESPType::Error(e.to_string()),
             },
-            Incr { key } => match Database::incr(&key).await {
-                Ok(value) => match value {
-                    DbValueType::Integer(i) => RESPType::Integer(i),
-                    DbValueType::String(s) => RESPType::BulkString(s),
-                    DbValueType::Stream(_) => unimplemented!("stream incr not supported"),
-                },
+            Get { key } => match Database::get(key).await {
+                Ok(None) => RESPType::NullBulkString,
+                Ok(Some(DbValueType::Integer(value))) => {
+                    RESPType::BulkString(value.to_string().into())
+                }
+                Ok(Some(DbValueType::String(value))) => RESPType::BulkString(value),
+                Ok(Some(_)) => RESPType::Error(DbError::WrongType.to_string()),
                 Err(e) => RESPType::Error(e.to_string()),
             },
             Info { .. } => {
@@ -73,7 +84,7 @@ impl ServerCommand {
                         AppConfig::get_master_repl_offset()
                     ));
                 }
-                RESPType::BulkString(info_vec.join(LINE_ENDING))
+                RESPType::BulkString(info_vec.join(LINE_ENDING).into())
             }
             ReplConf { .. } => RESPType::SimpleString("OK".to_string()),
             PSync { .. } => {
@@ -90,12 +101,24 @@ impl ServerCommand {
                 }
                 match key.as_str() {
                     "dir" => RESPType::Array(vec![
-                        RESPType::BulkString("dir".to_string()),
-                        RESPType::BulkString(AppConfig::get_rds_dir().to_string()),
+                        RESPType::BulkString("dir".into()),
+                        RESPType::BulkString(AppConfig::get_rds_dir().to_string().into()),
                     ]),
                     "dbfilename" => RESPType::Array(vec![
-                        RESPType::BulkString("dbfilename".to_string()),
-                        RESPType::BulkString(AppConfig::get_rds_file_name().to_string()),
+                        RESPType::BulkString("dbfilename".into()),
+                        RESPType::BulkString(AppConfig::get_rds_file_name().to_string().into()),
+                    ]),
+                    "maxmemory" => RESPType::Array(vec![
+                        RESPType::BulkString("maxmemory".into()),
+                        RESPType::BulkString(AppConfig::get_maxmemory().to_string().into()),
+                    ]),
+                    "maxmemory-policy" => RESPType::Array(vec![
+                        RESPType::BulkString("maxmemory-policy".into()),
+                        RESPType::BulkString(AppConfig::get_maxmemory_policy().name().into()),
+                    ]),
+                    "maxmemory-samples" => RESPType::Array(vec![
+                        RESPType::BulkString("maxmemory-samples".into()),
+                        RESPType::BulkString(AppConfig::get_maxmemory_samples().to_string().into()),
                     ]),
                     _ => bail!("CONFIG key not supported yet"),
                 }
@@ -111,13 +134,136 @@ impl ServerCommand {
             XAdd {
                 stream_key,
                 stream_id,
-                key,
-                value,
-            } => match Database::xadd(stream_key, stream_id, key, value).await {
-                Ok(value) => RESPType::BulkString(value),
+                fields,
+                options,
+            } => match Database::xadd(stream_key, stream_id, fields, options).await {
+                Ok(value) => bulk_string_or_null(value),
                 Err(err) => RESPType::Error(err),
             },
+            XTrim { .. } | XDel { .. } | XLen { .. } => self.process_stream_cmd().await?,
             XRange { .. } => self.process_xrange_cmd().await?,
+            HSet { .. }
+            | HSetNx { .. }
+            | HGet { .. }
+            | HMGet { .. }
+            | HGetAll { .. }
+            | HDel { .. }
+            | HExists { .. }
+            | HLen { .. }
+            | HKeys { .. }
+            | HVals { .. }
+            | HIncrBy { .. }
+            | HIncrByFloat { .. }
+            | HStrLen { .. }
+            | HRandField { .. }
+            | HScan { .. } => self.process_hash_cmd().await?,
+            ListPush { .. }
+            | ListPop { .. }
+            | LRange { .. }
+            | LIndex { .. }
+            | LLen { .. }
+            | LSet { .. }
+            | LInsert { .. }
+            | LRem { .. }
+            | LTrim { .. }
+            | LPos { .. }
+            | LMove { .. }
+            | BPop { .. }
+            | BLMove { .. }
+            | BLMPop { .. } => self.process_list_cmd().await?,
+            SAdd { .. }
+            | SRem { .. }
+            | SMembers { .. }
+            | SIsMember { .. }
+            | SMIsMember { .. }
+            | SCard { .. }
+            | SPop { .. }
+            | SRandMember { .. }
+            | SMove { .. }
+            | SetOperation { .. }
+            | SetOperationStore { .. }
+            | SInterCard { .. }
+            | SScan { .. } => self.process_set_cmd().await?,
+            ZAdd { .. }
+            | ZIncrBy { .. }
+            | ZRem { .. }
+            | ZScore { .. }
+            | ZMScore { .. }
+            | ZRank { .. }
+            | ZCard { .. }
+            | ZCount { .. }
+            | ZRange { .. }
+            | ZRangeStore { .. }
+            | ZPop { .. }
+            | ZSetOperationStore { .. }
+            | ZScan { .. } => self.process_zset_cmd().await?,
+            GeoAdd { .. }
+            | GeoPos { .. }
+            | GeoDist { .. }
+            | GeoHash { .. }
+            | GeoSearch { .. }
+            | GeoSearchStore { .. } => self.process_geo_cmd().await?,
+            SetBit { .. }
+            | GetBit { .. }
+            | BitCount { .. }
+            | BitPos { .. }
+            | BitOp { .. }
+            | BitField { .. } => self.process_bitmap_cmd().await?,
+            Append { .. }
+            | StrLen { .. }
+            | GetRange { .. }
+            | SetRange { .. }
+            | MGet { .. }
+            | MSet { .. }
+            | GetDel { .. }
+            | GetEx { .. }
+            | Lcs { .. }
+            | IncrBy { .. }
+            | IncrByFloat { .. } => self.process_string_cmd().await?,
+            Del { .. }
+            | Exists { .. }
+            | Rename { .. }
+            | Copy { .. }
+            | Touch { .. }
+            | Sort { .. }
+            | SortStore { .. }
+            | Dump { .. }
+            | Restore { .. }
+            | RandomKey
+            | DbSize
+            | Flush { .. }
+            | Expire { .. }
+            | Ttl { .. }
+            | Persist { .. }
+            | Scan { .. }
+            | Move { .. }
+            | SwapDb { .. } => self.process_keyspace_cmd().await?,
+            MemoryUsage { .. }
+            | MemoryStats
+            | ObjectEncoding(_)
+            | ObjectIdleTime(_)
+            | ObjectFreq(_)
+            | ObjectRefCount(_) => self.process_introspection_cmd().await?,
+            Select(index) => {
+                if *index >= AppConfig::get_databases() {
+                    RESPType::Error("ERR DB index is out of range".to_string())
+                } else {
+                    Database::select(*index);
+                    RESPType::SimpleString("OK".to_string())
+                }
+            }
+            PfAdd { key, elements } => match Database::pfadd(key, elements).await {
+                Ok(changed) => RESPType::Integer(changed),
+                Err(e) => RESPType::Error(e.to_string()),
+            },
+            PfCount { keys } => match Database::pfcount(keys).await {
+                Ok(count) => RESPType::Integer(count),
+                Err(e) => RESPType::Error(e.to_string()),
+            },
+            PfMerge { destination, keys } => match Database::pfmerge(destination, keys).await {
+                Ok(()) => RESPType::SimpleString("OK".to_string()),
+                Err(e) => RESPType::Error(e.to_string()),
+            },
             XRead { .. } => self.process_xread_cmd().await?,
             Multi => self.process_multi_cmd(tx_stack).await?,
             Exec => {
@@ -128,6 +274,7 @@ impl ServerCommand {
                 let tx = tx_stack.pop().unwrap();
                 let mut collect: Vec<RESPType> = vec![];
                 for s_cmd in tx {
+                    let s_cmd = s_cmd.without_blocking();
                     if let Some(resp) = s_cmd.process_client_cmd(tx_stack).await? {
                         collect.push(resp);
                     }
@@ -158,26 +305,717 @@ impl ServerCommand {
         Ok(resp)
     }
 
+    async fn process_hash_cmd(&self) -> anyhow::Result<RESPType> {
+        let resp = match self {
+            HSet { key, fields } => Database::hset(key, fields).await.map(RESPType::Integer),
+            HSetNx { key, field, value } => Database::hsetnx(key, field, value)
+                .await
+                .map(|set| RESPType::Integer(set as i64)),
+            HGet { key, field } => Database::hget(key, field).await.map(bulk_string_or_null),
+            HMGet { key, fields } => Database::hmget(key, fields).await.map(|values| {
+                RESPType::Array(values.into_iter().map(bulk_string_or_null).collect())
+            }),
+            HGetAll { key } => Database::hgetall(key).await.map(|pairs| {
+                let values = pairs.into_iter().flat_map(|(field, value)| [field, value]);
+                bulk_string_array(values)
+            }),
+            HDel { key, fields } => Database::hdel(key, fields).await.map(RESPType::Integer),
+            HExists { key, field } => Database::hexists(key, field)
+                .await
+                .map(|exists| RESPType::Integer(exists as i64)),
+            HLen { key } => Database::hlen(key).await.map(RESPType::Integer),
+            HKeys { key } => Database::hkeys(key).await.map(bulk_string_array),
+            HVals { key } => Database::hvals(key).await.map(bulk_string_array),
+            HIncrBy {
+                key,
+                field,
+                increment,
+            } => Database::hincrby(key, field, *increment)
+                .await
+                .map(RESPType::Integer),
+            HIncrByFloat {
+                key,
+                field,
+                increment,
+            } => Database::hincrbyfloat(key, field, *increment)
+                .await
+                .map(RESPType::BulkString),
+            HStrLen { key, field } => Database::hstrlen(key, field).await.map(RESPType::Integer),
+            HRandField {
+                key,
+                count,
+                with_values,
+            } => Database::hrandfield(key, *count)
+                .await
+                .map(|pairs| match count {
+                    None => bulk_string_or_null(pairs.into_iter().next().map(|(field, _)| field)),
+                    Some(_) if *with_values => bulk_string_array(
+                        pairs.into_iter().flat_map(|(field, value)| [field, value]),
+                    ),
+                    Some(_) => bulk_string_array(pairs.into_iter().map(|(field, _)| field)),
+                }),
+            HScan {
+                key,
+                cursor,
+                options,
+                no_values,
+            } => Database::hscan(key, *cursor, options)
+                .await
+                .map(|(cursor, pairs)| {
+                    let values = match no_values {
+                        true => bulk_string_array(pairs.into_iter().map(|(field, _)| field)),
+                        false => bulk_string_array(
+                            pairs.into_iter().flat_map(|(field, value)| [field, value]),
+                        ),
+                    };
+                    scan_reply(cursor, values)
+                }),
+            _ => bail!("Not a hash cmd"),
+        };
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
+    }
+
+    async fn process_list_cmd(&self) -> anyhow::Result<RESPType> {
+        let ok = |_| RESPType::SimpleString("OK".to_string());
+        let resp = match self {
+            ListPush {
+                key,
+                elements,
+                end,
+                only_if_exists,
+            } => Database::push(key, elements, *end, *only_if_exists)
+                .await
+                .map(RESPType::Integer),
+            ListPop { key, end, count } => {
+                Database::pop(key, *end, count.unwrap_or(1))
+                    .await
+                    .map(|popped| match (popped, count) {
+                        (None, None) => RESPType::NullBulkString,
+                        (None, Some(_)) => RESPType::NullArray,
+                        (Some(popped), None) => bulk_string_or_null(popped.into_iter().next()),
+                        (Some(popped), Some(_)) => bulk_string_array(popped),
+                    })
+            }
+            LRange { key, start, stop } => Database::lrange(key, *start, *stop)
+                .await
+                .map(bulk_string_array),
+            LIndex { key, index } => Database::lindex(key, *index).await.map(bulk_string_or_null),
+            LLen { key } => Database::llen(key).await.map(RESPType::Integer),
+            LSet {
+                key,
+                index,
+                element,
+            } => Database::lset(key, *index, element).await.map(ok),
+            LInsert {
+                key,
+                before,
+                pivot,
+                element,
+            } => Database::linsert(key, *before, pivot, element)
+                .await
+                .map(RESPType::Integer),
+            LRem {
+                key,
+                count,
+                element,
+            } => Database::lrem(key, *count, element)
+                .await
+                .map(RESPType::Integer),
+            LTrim { key, start, stop } => Database::ltrim(key, *start, *stop).await.map(ok),
+            LPos {
+                key,
+                element,
+                rank,
+                count,
+                max_len,
+            } => Database::lpos(key, element, *rank, count.unwrap_or(1), *max_len)
+                .await
+                .map(|positions| match count {
+                    None => match positions.first() {
+                        None => RESPType::NullBulkString,
+                        Some(position) => RESPType::Integer(*position),
+                    },
+                    Some(_) => {
+                        RESPType::Array(positions.into_iter().map(RESPType::Integer).collect())
+                    }
+                }),
+            LMove {
+                source,
+                destination,
+                from,
+                to,
+            } => Database::lmove(source, destination, *from, *to)
+                .await
+                .map(bulk_string_or_null),
+            BPop { keys, end, timeout } => {
+                let op = BlockingListOp::Pop {
+                    end: *end,
+                    count: 1,
+                };
+                Database::blocking_pop(keys, op, *timeout)
+                    .await
+                    .map(|popped| match popped {
+                        None => RESPType::NullArray,
+                        Some((key, elements)) => {
+                            bulk_string_array(std::iter::once(key).chain(elements))
+                        }
+                    })
+            }
+            BLMove {
+                source,
+                destination,
+                from,
+                to,
+                timeout,
+            } => {
+                let op = BlockingListOp::Move {
+                    destination: destination.clone(),
+                    from: *from,
+                    to: *to,
+                };
+                Database::blocking_pop(std::slice::from_ref(source), op, *timeout)
+                    .await
+                    .map(|popped| {
+                        bulk_string_or_null(
+                            popped.and_then(|(_, elements)| elements.into_iter().next()),
+                        )
+                    })
+            }
+            BLMPop {
+                keys,
+                end,
+                count,
+                timeout,
+            } => {
+                let op = BlockingListOp::Pop {
+                    end: *end,
+                    count: *count,
+                };
+                Database::blocking_pop(keys, op, *timeout)
+                    .await
+                    .map(|popped| match popped {
+                        None => RESPType::NullArray,
+                        Some((key, elements)) => RESPType::Array(vec![
+                            RESPType::BulkString(key),
+                            bulk_string_array(elements),
+                        ]),
+                    })
+            }
+            _ => bail!("Not a list cmd"),
+        };
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
+    }
+
+    async fn process_set_cmd(&self) -> anyhow::Result<RESPType> {
+        let resp = match self {
+            SAdd { key, members } => Database::sadd(key, members).await.map(RESPType::Integer),
+            SRem { key, members } => Database::srem(key, members).await.map(RESPType::Integer),
+            SMembers { key } => Database::smembers(key).await.map(bulk_string_array),
+            SIsMember { key, member } => Database::smismember(key, std::slice::from_ref(member))
+                .await
+                .map(|found| RESPType::Integer(found.first().copied().unwrap_or(false) as i64)),
+            SMIsMember { key, members } => Database::smismember(key, members).await.map(|found| {
+                RESPType::Array(
+                    found
+                        .into_iter()
+                        .map(|found| RESPType::Integer(found as i64))
+                        .collect(),
+                )
+            }),
+            SCard { key } => Database::scard(key).await.map(RESPType::Integer),
+            SPop { key, count } => {
+                Database::spop(key, count.unwrap_or(1))
+                    .await
+                    .map(|popped| match count {
+                        None => bulk_string_or_null(popped.into_iter().next()),
+                        Some(_) => bulk_string_array(popped),
+                    })
+            }
+            SRandMember { key, count } => {
+                Database::srandmember(key, *count)
+                    .await
+                    .map(|members| match count {
+                        None => bulk_string_or_null(members.into_iter().next()),
+                        Some(_) => bulk_string_array(members),
+                    })
+            }
+            SMove {
+                source,
+                destination,
+                member,
+            } => Database::smove(source, destination, member)
+                .await
+                .map(|moved| RESPType::Integer(moved as i64)),
+            SetOperation { op, keys } => Database::set_op(*op, keys).await.map(bulk_string_array),
+            SetOperationStore {
+                op,
+                destination,
+                keys,
+            } => Database::set_op_store(*op, destination, keys)
+                .await
+                .map(RESPType::Integer),
+            SInterCard { keys, limit } => Database::sintercard(keys, *limit)
+                .await
+                .map(RESPType::Integer),
+            SScan {
+                key,
+                cursor,
+                options,
+            } => Database::sscan(key, *cursor, options)
+                .await
+                .map(|(cursor, members)| scan_reply(cursor, bulk_string_array(members))),
+            _ => bail!("Not a set cmd"),
+        };
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
+    }
+
+    async fn process_zset_cmd(&self) -> anyhow::Result<RESPType> {
+        let resp = match self {
+            ZAdd {
+                key,
+                members,
+                options,
+                incr: true,
+            } => {
+                let (increment, member) = &members[0];
+                Database::zincrby(key, member, *increment, *options)
+                    .await
+                    .map(|score| bulk_string_or_null(score.map(format_float)))
+            }
+            ZAdd {
+                key,
+                members,
+                options,
+                ..
+            } => Database::zadd(key, members, *options)
+                .await
+                .map(RESPType::Integer),
+            ZIncrBy {
+                key,
+                increment,
+                member,
+            } => Database::zincrby(key, member, *increment, ZAddOptions::default())
+                .await
+                .map(|score| bulk_string_or_null(score.map(format_float))),
+            ZRem { key, members } => Database::zrem(key, members).await.map(RESPType::Integer),
+            ZScore { key, member } => Database::zmscore(key, std::slice::from_ref(member))
+                .await
+                .map(|scores| {
+                    bulk_string_or_null(scores.into_iter().flatten().next().map(format_float))
+                }),
+            ZMScore { key, members } => Database::zmscore(key, members).await.map(|scores| {
+                RESPType::Array(
+                    scores
+                        .into_iter()
+                        .map(|score| bulk_string_or_null(score.map(format_float)))
+                        .collect(),
+                )
+            }),
+            ZRank {
+                key,
+                member,
+                rev,
+                with_score,
+            } => Database::zrank(key, member, *rev)
+                .await
+                .map(|rank| match rank {
+                    None => RESPType::NullBulkString,
+                    Some((rank, score)) if *with_score => RESPType::Array(vec![
+                        RESPType::Integer(rank),
+                        RESPType::BulkString(format_float(score).into()),
+                    ]),
+                    Some((rank, _)) => RESPType::Integer(rank),
+                }),
+            ZCard { key } => Database::zcard(key).await.map(RESPType::Integer),
+            ZCount { key, min, max } => Database::zcount(key, *min, *max)
+                .await
+                .map(RESPType::Integer),
+            ZRange {
+                key,
+                spec,
+                with_scores,
+            } => Database::zrange(key, spec)
+                .await
+                .map(|members| match with_scores {
+                    true => scored_members_array(members),
+                    false => bulk_string_array(members.into_iter().map(|(member, _)| member)),
+                }),
+            ZRangeStore {
+                destination,
+                source,
+                spec,
+            } => Database::zrangestore(destination, source, spec)
+                .await
+                .map(RESPType::Integer),
+            ZPop { key, count, max } => Database::zpop(key, *count, *max)
+                .await
+                .map(scored_members_array),
+            ZSetOperationStore {
+                op,
+                destination,
+                keys,
+                weights,
+                aggregate,
+            } => Database::zset_op_store(*op, destination, keys, weights, *aggregate)
+                .await
+                .map(RESPType::Integer),
+            ZScan {
+                key,
+                cursor,
+                options,
+            } => Database::zscan(key, *cursor, options)
+                .await
+                .map(|(cursor, members)| scan_reply(cursor, scored_members_array(members))),
+            _ => bail!("Not a sorted set cmd"),
+        };
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
+    }
+
+    async fn process_geo_cmd(&self) -> anyhow::Result<RESPType> {
+        let coordinates = |longitude: f64, latitude: f64| {
+            bulk_string_array([format_float(longitude), format_float(latitude)])
+        };
+        let resp = match self {
+            GeoAdd {
+                key,
+                members,
+                options,
+            } => {
+                let members = members
+                    .iter()
+                    .map(|(longitude, latitude, member)| {
+                        (geo::encode(*longitude, *latitude) as f64, member.to_owned())
+                    })
+                    .collect::<Vec<_>>();
+                Database::zadd(key, &members, *options)
+                    .await
+                    .map(RESPType::Integer)
+            }
+            GeoPos { key, members } => Database::zmscore(key, members).await.map(|scores| {
+                let positions = scores.into_iter().map(|score| match score {
+                    None => RESPType::NullBulkString,
+                    Some(score) => {
+                        let (longitude, latitude) = geo::decode(score as u64);
+                        coordinates(longitude, latitude)
+                    }
+                });
+                RESPType::Array(positions.collect())
+            }),
+            GeoDist {
+                key,
+                from,
+                to,
+                unit,
+            } => {
+                let members = [from.to_owned(), to.to_owned()];
+                Database::zmscore(key, &members)
+                    .await
+                    .map(|scores| match scores[..] {
+                        [Some(from), Some(to)] => {
+                            let (lon1, lat1) = geo::decode(from as u64);
+                            let (lon2, lat2) = geo::decode(to as u64);
+                            let distance = geo::distance(lon1, lat1, lon2, lat2) / unit;
+                            RESPType::BulkString(format!("{:.4}", distance).into())
+                        }
+                        _ => RESPType::NullBulkString,
+                    })
+            }
+            GeoHash { key, members } => Database::zmscore(key, members).await.map(|scores| {
+                let hashes = scores.into_iter().map(|score| {
+                    bulk_string_or_null(score.map(|score| geo::hash_string(score as u64)))
+                });
+                RESPType::Array(hashes.collect())
+            }),
+            GeoSearch {
+                key,
+                query,
+                with_coord,
+                with_dist,
+                with_hash,
+            } => Database::geosearch(key, query).await.map(|matches| {
+                if !(*with_coord || *with_dist || *with_hash) {
+                    return bulk_string_array(matches.into_iter().map(|found| found.member));
+                }
+                let matches = matches.into_iter().map(|found| {
+                    let mut item = vec![RESPType::BulkString(found.member)];
+                    if *with_dist {
+                        let distance = found.distance / query.unit;
+                        item.push(RESPType::BulkString(format!("{:.4}", distance).into()));
+                    }
+                    if *with_hash {
+                        item.push(RESPType::Integer(found.hash as i64));
+                    }
+                    if *with_coord {
+                        item.push(coordinates(found.longitude, found.latitude));
+                    }
+                    RESPType::Array(item)
+                });
+                RESPType::Array(matches.collect())
+            }),
+            GeoSearchStore {
+                destination,
+                source,
+                query,
+                store_dist,
+            } => Database::geosearchstore(destination, source, query, *store_dist)
+                .await
+                .map(RESPType::Integer),
+            _ => bail!("Not a geo cmd"),
+        };
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
+    }
+
+    async fn process_bitmap_cmd(&self) -> anyhow::Result<RESPType> {
+        let resp = match self {
+            SetBit { key, offset, value } => Database::setbit(key, *offset, *value)
+                .await
+                .map(RESPType::Integer),
+            GetBit { key, offset } => Database::getbit(key, *offset).await.map(RESPType::Integer),
+            BitCount { key, range } => Database::bitcount(key, *range).await.map(RESPType::Integer),
+            BitPos { key, bit, range } => Database::bitpos(key, *bit, *range)
+                .await
+                .map(RESPType::Integer),
+            BitOp {
+                op,
+                destination,
+                keys,
+            } => Database::bitop(*op, destination, keys)
+                .await
+                .map(RESPType::Integer),
+            BitField { key, ops } => Database::bitfield(key, ops).await.map(|results| {
+                let results = results.into_iter().map(|result| match result {
+                    Some(value) => RESPType::Integer(value),
+                    None => RESPType::NullBulkString,
+                });
+                RESPType::Array(results.collect())
+            }),
+            _ => bail!("Not a bitmap cmd"),
+        };
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
+    }
+
+    async fn process_string_cmd(&self) -> anyhow::Result<RESPType> {
+        let resp = match self {
+            Append { key, value } => Database::append(key, value).await.map(RESPType::Integer),
+            StrLen { key } => Database::strlen(key).await.map(RESPType::Integer),
+            GetRange { key, start, end } => Database::getrange(key, *start, *end)
+                .await
+                .map(RESPType::BulkString),
+            SetRange { key, offset, value } => Database::setrange(key, *offset, value)
+                .await
+                .map(RESPType::Integer),
+            MGet { keys } => Database::mget(keys).await.map(|values| {
+                RESPType::Array(values.into_iter().map(bulk_string_or_null).collect())
+            }),
+            MSet {
+                pairs,
+                only_new: false,
+            } => Database::mset(pairs, false)
+                .await
+                .map(|_| RESPType::SimpleString("OK".to_string())),
+            MSet {
+                pairs,
+                only_new: true,
+            } => Database::mset(pairs, true)
+                .await
+                .map(|set| RESPType::Integer(set as i64)),
+            GetDel { key } => Database::getdel(key).await.map(bulk_string_or_null),
+            GetEx { key, expiry } => Database::getex(key, *expiry).await.map(bulk_string_or_null),
+            Lcs {
+                key1,
+                key2,
+                len,
+                idx,
+                min_match_len,
+                with_match_len,
+            } => Database::lcs(key1, key2).await.map(|lcs| match (len, idx) {
+                (_, true) => {
+                    let matches = lcs
+                        .matches
+                        .iter()
+                        .filter(|run| run.match_len() >= *min_match_len)
+                        .map(|run| {
+                            let range = |(start, end): (usize, usize)| {
+                                RESPType::Array(vec![
+                                    RESPType::Integer(start as i64),
+                                    RESPType::Integer(end as i64),
+                                ])
+                            };
+                            let mut item = vec![range(run.first), range(run.second)];
+                            if *with_match_len {
+                                item.push(RESPType::Integer(run.match_len() as i64));
+                            }
+                            RESPType::Array(item)
+                        });
+                    RESPType::Array(vec![
+                        RESPType::BulkString("matches".into()),
+                        RESPType::Array(matches.collect()),
+                        RESPType::BulkString("len".into()),
+                        RESPType::Integer(lcs.sequence.len() as i64),
+                    ])
+                }
+                (true, false) => RESPType::Integer(lcs.sequence.len() as i64),
+                (false, false) => RESPType::BulkString(lcs.sequence),
+            }),
+            IncrBy { key, increment } => Database::incrby(key, *increment)
+                .await
+                .map(RESPType::Integer),
+            IncrByFloat { key, increment } => Database::incrbyfloat(key, *increment)
+                .await
+                .map(RESPType::BulkString),
+            _ => bail!("Not a string cmd"),
+        };
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
+    }
+
+    async fn process_keyspace_cmd(&self) -> anyhow::Result<RESPType> {
+        let resp = match self {
+            Del { keys, lazy } => Database::del(keys, *lazy).await.map(RESPType::Integer),
+            Exists { keys } => Database::exists(keys).await.map(RESPType::Integer),
+            Rename {
+                key,
+                new_key,
+                only_new: false,
+            } => Database::rename(key, new_key, false)
+                .await
+                .map(|_| RESPType::SimpleString("OK".to_string())),
+            Rename {
+                key,
+                new_key,
+                only_new: true,
+            } => Database::rename(key, new_key, true)
+                .await
+                .map(|renamed| RESPType::Integer(renamed as i64)),
+            Copy {
+                source,
+                destination,
+                db,
+                replace,
+            } => Database::copy(source, destination, *db, *replace)
+                .await
+                .map(|copied| RESPType::Integer(copied as i64)),
+            Touch { keys } => Database::touch(keys).await.map(RESPType::Integer),
+            Sort { key, options } => Database::sort(key, options).await.map(|values| {
+                RESPType::Array(values.into_iter().map(bulk_string_or_null).collect())
+            }),
+            SortStore {
+                destination,
+                key,
+                options,
+            } => Database::sort_store(destination, key, options)
+                .await
+                .map(RESPType::Integer),
+            Dump { key } => Database::dump(key).await.map(bulk_string_or_null),
+            Restore {
+                key,
+                payload,
+                options,
+            } => Database::restore(key, payload, *options)
+                .await
+                .map(|_| RESPType::SimpleString("OK".to_string())),
+            RandomKey => Database::random_key().await.map(bulk_string_or_null),
+            DbSize => Database::dbsize().await.map(RESPType::Integer),
+            Flush { all, lazy } => Database::flush(*all, *lazy)
+                .await
+                .map(|_| RESPType::SimpleString("OK".to_string())),
+            Expire {
+                key,
+                expiry,
+                options,
+            } => Database::expire(key, *expiry, *options)
+                .await
+                .map(|changed| RESPType::Integer(changed as i64)),
+            Ttl {
+                key,
+                millis,
+                absolute,
+            } => Database::expire_time(key).await.map(|exp_time| {
+                let ttl = match exp_time {
+                    None => -2,
+                    Some(None) => -1,
+                    Some(Some(exp_time)) => {
+                        let ms = match absolute {
+                            true => exp_time,
+                            false => exp_time.saturating_sub(now_ms()),
+                        };
+                        match millis {
+                            true => ms as i64,
+                            // TTL rounds to the nearest second
+                            false if !absolute => ((ms + 500) / 1000) as i64,
+                            false => (ms / 1000) as i64,
+                        }
+                    }
+                };
+                RESPType::Integer(ttl)
+            }),
+            Persist { key } => Database::persist(key)
+                .await
+                .map(|persisted| RESPType::Integer(persisted as i64)),
+            Move { key, db } => Database::move_key(key, *db)
+                .await
+                .map(|moved| RESPType::Integer(moved as i64)),
+            SwapDb { first, second } => Database::swapdb(*first, *second)
+                .await
+                .map(|_| RESPType::SimpleString("OK".to_string())),
+            Scan { cursor, options } => Database::scan(*cursor, options)
+                .await
+                .map(|(cursor, keys)| scan_reply(cursor, bulk_string_array(keys))),
+            _ => bail!("Not a keyspace cmd"),
+        };
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
+    }
+
+    async fn process_introspection_cmd(&self) -> anyhow::Result<RESPType> {
+        let resp = match self {
+            MemoryUsage { key, samples } => {
+                Database::memory_usage(key, *samples)
+                    .await
+                    .map(|size| match size {
+                        Some(size) => RESPType::Integer(size as i64),
+                        None => RESPType::NullBulkString,
+                    })
+            }
+            MemoryStats => Database::memory_stats().await.map(memory_stats_reply),
+            ObjectEncoding(key) | ObjectIdleTime(key) | ObjectFreq(key) | ObjectRefCount(key) => {
+                Database::object(key).await.map(|info| match info {
+                    None => RESPType::NullBulkString,
+                    Some(info) => match self {
+                        ObjectEncoding(_) => RESPType::BulkString(info.encoding.into()),
+                        ObjectIdleTime(_) => RESPType::Integer(info.idle_time as i64),
+                        ObjectFreq(_) => RESPType::Integer(info.freq as i64),
+                        // Values are never shared between keys
+                        _ => RESPType::Integer(1),
+                    },
+                })
+            }
+            _ => bail!("Not an introspection cmd"),
+        };
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
+    }
+
+    async fn process_stream_cmd(&self) -> anyhow::Result<RESPType> {
+        let resp = match self {
+            XTrim { key, trim } => Database::xtrim(key, trim).await.map(RESPType::Integer),
+            XDel { key, ids } => Database::xdel(key, ids).await.map(RESPType::Integer),
+            XLen { key } => Database::xlen(key).await.map(RESPType::Integer),
+            _ => bail!("Not a stream cmd"),
+        };
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
+    }
+
     async fn process_xread_cmd(&self) -> anyhow::Result<RESPType> {
         let XRead(filters, block_ms) = self else {
             bail!("Not a xread cmd");
         };
 
-        let mut updated_filters: Vec<(String, String)> = vec![];
-        let mut iterable = filters.iter();
-        let mut item = iterable.next();
-        loop {
-            match item {
-                None => break,
-                Some((stream_key, stream_id)) => {
-                    let stream_id = match stream_id.as_str() == "$" {
-                        true => Database::get_last_stream_id(stream_key).await?,
-                        false => stream_id.clone(),
-                    };
-                    updated_filters.push((stream_key.clone(), stream_id.clone()));
-                    item = iterable.next();
-                }
+        // `$` reads what gets added after the command is run
+        let mut updated_filters: Vec<(Bytes, StreamId)> = vec![];
+        for (stream_key, stream_id) in filters {
+            let stream_id = match stream_id {
+                Some(stream_id) => *stream_id,
+                None => Database::get_last_stream_id(stream_key).await?,
             };
+            updated_filters.push((stream_key.clone(), stream_id));
         }
 
         debug!(?updated_filters, "Updated filters");
@@ -202,44 +1040,33 @@ impl ServerCommand {
             },
         };
         debug!("Final response: {:?}", resp);
-        let str = String::from_utf8(resp.as_bytes())?;
+        let str = String::from_utf8_lossy(&resp.as_bytes()).into_owned();
         debug!(?str, "Final String");
         Ok(resp)
     }
 
     async fn internal_process_xread_cmd(
         &self,
-        filters: &Vec<(String, String)>,
+        filters: &[(Bytes, StreamId)],
     ) -> anyhow::Result<RESPType> {
-        let db_value = Database::xread(filters).await?;
-        let mut outer_arr_value = vec![];
-        let mut got_value = false;
-        db_value
+        let db_value = match Database::xread(filters).await {
+            Ok(db_value) => db_value,
+            Err(e) => return Ok(RESPType::Error(e.to_string())),
+        };
+        // Only streams that have new entries are part of the reply
+        let outer_arr_value = db_value
             .into_iter()
-            .for_each(|(stream_key, stream_values)| {
-                let mut inner_arr_value = vec![];
-                inner_arr_value.push(RESPType::BulkString(stream_key.clone()));
-
-                stream_values.into_iter().for_each(|item| {
-                    got_value = true;
-                    let inner_resp = RESPType::Array(vec![
-                        RESPType::BulkString(format!(
-                            "{}-{}",
-                            item.stream_id_ms_part, item.stream_id_seq_part
-                        )),
-                        RESPType::Array(vec![
-                            RESPType::BulkString(item.key),
-                            RESPType::BulkString(item.value),
-                        ]),
-                    ]);
-                    inner_arr_value.push(RESPType::Array(vec![inner_resp]));
-                });
-
-                let outer_resp = RESPType::Array(inner_arr_value);
-                outer_arr_value.push(outer_resp);
-            });
+            .filter(|(_, stream_values)| !stream_values.is_empty())
+            .map(|(stream_key, stream_values)| {
+                let entries = stream_values.into_iter().map(stream_entry).collect();
+                RESPType::Array(vec![
+                    RESPType::BulkString(stream_key),
+                    RESPType::Array(entries),
+                ])
+            })
+            .collect::<Vec<_>>();
 
-        let resp = if got_value {
+        let resp = if !outer_arr_value.is_empty() {
             RESPType::Array(outer_arr_value)
         } else {
             RESPType::NullBulkString
@@ -248,36 +1075,17 @@ impl ServerCommand {
     }
 
     async fn process_xrange_cmd(&self) -> anyhow::Result<RESPType> {
-        let XRange {
-            stream_key,
-            start,
-            end,
-        } = self
-        else {
+        let XRange { stream_key, range } = self else {
             bail!("Not a xrange cmd");
         };
-        let db_value = Database::xrange(stream_key, start, end).await?;
-        let mut map: BTreeMap<String, StreamDbValueType> = BTreeMap::new();
-        db_value.into_iter().for_each(|item| {
-            map.insert(
-                format!("{}-{}", item.stream_id_ms_part, item.stream_id_seq_part),
-                item,
-            );
-        });
-        let mut outer_vec = vec![];
-        map.into_iter().for_each(|(key, value)| {
-            let resp = RESPType::Array(vec![
-                RESPType::BulkString(key),
-                RESPType::Array(vec![
-                    RESPType::BulkString(value.key),
-                    RESPType::BulkString(value.value),
-                ]),
-            ]);
-            outer_vec.push(resp);
+        let resp = Database::xrange(stream_key, range).await.map(|entries| {
+            match range.count {
+                // COUNT 0 gets a null reply, like in Redis
+                Some(0) => RESPType::NullBulkString,
+                _ => RESPType::Array(entries.into_iter().map(stream_entry).collect()),
+            }
         });
-        let final_resp = RESPType::Array(outer_vec);
-        debug!("Final response: {:?}", final_resp);
-        Ok(final_resp)
+        Ok(resp.unwrap_or_else(|e| RESPType::Error(e.to_string())))
     }
 
     async fn process_wait_cmd(&self) -> anyhow::Result<RESPType> {
@@ -302,7 +1110,7 @@ impl ServerCommand {
             return Ok(resp_type);
         }
         let was_last_command_set = Database::was_last_command_set().await?;
-        if was_last_command_set == false {
+        if !was_last_command_set {
             let resp_type = RESPType::Integer(num_replicas as i64);
             return Ok(resp_type);
         }
@@ -345,11 +1153,115 @@ impl ServerCommand {
     }
 }
 
+/// An entry as XRANGE and XREAD reply with it: its ID, then its fields and values in one array
+fn stream_entry(entry: StreamDbValueType) -> RESPType {
+    let id = format!("{}-{}", entry.stream_id_ms_part, entry.stream_id_seq_part);
+    RESPType::Array(vec![
+        RESPType::BulkString(id.into()),
+        bulk_string_array(
+            entry
+                .fields
+                .into_iter()
+                .flat_map(|(field, value)| [field, value]),
+        ),
+    ])
+}
+
+fn bulk_string_or_null(value: Option<impl Into<Bytes>>) -> RESPType {
+    match value {
+        None => RESPType::NullBulkString,
+        Some(value) => RESPType::BulkString(value.into()),
+    }
+}
+
+fn bulk_string_array(values: impl IntoIterator<Item = impl Into<Bytes>>) -> RESPType {
+    let values = values.into_iter();
+    RESPType::Array(
+        values
+            .map(|value| RESPType::BulkString(value.into()))
+            .collect(),
+    )
+}
+
+/// SCAN style replies: the next cursor followed by the batch
+fn scan_reply(cursor: u64, items: RESPType) -> RESPType {
+    RESPType::Array(vec![RESPType::BulkString(cursor.to_string().into()), items])
+}
+
+/// MEMORY STATS as name, value pairs. Only the keys are accounted, so their overhead and the
+/// dataset make up the whole total.
+fn memory_stats_reply(report: MemoryReport) -> RESPType {
+    let overhead: usize = report
+        .databases
+        .iter()
+        .map(|(_, main, expires)| main + expires)
+        .sum();
+    let (total, peak) = (report.dataset + overhead, report.peak);
+    let percentage = |part: usize, whole: usize| match whole {
+        0 => RESPType::BulkString("0".into()),
+        whole => RESPType::BulkString(format_float(part as f64 * 100.0 / whole as f64).into()),
+    };
+    let mut stats = vec![
+        ("peak.allocated".to_string(), RESPType::Integer(peak as i64)),
+        (
+            "total.allocated".to_string(),
+            RESPType::Integer(total as i64),
+        ),
+    ];
+    for (index, main, expires) in report.databases {
+        let db = RESPType::Array(vec![
+            RESPType::BulkString("overhead.hashtable.main".into()),
+            RESPType::Integer(main as i64),
+            RESPType::BulkString("overhead.hashtable.expires".into()),
+            RESPType::Integer(expires as i64),
+        ]);
+        stats.push((format!("db.{index}"), db));
+    }
+    let bytes_per_key = report.dataset.checked_div(report.keys).unwrap_or(0);
+    stats.extend([
+        (
+            "overhead.total".to_string(),
+            RESPType::Integer(overhead as i64),
+        ),
+        (
+            "keys.count".to_string(),
+            RESPType::Integer(report.keys as i64),
+        ),
+        (
+            "keys.bytes-per-key".to_string(),
+            RESPType::Integer(bytes_per_key as i64),
+        ),
+        (
+            "dataset.bytes".to_string(),
+            RESPType::Integer(report.dataset as i64),
+        ),
+        (
+            "dataset.percentage".to_string(),
+            percentage(report.dataset, total),
+        ),
+        ("peak.percentage".to_string(), percentage(total, peak)),
+    ]);
+    RESPType::Array(
+        stats
+            .into_iter()
+            .flat_map(|(name, value)| [RESPType::BulkString(name.into()), value])
+            .collect(),
+    )
+}
+
+/// Flattens sorted set members into `member, score, member, score, ...`
+fn scored_members_array(members: Vec<(Bytes, f64)>) -> RESPType {
+    let values = members
+        .into_iter()
+        .flat_map(|(member, score)| [member, format_float(score).into()]);
+    bulk_string_array(values)
+}
+
 pub async fn send_rds_file(writer: &mut WriteHalf<'_>) -> anyhow::Result<()> {
     use base64::prelude::*;
     let rds_content = b"UkVESVMwMDEx+glyZWRpcy12ZXIFNy4yLjD6CnJlZGlzLWJpdHPAQPoFY3RpbWXCbQi8ZfoIdXNlZC1tZW3CsMQQAPoIYW9mLWJhc2XAAP/wbjv+wP9aog==";
     let decoded = BASE64_STANDARD.decode(rds_content)?;
-    let resp_type = RESPType::RDB(decoded);
+    let resp_type = RESPType::Rdb(decoded);
     debug!(
         "Sending RDB file to client {:?}",
         resp_type.as_bytes().len()
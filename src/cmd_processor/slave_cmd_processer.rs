@@ -1,7 +1,11 @@
+use anyhow::bail;
 use tokio::{io::AsyncWriteExt, net::tcp::WriteHalf};
 use tracing::debug;
 
-use crate::{cmd_parser::slave_command::SlaveCommand, database::Database, resp_type::RESPType};
+use crate::{
+    app_config::AppConfig, cmd_parser::slave_command::SlaveCommand, database::Database,
+    resp_type::RESPType,
+};
 use SlaveCommand::*;
 
 impl SlaveCommand {
@@ -12,14 +16,28 @@ impl SlaveCommand {
     ) -> anyhow::Result<()> {
         match self {
             Ping => (),
-            Set { key, value, flags } => {
-                let _ = Database::set(key, value, flags).await?;
+            Select(index) => {
+                // Writes to a database we don't have can't be replayed
+                if *index >= AppConfig::get_databases() {
+                    bail!(
+                        "Master selected database {index}, but there are only {}",
+                        AppConfig::get_databases()
+                    );
+                }
+                Database::select(*index)
+            }
+            Set {
+                key,
+                value,
+                options,
+            } => {
+                Database::set(key, value, *options).await?;
             }
             ReplConf { .. } => {
                 let resp_type = RESPType::Array(vec![
-                    RESPType::BulkString("REPLCONF".to_string()),
-                    RESPType::BulkString("ACK".to_string()),
-                    RESPType::BulkString(format!("{}", bytes_received)),
+                    RESPType::BulkString("REPLCONF".into()),
+                    RESPType::BulkString("ACK".into()),
+                    RESPType::BulkString(format!("{}", bytes_received).into()),
                 ]);
                 let content = String::from_utf8(resp_type.as_bytes())?;
                 debug!("REpl conf content = {content:?}");
@@ -0,0 +1,464 @@
+use bytes::Bytes;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{
+    BitFieldOp, BitFieldOverflow, BitFieldType, BitOperation, BitRange, BitUnit, DatabaseValue,
+    DbError, DbValueType,
+};
+use super::list::normalize_range;
+use super::Database;
+
+impl Database {
+    pub async fn setbit(key: &Bytes, offset: u64, value: bool) -> anyhow::Result<i64> {
+        Database::request(|emitter| SetBit {
+            emitter,
+            key: key.to_owned(),
+            offset,
+            value,
+        })
+        .await
+    }
+
+    pub async fn getbit(key: &Bytes, offset: u64) -> anyhow::Result<i64> {
+        Database::request(|emitter| GetBit {
+            emitter,
+            key: key.to_owned(),
+            offset,
+        })
+        .await
+    }
+
+    pub async fn bitcount(key: &Bytes, range: Option<BitRange>) -> anyhow::Result<i64> {
+        Database::request(|emitter| BitCount {
+            emitter,
+            key: key.to_owned(),
+            range,
+        })
+        .await
+    }
+
+    pub async fn bitpos(key: &Bytes, bit: bool, range: Option<BitRange>) -> anyhow::Result<i64> {
+        Database::request(|emitter| BitPos {
+            emitter,
+            key: key.to_owned(),
+            bit,
+            range,
+        })
+        .await
+    }
+
+    pub async fn bitop(
+        op: BitOperation,
+        destination: &Bytes,
+        keys: &[Bytes],
+    ) -> anyhow::Result<i64> {
+        Database::request(|emitter| BitOp {
+            emitter,
+            op,
+            destination: destination.to_owned(),
+            keys: keys.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn bitfield(key: &Bytes, ops: &[BitFieldOp]) -> anyhow::Result<Vec<Option<i64>>> {
+        Database::request(|emitter| BitField {
+            emitter,
+            key: key.to_owned(),
+            ops: ops.to_vec(),
+        })
+        .await
+    }
+
+    /// Returns the previous bit, growing the string with zero bytes to reach `offset`
+    pub(super) fn _setbit(
+        &mut self,
+        key: &Bytes,
+        offset: u64,
+        value: bool,
+    ) -> Result<i64, DbError> {
+        let mut bytes = self._get_string(key)?.unwrap_or_default().to_vec();
+        let offset = offset as usize;
+        if bytes.len() <= offset / 8 {
+            bytes.resize(offset / 8 + 1, 0);
+        }
+        let previous = bit_at(&bytes, offset);
+        set_bit(&mut bytes, offset, value);
+        self._store_string(key, Bytes::from(bytes));
+        Ok(previous as i64)
+    }
+
+    pub(super) fn _getbit(&mut self, key: &Bytes, offset: u64) -> Result<i64, DbError> {
+        let bytes = self._get_string(key)?.unwrap_or_default();
+        Ok(bit_at(&bytes, offset as usize) as i64)
+    }
+
+    pub(super) fn _bitcount(
+        &mut self,
+        key: &Bytes,
+        range: Option<BitRange>,
+    ) -> Result<i64, DbError> {
+        let bytes = self._get_string(key)?.unwrap_or_default();
+        let count = match bit_range(range, bytes.len()) {
+            Some((first, last)) => count_bits(&bytes, first, last),
+            None => 0,
+        };
+        Ok(count as i64)
+    }
+
+    /// Position of the first `bit` in the range. Looking for a clear bit without an explicit end
+    /// treats the string as padded with zeros, so a string of all ones answers with the bit
+    /// right after it.
+    pub(super) fn _bitpos(
+        &mut self,
+        key: &Bytes,
+        bit: bool,
+        range: Option<BitRange>,
+    ) -> Result<i64, DbError> {
+        let Some(bytes) = self._get_string(key)? else {
+            return Ok(if bit { -1 } else { 0 });
+        };
+        let Some((first, last)) = bit_range(range, bytes.len()) else {
+            return Ok(-1);
+        };
+        // Whole bytes without the bit we are after are skipped in one go
+        let skip = if bit { 0x00 } else { 0xff };
+        let mut position = first;
+        while position <= last {
+            if position % 8 == 0 && position + 7 <= last && bytes[position / 8] == skip {
+                position += 8;
+                continue;
+            }
+            if bit_at(&bytes, position) == bit {
+                return Ok(position as i64);
+            }
+            position += 1;
+        }
+        let end_given = range.is_some_and(|range| range.end.is_some());
+        match bit || end_given {
+            true => Ok(-1),
+            false => Ok(last as i64 + 1),
+        }
+    }
+
+    /// Stores the result as long as the longest source, where shorter sources count as zero
+    /// padded. An empty result deletes the destination.
+    pub(super) fn _bitop(
+        &mut self,
+        op: BitOperation,
+        destination: &Bytes,
+        keys: &[Bytes],
+    ) -> Result<i64, DbError> {
+        let mut sources = vec![];
+        for key in keys {
+            sources.push(self._get_string(key)?.unwrap_or_default());
+        }
+        let len = sources.iter().map(|source| source.len()).max().unwrap_or(0);
+        let result = (0..len)
+            .map(|i| {
+                let mut bytes = sources
+                    .iter()
+                    .map(|source| source.get(i).copied().unwrap_or(0));
+                let first = bytes.next().unwrap_or(0);
+                match op {
+                    BitOperation::And => bytes.fold(first, |acc, byte| acc & byte),
+                    BitOperation::Or => bytes.fold(first, |acc, byte| acc | byte),
+                    BitOperation::Xor => bytes.fold(first, |acc, byte| acc ^ byte),
+                    BitOperation::Not => !first,
+                }
+            })
+            .collect::<Vec<u8>>();
+        self._lookup_key(destination);
+        if result.is_empty() {
+            self.db.remove(destination);
+        } else {
+            let value = DatabaseValue::new(DbValueType::String(Bytes::from(result)), None);
+            self.db.insert(destination.to_owned(), value);
+        }
+        Ok(len as i64)
+    }
+
+    /// Runs the subcommands in order. The string is grown up front to fit every write, even one
+    /// that ends up failing on overflow.
+    pub(super) fn _bitfield(
+        &mut self,
+        key: &Bytes,
+        ops: &[BitFieldOp],
+    ) -> Result<Vec<Option<i64>>, DbError> {
+        let mut bytes = self._get_string(key)?.unwrap_or_default().to_vec();
+        let write_end = ops
+            .iter()
+            .filter_map(|op| match op {
+                BitFieldOp::Get { .. } => None,
+                BitFieldOp::Set { field, offset, .. }
+                | BitFieldOp::IncrBy { field, offset, .. } => Some(offset + field.bits as u64),
+            })
+            .max();
+        if let Some(end) = write_end {
+            let len = end.div_ceil(8) as usize;
+            if bytes.len() < len {
+                bytes.resize(len, 0);
+            }
+        }
+        let results = ops
+            .iter()
+            .map(|op| match *op {
+                BitFieldOp::Get { field, offset } => Some(read_field(&bytes, field, offset)),
+                BitFieldOp::Set {
+                    field,
+                    offset,
+                    value,
+                    overflow,
+                } => {
+                    let previous = read_field(&bytes, field, offset);
+                    // Unsigned fields see negative values as their two's complement
+                    let value = match field.signed {
+                        true => value as i128,
+                        false => value as u64 as i128,
+                    };
+                    let value = fit_field(field, value, overflow)?;
+                    write_field(&mut bytes, field, offset, value);
+                    Some(previous)
+                }
+                BitFieldOp::IncrBy {
+                    field,
+                    offset,
+                    increment,
+                    overflow,
+                } => {
+                    let value = read_field(&bytes, field, offset) as i128 + increment as i128;
+                    let value = fit_field(field, value, overflow)?;
+                    write_field(&mut bytes, field, offset, value);
+                    Some(value)
+                }
+            })
+            .collect();
+        if write_end.is_some() {
+            self._store_string(key, Bytes::from(bytes));
+        }
+        Ok(results)
+    }
+}
+
+/// Bits are numbered from the most significant bit of the first byte
+fn bit_at(bytes: &[u8], bit: usize) -> bool {
+    bytes
+        .get(bit / 8)
+        .is_some_and(|byte| byte & (0x80 >> (bit % 8)) != 0)
+}
+
+fn set_bit(bytes: &mut [u8], bit: usize, value: bool) {
+    let mask = 0x80 >> (bit % 8);
+    match value {
+        true => bytes[bit / 8] |= mask,
+        false => bytes[bit / 8] &= !mask,
+    }
+}
+
+/// First and last bit covered by a BITCOUNT/BITPOS range over a string of `len` bytes
+fn bit_range(range: Option<BitRange>, len: usize) -> Option<(usize, usize)> {
+    let Some(BitRange { start, end, unit }) = range else {
+        return normalize_range(0, -1, len).map(|(first, last)| (first * 8, last * 8 + 7));
+    };
+    let end = end.unwrap_or(-1);
+    match unit {
+        BitUnit::Byte => {
+            normalize_range(start, end, len).map(|(first, last)| (first * 8, last * 8 + 7))
+        }
+        BitUnit::Bit => normalize_range(start, end, len * 8),
+    }
+}
+
+fn count_bits(bytes: &[u8], first: usize, last: usize) -> u32 {
+    (first / 8..=last / 8)
+        .map(|i| {
+            let mut byte = bytes[i];
+            if i == first / 8 {
+                byte &= 0xff >> (first % 8);
+            }
+            if i == last / 8 {
+                byte &= 0xff << (7 - last % 8);
+            }
+            byte.count_ones()
+        })
+        .sum()
+}
+
+fn read_field(bytes: &[u8], field: BitFieldType, offset: u64) -> i64 {
+    let offset = offset as usize;
+    let bits = field.bits as usize;
+    let raw = (0..bits).fold(0u64, |raw, i| raw << 1 | bit_at(bytes, offset + i) as u64);
+    let negative = field.signed && bits < 64 && raw >> (bits - 1) & 1 == 1;
+    match negative {
+        true => (raw | u64::MAX << bits) as i64,
+        false => raw as i64,
+    }
+}
+
+fn write_field(bytes: &mut [u8], field: BitFieldType, offset: u64, value: i64) {
+    let offset = offset as usize;
+    let bits = field.bits as usize;
+    for i in 0..bits {
+        let bit = (value as u64 >> (bits - 1 - i)) & 1 == 1;
+        set_bit(bytes, offset + i, bit);
+    }
+}
+
+/// Brings `value` into the range of `field`, or `None` when FAIL rejects it
+fn fit_field(field: BitFieldType, value: i128, overflow: BitFieldOverflow) -> Option<i64> {
+    let (min, max) = match field.signed {
+        true => (
+            -(1i128 << (field.bits - 1)),
+            (1i128 << (field.bits - 1)) - 1,
+        ),
+        false => (0, (1i128 << field.bits) - 1),
+    };
+    if (min..=max).contains(&value) {
+        return Some(value as i64);
+    }
+    match overflow {
+        BitFieldOverflow::Wrap => {
+            Some(((value - min).rem_euclid(1i128 << field.bits) + min) as i64)
+        }
+        BitFieldOverflow::Sat if value > max => Some(max as i64),
+        BitFieldOverflow::Sat => Some(min as i64),
+        BitFieldOverflow::Fail => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WRAP: BitFieldOverflow = BitFieldOverflow::Wrap;
+    const SAT: BitFieldOverflow = BitFieldOverflow::Sat;
+    const FAIL: BitFieldOverflow = BitFieldOverflow::Fail;
+
+    fn int(bits: u32) -> BitFieldType {
+        BitFieldType { signed: true, bits }
+    }
+
+    fn uint(bits: u32) -> BitFieldType {
+        BitFieldType {
+            signed: false,
+            bits,
+        }
+    }
+
+    #[test]
+    fn values_in_range_fit_whatever_the_overflow() {
+        for overflow in [WRAP, SAT, FAIL] {
+            assert_eq!(fit_field(int(8), -128, overflow), Some(-128));
+            assert_eq!(fit_field(int(8), 127, overflow), Some(127));
+            assert_eq!(fit_field(uint(8), 255, overflow), Some(255));
+            assert_eq!(
+                fit_field(int(64), i64::MIN as i128, overflow),
+                Some(i64::MIN)
+            );
+            assert_eq!(
+                fit_field(uint(63), i64::MAX as i128, overflow),
+                Some(i64::MAX)
+            );
+        }
+    }
+
+    #[test]
+    fn wrap_goes_around_the_type_limits() {
+        assert_eq!(fit_field(int(8), 128, WRAP), Some(-128));
+        assert_eq!(fit_field(int(8), -129, WRAP), Some(127));
+        assert_eq!(fit_field(int(8), 127 + 256 * 3, WRAP), Some(127));
+        assert_eq!(fit_field(uint(8), 256, WRAP), Some(0));
+        assert_eq!(fit_field(uint(8), -1, WRAP), Some(255));
+        assert_eq!(fit_field(uint(1), 3, WRAP), Some(1));
+        assert_eq!(fit_field(int(1), 1, WRAP), Some(-1));
+        let past_max = i64::MAX as i128 + 1;
+        assert_eq!(fit_field(int(64), past_max, WRAP), Some(i64::MIN));
+        assert_eq!(
+            fit_field(int(64), i64::MIN as i128 - 1, WRAP),
+            Some(i64::MAX)
+        );
+        assert_eq!(fit_field(uint(63), past_max, WRAP), Some(0));
+        assert_eq!(fit_field(uint(63), -1, WRAP), Some(i64::MAX));
+    }
+
+    #[test]
+    fn sat_stops_at_the_type_limits() {
+        assert_eq!(fit_field(int(8), 128, SAT), Some(127));
+        assert_eq!(fit_field(int(8), -129, SAT), Some(-128));
+        assert_eq!(fit_field(uint(8), 1000, SAT), Some(255));
+        assert_eq!(fit_field(uint(8), -1, SAT), Some(0));
+        let past_max = i64::MAX as i128 + 1;
+        assert_eq!(fit_field(int(64), past_max, SAT), Some(i64::MAX));
+        assert_eq!(
+            fit_field(int(64), i64::MIN as i128 - 1, SAT),
+            Some(i64::MIN)
+        );
+        assert_eq!(fit_field(uint(63), past_max, SAT), Some(i64::MAX));
+    }
+
+    #[test]
+    fn fail_refuses_anything_past_the_type_limits() {
+        assert_eq!(fit_field(int(8), 128, FAIL), None);
+        assert_eq!(fit_field(int(8), -129, FAIL), None);
+        assert_eq!(fit_field(uint(8), 256, FAIL), None);
+        assert_eq!(fit_field(uint(8), -1, FAIL), None);
+        assert_eq!(fit_field(int(64), i64::MAX as i128 + 1, FAIL), None);
+        assert_eq!(fit_field(uint(63), i64::MAX as i128 + 1, FAIL), None);
+    }
+
+    #[test]
+    fn fields_read_back_what_was_written_at_any_offset() {
+        for (field, value) in [
+            (int(64), i64::MIN),
+            (int(64), -1),
+            (int(5), -16),
+            (uint(63), i64::MAX),
+            (uint(1), 1),
+            (uint(12), 0xabc),
+        ] {
+            for offset in [0, 3, 8, 13] {
+                let mut bytes = vec![0xff; 12];
+                write_field(&mut bytes, field, offset, value);
+                assert_eq!(
+                    read_field(&bytes, field, offset),
+                    value,
+                    "{field:?} at {offset}"
+                );
+                // Bits around the field are left alone
+                assert!(bit_at(&bytes, offset as usize + field.bits as usize));
+            }
+        }
+        // Bits past the end of the string read as zeros
+        assert_eq!(read_field(&[0xff], uint(16), 0), 0xff00);
+    }
+
+    #[test]
+    fn bit_ranges_count_bits_or_bytes() {
+        let range = |start, end, unit| Some(BitRange { start, end, unit });
+        assert_eq!(bit_range(None, 2), Some((0, 15)));
+        assert_eq!(bit_range(None, 0), None);
+        assert_eq!(
+            bit_range(range(1, Some(1), BitUnit::Byte), 3),
+            Some((8, 15))
+        );
+        assert_eq!(bit_range(range(-1, None, BitUnit::Byte), 3), Some((16, 23)));
+        assert_eq!(bit_range(range(5, Some(9), BitUnit::Bit), 2), Some((5, 9)));
+        assert_eq!(bit_range(range(-3, None, BitUnit::Bit), 2), Some((13, 15)));
+        assert_eq!(
+            bit_range(range(7, Some(100), BitUnit::Bit), 2),
+            Some((7, 15))
+        );
+        assert_eq!(bit_range(range(9, Some(5), BitUnit::Bit), 2), None);
+        assert_eq!(bit_range(range(16, Some(20), BitUnit::Bit), 2), None);
+    }
+
+    #[test]
+    fn counts_only_bits_inside_the_range() {
+        let bytes = b"foobar";
+        assert_eq!(count_bits(bytes, 0, 47), 26);
+        assert_eq!(count_bits(bytes, 0, 7), 4);
+        assert_eq!(count_bits(bytes, 5, 30), 17);
+        assert_eq!(count_bits(&[0xff, 0xff], 3, 5), 3);
+        assert_eq!(count_bits(&[0xff, 0xff], 6, 9), 4);
+    }
+}
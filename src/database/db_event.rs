@@ -1,80 +1,1058 @@
-use std::{collections::HashMap, time::Instant};
+use std::{collections::VecDeque, time::Duration};
 
+use bytes::Bytes;
 use thiserror::Error;
+
+use super::memory::LFU_INIT_VAL;
+use super::now_ms;
+use super::scan::{ScanMap, ScanSet};
+use super::sorted_set::SortedSet;
+use super::stream::Stream;
 use tokio::sync::oneshot::Sender;
 
+/// The key a blocked client was served from together with the popped elements
+pub type BlockingPopResult = Result<Option<(Bytes, Vec<Bytes>)>, DbError>;
+
+/// The cursor to continue from together with the batch of elements
+pub type ScanResult<T> = Result<(u64, Vec<T>), DbError>;
+
+/// Each stream XREAD asked for with its entries after the given ID
+pub type XReadResult = Result<Vec<(Bytes, Vec<StreamDbValueType>)>, DbError>;
+
 #[derive(Debug)]
 pub enum DatabaseEvent {
     Set {
-        key: String,
-        value: String,
-        flags: HashMap<String, String>,
+        emitter: Sender<Result<(bool, Option<Bytes>), DbError>>,
+        key: Bytes,
+        value: Bytes,
+        options: SetOptions,
     },
     Get {
-        emitter: Sender<Option<DbValueType>>,
-        key: String,
-    },
-    Incr {
-        emitter: Sender<Result<DbValueType, String>>,
-        key: String,
+        emitter: Sender<Result<Option<DbValueType>, DbError>>,
+        key: Bytes,
     },
     Keys {
-        emitter: Sender<Vec<String>>,
-        flag: String,
+        emitter: Sender<Vec<Bytes>>,
+        flag: Bytes,
     },
     Type {
         emitter: Sender<String>,
-        key: String,
+        key: Bytes,
     },
     XAdd {
-        emitter: Sender<Result<String, String>>,
-        stream_key: String,
+        emitter: Sender<Result<Option<String>, String>>,
+        stream_key: Bytes,
         stream_id: String,
-        key: String,
-        value: String,
+        fields: Vec<(Bytes, Bytes)>,
+        options: XAddOptions,
+    },
+    XTrim {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        trim: StreamTrim,
+    },
+    XDel {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        ids: Vec<StreamId>,
+    },
+    XLen {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
     },
     XRange {
-        emitter: Sender<Vec<StreamDbValueType>>,
-        stream_key: String,
-        start: String,
-        end: String,
+        emitter: Sender<Result<Vec<StreamDbValueType>, DbError>>,
+        stream_key: Bytes,
+        range: StreamRange,
     },
     XRead {
-        emitter: Sender<Vec<(String, Vec<StreamDbValueType>)>>,
-        filters: Vec<(String, String)>,
+        emitter: Sender<XReadResult>,
+        filters: Vec<(Bytes, StreamId)>,
     },
     WasLastCommandSet {
         emitter: Sender<bool>,
     },
     _GetLastStreamId {
-        emitter: Sender<String>,
-        stream_key: String,
+        emitter: Sender<StreamId>,
+        stream_key: Bytes,
+    },
+    HSet {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        fields: Vec<(Bytes, Bytes)>,
+    },
+    HSetNx {
+        emitter: Sender<Result<bool, DbError>>,
+        key: Bytes,
+        field: Bytes,
+        value: Bytes,
+    },
+    HGet {
+        emitter: Sender<Result<Option<Bytes>, DbError>>,
+        key: Bytes,
+        field: Bytes,
+    },
+    HMGet {
+        emitter: Sender<Result<Vec<Option<Bytes>>, DbError>>,
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    HGetAll {
+        emitter: Sender<Result<Vec<(Bytes, Bytes)>, DbError>>,
+        key: Bytes,
+    },
+    HDel {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    HExists {
+        emitter: Sender<Result<bool, DbError>>,
+        key: Bytes,
+        field: Bytes,
+    },
+    HLen {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+    },
+    HKeys {
+        emitter: Sender<Result<Vec<Bytes>, DbError>>,
+        key: Bytes,
+    },
+    HVals {
+        emitter: Sender<Result<Vec<Bytes>, DbError>>,
+        key: Bytes,
+    },
+    HIncrBy {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        field: Bytes,
+        increment: i64,
+    },
+    HIncrByFloat {
+        emitter: Sender<Result<Bytes, DbError>>,
+        key: Bytes,
+        field: Bytes,
+        increment: f64,
+    },
+    HStrLen {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        field: Bytes,
+    },
+    HRandField {
+        emitter: Sender<Result<Vec<(Bytes, Bytes)>, DbError>>,
+        key: Bytes,
+        count: Option<i64>,
+    },
+    Push {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        elements: Vec<Bytes>,
+        end: ListEnd,
+        only_if_exists: bool,
+    },
+    Pop {
+        emitter: Sender<Result<Option<Vec<Bytes>>, DbError>>,
+        key: Bytes,
+        end: ListEnd,
+        count: usize,
+    },
+    LRange {
+        emitter: Sender<Result<Vec<Bytes>, DbError>>,
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    LIndex {
+        emitter: Sender<Result<Option<Bytes>, DbError>>,
+        key: Bytes,
+        index: i64,
+    },
+    LLen {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+    },
+    LSet {
+        emitter: Sender<Result<(), DbError>>,
+        key: Bytes,
+        index: i64,
+        element: Bytes,
+    },
+    LInsert {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        before: bool,
+        pivot: Bytes,
+        element: Bytes,
+    },
+    LRem {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        count: i64,
+        element: Bytes,
+    },
+    LTrim {
+        emitter: Sender<Result<(), DbError>>,
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    LPos {
+        emitter: Sender<Result<Vec<i64>, DbError>>,
+        key: Bytes,
+        element: Bytes,
+        rank: i64,
+        count: usize,
+        max_len: usize,
+    },
+    LMove {
+        emitter: Sender<Result<Option<Bytes>, DbError>>,
+        source: Bytes,
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    },
+    BlockingPop {
+        emitter: Sender<BlockingPopResult>,
+        keys: Vec<Bytes>,
+        op: BlockingListOp,
+        /// Whether to wait when every list is empty, rather than replying with nothing
+        block: bool,
+    },
+    SAdd {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SRem {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SMembers {
+        emitter: Sender<Result<Vec<Bytes>, DbError>>,
+        key: Bytes,
+    },
+    SMIsMember {
+        emitter: Sender<Result<Vec<bool>, DbError>>,
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    SCard {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+    },
+    SPop {
+        emitter: Sender<Result<Vec<Bytes>, DbError>>,
+        key: Bytes,
+        count: usize,
+    },
+    SRandMember {
+        emitter: Sender<Result<Vec<Bytes>, DbError>>,
+        key: Bytes,
+        count: Option<i64>,
+    },
+    SMove {
+        emitter: Sender<Result<bool, DbError>>,
+        source: Bytes,
+        destination: Bytes,
+        member: Bytes,
+    },
+    SetOperation {
+        emitter: Sender<Result<Vec<Bytes>, DbError>>,
+        op: SetOp,
+        keys: Vec<Bytes>,
+    },
+    SetOperationStore {
+        emitter: Sender<Result<i64, DbError>>,
+        op: SetOp,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    SInterCard {
+        emitter: Sender<Result<i64, DbError>>,
+        keys: Vec<Bytes>,
+        limit: usize,
+    },
+    ZAdd {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        members: Vec<(f64, Bytes)>,
+        options: ZAddOptions,
+    },
+    ZIncrBy {
+        emitter: Sender<Result<Option<f64>, DbError>>,
+        key: Bytes,
+        member: Bytes,
+        increment: f64,
+        options: ZAddOptions,
+    },
+    ZRem {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    ZMScore {
+        emitter: Sender<Result<Vec<Option<f64>>, DbError>>,
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    ZRank {
+        emitter: Sender<Result<Option<(i64, f64)>, DbError>>,
+        key: Bytes,
+        member: Bytes,
+        rev: bool,
+    },
+    ZCard {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+    },
+    ZCount {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        min: ScoreBound,
+        max: ScoreBound,
+    },
+    ZRange {
+        emitter: Sender<Result<Vec<(Bytes, f64)>, DbError>>,
+        key: Bytes,
+        spec: ZRangeSpec,
+    },
+    ZRangeStore {
+        emitter: Sender<Result<i64, DbError>>,
+        destination: Bytes,
+        source: Bytes,
+        spec: ZRangeSpec,
+    },
+    ZPop {
+        emitter: Sender<Result<Vec<(Bytes, f64)>, DbError>>,
+        key: Bytes,
+        count: usize,
+        max: bool,
+    },
+    ZSetOperationStore {
+        emitter: Sender<Result<i64, DbError>>,
+        op: SetOp,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+        weights: Vec<f64>,
+        aggregate: Aggregate,
+    },
+    GeoSearch {
+        emitter: Sender<Result<Vec<GeoMatch>, DbError>>,
+        key: Bytes,
+        query: GeoQuery,
+    },
+    GeoSearchStore {
+        emitter: Sender<Result<i64, DbError>>,
+        destination: Bytes,
+        source: Bytes,
+        query: GeoQuery,
+        store_dist: bool,
+    },
+    PfAdd {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        elements: Vec<Bytes>,
+    },
+    PfCount {
+        emitter: Sender<Result<i64, DbError>>,
+        keys: Vec<Bytes>,
+    },
+    PfMerge {
+        emitter: Sender<Result<(), DbError>>,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    SetBit {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        offset: u64,
+        value: bool,
+    },
+    GetBit {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        offset: u64,
+    },
+    BitCount {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        range: Option<BitRange>,
+    },
+    BitPos {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        bit: bool,
+        range: Option<BitRange>,
+    },
+    BitOp {
+        emitter: Sender<Result<i64, DbError>>,
+        op: BitOperation,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+    },
+    BitField {
+        emitter: Sender<Result<Vec<Option<i64>>, DbError>>,
+        key: Bytes,
+        ops: Vec<BitFieldOp>,
+    },
+    Append {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        value: Bytes,
+    },
+    StrLen {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+    },
+    GetRange {
+        emitter: Sender<Result<Bytes, DbError>>,
+        key: Bytes,
+        start: i64,
+        end: i64,
+    },
+    SetRange {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        offset: usize,
+        value: Bytes,
+    },
+    MGet {
+        emitter: Sender<Result<Vec<Option<Bytes>>, DbError>>,
+        keys: Vec<Bytes>,
+    },
+    MSet {
+        emitter: Sender<Result<bool, DbError>>,
+        pairs: Vec<(Bytes, Bytes)>,
+        only_new: bool,
+    },
+    GetDel {
+        emitter: Sender<Result<Option<Bytes>, DbError>>,
+        key: Bytes,
+    },
+    GetEx {
+        emitter: Sender<Result<Option<Bytes>, DbError>>,
+        key: Bytes,
+        expiry: Option<Expiry>,
+    },
+    Lcs {
+        emitter: Sender<Result<Subsequence, DbError>>,
+        key1: Bytes,
+        key2: Bytes,
+    },
+    IncrBy {
+        emitter: Sender<Result<i64, DbError>>,
+        key: Bytes,
+        increment: i64,
+    },
+    IncrByFloat {
+        emitter: Sender<Result<Bytes, DbError>>,
+        key: Bytes,
+        increment: f64,
+    },
+    Del {
+        emitter: Sender<Result<i64, DbError>>,
+        keys: Vec<Bytes>,
+        lazy: bool,
+    },
+    Exists {
+        emitter: Sender<Result<i64, DbError>>,
+        keys: Vec<Bytes>,
+    },
+    Rename {
+        emitter: Sender<Result<bool, DbError>>,
+        key: Bytes,
+        new_key: Bytes,
+        only_new: bool,
+    },
+    Copy {
+        emitter: Sender<Result<bool, DbError>>,
+        source: Bytes,
+        destination: Bytes,
+        /// Database to copy into, the selected one when `None`
+        db: Option<usize>,
+        replace: bool,
+    },
+    Touch {
+        emitter: Sender<Result<i64, DbError>>,
+        keys: Vec<Bytes>,
+    },
+    Sort {
+        emitter: Sender<Result<Vec<Option<Bytes>>, DbError>>,
+        key: Bytes,
+        options: SortOptions,
+    },
+    SortStore {
+        emitter: Sender<Result<i64, DbError>>,
+        destination: Bytes,
+        key: Bytes,
+        options: SortOptions,
+    },
+    Dump {
+        emitter: Sender<Result<Option<Bytes>, DbError>>,
+        key: Bytes,
+    },
+    Restore {
+        emitter: Sender<Result<(), DbError>>,
+        key: Bytes,
+        value: DbValueType,
+        options: RestoreOptions,
+    },
+    RandomKey {
+        emitter: Sender<Result<Option<Bytes>, DbError>>,
+    },
+    DbSize {
+        emitter: Sender<Result<i64, DbError>>,
+    },
+    Flush {
+        emitter: Sender<Result<(), DbError>>,
+        /// Every database instead of only the selected one
+        all: bool,
+        lazy: bool,
+    },
+    Move {
+        emitter: Sender<Result<bool, DbError>>,
+        key: Bytes,
+        db: usize,
+    },
+    SwapDb {
+        emitter: Sender<Result<(), DbError>>,
+        first: usize,
+        second: usize,
+    },
+    Expire {
+        emitter: Sender<Result<bool, DbError>>,
+        key: Bytes,
+        expiry: Expiry,
+        options: ExpireOptions,
+    },
+    /// `None` for a missing key, `Some(None)` for a key that never expires
+    ExpireTime {
+        emitter: Sender<Result<Option<Option<u64>>, DbError>>,
+        key: Bytes,
+    },
+    Persist {
+        emitter: Sender<Result<bool, DbError>>,
+        key: Bytes,
+    },
+    Scan {
+        emitter: Sender<ScanResult<Bytes>>,
+        cursor: u64,
+        options: ScanOptions,
+    },
+    HScan {
+        emitter: Sender<ScanResult<(Bytes, Bytes)>>,
+        key: Bytes,
+        cursor: u64,
+        options: ScanOptions,
+    },
+    SScan {
+        emitter: Sender<ScanResult<Bytes>>,
+        key: Bytes,
+        cursor: u64,
+        options: ScanOptions,
+    },
+    ZScan {
+        emitter: Sender<ScanResult<(Bytes, f64)>>,
+        key: Bytes,
+        cursor: u64,
+        options: ScanOptions,
+    },
+    /// Evicts keys until they fit in maxmemory again
+    FreeMemory {
+        emitter: Sender<Result<(), DbError>>,
+    },
+    MemoryUsage {
+        emitter: Sender<Result<Option<usize>, DbError>>,
+        key: Bytes,
+        samples: Option<usize>,
+    },
+    MemoryStats {
+        emitter: Sender<Result<MemoryReport, DbError>>,
+    },
+    Object {
+        emitter: Sender<Result<Option<ObjectInfo>, DbError>>,
+        key: Bytes,
     },
 }
 
 #[derive(Debug, Clone)]
 pub struct DatabaseValue {
     pub value: DbValueType,
-    pub exp_time: Option<Instant>,
+    /// Unix time in milliseconds from which the key is gone
+    pub exp_time: Option<u64>,
+    /// Unix time in milliseconds the key was last looked up, which is also when the LFU
+    /// counter last decayed
+    pub last_access: u64,
+    /// Logarithmic access counter the LFU policies evict by
+    pub lfu_counter: u8,
+    /// Bytes accounted against maxmemory for the key
+    pub size: usize,
 }
 
 #[derive(Clone, Debug)]
 pub enum DbValueType {
     Integer(i64),
-    String(String),
-    Stream(Vec<StreamDbValueType>),
+    String(Bytes),
+    Stream(Stream),
+    Hash(ScanMap<Bytes>),
+    List(VecDeque<Bytes>),
+    Set(ScanSet),
+    SortedSet(SortedSet),
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+/// Inclusive BITCOUNT/BITPOS range, where a missing end runs to the end of the string
+#[derive(Clone, Copy, Debug)]
+pub struct BitRange {
+    pub start: i64,
+    pub end: Option<i64>,
+    pub unit: BitUnit,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+/// Integer type of a BITFIELD field, such as `i5` or `u16`
+#[derive(Clone, Copy, Debug)]
+pub struct BitFieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BitFieldOverflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+/// A BITFIELD subcommand, with OVERFLOW already applied to the writes that follow it
+#[derive(Clone, Copy, Debug)]
+pub enum BitFieldOp {
+    Get {
+        field: BitFieldType,
+        offset: u64,
+    },
+    Set {
+        field: BitFieldType,
+        offset: u64,
+        value: i64,
+        overflow: BitFieldOverflow,
+    },
+    IncrBy {
+        field: BitFieldType,
+        offset: u64,
+        increment: i64,
+        overflow: BitFieldOverflow,
+    },
+}
+
+/// New expiry for a key as SET and GETEX take it
+#[derive(Clone, Copy, Debug)]
+pub enum Expiry {
+    /// Relative to when the command runs
+    In(Duration),
+    /// Unix time in milliseconds
+    At(u64),
+    Persist,
+}
+
+/// Longest common subsequence of two strings together with the matching ranges, listed from
+/// the end of the strings to the start
+#[derive(Clone, Debug)]
+pub struct Subsequence {
+    pub sequence: Bytes,
+    pub matches: Vec<LcsMatch>,
+}
+
+/// Inclusive byte ranges of one run of the subsequence in each string
+#[derive(Clone, Copy, Debug)]
+pub struct LcsMatch {
+    pub first: (usize, usize),
+    pub second: (usize, usize),
+}
+
+impl DbValueType {
+    /// Name of the type as TYPE reports it
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DbValueType::Integer(_) | DbValueType::String(_) => "string",
+            DbValueType::Stream(_) => "stream",
+            DbValueType::Hash(_) => "hash",
+            DbValueType::List(_) => "list",
+            DbValueType::Set(_) => "set",
+            DbValueType::SortedSet(_) => "zset",
+        }
+    }
+
+    /// Name OBJECT ENCODING gives the way the value is stored. Redis' names are used where the
+    /// representation is the same, lists being ring buffers which Redis has no name for.
+    pub fn encoding_name(&self) -> &'static str {
+        match self {
+            DbValueType::Integer(_) => "int",
+            DbValueType::String(_) => "raw",
+            DbValueType::Stream(_) => "stream",
+            DbValueType::Hash(_) | DbValueType::Set(_) => "hashtable",
+            DbValueType::List(_) => "ringbuffer",
+            DbValueType::SortedSet(_) => "skiplist",
+        }
+    }
+}
+
+impl DatabaseValue {
+    pub fn new(value: DbValueType, exp_time: Option<u64>) -> Self {
+        DatabaseValue {
+            value,
+            exp_time,
+            last_access: now_ms(),
+            lfu_counter: LFU_INIT_VAL,
+            // Filled in once the command that created the key is done
+            size: 0,
+        }
+    }
+
+    /// Whether the key is past its expiry at Unix time `now_ms`
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        self.exp_time.is_some_and(|exp_time| exp_time <= now_ms)
+    }
+}
+
+impl LcsMatch {
+    pub fn match_len(&self) -> usize {
+        self.first.1 - self.first.0 + 1
+    }
+}
+
+/// Without an expiry or `keep_ttl`, SET clears any expiry the key had
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SetOptions {
+    /// Only set a missing key
+    pub nx: bool,
+    /// Only set an existing key
+    pub xx: bool,
+    /// Reply with the previous value
+    pub get: bool,
+    pub keep_ttl: bool,
+    pub expiry: Option<Expiry>,
+}
+
+/// What OBJECT reports about a key
+#[derive(Clone, Debug)]
+pub struct ObjectInfo {
+    pub encoding: &'static str,
+    /// Seconds since the key was last looked up
+    pub idle_time: u64,
+    /// LFU counter, decayed for the time since the last access
+    pub freq: u8,
+}
+
+/// Figures behind MEMORY STATS, in bytes
+#[derive(Clone, Debug)]
+pub struct MemoryReport {
+    /// Most bytes the keys ever took, overhead included
+    pub peak: usize,
+    /// What the keys take
+    pub dataset: usize,
+    pub keys: usize,
+    /// Database number along with the spare room in its key table and in its expiry index, for
+    /// databases that have keys
+    pub databases: Vec<(usize, usize, usize)>,
+}
+
+/// Conditions EXPIRE and friends check before changing the expiry, a key without an expiry
+/// counting as one that never expires
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExpireOptions {
+    /// Only set an expiry on a key that has none
+    pub nx: bool,
+    /// Only change the expiry of a key that has one
+    pub xx: bool,
+    /// Only change the expiry when the new one is later
+    pub gt: bool,
+    /// Only change the expiry when the new one is earlier
+    pub lt: bool,
+}
+
+/// How RESTORE creates the key
+#[derive(Clone, Copy, Debug)]
+pub struct RestoreOptions {
+    pub expiry: Expiry,
+    /// Overwrite an existing key instead of failing
+    pub replace: bool,
+    /// Seconds the key is made to look idle for
+    pub idle_time: Option<u64>,
+    /// LFU counter the key starts out with
+    pub freq: Option<u8>,
+}
+
+/// How SORT orders the elements and what it returns for them
+#[derive(Clone, Debug, Default)]
+pub struct SortOptions {
+    /// Pattern of the keys holding the weights to sort by
+    pub by: Option<Bytes>,
+    /// Offset and count of the sorted elements to return, a negative count meaning all
+    pub limit: Option<(i64, i64)>,
+    /// Patterns of the values returned for each element, `#` being the element itself
+    pub get: Vec<Bytes>,
+    pub desc: bool,
+    /// Compare as strings instead of as numbers
+    pub alpha: bool,
+}
+
+/// Which entries XADD and XTRIM drop from the start of a stream
+#[derive(Clone, Debug)]
+pub struct StreamTrim {
+    pub strategy: TrimStrategy,
+    /// `~`: only whole nodes are dropped, which can leave more entries than asked for
+    pub approximate: bool,
+    /// Most entries dropped at once, 0 for no limit
+    pub limit: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum TrimStrategy {
+    /// Keep at most this many entries
+    MaxLen(usize),
+    /// Drop the entries with a lower ID
+    MinId(StreamId),
+}
+
+/// Entries XRANGE and XREVRANGE return. `(` bounds are already moved to the next ID in, so
+/// both ends are included.
+#[derive(Clone, Copy, Debug)]
+pub struct StreamRange {
+    pub start: StreamId,
+    pub end: StreamId,
+    /// Most entries returned
+    pub count: Option<usize>,
+    /// Newest entries first
+    pub rev: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct XAddOptions {
+    /// Do not create the stream when it does not exist
+    pub no_mkstream: bool,
+    pub trim: Option<StreamTrim>,
+}
+
+/// Filters shared by SCAN and the per-collection scans. They are applied to a batch after it
+/// is picked, so a batch can come back empty before the iteration is over.
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// Roughly how many elements to look at in one call
+    pub count: usize,
+    /// Glob pattern names must match
+    pub pattern: Option<Bytes>,
+    /// Only keys of this type, SCAN only
+    pub value_type: Option<String>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            count: 10,
+            pattern: None,
+            value_type: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    pub fn matches(&self, name: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .map_or(true, |pattern| crate::glob::matches(pattern, name))
+    }
+}
+
+/// Conditions ZADD and ZINCRBY apply before touching a member
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZAddOptions {
+    /// Only add new members
+    pub nx: bool,
+    /// Only update existing members
+    pub xx: bool,
+    /// Only update when the new score is greater
+    pub gt: bool,
+    /// Only update when the new score is less
+    pub lt: bool,
+    /// Count changed members in the reply, not only added ones
+    pub ch: bool,
+}
+
+/// One end of a score range, where `-inf`/`+inf` are inclusive infinities
+#[derive(Clone, Copy, Debug)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+impl ScoreBound {
+    pub fn is_before_min(&self, score: f64) -> bool {
+        match self {
+            ScoreBound::Inclusive(min) => score < *min,
+            ScoreBound::Exclusive(min) => score <= *min,
+        }
+    }
+
+    pub fn is_after_max(&self, score: f64) -> bool {
+        match self {
+            ScoreBound::Inclusive(max) => score > *max,
+            ScoreBound::Exclusive(max) => score >= *max,
+        }
+    }
+}
+
+/// One end of a lexicographic range, `-` and `+` being the smallest and largest strings
+#[derive(Clone, Debug)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+impl LexBound {
+    pub fn is_before_min(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(min) => member < min.as_ref(),
+            LexBound::Exclusive(min) => member <= min.as_ref(),
+        }
+    }
+
+    pub fn is_after_max(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(max) => member > max.as_ref(),
+            LexBound::Exclusive(max) => member >= max.as_ref(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ZRangeBy {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+/// Which members ZRANGE and ZRANGESTORE select. Bounds are always given as min then max, even
+/// for `rev` ranges.
+#[derive(Clone, Debug)]
+pub struct ZRangeSpec {
+    pub by: ZRangeBy,
+    pub rev: bool,
+    /// Offset and count, a negative count meaning everything after the offset
+    pub limit: Option<(i64, i64)>,
+}
+
+/// How ZUNIONSTORE and ZINTERSTORE combine the scores of a member found in several keys
+#[derive(Clone, Copy, Debug)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+#[derive(Clone, Debug)]
+pub enum GeoOrigin {
+    Member(Bytes),
+    Coordinates(f64, f64),
+}
+
+/// Area a geo search covers, in meters
+#[derive(Clone, Copy, Debug)]
+pub enum GeoShape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct GeoQuery {
+    pub origin: GeoOrigin,
+    pub shape: GeoShape,
+    /// Meters per unit the distances are reported in
+    pub unit: f64,
+    pub descending: bool,
+    pub count: Option<usize>,
+    /// Stop at the first `count` matches instead of looking for the closest ones
+    pub any: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct GeoMatch {
+    pub member: Bytes,
+    /// Distance from the search origin in meters
+    pub distance: f64,
+    pub hash: u64,
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+/// What a blocked client does with the first list that becomes non-empty
+#[derive(Clone, Debug)]
+pub enum BlockingListOp {
+    Pop {
+        end: ListEnd,
+        count: usize,
+    },
+    Move {
+        destination: Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    },
+}
+
+/// Millisecond and sequence parts of a stream entry ID
+pub type StreamId = (u128, usize);
+
+/// `+` in a stream range, above any ID an entry can have
+pub const MAX_STREAM_ID: StreamId = (u128::MAX, usize::MAX);
+
 #[derive(Clone, Debug)]
 pub struct StreamDbValueType {
     pub stream_id_ms_part: u128,
     pub stream_id_seq_part: usize,
-    pub key: String,
-    pub value: String,
+    /// Field/value pairs in the order XADD was given them
+    pub fields: Vec<(Bytes, Bytes)>,
 }
 
 #[derive(Error, Debug, Clone)]
 pub enum DbError {
     #[error("{0}")]
     UnableToPerformAction(String),
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("OOM command not allowed when used memory > 'maxmemory'.")]
+    OutOfMemory,
 }
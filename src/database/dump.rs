@@ -0,0 +1,978 @@
+//! DUMP and RESTORE payloads: a single value in the RDB encoding, followed by the RDB version
+//! it was written with and a CRC64 of everything before the checksum, the same layout Redis uses.
+//!
+//! Values are written with the plain RDB types, which every Redis since 5.0 can restore. When
+//! restoring, the compact ziplist, listpack, intset and quicklist encodings Redis dumps small
+//! values in are accepted too, as are LZF compressed strings.
+
+use std::collections::VecDeque;
+
+use anyhow::{bail, Context};
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{DatabaseValue, DbError, DbValueType, RestoreOptions, StreamDbValueType};
+use super::scan::{ScanMap, ScanSet};
+use super::sorted_set::SortedSet;
+use super::stream::{Stream, NODE_MAX_ENTRIES};
+use super::{expiry_time, now_ms, parse_float, parse_i64, string_value, Database};
+
+/// Version written in the footer, the oldest one that has every type we write
+const DUMP_RDB_VERSION: u16 = 9;
+/// Payloads from newer versions may use types we don't know about
+const MAX_RDB_VERSION: u16 = 12;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+/// Top two bits of a length byte telling that a specially encoded string follows
+const ENCODED: u8 = 0b11;
+const ENC_INT8: u64 = 0;
+const ENC_INT16: u64 = 1;
+const ENC_INT32: u64 = 2;
+const ENC_LZF: u64 = 3;
+
+/// Quicklist node holding one large element as is instead of a listpack
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+/// CRC-64/Jones, reflected, as Redis checksums RDB files and payloads with
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0x95ac9329ac4bc9b5,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+impl Database {
+    pub async fn dump(key: &Bytes) -> anyhow::Result<Option<Bytes>> {
+        Database::request(|emitter| Dump {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    /// The payload is decoded before it is handed to the database, so a large one doesn't hold
+    /// up other clients
+    pub async fn restore(
+        key: &Bytes,
+        payload: &[u8],
+        options: RestoreOptions,
+    ) -> anyhow::Result<()> {
+        let value = decode_payload(payload)?;
+        Database::request(|emitter| Restore {
+            emitter,
+            key: key.to_owned(),
+            value,
+            options,
+        })
+        .await
+    }
+
+    pub(super) fn _dump(&mut self, key: &Bytes) -> Result<Option<Bytes>, DbError> {
+        self._lookup_key(key);
+        Ok(self
+            .db
+            .get(key)
+            .map(|db_value| encode_payload(&db_value.value)))
+    }
+
+    /// A key that would already have expired is not created, though with REPLACE the key it
+    /// replaces is still deleted
+    pub(super) fn _restore(
+        &mut self,
+        key: &Bytes,
+        value: DbValueType,
+        options: RestoreOptions,
+    ) -> Result<(), DbError> {
+        self._lookup_key(key);
+        if self.db.contains_key(key) && !options.replace {
+            return Err(DbError::UnableToPerformAction(
+                "BUSYKEY Target key name already exists.".to_string(),
+            ));
+        }
+        let now = now_ms();
+        let exp_time = expiry_time(options.expiry);
+        if exp_time.is_some_and(|exp_time| exp_time <= now) {
+            self.db.remove(key);
+            return Ok(());
+        }
+        let mut db_value = DatabaseValue::new(value, exp_time);
+        if let Some(idle_time) = options.idle_time {
+            db_value.last_access = now.saturating_sub(idle_time.saturating_mul(1000));
+        }
+        if let Some(freq) = options.freq {
+            db_value.lfu_counter = freq;
+        }
+        if exp_time.is_some() {
+            self.volatile_keys.insert(key);
+        }
+        self.db.insert(key.to_owned(), db_value);
+        Ok(())
+    }
+}
+
+/// Serializes `value` the way DUMP returns it
+fn encode_payload(value: &DbValueType) -> Bytes {
+    let mut buf = BytesMut::new();
+    write_value(&mut buf, value);
+    buf.put_u16_le(DUMP_RDB_VERSION);
+    let crc = crc64(&buf);
+    buf.put_u64_le(crc);
+    buf.freeze()
+}
+
+/// Checks the footer of a RESTORE payload and reads the value out of it
+fn decode_payload(payload: &[u8]) -> anyhow::Result<DbValueType> {
+    let Some(body_len) = payload.len().checked_sub(10) else {
+        bail!("ERR DUMP payload version or checksum are wrong");
+    };
+    let (body, footer) = payload.split_at(body_len);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let crc = u64::from_le_bytes(footer[2..].try_into().expect("footer is 10 bytes"));
+    if version > MAX_RDB_VERSION || crc64(&payload[..body_len + 2]) != crc {
+        bail!("ERR DUMP payload version or checksum are wrong");
+    }
+    let mut reader = Reader::new(body);
+    read_value(&mut reader)
+        .filter(|_| reader.is_empty())
+        .context("ERR Bad data format")
+}
+
+fn crc64(data: &[u8]) -> u64 {
+    data.iter().fold(0, |crc, &byte| {
+        CRC64_TABLE[((crc ^ byte as u64) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn write_value(buf: &mut BytesMut, value: &DbValueType) {
+    match value {
+        DbValueType::Integer(value) => {
+            buf.put_u8(TYPE_STRING);
+            write_integer(buf, *value);
+        }
+        DbValueType::String(value) => {
+            buf.put_u8(TYPE_STRING);
+            write_string(buf, value);
+        }
+        DbValueType::List(list) => {
+            buf.put_u8(TYPE_LIST);
+            write_length(buf, list.len() as u64);
+            list.iter().for_each(|element| write_string(buf, element));
+        }
+        DbValueType::Set(set) => {
+            buf.put_u8(TYPE_SET);
+            write_length(buf, set.len() as u64);
+            set.iter().for_each(|member| write_string(buf, member));
+        }
+        DbValueType::Hash(hash) => {
+            buf.put_u8(TYPE_HASH);
+            write_length(buf, hash.len() as u64);
+            for (field, value) in hash {
+                write_string(buf, field);
+                write_string(buf, value);
+            }
+        }
+        DbValueType::SortedSet(zset) => {
+            buf.put_u8(TYPE_ZSET_2);
+            write_length(buf, zset.len() as u64);
+            for (member, score) in zset.iter(false) {
+                write_string(buf, member);
+                buf.put_f64_le(score);
+            }
+        }
+        DbValueType::Stream(stream) => write_stream(buf, stream),
+    }
+}
+
+fn write_length(buf: &mut BytesMut, len: u64) {
+    match len {
+        0..=0x3f => buf.put_u8(len as u8),
+        0x40..=0x3fff => buf.put_u16(0x4000 | len as u16),
+        _ if len <= u32::MAX as u64 => {
+            buf.put_u8(0x80);
+            buf.put_u32(len as u32);
+        }
+        _ => {
+            buf.put_u8(0x81);
+            buf.put_u64(len);
+        }
+    }
+}
+
+fn write_string(buf: &mut BytesMut, value: &[u8]) {
+    write_length(buf, value.len() as u64);
+    buf.put_slice(value);
+}
+
+/// Integers that fit 32 bits get the compact integer encoding, longer ones are written as text
+fn write_integer(buf: &mut BytesMut, value: i64) {
+    let encoded = ENCODED << 6;
+    if let Ok(value) = i8::try_from(value) {
+        buf.put_u8(encoded | ENC_INT8 as u8);
+        buf.put_i8(value);
+    } else if let Ok(value) = i16::try_from(value) {
+        buf.put_u8(encoded | ENC_INT16 as u8);
+        buf.put_i16_le(value);
+    } else if let Ok(value) = i32::try_from(value) {
+        buf.put_u8(encoded | ENC_INT32 as u8);
+        buf.put_i32_le(value);
+    } else {
+        write_string(buf, value.to_string().as_bytes());
+    }
+}
+
+/// Streams are a tree of listpacks keyed by the ID of their first entry, the master entry.
+/// Entries store their ID relative to it and only list their values when their fields are the
+/// same as the master entry's.
+fn write_stream(buf: &mut BytesMut, stream: &Stream) {
+    buf.put_u8(TYPE_STREAM_LISTPACKS);
+    let entries = stream.iter().collect::<Vec<_>>();
+    let nodes = entries.chunks(NODE_MAX_ENTRIES);
+    write_length(buf, nodes.len() as u64);
+    for node in nodes {
+        let master = &node[0];
+        let mut master_id = BytesMut::new();
+        master_id.put_u64(master.stream_id_ms_part as u64);
+        master_id.put_u64(master.stream_id_seq_part as u64);
+        write_string(buf, &master_id);
+        write_string(buf, &stream_listpack(node));
+    }
+    write_length(buf, stream.len() as u64);
+    let (last_ms, last_seq) = stream.last_id();
+    write_length(buf, last_ms as u64);
+    write_length(buf, last_seq as u64);
+    // Consumer groups
+    write_length(buf, 0);
+}
+
+fn stream_listpack(node: &[&StreamDbValueType]) -> Bytes {
+    let master = &node[0];
+    let master_fields = master
+        .fields
+        .iter()
+        .map(|(field, _)| field)
+        .collect::<Vec<_>>();
+    let mut listpack = ListpackWriter::default();
+    listpack.push_int(node.len() as i64);
+    // Deleted entries
+    listpack.push_int(0);
+    listpack.push_int(master_fields.len() as i64);
+    master_fields
+        .iter()
+        .for_each(|field| listpack.push_str(field));
+    // Ends the master entry
+    listpack.push_int(0);
+    for entry in node {
+        let fields = &entry.fields;
+        let same_fields = fields
+            .iter()
+            .map(|(field, _)| field)
+            .eq(master_fields.iter().copied());
+        let ms_diff =
+            (entry.stream_id_ms_part as i64).wrapping_sub(master.stream_id_ms_part as i64);
+        let seq_diff =
+            (entry.stream_id_seq_part as i64).wrapping_sub(master.stream_id_seq_part as i64);
+        listpack.push_int(match same_fields {
+            true => STREAM_ITEM_FLAG_SAMEFIELDS,
+            false => 0,
+        });
+        listpack.push_int(ms_diff);
+        listpack.push_int(seq_diff);
+        let lp_count = if same_fields {
+            fields
+                .iter()
+                .for_each(|(_, value)| listpack.push_str(value));
+            fields.len()
+        } else {
+            listpack.push_int(fields.len() as i64);
+            for (field, value) in fields {
+                listpack.push_str(field);
+                listpack.push_str(value);
+            }
+            fields.len() * 2 + 1
+        };
+        // How many elements to step back over to reach the flags from the end of the entry
+        listpack.push_int(lp_count as i64 + 3);
+    }
+    listpack.finish()
+}
+
+/// Builds a listpack, the flat list Redis packs small collections and stream nodes into. Every
+/// element is followed by its own length so the list can be walked from either end.
+#[derive(Default)]
+struct ListpackWriter {
+    elements: BytesMut,
+    count: usize,
+}
+
+impl ListpackWriter {
+    fn push_int(&mut self, value: i64) {
+        let start = self.elements.len();
+        match value {
+            0..=127 => self.elements.put_u8(value as u8),
+            -4096..=4095 => self.elements.put_u16(0xc000 | (value as u16 & 0x1fff)),
+            _ if i16::try_from(value).is_ok() => {
+                self.elements.put_u8(0xf1);
+                self.elements.put_i16_le(value as i16);
+            }
+            -0x80_0000..=0x7f_ffff => {
+                self.elements.put_u8(0xf2);
+                self.elements.put_slice(&value.to_le_bytes()[..3]);
+            }
+            _ if i32::try_from(value).is_ok() => {
+                self.elements.put_u8(0xf3);
+                self.elements.put_i32_le(value as i32);
+            }
+            _ => {
+                self.elements.put_u8(0xf4);
+                self.elements.put_i64_le(value);
+            }
+        }
+        self.end_element(start);
+    }
+
+    fn push_str(&mut self, value: &[u8]) {
+        let start = self.elements.len();
+        match value.len() {
+            len @ 0..=63 => self.elements.put_u8(0x80 | len as u8),
+            len @ 64..=4095 => self.elements.put_u16(0xe000 | len as u16),
+            len => {
+                self.elements.put_u8(0xf0);
+                self.elements.put_u32_le(len as u32);
+            }
+        }
+        self.elements.put_slice(value);
+        self.end_element(start);
+    }
+
+    /// Appends the back length, the size of the element in 7 bit groups, most significant
+    /// first, every group after the first having its high bit set
+    fn end_element(&mut self, start: usize) {
+        let len = self.elements.len() - start;
+        let groups = backlen_size(len);
+        for group in (0..groups).rev() {
+            let byte = (len >> (7 * group)) as u8 & 0x7f;
+            self.elements.put_u8(match group == groups - 1 {
+                true => byte,
+                false => byte | 0x80,
+            });
+        }
+        self.count += 1;
+    }
+
+    fn finish(self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(self.elements.len() + 7);
+        buf.put_u32_le(self.elements.len() as u32 + 7);
+        // Counts that don't fit are left for readers to find out by walking the list
+        buf.put_u16_le(self.count.min(u16::MAX as usize) as u16);
+        buf.put_slice(&self.elements);
+        buf.put_u8(0xff);
+        buf.freeze()
+    }
+}
+
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// Walks a payload, every read giving `None` once the data runs out
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.buf.len()
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.buf.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.bytes(N)?.try_into().ok()
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.array::<1>()?[0])
+    }
+
+    /// A length, or which special encoding the string that follows uses
+    fn length_or_encoding(&mut self) -> Option<(u64, bool)> {
+        let byte = self.byte()?;
+        let value = match byte >> 6 {
+            0b00 => (byte as u64 & 0x3f, false),
+            0b01 => ((byte as u64 & 0x3f) << 8 | self.byte()? as u64, false),
+            0b10 => match byte {
+                0x80 => (u32::from_be_bytes(self.array()?) as u64, false),
+                0x81 => (u64::from_be_bytes(self.array()?), false),
+                _ => return None,
+            },
+            _ => (byte as u64 & 0x3f, true),
+        };
+        Some(value)
+    }
+
+    fn length(&mut self) -> Option<u64> {
+        match self.length_or_encoding()? {
+            (len, false) => Some(len),
+            (_, true) => None,
+        }
+    }
+
+    fn string(&mut self) -> Option<Bytes> {
+        let value = match self.length_or_encoding()? {
+            (len, false) => Bytes::copy_from_slice(self.bytes(usize::try_from(len).ok()?)?),
+            (ENC_INT8, true) => int_text(self.array().map(i8::from_le_bytes)? as i64),
+            (ENC_INT16, true) => int_text(self.array().map(i16::from_le_bytes)? as i64),
+            (ENC_INT32, true) => int_text(self.array().map(i32::from_le_bytes)? as i64),
+            (ENC_LZF, true) => {
+                let compressed_len = usize::try_from(self.length()?).ok()?;
+                let len = usize::try_from(self.length()?).ok()?;
+                lzf_decompress(self.bytes(compressed_len)?, len)?.into()
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn strings(&mut self, count: u64) -> Option<Vec<Bytes>> {
+        (0..count).map(|_| self.string()).collect()
+    }
+
+    /// Scores of the original zset type are text, with lengths that can't occur standing for
+    /// the values that have no short spelling
+    fn text_score(&mut self) -> Option<f64> {
+        let score = match self.byte()? {
+            253 => f64::NAN,
+            254 => f64::INFINITY,
+            255 => f64::NEG_INFINITY,
+            len => std::str::from_utf8(self.bytes(len as usize)?)
+                .ok()?
+                .parse()
+                .ok()?,
+        };
+        Some(score)
+    }
+}
+
+fn read_value(reader: &mut Reader) -> Option<DbValueType> {
+    let value_type = reader.byte()?;
+    let value = match value_type {
+        TYPE_STRING => string_value(reader.string()?),
+        TYPE_LIST => {
+            let len = reader.length()?;
+            list(reader.strings(len)?)?
+        }
+        TYPE_SET => {
+            let len = reader.length()?;
+            set(reader.strings(len)?)?
+        }
+        TYPE_HASH => {
+            let len = reader.length()?;
+            hash(reader.strings(len.checked_mul(2)?)?)?
+        }
+        TYPE_ZSET | TYPE_ZSET_2 => {
+            let members = (0..reader.length()?)
+                .map(|_| {
+                    let member = reader.string()?;
+                    let score = match value_type {
+                        TYPE_ZSET => reader.text_score()?,
+                        _ => f64::from_le_bytes(reader.array()?),
+                    };
+                    Some((member, score))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            zset(members)?
+        }
+        TYPE_LIST_ZIPLIST => list(ziplist(&reader.string()?)?)?,
+        TYPE_SET_INTSET => set(intset(&reader.string()?)?)?,
+        TYPE_SET_LISTPACK => set(listpack(&reader.string()?)?)?,
+        TYPE_HASH_ZIPLIST => hash(ziplist(&reader.string()?)?)?,
+        TYPE_HASH_LISTPACK => hash(listpack(&reader.string()?)?)?,
+        TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+            let blob = reader.string()?;
+            let elements = match value_type {
+                TYPE_ZSET_ZIPLIST => ziplist(&blob)?,
+                _ => listpack(&blob)?,
+            };
+            if elements.len() % 2 != 0 {
+                return None;
+            }
+            let members = elements
+                .chunks(2)
+                .map(|pair| Some((pair[0].clone(), parse_float(&pair[1])?)))
+                .collect::<Option<Vec<_>>>()?;
+            zset(members)?
+        }
+        TYPE_LIST_QUICKLIST | TYPE_LIST_QUICKLIST_2 => {
+            let mut elements = vec![];
+            for _ in 0..reader.length()? {
+                if value_type == TYPE_LIST_QUICKLIST {
+                    elements.extend(ziplist(&reader.string()?)?);
+                    continue;
+                }
+                let container = reader.length()?;
+                let node = reader.string()?;
+                match container {
+                    QUICKLIST_NODE_PLAIN => elements.push(node),
+                    _ => elements.extend(listpack(&node)?),
+                }
+            }
+            list(elements)?
+        }
+        TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+            read_stream(reader, value_type)?
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn read_stream(reader: &mut Reader, value_type: u8) -> Option<DbValueType> {
+    let mut stream = Stream::default();
+    for _ in 0..reader.length()? {
+        let master_id = reader.string()?;
+        let node = reader.string()?;
+        let master_id: [u8; 16] = master_id.as_ref().try_into().ok()?;
+        let master_ms = u64::from_be_bytes(master_id[..8].try_into().ok()?);
+        let master_seq = u64::from_be_bytes(master_id[8..].try_into().ok()?);
+        read_stream_node(&listpack(&node)?, (master_ms, master_seq), &mut stream)?;
+    }
+    // Entry count, then the last ID handed out
+    reader.length()?;
+    stream.raise_last_id((reader.length()? as u128, reader.length()? as usize));
+    if value_type != TYPE_STREAM_LISTPACKS {
+        // First ID, largest deleted ID and how many entries were ever added
+        for _ in 0..5 {
+            reader.length()?;
+        }
+    }
+    // Consumer groups are not supported
+    if reader.length()? != 0 {
+        return None;
+    }
+    Some(DbValueType::Stream(stream))
+}
+
+fn read_stream_node(
+    elements: &[Bytes],
+    (master_ms, master_seq): (u64, u64),
+    stream: &mut Stream,
+) -> Option<()> {
+    let mut elements = elements.iter();
+    // Live and deleted entry counts
+    elements.next()?;
+    elements.next()?;
+    let master_field_count = parse_i64(elements.next()?)?;
+    let master_fields = (0..master_field_count)
+        .map(|_| elements.next().cloned())
+        .collect::<Option<Vec<_>>>()?;
+    // Master entry terminator
+    elements.next()?;
+    while let Some(flags) = elements.next() {
+        let flags = parse_i64(flags)?;
+        let ms = master_ms.wrapping_add(parse_i64(elements.next()?)? as u64);
+        let seq = master_seq.wrapping_add(parse_i64(elements.next()?)? as u64);
+        let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Some((field.clone(), elements.next()?.clone())))
+                .collect::<Option<Vec<_>>>()?
+        } else {
+            let field_count = parse_i64(elements.next()?)?;
+            (0..field_count)
+                .map(|_| Some((elements.next()?.clone(), elements.next()?.clone())))
+                .collect::<Option<Vec<_>>>()?
+        };
+        // Back count of the entry
+        elements.next()?;
+        if flags & STREAM_ITEM_FLAG_DELETED != 0 {
+            continue;
+        }
+        let entry = StreamDbValueType {
+            stream_id_ms_part: ms as u128,
+            stream_id_seq_part: seq as usize,
+            fields,
+        };
+        // Entries have to come in ID order
+        if entry.id() <= stream.last_id() {
+            return None;
+        }
+        stream.push(entry);
+    }
+    Some(())
+}
+
+/// Empty collections and duplicate members make for a bad payload, like they do in Redis
+fn list(elements: Vec<Bytes>) -> Option<DbValueType> {
+    (!elements.is_empty()).then(|| DbValueType::List(VecDeque::from(elements)))
+}
+
+fn set(members: Vec<Bytes>) -> Option<DbValueType> {
+    let mut set = ScanSet::default();
+    for member in members {
+        if !set.insert(member) {
+            return None;
+        }
+    }
+    (!set.is_empty()).then_some(DbValueType::Set(set))
+}
+
+fn hash(elements: Vec<Bytes>) -> Option<DbValueType> {
+    if elements.len() % 2 != 0 {
+        return None;
+    }
+    let mut hash = ScanMap::default();
+    let mut elements = elements.into_iter();
+    while let (Some(field), Some(value)) = (elements.next(), elements.next()) {
+        if hash.insert(field, value).is_some() {
+            return None;
+        }
+    }
+    (!hash.is_empty()).then_some(DbValueType::Hash(hash))
+}
+
+fn zset(members: Vec<(Bytes, f64)>) -> Option<DbValueType> {
+    let mut zset = SortedSet::default();
+    for (member, score) in members {
+        if score.is_nan() || !zset.insert(member, score) {
+            return None;
+        }
+    }
+    (!zset.is_empty()).then_some(DbValueType::SortedSet(zset))
+}
+
+fn int_text(value: i64) -> Bytes {
+    Bytes::from(value.to_string())
+}
+
+/// Elements of a listpack, integers rendered as text
+fn listpack(blob: &[u8]) -> Option<Vec<Bytes>> {
+    let mut reader = Reader::new(blob);
+    // Total size and element count
+    reader.bytes(6)?;
+    let mut elements = vec![];
+    loop {
+        let start = reader.pos;
+        let element = match reader.byte()? {
+            0xff => break,
+            byte if byte & 0x80 == 0 => int_text(byte as i64),
+            byte if byte & 0xc0 == 0x80 => {
+                Bytes::copy_from_slice(reader.bytes(byte as usize & 0x3f)?)
+            }
+            byte if byte & 0xe0 == 0xc0 => {
+                let value = (byte as i64 & 0x1f) << 8 | reader.byte()? as i64;
+                // 13 bit two's complement
+                int_text(if value >= 1 << 12 {
+                    value - (1 << 13)
+                } else {
+                    value
+                })
+            }
+            byte if byte & 0xf0 == 0xe0 => {
+                let len = (byte as usize & 0x0f) << 8 | reader.byte()? as usize;
+                Bytes::copy_from_slice(reader.bytes(len)?)
+            }
+            0xf0 => {
+                let len = u32::from_le_bytes(reader.array()?) as usize;
+                Bytes::copy_from_slice(reader.bytes(len)?)
+            }
+            0xf1 => int_text(i16::from_le_bytes(reader.array()?) as i64),
+            0xf2 => {
+                let [a, b, c] = reader.array()?;
+                int_text((i32::from_le_bytes([0, a, b, c]) >> 8) as i64)
+            }
+            0xf3 => int_text(i32::from_le_bytes(reader.array()?) as i64),
+            0xf4 => int_text(i64::from_le_bytes(reader.array()?)),
+            _ => return None,
+        };
+        reader.bytes(backlen_size(reader.pos - start))?;
+        elements.push(element);
+    }
+    Some(elements)
+}
+
+/// Elements of a ziplist, the listpack's predecessor, integers rendered as text
+fn ziplist(blob: &[u8]) -> Option<Vec<Bytes>> {
+    let mut reader = Reader::new(blob);
+    // Total size, offset of the last entry and entry count
+    reader.bytes(10)?;
+    let mut elements = vec![];
+    loop {
+        // Length of the previous entry, where a byte that can't start one ends the list
+        match reader.byte()? {
+            0xff => break,
+            0xfe => {
+                reader.bytes(4)?;
+            }
+            _ => {}
+        }
+        let byte = reader.byte()?;
+        let element = match byte >> 6 {
+            0b00 => Bytes::copy_from_slice(reader.bytes(byte as usize & 0x3f)?),
+            0b01 => {
+                let len = (byte as usize & 0x3f) << 8 | reader.byte()? as usize;
+                Bytes::copy_from_slice(reader.bytes(len)?)
+            }
+            0b10 => {
+                let len = u32::from_be_bytes(reader.array()?) as usize;
+                Bytes::copy_from_slice(reader.bytes(len)?)
+            }
+            _ => int_text(match byte {
+                0xc0 => i16::from_le_bytes(reader.array()?) as i64,
+                0xd0 => i32::from_le_bytes(reader.array()?) as i64,
+                0xe0 => i64::from_le_bytes(reader.array()?),
+                0xf0 => {
+                    let [a, b, c] = reader.array()?;
+                    (i32::from_le_bytes([0, a, b, c]) >> 8) as i64
+                }
+                0xfe => i8::from_le_bytes(reader.array()?) as i64,
+                // Small values live in the encoding byte, offset by one
+                0xf1..=0xfd => (byte & 0x0f) as i64 - 1,
+                _ => return None,
+            }),
+        };
+        elements.push(element);
+    }
+    Some(elements)
+}
+
+/// Members of an intset, a sorted array of integers all of the same width
+fn intset(blob: &[u8]) -> Option<Vec<Bytes>> {
+    let mut reader = Reader::new(blob);
+    let width = u32::from_le_bytes(reader.array()?);
+    let len = u32::from_le_bytes(reader.array()?);
+    (0..len)
+        .map(|_| {
+            let value = match width {
+                2 => i16::from_le_bytes(reader.array()?) as i64,
+                4 => i32::from_le_bytes(reader.array()?) as i64,
+                8 => i64::from_le_bytes(reader.array()?),
+                _ => return None,
+            };
+            Some(int_text(value))
+        })
+        .collect()
+}
+
+/// LZF, which Redis compresses long strings with. Every byte either starts a run of literals or
+/// a back reference into what was decompressed so far.
+pub(crate) fn lzf_decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(len.min(input.len().saturating_mul(64)));
+    let mut input = input.iter().copied();
+    while let Some(ctrl) = input.next() {
+        let ctrl = ctrl as usize;
+        if ctrl < 32 {
+            for _ in 0..=ctrl {
+                output.push(input.next()?);
+            }
+        } else {
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += input.next()? as usize;
+            }
+            let distance = ((ctrl & 0x1f) << 8) + input.next()? as usize + 1;
+            let start = output.len().checked_sub(distance)?;
+            for i in start..start + run + 2 {
+                output.push(output[i]);
+            }
+        }
+        if output.len() > len {
+            return None;
+        }
+    }
+    (output.len() == len).then_some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The elements of a value in an order that doesn't depend on how it is stored
+    fn contents(value: &DbValueType) -> Vec<String> {
+        let mut contents: Vec<_> = match value {
+            DbValueType::Integer(value) => return vec![format!("int {value}")],
+            DbValueType::String(value) => return vec![format!("string {value:?}")],
+            DbValueType::List(list) => return list.iter().map(|e| format!("{e:?}")).collect(),
+            DbValueType::Set(set) => set.iter().map(|m| format!("{m:?}")).collect(),
+            DbValueType::Hash(hash) => hash.iter().map(|p| format!("{p:?}")).collect(),
+            DbValueType::SortedSet(zset) => zset.iter(false).map(|m| format!("{m:?}")).collect(),
+            DbValueType::Stream(stream) => {
+                let mut entries = stream
+                    .iter()
+                    .map(|entry| format!("{:?} {:?}", entry.id(), entry.fields))
+                    .collect::<Vec<_>>();
+                entries.push(format!("last {:?}", stream.last_id()));
+                return entries;
+            }
+        };
+        contents.sort();
+        contents
+    }
+
+    fn round_trip(value: DbValueType) {
+        let decoded = decode_payload(&encode_payload(&value)).expect("payload decodes");
+        assert_eq!(decoded.type_name(), value.type_name());
+        assert_eq!(contents(&decoded), contents(&value));
+    }
+
+    /// Wraps a value Redis encoded itself in a payload footer
+    fn payload(body: &[u8]) -> Vec<u8> {
+        let mut payload = body.to_vec();
+        payload.extend(11u16.to_le_bytes());
+        let crc = crc64(&payload);
+        payload.extend(crc.to_le_bytes());
+        payload
+    }
+
+    #[test]
+    fn crc64_matches_redis() {
+        // The check value of CRC-64/Jones, which Redis' own tests use
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+        assert_eq!(crc64(b""), 0);
+    }
+
+    #[test]
+    fn dump_of_a_small_integer_matches_redis() {
+        // DUMP after `SET mykey 10`, as given in the Redis documentation
+        let expected = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+        assert_eq!(&encode_payload(&DbValueType::Integer(10))[..], expected);
+        assert!(matches!(
+            decode_payload(expected),
+            Ok(DbValueType::Integer(10))
+        ));
+    }
+
+    #[test]
+    fn strings_and_integers_round_trip() {
+        for value in [0, -1, 127, -129, 40_000, -70_000, i64::MAX, i64::MIN] {
+            round_trip(DbValueType::Integer(value));
+        }
+        for len in [0, 5, 0x40, 0x4000] {
+            round_trip(DbValueType::String(Bytes::from(vec![b'x'; len])));
+        }
+    }
+
+    #[test]
+    fn collections_round_trip() {
+        let elements = (0..300).map(|i| Bytes::from(format!("element:{i}")));
+        round_trip(DbValueType::List(elements.clone().collect()));
+        round_trip(DbValueType::Set(elements.clone().collect()));
+        round_trip(DbValueType::Hash(
+            elements.clone().map(|e| (e.clone(), e)).collect(),
+        ));
+        let mut zset = SortedSet::default();
+        for (i, member) in elements.enumerate() {
+            zset.insert(member, i as f64 / 3.0);
+        }
+        zset.insert(Bytes::from("inf"), f64::INFINITY);
+        round_trip(DbValueType::SortedSet(zset));
+    }
+
+    #[test]
+    fn streams_round_trip() {
+        let mut stream = Stream::default();
+        for i in 0..(NODE_MAX_ENTRIES * 2 + 3) {
+            let fields = match i % 3 {
+                0 => vec![(Bytes::from("field"), Bytes::from(i.to_string()))],
+                1 => vec![(Bytes::from("other"), Bytes::from("value"))],
+                _ => vec![
+                    (Bytes::from("field"), Bytes::from("-12")),
+                    (Bytes::from("more"), Bytes::from(vec![b'y'; 100])),
+                ],
+            };
+            stream.push(StreamDbValueType {
+                stream_id_ms_part: 1_700_000_000_000 + i as u128,
+                stream_id_seq_part: i % 2,
+                fields,
+            });
+        }
+        stream.raise_last_id((u64::MAX as u128, 7));
+        round_trip(DbValueType::Stream(stream));
+    }
+
+    #[test]
+    fn compact_encodings_are_restored() {
+        // intset of 16 bit integers 1, 2 and 300
+        let mut body = vec![TYPE_SET_INTSET, 14];
+        body.extend([2, 0, 0, 0, 3, 0, 0, 0, 1, 0, 2, 0, 0x2c, 0x01]);
+        let set = decode_payload(&payload(&body)).expect("intset decodes");
+        assert_eq!(contents(&set), ["b\"1\"", "b\"2\"", "b\"300\""]);
+
+        // listpack holding the hash field `f` with the value 5
+        let listpack = [13, 0, 0, 0, 2, 0, 0x81, b'f', 2, 5, 1, 0xff];
+        let mut body = vec![TYPE_HASH_LISTPACK, listpack.len() as u8];
+        body.extend(listpack);
+        let hash = decode_payload(&payload(&body)).expect("listpack decodes");
+        assert_eq!(contents(&hash), ["(b\"f\", b\"5\")"]);
+    }
+
+    #[test]
+    fn damaged_payloads_are_refused() {
+        let mut damaged = encode_payload(&DbValueType::String(Bytes::from("value"))).to_vec();
+        let last = damaged.len() - 1;
+        damaged[last] ^= 1;
+        let error = decode_payload(&damaged).unwrap_err().to_string();
+        assert_eq!(error, "ERR DUMP payload version or checksum are wrong");
+        assert!(decode_payload(b"short").is_err());
+
+        // A newer RDB version than we know
+        let mut body = vec![TYPE_STRING, 1, b'x'];
+        body.extend((MAX_RDB_VERSION + 1).to_le_bytes());
+        let crc = crc64(&body);
+        body.extend(crc.to_le_bytes());
+        assert!(decode_payload(&body).is_err());
+
+        // Bytes left over after the value
+        let error = decode_payload(&payload(&[TYPE_STRING, 1, b'x', 0]))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "ERR Bad data format");
+    }
+}
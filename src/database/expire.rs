@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    mem::size_of,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use rand::Rng;
+use tracing::debug;
+
+use super::{now_ms, Database};
+
+/// How often the actor runs an active expiry cycle
+pub(super) const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+/// Keys checked per sampling round
+const KEYS_PER_LOOP: usize = 20;
+/// Another round is run while more than this percentage of the sampled keys had expired
+const ACCEPTABLE_STALE_PERCENT: usize = 10;
+/// A cycle gives up after this long so commands are not held back, a quarter of the interval
+const CYCLE_TIME_LIMIT: Duration = Duration::from_millis(25);
+
+/// Keys kept in a vector so random ones can be picked cheaply, for the expiry cycle and the
+/// eviction to sample from. Entries are not removed when the key is deleted or persisted;
+/// whoever samples them drops them.
+#[derive(Debug, Default)]
+pub(super) struct SampledKeys {
+    keys: Vec<Bytes>,
+    positions: HashMap<Bytes, usize>,
+}
+
+impl SampledKeys {
+    pub(super) fn insert(&mut self, key: &Bytes) {
+        if self.positions.contains_key(key) {
+            return;
+        }
+        self.positions.insert(key.to_owned(), self.keys.len());
+        self.keys.push(key.to_owned());
+    }
+
+    /// Bytes the index itself takes
+    pub(super) fn overhead(&self) -> usize {
+        self.keys.capacity() * size_of::<Bytes>()
+            + self.positions.capacity() * size_of::<(Bytes, usize)>()
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.keys.clear();
+        self.positions.clear();
+    }
+
+    pub(super) fn remove(&mut self, key: &Bytes) {
+        let Some(position) = self.positions.remove(key) else {
+            return;
+        };
+        self.keys.swap_remove(position);
+        if let Some(moved) = self.keys.get(position) {
+            self.positions.insert(moved.to_owned(), position);
+        }
+    }
+
+    pub(super) fn random(&self, rng: &mut impl Rng) -> Option<Bytes> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        Some(self.keys[rng.gen_range(0..self.keys.len())].clone())
+    }
+}
+
+impl Database {
+    /// Runs the expiry of every database in turn, sharing one time budget
+    pub(super) fn _active_expire_cycle(&mut self) {
+        let start = Instant::now();
+        let selected = self.selected;
+        for index in 0..self.keyspaces.len() {
+            self._select(index);
+            self._expire_selected(start);
+            if start.elapsed() >= CYCLE_TIME_LIMIT {
+                break;
+            }
+        }
+        self._select(selected);
+    }
+
+    /// Redis' adaptive expiry: sample a few keys with an expiry and delete the expired ones,
+    /// going again while a good share of the sample had expired and there is time left
+    fn _expire_selected(&mut self, start: Instant) {
+        let mut rng = rand::thread_rng();
+        let mut total_expired = 0;
+        loop {
+            let (mut sampled, mut expired) = (0, 0);
+            let now = now_ms();
+            for _ in 0..KEYS_PER_LOOP {
+                let Some(key) = self.volatile_keys.random(&mut rng) else {
+                    break;
+                };
+                sampled += 1;
+                match self.db.get(&key).and_then(|db_value| db_value.exp_time) {
+                    Some(exp_time) if exp_time <= now => {
+                        if let Some(db_value) = self.db.remove(&key) {
+                            self.memory.release(db_value.size);
+                        }
+                        self.volatile_keys.remove(&key);
+                        expired += 1;
+                    }
+                    Some(_) => {}
+                    // Deleted or persisted since the expiry was set
+                    None => self.volatile_keys.remove(&key),
+                }
+            }
+            total_expired += expired;
+            if sampled == 0
+                || expired * 100 <= sampled * ACCEPTABLE_STALE_PERCENT
+                || start.elapsed() >= CYCLE_TIME_LIMIT
+            {
+                break;
+            }
+        }
+        if total_expired > 0 {
+            debug!(db = self.selected, total_expired, "Active expiry cycle");
+        }
+    }
+}
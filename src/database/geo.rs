@@ -0,0 +1,388 @@
+use bytes::Bytes;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{DbError, GeoMatch, GeoOrigin, GeoQuery, GeoShape, ScoreBound};
+use super::Database;
+
+/// Bits per coordinate in the 52 bit geohash used as the sorted set score
+const STEP: u32 = 26;
+/// Latitude limits of the Web Mercator projection Redis indexes against
+const LAT_MIN: f64 = -85.05112878;
+const LAT_MAX: f64 = 85.05112878;
+const LON_MIN: f64 = -180.0;
+const LON_MAX: f64 = 180.0;
+const EARTH_RADIUS_METERS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+/// Longitude bits sit in the odd positions of an interleaved hash, latitude bits in the even
+const LON_BITS: u64 = 0xaaaaaaaaaaaaaaaa;
+const LAT_BITS: u64 = 0x5555555555555555;
+
+impl Database {
+    pub async fn geosearch(key: &Bytes, query: &GeoQuery) -> anyhow::Result<Vec<GeoMatch>> {
+        Database::request(|emitter| GeoSearch {
+            emitter,
+            key: key.to_owned(),
+            query: query.clone(),
+        })
+        .await
+    }
+
+    pub async fn geosearchstore(
+        destination: &Bytes,
+        source: &Bytes,
+        query: &GeoQuery,
+        store_dist: bool,
+    ) -> anyhow::Result<i64> {
+        Database::request(|emitter| GeoSearchStore {
+            emitter,
+            destination: destination.to_owned(),
+            source: source.to_owned(),
+            query: query.clone(),
+            store_dist,
+        })
+        .await
+    }
+
+    /// Looks up the geohash cells covering the search shape and filters their members by exact
+    /// distance, sorting the matches by distance
+    pub(super) fn _geosearch(
+        &mut self,
+        key: &Bytes,
+        query: &GeoQuery,
+    ) -> Result<Vec<GeoMatch>, DbError> {
+        let Some(zset) = self._get_zset(key)? else {
+            return Ok(vec![]);
+        };
+        let (longitude, latitude) = match &query.origin {
+            GeoOrigin::Coordinates(longitude, latitude) => (*longitude, *latitude),
+            GeoOrigin::Member(member) => match zset.score(member) {
+                Some(score) => decode(score as u64),
+                None => {
+                    return Err(DbError::UnableToPerformAction(
+                        "ERR could not decode requested zset member".to_string(),
+                    ))
+                }
+            },
+        };
+        let limit = query.count.unwrap_or(usize::MAX);
+        let mut matches = vec![];
+        'cells: for (min, max) in search_cells(longitude, latitude, &query.shape) {
+            let members = zset.range_by_score(
+                ScoreBound::Inclusive(min as f64),
+                ScoreBound::Exclusive(max as f64),
+                false,
+            );
+            for (member, score) in members {
+                let hash = score as u64;
+                let (member_longitude, member_latitude) = decode(hash);
+                let Some(distance) = distance_within(
+                    &query.shape,
+                    longitude,
+                    latitude,
+                    member_longitude,
+                    member_latitude,
+                ) else {
+                    continue;
+                };
+                matches.push(GeoMatch {
+                    member: member.to_owned(),
+                    distance,
+                    hash,
+                    longitude: member_longitude,
+                    latitude: member_latitude,
+                });
+                // ANY settles for the first matches found rather than the closest ones
+                if query.any && matches.len() >= limit {
+                    break 'cells;
+                }
+            }
+        }
+        matches.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        if query.descending {
+            matches.reverse();
+        }
+        matches.truncate(limit);
+        Ok(matches)
+    }
+
+    pub(super) fn _geosearchstore(
+        &mut self,
+        destination: &Bytes,
+        source: &Bytes,
+        query: &GeoQuery,
+        store_dist: bool,
+    ) -> Result<i64, DbError> {
+        let matches = self._geosearch(source, query)?;
+        let len = matches.len();
+        let members = matches.into_iter().map(|found| {
+            let score = match store_dist {
+                true => found.distance / query.unit,
+                false => found.hash as f64,
+            };
+            (found.member, score)
+        });
+        self._store_zset(destination, members);
+        Ok(len as i64)
+    }
+}
+
+pub(crate) fn valid_coordinates(longitude: f64, latitude: f64) -> bool {
+    (LON_MIN..=LON_MAX).contains(&longitude) && (LAT_MIN..=LAT_MAX).contains(&latitude)
+}
+
+/// Geohash stored as the sorted set score of a member
+pub(crate) fn encode(longitude: f64, latitude: f64) -> u64 {
+    encode_with(longitude, latitude, STEP, LAT_MIN, LAT_MAX)
+}
+
+/// Center of the geohash cell, which is as close to the original coordinates as the hash allows
+pub(crate) fn decode(hash: u64) -> (f64, f64) {
+    let area = decode_area(hash, STEP);
+    let longitude = ((area.lon_min + area.lon_max) / 2.0).clamp(LON_MIN, LON_MAX);
+    let latitude = ((area.lat_min + area.lat_max) / 2.0).clamp(LAT_MIN, LAT_MAX);
+    (longitude, latitude)
+}
+
+/// Standard 11 character geohash, which uses the full -90..90 latitude range unlike the score
+pub(crate) fn hash_string(hash: u64) -> String {
+    let (longitude, latitude) = decode(hash);
+    let hash = encode_with(longitude, latitude, STEP, -90.0, 90.0);
+    (0..11)
+        .map(|i| {
+            // 52 bits fill ten and a half characters, the last one is padded with zeros
+            let index = match i {
+                10 => 0,
+                _ => (hash >> (52 - (i + 1) * 5)) & 0x1f,
+            };
+            BASE32[index as usize] as char
+        })
+        .collect()
+}
+
+/// Great circle distance in meters
+pub(crate) fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    2.0 * EARTH_RADIUS_METERS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+fn encode_with(longitude: f64, latitude: f64, step: u32, lat_min: f64, lat_max: f64) -> u64 {
+    let scale = (1u64 << step) as f64;
+    let lat_offset = (latitude - lat_min) / (lat_max - lat_min) * scale;
+    let lon_offset = (longitude - LON_MIN) / (LON_MAX - LON_MIN) * scale;
+    interleave(
+        lat_offset.min(scale - 1.0) as u32,
+        lon_offset.min(scale - 1.0) as u32,
+    )
+}
+
+fn interleave(latitude: u32, longitude: u32) -> u64 {
+    (0..32).fold(0, |hash, i| {
+        let lat_bit = (latitude as u64 >> i) & 1;
+        let lon_bit = (longitude as u64 >> i) & 1;
+        hash | lat_bit << (2 * i) | lon_bit << (2 * i + 1)
+    })
+}
+
+fn deinterleave(hash: u64) -> (u32, u32) {
+    (0..32).fold((0, 0), |(latitude, longitude), i| {
+        let lat_bit = ((hash >> (2 * i)) & 1) as u32;
+        let lon_bit = ((hash >> (2 * i + 1)) & 1) as u32;
+        (latitude | lat_bit << i, longitude | lon_bit << i)
+    })
+}
+
+struct Area {
+    lon_min: f64,
+    lon_max: f64,
+    lat_min: f64,
+    lat_max: f64,
+}
+
+fn decode_area(hash: u64, step: u32) -> Area {
+    let (latitude, longitude) = deinterleave(hash);
+    let scale = (1u64 << step) as f64;
+    let lat_span = LAT_MAX - LAT_MIN;
+    let lon_span = LON_MAX - LON_MIN;
+    Area {
+        lon_min: LON_MIN + longitude as f64 / scale * lon_span,
+        lon_max: LON_MIN + (longitude as f64 + 1.0) / scale * lon_span,
+        lat_min: LAT_MIN + latitude as f64 / scale * lat_span,
+        lat_max: LAT_MIN + (latitude as f64 + 1.0) / scale * lat_span,
+    }
+}
+
+/// Moves a hash one cell east (`1`) or west (`-1`)
+fn move_x(hash: u64, step: u32, direction: i8) -> u64 {
+    let x = hash & LON_BITS;
+    let y = hash & LAT_BITS;
+    let zz = LAT_BITS >> (64 - step * 2);
+    let x = match direction {
+        1 => x.wrapping_add(zz + 1),
+        _ => (x | zz).wrapping_sub(zz + 1),
+    };
+    (x & (LON_BITS >> (64 - step * 2))) | y
+}
+
+/// Moves a hash one cell north (`1`) or south (`-1`)
+fn move_y(hash: u64, step: u32, direction: i8) -> u64 {
+    let x = hash & LON_BITS;
+    let y = hash & LAT_BITS;
+    let zz = LON_BITS >> (64 - step * 2);
+    let y = match direction {
+        1 => y.wrapping_add(zz + 1),
+        _ => (y | zz).wrapping_sub(zz + 1),
+    };
+    x | (y & (LAT_BITS >> (64 - step * 2)))
+}
+
+/// Coarsest step whose cells are still small enough that the searched cell and its neighbours
+/// cover a radius of `range` meters
+fn estimate_step(range: f64, latitude: f64) -> u32 {
+    if range == 0.0 {
+        return STEP;
+    }
+    let mut range = range;
+    let mut step: i32 = 1;
+    while range < MERCATOR_MAX {
+        range *= 2.0;
+        step += 1;
+    }
+    step -= 2;
+    // Cells shrink towards the poles, so a coarser step is needed there
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+    step.clamp(1, STEP as i32) as u32
+}
+
+/// `(min_lon, min_lat, max_lon, max_lat)` of the rectangle enclosing the shape
+fn bounding_box(longitude: f64, latitude: f64, shape: &GeoShape) -> (f64, f64, f64, f64) {
+    let (width, height) = match shape {
+        GeoShape::Radius(radius) => (*radius, *radius),
+        GeoShape::Box { width, height } => (width / 2.0, height / 2.0),
+    };
+    let lat_delta = (height / EARTH_RADIUS_METERS).to_degrees();
+    let lon_delta_top =
+        (width / EARTH_RADIUS_METERS / (latitude + lat_delta).to_radians().cos()).to_degrees();
+    let lon_delta_bottom =
+        (width / EARTH_RADIUS_METERS / (latitude - lat_delta).to_radians().cos()).to_degrees();
+    // The edge closer to the equator is the wider one
+    let lon_delta = if latitude < 0.0 {
+        lon_delta_bottom
+    } else {
+        lon_delta_top
+    };
+    (
+        longitude - lon_delta,
+        latitude - lat_delta,
+        longitude + lon_delta,
+        latitude + lat_delta,
+    )
+}
+
+/// Score ranges `[min, max)` of the geohash cells that may hold members inside the shape: the
+/// cell holding the center and whichever of its eight neighbours the shape reaches into
+fn search_cells(longitude: f64, latitude: f64, shape: &GeoShape) -> Vec<(u64, u64)> {
+    let (min_lon, min_lat, max_lon, max_lat) = bounding_box(longitude, latitude, shape);
+    let radius = match shape {
+        GeoShape::Radius(radius) => *radius,
+        GeoShape::Box { width, height } => (width / 2.0).hypot(height / 2.0),
+    };
+    let mut step = estimate_step(radius, latitude);
+    let mut center = encode_with(longitude, latitude, step, LAT_MIN, LAT_MAX);
+    // The estimate can still leave part of the shape outside the neighbours near cell edges
+    let north = decode_area(move_y(center, step, 1), step);
+    let south = decode_area(move_y(center, step, -1), step);
+    let east = decode_area(move_x(center, step, 1), step);
+    let west = decode_area(move_x(center, step, -1), step);
+    if step > 1
+        && (north.lat_max < max_lat
+            || south.lat_min > min_lat
+            || east.lon_max < max_lon
+            || west.lon_min > min_lon)
+    {
+        step -= 1;
+        center = encode_with(longitude, latitude, step, LAT_MIN, LAT_MAX);
+    }
+    let area = decode_area(center, step);
+    // Neighbours in a direction are only needed when the shape pokes out of the center cell
+    let (reach_north, reach_south, reach_east, reach_west) = match step >= 2 {
+        true => (
+            area.lat_max < max_lat,
+            area.lat_min > min_lat,
+            area.lon_max < max_lon,
+            area.lon_min > min_lon,
+        ),
+        false => (true, true, true, true),
+    };
+    let mut cells = vec![center];
+    for (dx, dy, reached) in [
+        (0, 1, reach_north),
+        (0, -1, reach_south),
+        (1, 0, reach_east),
+        (-1, 0, reach_west),
+        (1, 1, reach_north && reach_east),
+        (-1, 1, reach_north && reach_west),
+        (1, -1, reach_south && reach_east),
+        (-1, -1, reach_south && reach_west),
+    ] {
+        if !reached {
+            continue;
+        }
+        let cell = match dx {
+            0 => center,
+            _ => move_x(center, step, dx),
+        };
+        let cell = match dy {
+            0 => cell,
+            _ => move_y(cell, step, dy),
+        };
+        cells.push(cell);
+    }
+    let shift = 52 - step * 2;
+    let mut ranges = cells
+        .into_iter()
+        .map(|cell| (cell << shift, (cell + 1) << shift))
+        .collect::<Vec<_>>();
+    // Coarse steps wrap around the globe and can make neighbours coincide
+    ranges.sort_unstable();
+    ranges.dedup();
+    ranges
+}
+
+/// Distance to the member when it lies inside the shape centered on the given coordinates
+fn distance_within(
+    shape: &GeoShape,
+    longitude: f64,
+    latitude: f64,
+    member_longitude: f64,
+    member_latitude: f64,
+) -> Option<f64> {
+    if let GeoShape::Box { width, height } = shape {
+        let lat_distance =
+            EARTH_RADIUS_METERS * (member_latitude.to_radians() - latitude.to_radians()).abs();
+        if lat_distance > height / 2.0 {
+            return None;
+        }
+        let lon_distance = distance(
+            member_longitude,
+            member_latitude,
+            longitude,
+            member_latitude,
+        );
+        if lon_distance > width / 2.0 {
+            return None;
+        }
+    }
+    let distance = distance(longitude, latitude, member_longitude, member_latitude);
+    match shape {
+        GeoShape::Radius(radius) if distance > *radius => None,
+        _ => Some(distance),
+    }
+}
@@ -0,0 +1,359 @@
+use bytes::Bytes;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{DatabaseValue, DbError, DbValueType, ScanOptions};
+use super::scan::ScanMap;
+use super::{format_float, parse_float, parse_i64, random_sample, Database};
+
+impl Database {
+    pub async fn hset(key: &Bytes, fields: &[(Bytes, Bytes)]) -> anyhow::Result<i64> {
+        Database::request(|emitter| HSet {
+            emitter,
+            key: key.to_owned(),
+            fields: fields.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn hsetnx(key: &Bytes, field: &Bytes, value: &Bytes) -> anyhow::Result<bool> {
+        Database::request(|emitter| HSetNx {
+            emitter,
+            key: key.to_owned(),
+            field: field.to_owned(),
+            value: value.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn hget(key: &Bytes, field: &Bytes) -> anyhow::Result<Option<Bytes>> {
+        Database::request(|emitter| HGet {
+            emitter,
+            key: key.to_owned(),
+            field: field.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn hmget(key: &Bytes, fields: &[Bytes]) -> anyhow::Result<Vec<Option<Bytes>>> {
+        Database::request(|emitter| HMGet {
+            emitter,
+            key: key.to_owned(),
+            fields: fields.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn hgetall(key: &Bytes) -> anyhow::Result<Vec<(Bytes, Bytes)>> {
+        Database::request(|emitter| HGetAll {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn hdel(key: &Bytes, fields: &[Bytes]) -> anyhow::Result<i64> {
+        Database::request(|emitter| HDel {
+            emitter,
+            key: key.to_owned(),
+            fields: fields.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn hexists(key: &Bytes, field: &Bytes) -> anyhow::Result<bool> {
+        Database::request(|emitter| HExists {
+            emitter,
+            key: key.to_owned(),
+            field: field.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn hlen(key: &Bytes) -> anyhow::Result<i64> {
+        Database::request(|emitter| HLen {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn hkeys(key: &Bytes) -> anyhow::Result<Vec<Bytes>> {
+        Database::request(|emitter| HKeys {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn hvals(key: &Bytes) -> anyhow::Result<Vec<Bytes>> {
+        Database::request(|emitter| HVals {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn hincrby(key: &Bytes, field: &Bytes, increment: i64) -> anyhow::Result<i64> {
+        Database::request(|emitter| HIncrBy {
+            emitter,
+            key: key.to_owned(),
+            field: field.to_owned(),
+            increment,
+        })
+        .await
+    }
+
+    pub async fn hincrbyfloat(key: &Bytes, field: &Bytes, increment: f64) -> anyhow::Result<Bytes> {
+        Database::request(|emitter| HIncrByFloat {
+            emitter,
+            key: key.to_owned(),
+            field: field.to_owned(),
+            increment,
+        })
+        .await
+    }
+
+    pub async fn hstrlen(key: &Bytes, field: &Bytes) -> anyhow::Result<i64> {
+        Database::request(|emitter| HStrLen {
+            emitter,
+            key: key.to_owned(),
+            field: field.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn hrandfield(
+        key: &Bytes,
+        count: Option<i64>,
+    ) -> anyhow::Result<Vec<(Bytes, Bytes)>> {
+        Database::request(|emitter| HRandField {
+            emitter,
+            key: key.to_owned(),
+            count,
+        })
+        .await
+    }
+
+    pub async fn hscan(
+        key: &Bytes,
+        cursor: u64,
+        options: &ScanOptions,
+    ) -> anyhow::Result<(u64, Vec<(Bytes, Bytes)>)> {
+        Database::request(|emitter| HScan {
+            emitter,
+            key: key.to_owned(),
+            cursor,
+            options: options.to_owned(),
+        })
+        .await
+    }
+
+    pub(super) fn _hset(
+        &mut self,
+        key: &Bytes,
+        fields: Vec<(Bytes, Bytes)>,
+    ) -> Result<i64, DbError> {
+        let hash = self._get_or_create_hash(key)?;
+        let mut added = 0;
+        for (field, value) in fields {
+            if hash.insert(field, value).is_none() {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    pub(super) fn _hsetnx(
+        &mut self,
+        key: &Bytes,
+        field: &Bytes,
+        value: &Bytes,
+    ) -> Result<bool, DbError> {
+        let hash = self._get_or_create_hash(key)?;
+        if hash.contains_key(field) {
+            return Ok(false);
+        }
+        hash.insert(field.to_owned(), value.to_owned());
+        Ok(true)
+    }
+
+    pub(super) fn _hget(&mut self, key: &Bytes, field: &Bytes) -> Result<Option<Bytes>, DbError> {
+        let value = self
+            ._get_hash(key)?
+            .and_then(|hash| hash.get(field).cloned());
+        Ok(value)
+    }
+
+    pub(super) fn _hmget(
+        &mut self,
+        key: &Bytes,
+        fields: &[Bytes],
+    ) -> Result<Vec<Option<Bytes>>, DbError> {
+        let hash = self._get_hash(key)?;
+        let values = fields
+            .iter()
+            .map(|field| hash.as_ref().and_then(|hash| hash.get(field).cloned()))
+            .collect();
+        Ok(values)
+    }
+
+    pub(super) fn _hgetall(&mut self, key: &Bytes) -> Result<Vec<(Bytes, Bytes)>, DbError> {
+        let pairs = match self._get_hash(key)? {
+            None => vec![],
+            Some(hash) => hash.iter().map(|(f, v)| (f.clone(), v.clone())).collect(),
+        };
+        Ok(pairs)
+    }
+
+    pub(super) fn _hdel(&mut self, key: &Bytes, fields: &[Bytes]) -> Result<i64, DbError> {
+        let Some(hash) = self._get_hash(key)? else {
+            return Ok(0);
+        };
+        let removed = fields
+            .iter()
+            .filter(|field| hash.remove(*field).is_some())
+            .count();
+        if hash.is_empty() {
+            self.db.remove(key);
+        }
+        Ok(removed as i64)
+    }
+
+    pub(super) fn _hexists(&mut self, key: &Bytes, field: &Bytes) -> Result<bool, DbError> {
+        let exists = self
+            ._get_hash(key)?
+            .is_some_and(|hash| hash.contains_key(field));
+        Ok(exists)
+    }
+
+    pub(super) fn _hlen(&mut self, key: &Bytes) -> Result<i64, DbError> {
+        let len = self._get_hash(key)?.map(|hash| hash.len()).unwrap_or(0);
+        Ok(len as i64)
+    }
+
+    pub(super) fn _hkeys(&mut self, key: &Bytes) -> Result<Vec<Bytes>, DbError> {
+        let keys = match self._get_hash(key)? {
+            None => vec![],
+            Some(hash) => hash.keys().cloned().collect(),
+        };
+        Ok(keys)
+    }
+
+    pub(super) fn _hvals(&mut self, key: &Bytes) -> Result<Vec<Bytes>, DbError> {
+        let values = match self._get_hash(key)? {
+            None => vec![],
+            Some(hash) => hash.values().cloned().collect(),
+        };
+        Ok(values)
+    }
+
+    pub(super) fn _hincrby(
+        &mut self,
+        key: &Bytes,
+        field: &Bytes,
+        increment: i64,
+    ) -> Result<i64, DbError> {
+        let current = match self._hget(key, field)? {
+            None => 0,
+            Some(value) => parse_i64(&value).ok_or_else(|| {
+                DbError::UnableToPerformAction("ERR hash value is not an integer".to_string())
+            })?,
+        };
+        let Some(value) = current.checked_add(increment) else {
+            return Err(DbError::UnableToPerformAction(
+                "ERR increment or decrement would overflow".to_string(),
+            ));
+        };
+        let hash = self._get_or_create_hash(key)?;
+        hash.insert(field.to_owned(), Bytes::from(value.to_string()));
+        Ok(value)
+    }
+
+    pub(super) fn _hincrbyfloat(
+        &mut self,
+        key: &Bytes,
+        field: &Bytes,
+        increment: f64,
+    ) -> Result<Bytes, DbError> {
+        let current = match self._hget(key, field)? {
+            None => 0.0,
+            Some(value) => parse_float(&value).ok_or_else(|| {
+                DbError::UnableToPerformAction("ERR hash value is not a float".to_string())
+            })?,
+        };
+        let value = current + increment;
+        if !value.is_finite() {
+            return Err(DbError::UnableToPerformAction(
+                "ERR increment would produce NaN or Infinity".to_string(),
+            ));
+        }
+        let value = Bytes::from(format_float(value));
+        let hash = self._get_or_create_hash(key)?;
+        hash.insert(field.to_owned(), value.clone());
+        Ok(value)
+    }
+
+    pub(super) fn _hstrlen(&mut self, key: &Bytes, field: &Bytes) -> Result<i64, DbError> {
+        let len = self
+            ._get_hash(key)?
+            .and_then(|hash| hash.get(field).map(|value| value.len()))
+            .unwrap_or(0);
+        Ok(len as i64)
+    }
+
+    pub(super) fn _hrandfield(
+        &mut self,
+        key: &Bytes,
+        count: Option<i64>,
+    ) -> Result<Vec<(Bytes, Bytes)>, DbError> {
+        let Some(hash) = self._get_hash(key)? else {
+            return Ok(vec![]);
+        };
+        let pairs = random_sample(hash.iter(), count)
+            .into_iter()
+            .map(|(f, v)| (f.clone(), v.clone()))
+            .collect();
+        Ok(pairs)
+    }
+
+    pub(super) fn _hscan(
+        &mut self,
+        key: &Bytes,
+        cursor: u64,
+        options: &ScanOptions,
+    ) -> Result<(u64, Vec<(Bytes, Bytes)>), DbError> {
+        let Some(hash) = self._get_hash(key)? else {
+            return Ok((0, vec![]));
+        };
+        let (cursor, fields) = hash.scan(cursor, options.count);
+        let pairs = fields
+            .into_iter()
+            .filter(|field| options.matches(field))
+            .map(|field| (field.clone(), hash[field].clone()))
+            .collect();
+        Ok((cursor, pairs))
+    }
+
+    fn _get_hash(&mut self, key: &Bytes) -> Result<Option<&mut ScanMap<Bytes>>, DbError> {
+        self._lookup_key(key);
+        match self.db.get_mut(key) {
+            None => Ok(None),
+            Some(DatabaseValue {
+                value: DbValueType::Hash(hash),
+                ..
+            }) => Ok(Some(hash)),
+            Some(_) => Err(DbError::WrongType),
+        }
+    }
+
+    fn _get_or_create_hash(&mut self, key: &Bytes) -> Result<&mut ScanMap<Bytes>, DbError> {
+        self._lookup_key(key);
+        let db_value = self.db.get_or_insert_with(key, || {
+            DatabaseValue::new(DbValueType::Hash(ScanMap::default()), None)
+        });
+        match &mut db_value.value {
+            DbValueType::Hash(hash) => Ok(hash),
+            _ => Err(DbError::WrongType),
+        }
+    }
+}
@@ -0,0 +1,533 @@
+use bytes::Bytes;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{DbError, DbValueType};
+use super::Database;
+
+/// Bits of the hash used to pick a register
+const P: u32 = 14;
+const REGISTERS: usize = 1 << P;
+/// Bits of the hash left over to count leading zeros in
+const Q: u32 = 64 - P;
+const REGISTER_BITS: usize = 6;
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = (REGISTERS * REGISTER_BITS).div_ceil(8);
+const MAGIC: &[u8] = b"HYLL";
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+/// Largest register value a sparse VAL opcode can hold
+const SPARSE_VAL_MAX: u8 = 32;
+/// Sparse encodings grow past this size only by switching to the dense one, like Redis's
+/// default `hll-sparse-max-bytes`
+const SPARSE_MAX_BYTES: usize = 3000;
+/// Set in the last byte of the cached cardinality when it is stale
+const CACHE_STALE: u8 = 0x80;
+const HASH_SEED: u64 = 0xadc83b19;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+impl Database {
+    pub async fn pfadd(key: &Bytes, elements: &[Bytes]) -> anyhow::Result<i64> {
+        Database::request(|emitter| PfAdd {
+            emitter,
+            key: key.to_owned(),
+            elements: elements.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn pfcount(keys: &[Bytes]) -> anyhow::Result<i64> {
+        Database::request(|emitter| PfCount {
+            emitter,
+            keys: keys.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn pfmerge(destination: &Bytes, keys: &[Bytes]) -> anyhow::Result<()> {
+        Database::request(|emitter| PfMerge {
+            emitter,
+            destination: destination.to_owned(),
+            keys: keys.to_vec(),
+        })
+        .await
+    }
+
+    pub(super) fn _pfadd(&mut self, key: &Bytes, elements: &[Bytes]) -> Result<i64, DbError> {
+        let (mut hll, created) = match self._get_hll(key)? {
+            Some(hll) => (hll, false),
+            None => (HyperLogLog::default(), true),
+        };
+        let mut changed = created;
+        for element in elements {
+            changed |= hll.add(element);
+        }
+        if changed {
+            self._store_string(key, hll.into_bytes());
+        }
+        Ok(changed as i64)
+    }
+
+    /// A single key answers from, and refreshes, its cached cardinality. Several keys are
+    /// counted through a throwaway union of their registers.
+    pub(super) fn _pfcount(&mut self, keys: &[Bytes]) -> Result<i64, DbError> {
+        if let [key] = keys {
+            let Some(mut hll) = self._get_hll(key)? else {
+                return Ok(0);
+            };
+            if let Some(count) = hll.cached {
+                return Ok(count as i64);
+            }
+            let count = hll.count();
+            self._store_string(key, hll.into_bytes());
+            return Ok(count as i64);
+        }
+        let mut union = HyperLogLog::default();
+        for key in keys {
+            if let Some(hll) = self._get_hll(key)? {
+                union.merge(&hll);
+            }
+        }
+        Ok(union.count() as i64)
+    }
+
+    pub(super) fn _pfmerge(&mut self, destination: &Bytes, keys: &[Bytes]) -> Result<(), DbError> {
+        let mut merged = self._get_hll(destination)?.unwrap_or_default();
+        for key in keys {
+            if let Some(hll) = self._get_hll(key)? {
+                merged.merge(&hll);
+            }
+        }
+        self._store_string(destination, merged.into_bytes());
+        Ok(())
+    }
+
+    fn _get_hll(&mut self, key: &Bytes) -> Result<Option<HyperLogLog>, DbError> {
+        self._lookup_key(key);
+        match self.db.get(key).map(|v| &v.value) {
+            None => Ok(None),
+            Some(DbValueType::String(bytes)) => HyperLogLog::from_bytes(bytes).map(Some),
+            Some(DbValueType::Integer(_)) => Err(not_a_hyperloglog()),
+            Some(_) => Err(DbError::WrongType),
+        }
+    }
+}
+
+fn not_a_hyperloglog() -> DbError {
+    DbError::UnableToPerformAction(
+        "WRONGTYPE Key is not a valid HyperLogLog string value.".to_string(),
+    )
+}
+
+fn corrupted() -> DbError {
+    DbError::UnableToPerformAction("INVALIDOBJ Corrupted HLL object detected".to_string())
+}
+
+/// HyperLogLog registers decoded from, and encoded back to, the same string layout Redis uses:
+/// a 16 byte header holding `HYLL`, the encoding and a cached cardinality, followed by either
+/// the sparse opcodes or 16384 packed 6 bit registers.
+struct HyperLogLog {
+    registers: Vec<u8>,
+    dense: bool,
+    cached: Option<u64>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog {
+            registers: vec![0; REGISTERS],
+            dense: false,
+            cached: Some(0),
+        }
+    }
+}
+
+impl HyperLogLog {
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DbError> {
+        if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(not_a_hyperloglog());
+        }
+        let data = &bytes[HEADER_LEN..];
+        let registers = match bytes[4] {
+            DENSE if data.len() == DENSE_LEN => decode_dense(data),
+            SPARSE => decode_sparse(data).ok_or_else(corrupted)?,
+            _ => return Err(not_a_hyperloglog()),
+        };
+        let cache: [u8; 8] = bytes[8..HEADER_LEN].try_into().expect("header is 16 bytes");
+        Ok(HyperLogLog {
+            registers,
+            dense: bytes[4] == DENSE,
+            cached: (cache[7] & CACHE_STALE == 0).then(|| u64::from_le_bytes(cache)),
+        })
+    }
+
+    /// Encodes the registers, switching to the dense encoding once the sparse one no longer
+    /// fits. A dense value never goes back to sparse.
+    fn into_bytes(self) -> Bytes {
+        let sparse = match self.dense {
+            true => None,
+            false => encode_sparse(&self.registers)
+                .filter(|data| HEADER_LEN + data.len() <= SPARSE_MAX_BYTES),
+        };
+        let (encoding, data) = match sparse {
+            Some(data) => (SPARSE, data),
+            None => (DENSE, encode_dense(&self.registers)),
+        };
+        let cache = match self.cached {
+            Some(count) => count.to_le_bytes(),
+            None => {
+                let mut cache = [0; 8];
+                cache[7] = CACHE_STALE;
+                cache
+            }
+        };
+        let mut bytes = Vec::with_capacity(HEADER_LEN + data.len());
+        bytes.extend(MAGIC);
+        bytes.extend([encoding, 0, 0, 0]);
+        bytes.extend(cache);
+        bytes.extend(data);
+        Bytes::from(bytes)
+    }
+
+    /// Returns whether a register changed
+    fn add(&mut self, element: &[u8]) -> bool {
+        let (index, count) = hash_element(element);
+        if count <= self.registers[index] {
+            return false;
+        }
+        self.registers[index] = count;
+        self.cached = None;
+        true
+    }
+
+    fn merge(&mut self, other: &HyperLogLog) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+        self.dense |= other.dense;
+        self.cached = None;
+    }
+
+    /// Estimates the cardinality with the improved estimator Redis uses, which stays accurate
+    /// for small counts without switching to linear counting
+    fn count(&mut self) -> u64 {
+        let mut histogram = [0u32; Q as usize + 2];
+        for register in &self.registers {
+            histogram[*register as usize] += 1;
+        }
+        let m = REGISTERS as f64;
+        let mut z = m * tau((m - histogram[Q as usize + 1] as f64) / m);
+        for count in histogram[1..=Q as usize].iter().rev() {
+            z += *count as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+        let count = (ALPHA_INF * m * m / z).round() as u64;
+        self.cached = Some(count);
+        count
+    }
+}
+
+/// Register index and the position of the first set bit in the rest of the hash
+fn hash_element(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash64a(element, HASH_SEED);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // The sentinel bit caps the count at Q + 1
+    let rest = (hash >> P) | (1 << Q);
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("chunk is 8 bytes"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+/// Registers are packed little endian, six bits each, so one may straddle two bytes
+fn decode_dense(data: &[u8]) -> Vec<u8> {
+    (0..REGISTERS)
+        .map(|i| {
+            let bit = i * REGISTER_BITS;
+            let low = data[bit / 8] as u16;
+            let high = data.get(bit / 8 + 1).copied().unwrap_or(0) as u16;
+            (((low | high << 8) >> (bit % 8)) & 0x3f) as u8
+        })
+        .collect()
+}
+
+fn encode_dense(registers: &[u8]) -> Vec<u8> {
+    let mut data = vec![0; DENSE_LEN];
+    for (i, register) in registers.iter().enumerate() {
+        let bit = i * REGISTER_BITS;
+        let value = (*register as u16) << (bit % 8);
+        data[bit / 8] |= value as u8;
+        if let Some(next) = data.get_mut(bit / 8 + 1) {
+            *next |= (value >> 8) as u8;
+        }
+    }
+    data
+}
+
+/// Sparse opcodes describe runs of registers:
+/// `00xxxxxx` is up to 64 zeros, `01xxxxxx yyyyyyyy` up to 16384 zeros and `1vvvvvxx` up to 4
+/// registers set to a value between 1 and 32
+fn decode_sparse(data: &[u8]) -> Option<Vec<u8>> {
+    let mut registers = vec![0; REGISTERS];
+    let mut index = 0;
+    let mut opcodes = data.iter();
+    while let Some(&opcode) = opcodes.next() {
+        let (value, len) = match opcode >> 6 {
+            0b00 => (0, (opcode & 0x3f) as usize + 1),
+            0b01 => {
+                let low = *opcodes.next()? as usize;
+                (0, (((opcode & 0x3f) as usize) << 8 | low) + 1)
+            }
+            _ => (((opcode >> 2) & 0x1f) + 1, (opcode & 0x03) as usize + 1),
+        };
+        registers.get_mut(index..index + len)?.fill(value);
+        index += len;
+    }
+    (index == REGISTERS).then_some(registers)
+}
+
+/// Returns `None` when a register is too large for the sparse encoding
+fn encode_sparse(registers: &[u8]) -> Option<Vec<u8>> {
+    let mut data = vec![];
+    let mut index = 0;
+    while index < REGISTERS {
+        let value = registers[index];
+        let run = registers[index..]
+            .iter()
+            .take_while(|r| **r == value)
+            .count();
+        index += run;
+        let mut left = run;
+        while left > 0 {
+            let len = match value {
+                0 if left > 64 => {
+                    let len = left.min(REGISTERS);
+                    data.extend([0x40 | ((len - 1) >> 8) as u8, ((len - 1) & 0xff) as u8]);
+                    len
+                }
+                0 => {
+                    data.push((left - 1) as u8);
+                    left
+                }
+                value if value > SPARSE_VAL_MAX => return None,
+                value => {
+                    let len = left.min(4);
+                    data.push(0x80 | (value - 1) << 2 | (len - 1) as u8);
+                    len
+                }
+            };
+            left -= len;
+        }
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    /// What Redis stores for an HLL nothing was added to: the sparse header with a cached
+    /// cardinality of 0 and a single run of 16384 zero registers
+    const EMPTY: &[u8] = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x7f\xff";
+
+    fn random_registers(seed: u64, max: u8) -> Vec<u8> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..REGISTERS)
+            .map(|_| match rng.gen_bool(0.05) {
+                true => rng.gen_range(1..=max),
+                false => 0,
+            })
+            .collect()
+    }
+
+    fn hll(bytes: &[u8]) -> HyperLogLog {
+        HyperLogLog::from_bytes(bytes).expect("valid HLL")
+    }
+
+    #[test]
+    fn empty_hll_has_the_redis_layout() {
+        assert_eq!(HyperLogLog::default().into_bytes(), EMPTY);
+        let mut empty = hll(EMPTY);
+        assert!(!empty.dense);
+        assert_eq!(empty.cached, Some(0));
+        assert_eq!(empty.count(), 0);
+    }
+
+    #[test]
+    fn sparse_and_dense_encodings_round_trip() {
+        for seed in 0..5 {
+            let registers = random_registers(seed, SPARSE_VAL_MAX);
+            let sparse = encode_sparse(&registers).expect("values fit the sparse encoding");
+            assert_eq!(decode_sparse(&sparse), Some(registers.clone()));
+            let registers = random_registers(seed, 63);
+            assert_eq!(decode_dense(&encode_dense(&registers)), registers);
+        }
+    }
+
+    #[test]
+    fn values_survive_a_trip_through_bytes() {
+        let mut sparse = HyperLogLog::default();
+        for element in ["a", "b", "c"] {
+            assert!(sparse.add(element.as_bytes()));
+        }
+        assert!(!sparse.add(b"a"));
+        let bytes = sparse.into_bytes();
+        assert_eq!(bytes[4], SPARSE);
+        // The stale cache is flagged rather than counted
+        assert_eq!(bytes[15], CACHE_STALE);
+        let mut decoded = hll(&bytes);
+        assert_eq!(decoded.cached, None);
+        assert_eq!(decoded.count(), 3);
+        assert_eq!(hll(&decoded.into_bytes()).cached, Some(3));
+    }
+
+    #[test]
+    fn large_registers_switch_to_dense() {
+        let mut registers = vec![0; REGISTERS];
+        registers[100] = SPARSE_VAL_MAX + 1;
+        assert_eq!(encode_sparse(&registers), None);
+        let value = HyperLogLog {
+            registers: registers.clone(),
+            dense: false,
+            cached: None,
+        };
+        let bytes = value.into_bytes();
+        assert_eq!(bytes[4], DENSE);
+        assert_eq!(bytes.len(), HEADER_LEN + DENSE_LEN);
+        assert_eq!(hll(&bytes).registers, registers);
+    }
+
+    #[test]
+    fn many_registers_switch_to_dense_for_good() {
+        let mut value = HyperLogLog::default();
+        let mut switched = None;
+        for i in 0..5000 {
+            value.add(format!("element:{i}").as_bytes());
+            let bytes = value.into_bytes();
+            if bytes[4] == DENSE {
+                switched = Some(i);
+                break;
+            }
+            assert!(bytes.len() <= SPARSE_MAX_BYTES);
+            value = hll(&bytes);
+        }
+        assert!(switched.is_some(), "sparse encoding kept growing");
+        // Dense values stay dense even once they could be sparse again
+        let dense = HyperLogLog {
+            registers: vec![0; REGISTERS],
+            dense: true,
+            cached: Some(0),
+        };
+        let bytes = hll(&dense.into_bytes()).into_bytes();
+        assert_eq!(bytes[4], DENSE);
+    }
+
+    #[test]
+    fn counts_match_redis() {
+        // Four registers set to 3 followed by 16380 zeros, as Redis would encode them
+        let mut bytes = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80".to_vec();
+        bytes.extend([
+            0x80 | 2 << 2 | 3,
+            0x40 | (16379 >> 8) as u8,
+            (16379 & 0xff) as u8,
+        ]);
+        assert_eq!(hll(&bytes).count(), 4);
+        // From the Redis test suite
+        let mut value = HyperLogLog::default();
+        for element in ["1", "2", "3", "4", "5"] {
+            value.add(element.as_bytes());
+        }
+        assert_eq!(value.count(), 5);
+        for element in ["6", "7", "8", "8", "9", "10"] {
+            value.add(element.as_bytes());
+        }
+        assert_eq!(value.count(), 10);
+        // Stays within a few percent of the real count for large sets
+        let mut value = HyperLogLog::default();
+        for i in 0..100_000 {
+            value.add(format!("element:{i}").as_bytes());
+        }
+        let count = value.count();
+        assert!((97_000..=103_000).contains(&count), "{count}");
+    }
+
+    #[test]
+    fn malformed_values_are_refused() {
+        let error = |bytes: &[u8]| HyperLogLog::from_bytes(bytes).err().map(|e| e.to_string());
+        let not_hll = Some(not_a_hyperloglog().to_string());
+        assert_eq!(error(b"HYLL"), not_hll);
+        assert_eq!(error(&[b"XYLL", &EMPTY[4..]].concat()), not_hll);
+        // Dense values of the wrong length and unknown encodings
+        assert_eq!(
+            error(&[&EMPTY[..4], b"\x00", &EMPTY[5..]].concat()),
+            not_hll
+        );
+        assert_eq!(
+            error(&[&EMPTY[..4], b"\x02", &EMPTY[5..]].concat()),
+            not_hll
+        );
+        // Sparse opcodes that run short of or past the registers
+        let corrupted = Some(corrupted().to_string());
+        assert_eq!(error(&EMPTY[..HEADER_LEN + 1]), corrupted);
+        assert_eq!(error(&[EMPTY, b"\x00"].concat()), corrupted);
+    }
+}
@@ -0,0 +1,412 @@
+use bytes::Bytes;
+use rand::seq::IteratorRandom;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{DbError, DbValueType, ExpireOptions, Expiry, ScanOptions};
+use super::{expiry_time, now_ms, Database};
+
+/// Values that take more allocations than this to free are dropped on a blocking thread when
+/// the caller asks for a lazy delete, the same threshold Redis uses
+const LAZYFREE_THRESHOLD: usize = 64;
+
+impl Database {
+    /// DEL, or UNLINK when `lazy` is set
+    pub async fn del(keys: &[Bytes], lazy: bool) -> anyhow::Result<i64> {
+        Database::request(|emitter| Del {
+            emitter,
+            keys: keys.to_vec(),
+            lazy,
+        })
+        .await
+    }
+
+    pub async fn exists(keys: &[Bytes]) -> anyhow::Result<i64> {
+        Database::request(|emitter| Exists {
+            emitter,
+            keys: keys.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn rename(key: &Bytes, new_key: &Bytes, only_new: bool) -> anyhow::Result<bool> {
+        Database::request(|emitter| Rename {
+            emitter,
+            key: key.to_owned(),
+            new_key: new_key.to_owned(),
+            only_new,
+        })
+        .await
+    }
+
+    pub async fn copy(
+        source: &Bytes,
+        destination: &Bytes,
+        db: Option<usize>,
+        replace: bool,
+    ) -> anyhow::Result<bool> {
+        Database::request(|emitter| Copy {
+            emitter,
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            db,
+            replace,
+        })
+        .await
+    }
+
+    pub async fn touch(keys: &[Bytes]) -> anyhow::Result<i64> {
+        Database::request(|emitter| Touch {
+            emitter,
+            keys: keys.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn random_key() -> anyhow::Result<Option<Bytes>> {
+        Database::request(|emitter| RandomKey { emitter }).await
+    }
+
+    pub async fn dbsize() -> anyhow::Result<i64> {
+        Database::request(|emitter| DbSize { emitter }).await
+    }
+
+    /// FLUSHALL when `all` is set, FLUSHDB otherwise, `lazy` being their ASYNC option
+    pub async fn flush(all: bool, lazy: bool) -> anyhow::Result<()> {
+        Database::request(|emitter| Flush { emitter, all, lazy }).await
+    }
+
+    pub async fn move_key(key: &Bytes, db: usize) -> anyhow::Result<bool> {
+        Database::request(|emitter| Move {
+            emitter,
+            key: key.to_owned(),
+            db,
+        })
+        .await
+    }
+
+    pub async fn swapdb(first: usize, second: usize) -> anyhow::Result<()> {
+        Database::request(|emitter| SwapDb {
+            emitter,
+            first,
+            second,
+        })
+        .await
+    }
+
+    /// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT. Returns whether the expiry was changed.
+    pub async fn expire(
+        key: &Bytes,
+        expiry: Expiry,
+        options: ExpireOptions,
+    ) -> anyhow::Result<bool> {
+        Database::request(|emitter| Expire {
+            emitter,
+            key: key.to_owned(),
+            expiry,
+            options,
+        })
+        .await
+    }
+
+    /// Unix time in milliseconds at which `key` expires, `None` for a missing key and
+    /// `Some(None)` for a key without an expiry
+    pub async fn expire_time(key: &Bytes) -> anyhow::Result<Option<Option<u64>>> {
+        Database::request(|emitter| ExpireTime {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn persist(key: &Bytes) -> anyhow::Result<bool> {
+        Database::request(|emitter| Persist {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn scan(cursor: u64, options: &ScanOptions) -> anyhow::Result<(u64, Vec<Bytes>)> {
+        Database::request(|emitter| Scan {
+            emitter,
+            cursor,
+            options: options.to_owned(),
+        })
+        .await
+    }
+
+    pub(super) fn _del(&mut self, keys: &[Bytes], lazy: bool) -> Result<i64, DbError> {
+        let mut removed = vec![];
+        for key in keys {
+            self._lookup_key(key);
+            removed.extend(self.db.remove(key));
+        }
+        let count = removed.len() as i64;
+        if lazy {
+            removed.retain(|db_value| free_effort(&db_value.value) > LAZYFREE_THRESHOLD);
+            if !removed.is_empty() {
+                free_lazily(removed);
+            }
+        }
+        Ok(count)
+    }
+
+    /// Keys given more than once are counted every time
+    pub(super) fn _exists(&mut self, keys: &[Bytes]) -> Result<i64, DbError> {
+        let mut count = 0;
+        for key in keys {
+            self._lookup_key(key);
+            if self.db.contains_key(key) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Moves the value of `key`, expiry included, over to `new_key`. With `only_new` nothing
+    /// happens when `new_key` already exists.
+    pub(super) fn _rename(
+        &mut self,
+        key: &Bytes,
+        new_key: &Bytes,
+        only_new: bool,
+    ) -> Result<bool, DbError> {
+        self._lookup_key(key);
+        self._lookup_key(new_key);
+        if !self.db.contains_key(key) {
+            return Err(DbError::UnableToPerformAction(
+                "ERR no such key".to_string(),
+            ));
+        }
+        if self.db.contains_key(new_key) && (only_new || key == new_key) {
+            return Ok(!only_new);
+        }
+        let db_value = self.db.remove(key).expect("key was just checked");
+        if db_value.exp_time.is_some() {
+            self.volatile_keys.insert(new_key);
+        }
+        self.db.insert(new_key.to_owned(), db_value);
+        Ok(true)
+    }
+
+    /// Copies the value of `source`, expiry included, to `destination` in database `db`. An
+    /// existing `destination` is only overwritten with `replace`.
+    pub(super) fn _copy(
+        &mut self,
+        source: &Bytes,
+        destination: &Bytes,
+        db: Option<usize>,
+        replace: bool,
+    ) -> Result<bool, DbError> {
+        let index = self._check_db_index(db.unwrap_or(self.selected))?;
+        if index == self.selected && source == destination {
+            return Err(DbError::UnableToPerformAction(
+                "ERR source and destination objects are the same".to_string(),
+            ));
+        }
+        self._lookup_key(source);
+        let Some(db_value) = self.db.get(source).cloned() else {
+            return Ok(false);
+        };
+        self._track_key(index, destination);
+        let (db, volatile_keys) = self._keyspace(index);
+        let exists = db
+            .get(destination)
+            .is_some_and(|existing| !existing.is_expired(now_ms()));
+        if exists && !replace {
+            return Ok(false);
+        }
+        if db_value.exp_time.is_some() {
+            volatile_keys.insert(destination);
+        }
+        db.insert(destination.to_owned(), db_value);
+        Ok(true)
+    }
+
+    /// Moves `key`, expiry included, to database `db` unless the key is already there
+    pub(super) fn _move(&mut self, key: &Bytes, db: usize) -> Result<bool, DbError> {
+        let index = self._check_db_index(db)?;
+        if index == self.selected {
+            return Err(DbError::UnableToPerformAction(
+                "ERR source and destination objects are the same".to_string(),
+            ));
+        }
+        self._lookup_key(key);
+        if !self.db.contains_key(key) {
+            return Ok(false);
+        }
+        let (db, _) = self._keyspace(index);
+        if db
+            .get(key)
+            .is_some_and(|existing| !existing.is_expired(now_ms()))
+        {
+            return Ok(false);
+        }
+        self._track_key(index, key);
+        let db_value = self.db.remove(key).expect("key was just checked");
+        let (db, volatile_keys) = self._keyspace(index);
+        if db_value.exp_time.is_some() {
+            volatile_keys.insert(key);
+        }
+        db.insert(key.to_owned(), db_value);
+        Ok(true)
+    }
+
+    /// Swaps the contents of two databases. Clients blocked on either of them get served from
+    /// the keys they see now.
+    pub(super) fn _swapdb(&mut self, first: usize, second: usize) -> Result<(), DbError> {
+        let first = self._check_db_index(first)?;
+        let second = self._check_db_index(second)?;
+        self._swap_selected();
+        self.keyspaces.swap(first, second);
+        self._swap_selected();
+        self.memory.swapped(first, second);
+        let selected = self.selected;
+        for index in [first, second] {
+            self._select(index);
+            self._serve_all_list_waiters();
+        }
+        self._select(selected);
+        Ok(())
+    }
+
+    fn _check_db_index(&self, index: usize) -> Result<usize, DbError> {
+        if index >= self.keyspaces.len() {
+            return Err(DbError::UnableToPerformAction(
+                "ERR DB index is out of range".to_string(),
+            ));
+        }
+        Ok(index)
+    }
+
+    pub(super) fn _touch(&mut self, keys: &[Bytes]) -> Result<i64, DbError> {
+        self._exists(keys)
+    }
+
+    /// Expired keys that get picked are dropped and another key is tried
+    pub(super) fn _random_key(&mut self) -> Result<Option<Bytes>, DbError> {
+        let mut rng = rand::thread_rng();
+        while let Some(key) = self.db.keys().choose(&mut rng).cloned() {
+            self._lookup_key(&key);
+            if self.db.contains_key(&key) {
+                return Ok(Some(key));
+            }
+        }
+        Ok(None)
+    }
+
+    pub(super) fn _dbsize(&mut self) -> Result<i64, DbError> {
+        Ok(self.db.len() as i64)
+    }
+
+    pub(super) fn _flush(&mut self, all: bool, lazy: bool) -> Result<(), DbError> {
+        let mut flushed = vec![std::mem::take(&mut self.db)];
+        self.volatile_keys.clear();
+        self.memory.flushed(self.selected, &flushed[0]);
+        if all {
+            for (index, keyspace) in self.keyspaces.iter_mut().enumerate() {
+                let db = std::mem::take(&mut keyspace.db);
+                keyspace.volatile_keys.clear();
+                self.memory.flushed(index, &db);
+                flushed.push(db);
+            }
+        }
+        if lazy {
+            free_lazily(flushed);
+        }
+        Ok(())
+    }
+
+    pub(super) fn _scan(
+        &mut self,
+        cursor: u64,
+        options: &ScanOptions,
+    ) -> Result<(u64, Vec<Bytes>), DbError> {
+        let now = now_ms();
+        let (cursor, batch) = self.db.scan(cursor, options.count);
+        let keys = batch
+            .into_iter()
+            .filter(|key| {
+                let db_value = &self.db[*key];
+                let value_type = db_value.value.type_name();
+                !db_value.is_expired(now)
+                    && options.matches(key)
+                    && options
+                        .value_type
+                        .as_ref()
+                        .map_or(true, |wanted| wanted.eq_ignore_ascii_case(value_type))
+            })
+            .cloned()
+            .collect();
+        Ok((cursor, keys))
+    }
+
+    /// An expiry that is already in the past deletes the key right away
+    pub(super) fn _expire(
+        &mut self,
+        key: &Bytes,
+        expiry: Expiry,
+        options: ExpireOptions,
+    ) -> Result<bool, DbError> {
+        self._lookup_key(key);
+        let Some(db_value) = self.db.get_mut(key) else {
+            return Ok(false);
+        };
+        let exp_time = expiry_time(expiry);
+        // No expiry sorts as infinitely far away
+        let (current, new) = (
+            db_value.exp_time.unwrap_or(u64::MAX),
+            exp_time.unwrap_or(u64::MAX),
+        );
+        let skip = (options.nx && db_value.exp_time.is_some())
+            || (options.xx && db_value.exp_time.is_none())
+            || (options.gt && new <= current)
+            || (options.lt && new >= current);
+        if skip {
+            return Ok(false);
+        }
+        match exp_time {
+            Some(exp_time) if exp_time <= now_ms() => {
+                self.db.remove(key);
+            }
+            Some(_) => {
+                db_value.exp_time = exp_time;
+                self.volatile_keys.insert(key);
+            }
+            None => db_value.exp_time = None,
+        }
+        Ok(true)
+    }
+
+    pub(super) fn _expire_time(&mut self, key: &Bytes) -> Result<Option<Option<u64>>, DbError> {
+        self._lookup_key(key);
+        Ok(self.db.get(key).map(|db_value| db_value.exp_time))
+    }
+
+    pub(super) fn _persist(&mut self, key: &Bytes) -> Result<bool, DbError> {
+        self._lookup_key(key);
+        let persisted = self
+            .db
+            .get_mut(key)
+            .and_then(|db_value| db_value.exp_time.take())
+            .is_some();
+        Ok(persisted)
+    }
+}
+
+/// Roughly how many allocations dropping the value takes
+fn free_effort(value: &DbValueType) -> usize {
+    match value {
+        DbValueType::Integer(_) | DbValueType::String(_) => 1,
+        DbValueType::Stream(stream) => stream.len(),
+        DbValueType::Hash(hash) => hash.len(),
+        DbValueType::List(list) => list.len(),
+        DbValueType::Set(set) => set.len(),
+        DbValueType::SortedSet(zset) => zset.len(),
+    }
+}
+
+/// Drops the values on a blocking thread so the actor can go on answering commands
+fn free_lazily(values: impl Send + 'static) {
+    tokio::task::spawn_blocking(move || drop(values));
+}
@@ -0,0 +1,597 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bytes::Bytes;
+use tokio::sync::oneshot;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{
+    BlockingListOp, BlockingPopResult, DatabaseValue, DbError, DbValueType, ListEnd,
+};
+use super::Database;
+
+/// A client parked by BLPOP/BRPOP/BLMOVE/BLMPOP until one of its keys receives a push
+#[derive(Debug)]
+pub(super) struct ListWaiter {
+    /// Database the keys live in
+    db: usize,
+    keys: Vec<Bytes>,
+    op: BlockingListOp,
+    emitter: oneshot::Sender<BlockingPopResult>,
+}
+
+impl Database {
+    pub async fn push(
+        key: &Bytes,
+        elements: &[Bytes],
+        end: ListEnd,
+        only_if_exists: bool,
+    ) -> anyhow::Result<i64> {
+        Database::request(|emitter| Push {
+            emitter,
+            key: key.to_owned(),
+            elements: elements.to_vec(),
+            end,
+            only_if_exists,
+        })
+        .await
+    }
+
+    pub async fn pop(
+        key: &Bytes,
+        end: ListEnd,
+        count: usize,
+    ) -> anyhow::Result<Option<Vec<Bytes>>> {
+        Database::request(|emitter| Pop {
+            emitter,
+            key: key.to_owned(),
+            end,
+            count,
+        })
+        .await
+    }
+
+    pub async fn lrange(key: &Bytes, start: i64, stop: i64) -> anyhow::Result<Vec<Bytes>> {
+        Database::request(|emitter| LRange {
+            emitter,
+            key: key.to_owned(),
+            start,
+            stop,
+        })
+        .await
+    }
+
+    pub async fn lindex(key: &Bytes, index: i64) -> anyhow::Result<Option<Bytes>> {
+        Database::request(|emitter| LIndex {
+            emitter,
+            key: key.to_owned(),
+            index,
+        })
+        .await
+    }
+
+    pub async fn llen(key: &Bytes) -> anyhow::Result<i64> {
+        Database::request(|emitter| LLen {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn lset(key: &Bytes, index: i64, element: &Bytes) -> anyhow::Result<()> {
+        Database::request(|emitter| LSet {
+            emitter,
+            key: key.to_owned(),
+            index,
+            element: element.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn linsert(
+        key: &Bytes,
+        before: bool,
+        pivot: &Bytes,
+        element: &Bytes,
+    ) -> anyhow::Result<i64> {
+        Database::request(|emitter| LInsert {
+            emitter,
+            key: key.to_owned(),
+            before,
+            pivot: pivot.to_owned(),
+            element: element.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn lrem(key: &Bytes, count: i64, element: &Bytes) -> anyhow::Result<i64> {
+        Database::request(|emitter| LRem {
+            emitter,
+            key: key.to_owned(),
+            count,
+            element: element.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn ltrim(key: &Bytes, start: i64, stop: i64) -> anyhow::Result<()> {
+        Database::request(|emitter| LTrim {
+            emitter,
+            key: key.to_owned(),
+            start,
+            stop,
+        })
+        .await
+    }
+
+    pub async fn lpos(
+        key: &Bytes,
+        element: &Bytes,
+        rank: i64,
+        count: usize,
+        max_len: usize,
+    ) -> anyhow::Result<Vec<i64>> {
+        Database::request(|emitter| LPos {
+            emitter,
+            key: key.to_owned(),
+            element: element.to_owned(),
+            rank,
+            count,
+            max_len,
+        })
+        .await
+    }
+
+    pub async fn lmove(
+        source: &Bytes,
+        destination: &Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    ) -> anyhow::Result<Option<Bytes>> {
+        Database::request(|emitter| LMove {
+            emitter,
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            from,
+            to,
+        })
+        .await
+    }
+
+    /// Pops from the first non-empty list in `keys`, waiting up to `timeout` (forever when `None`)
+    /// for a push if they are all empty. Returns the key that was served and the popped elements.
+    pub async fn blocking_pop(
+        keys: &[Bytes],
+        op: BlockingListOp,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Option<(Bytes, Vec<Bytes>)>> {
+        let (emitter, mut listener) = oneshot::channel::<BlockingPopResult>();
+        // A zero timeout has already run out, so the actor answers without parking us
+        let block = timeout != Some(Duration::ZERO);
+        Database::emit(BlockingPop {
+            emitter,
+            keys: keys.to_vec(),
+            op,
+            block,
+        })
+        .await?;
+        let Some(timeout) = timeout.filter(|_| block) else {
+            return Ok(listener.await??);
+        };
+        tokio::select! {
+            result = &mut listener => Ok(result??),
+            _ = tokio::time::sleep(timeout) => {
+                // Close first so the actor can't serve us after we gave up, then pick up
+                // anything it managed to send in the meantime
+                listener.close();
+                match listener.try_recv() {
+                    Ok(result) => Ok(result?),
+                    Err(_) => Ok(None),
+                }
+            }
+        }
+    }
+
+    pub(super) fn _push(
+        &mut self,
+        key: &Bytes,
+        elements: Vec<Bytes>,
+        end: ListEnd,
+        only_if_exists: bool,
+    ) -> Result<i64, DbError> {
+        let list = match only_if_exists {
+            true => match self._get_list(key)? {
+                None => return Ok(0),
+                Some(list) => list,
+            },
+            false => self._get_or_create_list(key)?,
+        };
+        for element in elements {
+            match end {
+                ListEnd::Left => list.push_front(element),
+                ListEnd::Right => list.push_back(element),
+            }
+        }
+        Ok(list.len() as i64)
+    }
+
+    pub(super) fn _pop(
+        &mut self,
+        key: &Bytes,
+        end: ListEnd,
+        count: usize,
+    ) -> Result<Option<Vec<Bytes>>, DbError> {
+        let Some(list) = self._get_list(key)? else {
+            return Ok(None);
+        };
+        let count = count.min(list.len());
+        let popped = match end {
+            ListEnd::Left => list.drain(..count).collect(),
+            ListEnd::Right => (0..count).filter_map(|_| list.pop_back()).collect(),
+        };
+        self._remove_if_empty_list(key);
+        Ok(Some(popped))
+    }
+
+    pub(super) fn _lrange(
+        &mut self,
+        key: &Bytes,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Bytes>, DbError> {
+        let Some(list) = self._get_list(key)? else {
+            return Ok(vec![]);
+        };
+        let range = match normalize_range(start, stop, list.len()) {
+            None => vec![],
+            Some((start, stop)) => list.range(start..=stop).cloned().collect(),
+        };
+        Ok(range)
+    }
+
+    pub(super) fn _lindex(&mut self, key: &Bytes, index: i64) -> Result<Option<Bytes>, DbError> {
+        let value = self._get_list(key)?.and_then(|list| {
+            normalize_index(index, list.len()).and_then(|index| list.get(index).cloned())
+        });
+        Ok(value)
+    }
+
+    pub(super) fn _llen(&mut self, key: &Bytes) -> Result<i64, DbError> {
+        let len = self._get_list(key)?.map(|list| list.len()).unwrap_or(0);
+        Ok(len as i64)
+    }
+
+    pub(super) fn _lset(&mut self, key: &Bytes, index: i64, element: Bytes) -> Result<(), DbError> {
+        let Some(list) = self._get_list(key)? else {
+            return Err(DbError::UnableToPerformAction(
+                "ERR no such key".to_string(),
+            ));
+        };
+        let Some(index) = normalize_index(index, list.len()) else {
+            return Err(DbError::UnableToPerformAction(
+                "ERR index out of range".to_string(),
+            ));
+        };
+        list[index] = element;
+        Ok(())
+    }
+
+    pub(super) fn _linsert(
+        &mut self,
+        key: &Bytes,
+        before: bool,
+        pivot: &Bytes,
+        element: Bytes,
+    ) -> Result<i64, DbError> {
+        let Some(list) = self._get_list(key)? else {
+            return Ok(0);
+        };
+        let Some(position) = list.iter().position(|item| item == pivot) else {
+            return Ok(-1);
+        };
+        let position = if before { position } else { position + 1 };
+        list.insert(position, element);
+        Ok(list.len() as i64)
+    }
+
+    pub(super) fn _lrem(
+        &mut self,
+        key: &Bytes,
+        count: i64,
+        element: &Bytes,
+    ) -> Result<i64, DbError> {
+        let Some(list) = self._get_list(key)? else {
+            return Ok(0);
+        };
+        let limit = match count {
+            0 => usize::MAX,
+            count => count.unsigned_abs() as usize,
+        };
+        let mut removed = 0;
+        if count >= 0 {
+            let mut index = 0;
+            while index < list.len() && removed < limit {
+                if list[index] == element {
+                    list.remove(index);
+                    removed += 1;
+                } else {
+                    index += 1;
+                }
+            }
+        } else {
+            let mut index = list.len();
+            while index > 0 && removed < limit {
+                index -= 1;
+                if list[index] == element {
+                    list.remove(index);
+                    removed += 1;
+                }
+            }
+        }
+        self._remove_if_empty_list(key);
+        Ok(removed as i64)
+    }
+
+    pub(super) fn _ltrim(&mut self, key: &Bytes, start: i64, stop: i64) -> Result<(), DbError> {
+        let Some(list) = self._get_list(key)? else {
+            return Ok(());
+        };
+        match normalize_range(start, stop, list.len()) {
+            None => list.clear(),
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            }
+        }
+        self._remove_if_empty_list(key);
+        Ok(())
+    }
+
+    pub(super) fn _lpos(
+        &mut self,
+        key: &Bytes,
+        element: &Bytes,
+        rank: i64,
+        count: usize,
+        max_len: usize,
+    ) -> Result<Vec<i64>, DbError> {
+        let Some(list) = self._get_list(key)? else {
+            return Ok(vec![]);
+        };
+        // max_len and count of 0 mean "no limit"
+        let max_len = if max_len == 0 { list.len() } else { max_len };
+        let count = if count == 0 { usize::MAX } else { count };
+        let skip = (rank.unsigned_abs() - 1) as usize;
+        let indexes: Box<dyn Iterator<Item = usize>> = match rank > 0 {
+            true => Box::new(0..list.len()),
+            false => Box::new((0..list.len()).rev()),
+        };
+        let positions = indexes
+            .take(max_len)
+            .filter(|index| list[*index] == element)
+            .skip(skip)
+            .take(count)
+            .map(|index| index as i64)
+            .collect();
+        Ok(positions)
+    }
+
+    pub(super) fn _lmove(
+        &mut self,
+        source: &Bytes,
+        destination: &Bytes,
+        from: ListEnd,
+        to: ListEnd,
+    ) -> Result<Option<Bytes>, DbError> {
+        if self._get_list(source)?.is_none() {
+            return Ok(None);
+        }
+        // Validate destination type before popping so a WRONGTYPE leaves source untouched
+        self._get_list(destination)?;
+        let Some(element) = self._pop(source, from, 1)?.and_then(|mut v| v.pop()) else {
+            return Ok(None);
+        };
+        self._push(destination, vec![element.clone()], to, false)?;
+        Ok(Some(element))
+    }
+
+    pub(super) fn _block_on_lists(
+        &mut self,
+        keys: Vec<Bytes>,
+        op: BlockingListOp,
+        emitter: oneshot::Sender<BlockingPopResult>,
+        block: bool,
+    ) {
+        let waiter = ListWaiter {
+            db: self.selected,
+            keys: keys.clone(),
+            op,
+            emitter,
+        };
+        let mut touched = VecDeque::new();
+        if let Some(waiter) = self._serve_list_waiter(waiter, &keys, &mut touched) {
+            if !block {
+                let _ = waiter.emitter.send(Ok(None));
+                return;
+            }
+            // Clients that timed out or disconnected have dropped their listener
+            self.list_waiters
+                .retain(|waiter| !waiter.emitter.is_closed());
+            self.list_waiters.push_back(waiter);
+        }
+        while let Some(key) = touched.pop_front() {
+            self._serve_list_waiters_on(&key, &mut touched);
+        }
+    }
+
+    /// Hands elements pushed to `key` to the clients blocked on it, oldest first
+    pub(super) fn _serve_list_waiters(&mut self, key: &Bytes) {
+        let mut touched = VecDeque::from([key.to_owned()]);
+        while let Some(key) = touched.pop_front() {
+            self._serve_list_waiters_on(&key, &mut touched);
+        }
+    }
+
+    /// Hands elements that landed on `key` in database `index` to the clients blocked on it
+    pub(super) fn _serve_list_waiters_in(&mut self, index: usize, key: &Bytes) {
+        let selected = self.selected;
+        self._select(index);
+        self._serve_list_waiters(key);
+        self._select(selected);
+    }
+
+    /// Serves the clients blocked on the selected database from whatever their keys hold now,
+    /// for when a whole database changes at once
+    pub(super) fn _serve_all_list_waiters(&mut self) {
+        let keys = self
+            .list_waiters
+            .iter()
+            .filter(|waiter| waiter.db == self.selected)
+            .flat_map(|waiter| waiter.keys.clone())
+            .collect::<Vec<_>>();
+        for key in keys {
+            self._serve_list_waiters(&key);
+        }
+    }
+
+    fn _serve_list_waiters_on(&mut self, key: &Bytes, touched: &mut VecDeque<Bytes>) {
+        let waiters = std::mem::take(&mut self.list_waiters);
+        for waiter in waiters {
+            if waiter.db != self.selected || !waiter.keys.contains(key) {
+                self.list_waiters.push_back(waiter);
+                continue;
+            }
+            if let Some(waiter) =
+                self._serve_list_waiter(waiter, std::slice::from_ref(key), touched)
+            {
+                self.list_waiters.push_back(waiter);
+            }
+        }
+    }
+
+    /// Serves `waiter` from the first non-empty list in `keys`, handing it back when they are
+    /// all empty. Destinations of BLMOVE are pushed to `touched` so their waiters get served too.
+    fn _serve_list_waiter(
+        &mut self,
+        waiter: ListWaiter,
+        keys: &[Bytes],
+        touched: &mut VecDeque<Bytes>,
+    ) -> Option<ListWaiter> {
+        if waiter.emitter.is_closed() {
+            return None;
+        }
+        for key in keys {
+            match self._get_list(key) {
+                Ok(None) => continue,
+                Ok(Some(_)) => {}
+                Err(e) => {
+                    let _ = waiter.emitter.send(Err(e));
+                    return None;
+                }
+            }
+            let end = match &waiter.op {
+                BlockingListOp::Pop { end, .. } => *end,
+                BlockingListOp::Move {
+                    destination, from, ..
+                } => {
+                    if let Err(e) = self._get_list(destination) {
+                        let _ = waiter.emitter.send(Err(e));
+                        return None;
+                    }
+                    *from
+                }
+            };
+            let count = match &waiter.op {
+                BlockingListOp::Pop { count, .. } => *count,
+                BlockingListOp::Move { .. } => 1,
+            };
+            let elements = self
+                ._pop(key, end, count)
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            if let Err(Ok(Some((_, elements)))) = waiter
+                .emitter
+                .send(Ok(Some((key.clone(), elements.clone()))))
+            {
+                // The client went away between the check above and now, put everything back
+                let _ = self._push(key, elements.into_iter().rev().collect(), end, false);
+                return None;
+            }
+            if let BlockingListOp::Move {
+                destination, to, ..
+            } = waiter.op
+            {
+                let _ = self._push(&destination, elements, to, false);
+                touched.push_back(destination);
+            }
+            return None;
+        }
+        Some(waiter)
+    }
+
+    pub(super) fn _get_list(
+        &mut self,
+        key: &Bytes,
+    ) -> Result<Option<&mut VecDeque<Bytes>>, DbError> {
+        self._lookup_key(key);
+        match self.db.get_mut(key) {
+            None => Ok(None),
+            Some(DatabaseValue {
+                value: DbValueType::List(list),
+                ..
+            }) => Ok(Some(list)),
+            Some(_) => Err(DbError::WrongType),
+        }
+    }
+
+    fn _get_or_create_list(&mut self, key: &Bytes) -> Result<&mut VecDeque<Bytes>, DbError> {
+        self._lookup_key(key);
+        let db_value = self.db.get_or_insert_with(key, || {
+            DatabaseValue::new(DbValueType::List(VecDeque::new()), None)
+        });
+        match &mut db_value.value {
+            DbValueType::List(list) => Ok(list),
+            _ => Err(DbError::WrongType),
+        }
+    }
+
+    fn _remove_if_empty_list(&mut self, key: &Bytes) {
+        if let Some(DatabaseValue {
+            value: DbValueType::List(list),
+            ..
+        }) = self.db.get(key)
+        {
+            if list.is_empty() {
+                self.db.remove(key);
+            }
+        }
+    }
+}
+
+/// Resolves a possibly negative index against a list of `len` items
+fn normalize_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    if index < 0 || index >= len as i64 {
+        return None;
+    }
+    Some(index as usize)
+}
+
+/// Resolves an inclusive `start..=stop` range with Redis semantics, `None` when empty
+pub(super) fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        return None;
+    }
+    Some((start as usize, stop as usize))
+}
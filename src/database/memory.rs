@@ -0,0 +1,450 @@
+//! Memory accounting for maxmemory and the eviction that keeps the keys within it.
+//!
+//! Like in Redis the limit is approximate. Every key is charged an estimate of what it takes,
+//! collections being sized from a few of their elements, and the keys a command looked up are
+//! charged again once it is done. Eviction samples a handful of keys per database and keeps the
+//! best candidates seen so far in a small pool, which picks good keys to evict without having
+//! to look at all of them.
+
+use std::{collections::HashMap, mem::size_of};
+
+use bytes::Bytes;
+use rand::Rng;
+use tracing::debug;
+
+use crate::app_config::{AppConfig, EvictionPolicy};
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{DatabaseValue, DbError, DbValueType, MemoryReport, ObjectInfo};
+use super::scan::ScanMap;
+use super::{expire::SampledKeys, now_ms, Database};
+
+/// Elements looked at to size a collection, as many as MEMORY USAGE samples by default
+const SIZE_SAMPLES: usize = 5;
+/// Eviction candidates kept between samplings
+const EVICTION_POOL_SIZE: usize = 16;
+/// What a key takes besides its name and value: the keyspace entry holding both
+const KEY_OVERHEAD: usize = size_of::<Bytes>() + size_of::<DatabaseValue>();
+/// Counter new keys start from, so they get a chance to be used before being evicted
+pub(super) const LFU_INIT_VAL: u8 = 5;
+/// How much harder the counter gets to increment as it grows, Redis' lfu-log-factor
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// The counter drops by one for every minute without an access, Redis' lfu-decay-time
+const LFU_DECAY_PERIOD_MS: u64 = 60_000;
+
+pub(super) struct Memory {
+    /// Bytes accounted to the keys of every database
+    used: usize,
+    /// Most bytes ever accounted, along with the overhead last time MEMORY STATS looked
+    peak: usize,
+    /// 0 when there is no limit
+    limit: usize,
+    policy: EvictionPolicy,
+    samples: usize,
+    /// Keys the running command looked up, by database, with what they were charged before
+    tracked: HashMap<(usize, Bytes), usize>,
+    /// Every key by database, for the allkeys policies to sample from
+    keys: Vec<SampledKeys>,
+    /// Best keys to evict found so far, the best one last
+    pool: Vec<Candidate>,
+    /// Database the random policies evict from next, so they take turns
+    next_db: usize,
+}
+
+struct Candidate {
+    /// Keys with a higher score are evicted first
+    score: u64,
+    db: usize,
+    key: Bytes,
+}
+
+impl Memory {
+    pub(super) fn new(databases: usize) -> Self {
+        Memory {
+            used: 0,
+            peak: 0,
+            limit: AppConfig::get_maxmemory(),
+            policy: AppConfig::get_maxmemory_policy(),
+            samples: AppConfig::get_maxmemory_samples(),
+            tracked: HashMap::new(),
+            keys: (0..databases).map(|_| SampledKeys::default()).collect(),
+            pool: Vec::with_capacity(EVICTION_POOL_SIZE),
+            next_db: 0,
+        }
+    }
+
+    /// Updates how often and how recently the key was used
+    pub(super) fn record_access(&self, db_value: &mut DatabaseValue, now: u64) {
+        db_value.lfu_counter = lfu_increment(lfu_decay(db_value, now));
+        db_value.last_access = now;
+    }
+
+    /// Stops charging for a key that went away without a command looking it up
+    pub(super) fn release(&mut self, size: usize) {
+        self.used = self.used.saturating_sub(size);
+    }
+
+    /// Forgets the keys of database `index`, which was just emptied of `db`
+    pub(super) fn flushed(&mut self, index: usize, db: &ScanMap<DatabaseValue>) {
+        self.release(db.values().map(|db_value| db_value.size).sum());
+        self.keys[index].clear();
+        self.pool.retain(|candidate| candidate.db != index);
+    }
+
+    /// Whether the eviction samples from every key, which then need to be kept in `keys`
+    fn samples_all_keys(&self) -> bool {
+        self.limit > 0 && self.policy != EvictionPolicy::NoEviction && !self.policy.is_volatile()
+    }
+
+    pub(super) fn swapped(&mut self, first: usize, second: usize) {
+        self.keys.swap(first, second);
+        self.pool.clear();
+    }
+
+    /// Keeps `candidate` in the pool unless the pool is full of better ones
+    fn offer(&mut self, candidate: Candidate) {
+        // A key sampled again replaces its old entry, which has an outdated score
+        self.pool
+            .retain(|pooled| pooled.db != candidate.db || pooled.key != candidate.key);
+        let position = self
+            .pool
+            .partition_point(|pooled| pooled.score <= candidate.score);
+        if self.pool.len() < EVICTION_POOL_SIZE {
+            self.pool.insert(position, candidate);
+        } else if position > 0 {
+            self.pool.remove(0);
+            self.pool.insert(position - 1, candidate);
+        }
+    }
+}
+
+impl Database {
+    /// Evicts keys until they fit in maxmemory, failing when they can't be made to fit
+    pub async fn free_memory() -> anyhow::Result<()> {
+        Database::request(|emitter| FreeMemory { emitter }).await
+    }
+
+    /// Estimated bytes `key` takes, sizing collections from `samples` of their elements, all
+    /// of them for 0
+    pub async fn memory_usage(
+        key: &Bytes,
+        samples: Option<usize>,
+    ) -> anyhow::Result<Option<usize>> {
+        Database::request(|emitter| MemoryUsage {
+            emitter,
+            key: key.to_owned(),
+            samples,
+        })
+        .await
+    }
+
+    pub async fn memory_stats() -> anyhow::Result<MemoryReport> {
+        Database::request(|emitter| MemoryStats { emitter }).await
+    }
+
+    pub async fn object(key: &Bytes) -> anyhow::Result<Option<ObjectInfo>> {
+        Database::request(|emitter| Object {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    /// Remembers what `key` of database `index` is charged before the running command changes
+    /// it, so it gets charged again once the command is done
+    pub(super) fn _track_key(&mut self, index: usize, key: &Bytes) {
+        if self.memory.tracked.contains_key(&(index, key.clone())) {
+            return;
+        }
+        let (db, _) = self._keyspace(index);
+        let size = db.get(key).map_or(0, |db_value| db_value.size);
+        self.memory.tracked.insert((index, key.to_owned()), size);
+    }
+
+    /// Charges the keys the command that just ran looked up at what they take now
+    pub(super) fn _account_memory(&mut self) {
+        if self.memory.tracked.is_empty() {
+            return;
+        }
+        let tracked = std::mem::take(&mut self.memory.tracked);
+        for ((index, key), old_size) in tracked {
+            let (db, _) = self._keyspace(index);
+            let new_size = match db.get_mut(&key) {
+                Some(db_value) => {
+                    db_value.size = key_size(&key, &db_value.value, SIZE_SAMPLES);
+                    db_value.size
+                }
+                None => 0,
+            };
+            if new_size > 0 && self.memory.samples_all_keys() {
+                self.memory.keys[index].insert(&key);
+            }
+            self.memory.used = (self.memory.used + new_size).saturating_sub(old_size);
+        }
+        self.memory.peak = self.memory.peak.max(self.memory.used);
+    }
+
+    /// MEMORY USAGE, which like OBJECT leaves the access time and counter alone
+    pub(super) fn _memory_usage(
+        &mut self,
+        key: &Bytes,
+        samples: Option<usize>,
+    ) -> Result<Option<usize>, DbError> {
+        let samples = samples.unwrap_or(SIZE_SAMPLES);
+        Ok(self
+            ._peek(key)
+            .map(|db_value| key_size(key, &db_value.value, samples)))
+    }
+
+    pub(super) fn _memory_stats(&mut self) -> Result<MemoryReport, DbError> {
+        let mut databases = vec![];
+        let mut keys = 0;
+        for index in 0..self.keyspaces.len() {
+            let (db, volatile_keys) = self._keyspace(index);
+            if db.is_empty() {
+                continue;
+            }
+            keys += db.len();
+            // Each key is charged for its own slot in the table, the spare ones are overhead
+            let main = (db.capacity() - db.len()) * size_of::<(Bytes, DatabaseValue)>()
+                + db.capacity()
+                + db.overhead();
+            databases.push((index, main, volatile_keys.overhead()));
+        }
+        let overhead: usize = databases
+            .iter()
+            .map(|(_, main, expires)| main + expires)
+            .sum();
+        // The overhead is only known here, so this is where it makes it into the peak
+        self.memory.peak = self.memory.peak.max(self.memory.used + overhead);
+        Ok(MemoryReport {
+            peak: self.memory.peak,
+            dataset: self.memory.used,
+            keys,
+            databases,
+        })
+    }
+
+    pub(super) fn _object(&mut self, key: &Bytes) -> Result<Option<ObjectInfo>, DbError> {
+        let now = now_ms();
+        Ok(self._peek(key).map(|db_value| ObjectInfo {
+            encoding: db_value.value.encoding_name(),
+            idle_time: now.saturating_sub(db_value.last_access) / 1000,
+            freq: lfu_decay(db_value, now),
+        }))
+    }
+
+    /// The value of `key` without counting it as an access
+    fn _peek(&self, key: &Bytes) -> Option<&DatabaseValue> {
+        self.db
+            .get(key)
+            .filter(|db_value| !db_value.is_expired(now_ms()))
+    }
+
+    /// Redis' performEvictions: evicts keys the policy picks until the memory is back under
+    /// the limit
+    pub(super) fn _free_memory(&mut self) -> Result<(), DbError> {
+        let mut evicted = 0;
+        while self.memory.limit > 0 && self.memory.used > self.memory.limit {
+            let Some((index, key)) = self._pick_eviction_candidate() else {
+                return Err(DbError::OutOfMemory);
+            };
+            let (db, _) = self._keyspace(index);
+            if let Some(db_value) = db.remove(&key) {
+                self.memory.release(db_value.size);
+            }
+            evicted += 1;
+        }
+        if evicted > 0 {
+            debug!(evicted, used = self.memory.used, "Evicted keys");
+        }
+        Ok(())
+    }
+
+    fn _pick_eviction_candidate(&mut self) -> Option<(usize, Bytes)> {
+        match self.memory.policy {
+            EvictionPolicy::NoEviction => None,
+            EvictionPolicy::AllKeysRandom | EvictionPolicy::VolatileRandom => {
+                self._pick_random_key()
+            }
+            _ => self._pick_pooled_key(),
+        }
+    }
+
+    /// A random key, going through the databases in turn
+    fn _pick_random_key(&mut self) -> Option<(usize, Bytes)> {
+        let mut rng = rand::thread_rng();
+        let databases = self.keyspaces.len();
+        for offset in 0..databases {
+            let index = (self.memory.next_db + offset) % databases;
+            if let Some(key) = self._sample_key(index, &mut rng) {
+                self.memory.next_db = index + 1;
+                return Some((index, key));
+            }
+        }
+        None
+    }
+
+    /// Samples every database into the pool and takes the best candidate that is still there
+    fn _pick_pooled_key(&mut self) -> Option<(usize, Bytes)> {
+        let mut rng = rand::thread_rng();
+        let policy = self.memory.policy;
+        loop {
+            let now = now_ms();
+            let mut sampled = false;
+            for index in 0..self.keyspaces.len() {
+                for _ in 0..self.memory.samples {
+                    let Some(key) = self._sample_key(index, &mut rng) else {
+                        break;
+                    };
+                    sampled = true;
+                    let (db, _) = self._keyspace(index);
+                    let score = eviction_score(policy, &db[&key], now);
+                    self.memory.offer(Candidate {
+                        score,
+                        db: index,
+                        key,
+                    });
+                }
+            }
+            while let Some(candidate) = self.memory.pool.pop() {
+                let (db, _) = self._keyspace(candidate.db);
+                if db
+                    .get(&candidate.key)
+                    .is_some_and(|db_value| is_evictable(policy, db_value))
+                {
+                    return Some((candidate.db, candidate.key));
+                }
+            }
+            // The pool only held keys that were gone, sampling again fills it with live ones
+            if !sampled {
+                return None;
+            }
+        }
+    }
+
+    /// A random key of database `index` the policy may evict. Sampled entries whose key is
+    /// gone are dropped along the way.
+    fn _sample_key(&mut self, index: usize, rng: &mut impl Rng) -> Option<Bytes> {
+        let policy = self.memory.policy;
+        let (db, volatile_keys) = match index == self.selected {
+            true => (&self.db, &mut self.volatile_keys),
+            false => {
+                let keyspace = &mut self.keyspaces[index];
+                (&keyspace.db, &mut keyspace.volatile_keys)
+            }
+        };
+        let sample = match policy.is_volatile() {
+            true => volatile_keys,
+            false => &mut self.memory.keys[index],
+        };
+        loop {
+            let key = sample.random(rng)?;
+            if db
+                .get(&key)
+                .is_some_and(|db_value| is_evictable(policy, db_value))
+            {
+                return Some(key);
+            }
+            sample.remove(&key);
+        }
+    }
+}
+
+fn is_evictable(policy: EvictionPolicy, db_value: &DatabaseValue) -> bool {
+    !policy.is_volatile() || db_value.exp_time.is_some()
+}
+
+fn eviction_score(policy: EvictionPolicy, db_value: &DatabaseValue, now: u64) -> u64 {
+    match policy {
+        EvictionPolicy::AllKeysLfu | EvictionPolicy::VolatileLfu => {
+            (u8::MAX - lfu_decay(db_value, now)) as u64
+        }
+        // Sooner to expire is better
+        EvictionPolicy::VolatileTtl => u64::MAX - db_value.exp_time.unwrap_or(u64::MAX),
+        // Idle for longer is better
+        _ => now.saturating_sub(db_value.last_access),
+    }
+}
+
+/// The LFU counter once it has decayed for the time since the key was last used
+fn lfu_decay(db_value: &DatabaseValue, now: u64) -> u8 {
+    let periods = now.saturating_sub(db_value.last_access) / LFU_DECAY_PERIOD_MS;
+    db_value
+        .lfu_counter
+        .saturating_sub(periods.min(u8::MAX as u64) as u8)
+}
+
+/// Increments the counter with a chance that shrinks as it grows, so that it takes about a
+/// million accesses to saturate
+fn lfu_increment(counter: u8) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    let probability = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+    match rand::thread_rng().gen::<f64>() < probability {
+        true => counter + 1,
+        false => counter,
+    }
+}
+
+/// Rough number of bytes `key` takes. Collections are sized from `samples` of their
+/// elements, or from all of them when `samples` is 0.
+pub(super) fn key_size(key: &Bytes, value: &DbValueType, samples: usize) -> usize {
+    let value_size = match value {
+        DbValueType::Integer(_) => 0,
+        DbValueType::String(value) => value.len(),
+        DbValueType::Stream(stream) => {
+            stream.overhead()
+                + sampled_size(stream.iter(), stream.len(), samples, |entry| {
+                    entry.fields.capacity() * size_of::<(Bytes, Bytes)>()
+                        + entry
+                            .fields
+                            .iter()
+                            .map(|(field, value)| field.len() + value.len())
+                            .sum::<usize>()
+                })
+        }
+        DbValueType::Hash(hash) => {
+            hash.capacity() * size_of::<(Bytes, Bytes)>()
+                + hash.overhead()
+                + sampled_size(hash.iter(), hash.len(), samples, |(field, value)| {
+                    field.len() + value.len()
+                })
+        }
+        DbValueType::List(list) => {
+            list.capacity() * size_of::<Bytes>()
+                + sampled_size(list.iter(), list.len(), samples, |element| element.len())
+        }
+        DbValueType::Set(set) => {
+            set.capacity() * size_of::<Bytes>()
+                + set.overhead()
+                + sampled_size(set.iter(), set.len(), samples, |member| member.len())
+        }
+        DbValueType::SortedSet(zset) => {
+            zset.overhead()
+                + sampled_size(zset.iter(false), zset.len(), samples, |(member, _)| {
+                    member.len()
+                })
+        }
+    };
+    KEY_OVERHEAD + key.len() + value_size
+}
+
+/// Scales what the first `samples` of the `len` items take up to all of them
+fn sampled_size<I: Iterator>(
+    items: I,
+    len: usize,
+    samples: usize,
+    size: impl Fn(I::Item) -> usize,
+) -> usize {
+    let samples = match samples {
+        0 => len,
+        samples => samples.min(len),
+    };
+    if samples == 0 {
+        return 0;
+    }
+    items.take(samples).map(size).sum::<usize>() * len / samples
+}
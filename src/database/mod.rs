@@ -1,35 +1,78 @@
-use crate::fdbg;
-use std::{
-    collections::HashMap,
-    sync::OnceLock,
-    time::{Duration, Instant, SystemTime},
-};
+use crate::{app_config::AppConfig, glob};
+use std::{cell::Cell, collections::VecDeque, future::Future, sync::OnceLock, time::SystemTime};
 
 use self::db_event::DatabaseEvent::*;
-use self::db_event::{DatabaseEvent, DatabaseValue, DbValueType, StreamDbValueType};
-use anyhow::Context;
+use self::db_event::{
+    DatabaseEvent, DatabaseValue, DbValueType, Expiry, SetOptions, StreamDbValueType, StreamId,
+    StreamRange, XAddOptions, MAX_STREAM_ID,
+};
+use self::scan::ScanMap;
+use bytes::Bytes;
 use db_event::DbError;
+use rand::{seq::IteratorRandom, Rng};
 use tokio::sync::{
     mpsc::{self, channel},
     oneshot,
 };
 use tracing::{debug, info};
 
+mod bitmap;
 pub(crate) mod db_event;
+mod dump;
+mod expire;
+pub(crate) mod geo;
+mod hash;
+mod hyperloglog;
+mod keyspace;
+mod list;
+mod memory;
+mod scan;
+mod set;
+mod sort;
+mod sorted_set;
+mod stream;
+mod string;
+mod zset;
 
-pub type DatabaseEventEmitter = mpsc::Sender<DatabaseEvent>;
+pub(crate) use self::dump::lzf_decompress;
+
+/// Events are tagged with the database they run against
+pub type DatabaseEventEmitter = mpsc::Sender<(usize, DatabaseEvent)>;
 
 // Probably shouldn't have this as static, but this makes program a bit easier to write
 // TODO: Find better way? How to not pass this everywhere?
 static LISTENER: OnceLock<DatabaseEventEmitter> = OnceLock::new();
 
+tokio::task_local! {
+    /// Database the current connection has selected. Tasks outside of `Database::scoped` always
+    /// use database 0.
+    static SELECTED_DB: Cell<usize>;
+}
+
 pub struct Database {
-    db: HashMap<String, DatabaseValue>,
+    /// Keys of the selected database
+    db: ScanMap<DatabaseValue>,
+    /// Keys the active expiry cycle samples from, for the selected database
+    volatile_keys: expire::SampledKeys,
+    selected: usize,
+    /// Every database by number. The slot of the selected one is left empty while its keys
+    /// live in `db` and `volatile_keys`.
+    keyspaces: Vec<Keyspace>,
+    /// Clients blocked on list keys, in the order they started waiting
+    list_waiters: VecDeque<list::ListWaiter>,
+    /// What the keys are charged against maxmemory and which ones to evict
+    memory: memory::Memory,
+}
+
+#[derive(Default)]
+struct Keyspace {
+    db: ScanMap<DatabaseValue>,
+    volatile_keys: expire::SampledKeys,
 }
 
 impl Database {
-    pub fn new() -> DatabaseEventEmitter {
-        let (db_event_listener, db_event_receiver) = channel::<DatabaseEvent>(100);
+    pub fn setup() -> DatabaseEventEmitter {
+        let (db_event_listener, db_event_receiver) = channel::<(usize, DatabaseEvent)>(100);
         LISTENER.get_or_init(|| db_event_listener.clone());
         tokio::spawn(async move {
             Database::_setup_db_event_listener(db_event_receiver).await;
@@ -37,116 +80,116 @@ impl Database {
         db_event_listener
     }
 
-    pub async fn set(
-        key: &String,
-        value: &String,
-        flags: &HashMap<String, String>,
-    ) -> anyhow::Result<()> {
-        let set_event = Set {
-            key: key.clone(),
-            value: value.clone(),
-            flags: flags.clone(),
-        };
-        Database::emit(set_event).await
+    /// Runs `f` with its own selected database, starting at 0. Every connection runs in one of
+    /// these so SELECT only affects the client that sent it.
+    pub async fn scoped<F: Future>(f: F) -> F::Output {
+        SELECTED_DB.scope(Cell::new(0), f).await
+    }
+
+    /// Database that commands sent from the current task run against
+    pub fn selected_db() -> usize {
+        SELECTED_DB.try_with(Cell::get).unwrap_or(0)
+    }
+
+    /// Switches the current task over to database `index`. The caller checks that it exists.
+    pub fn select(index: usize) {
+        SELECTED_DB.with(|selected| selected.set(index));
     }
 
-    pub async fn get(key: &String) -> anyhow::Result<Option<DbValueType>> {
-        let (resp_emitter, listener) = oneshot::channel::<Option<DbValueType>>();
-        let kv_cmd = Get {
-            emitter: resp_emitter,
+    /// Returns whether the value was written, along with the previous value when `options.get`
+    /// asks for it
+    pub async fn set(
+        key: &Bytes,
+        value: &Bytes,
+        options: SetOptions,
+    ) -> anyhow::Result<(bool, Option<Bytes>)> {
+        Database::request(|emitter| Set {
+            emitter,
             key: key.to_owned(),
-        };
-        Database::emit(kv_cmd).await?;
-        Ok(listener.await?)
+            value: value.to_owned(),
+            options,
+        })
+        .await
     }
 
-    pub async fn incr(key: &String) -> anyhow::Result<DbValueType> {
-        let (resp_emitter, listener) = oneshot::channel::<Result<DbValueType, String>>();
-        let kv_cmd = Incr {
-            emitter: resp_emitter,
+    pub async fn get(key: &Bytes) -> anyhow::Result<Option<DbValueType>> {
+        Database::request(|emitter| Get {
+            emitter,
             key: key.to_owned(),
-        };
-        Database::emit(kv_cmd).await?;
-        listener.await?.map_err(|s| anyhow::anyhow!(s))
+        })
+        .await
     }
 
-    pub async fn keys(flag: &String) -> anyhow::Result<Vec<String>> {
-        let (emitter, listener) = oneshot::channel::<Vec<String>>();
+    pub async fn keys(flag: &Bytes) -> anyhow::Result<Vec<Bytes>> {
+        let (emitter, listener) = oneshot::channel::<Vec<Bytes>>();
         let keys_event = Keys {
             emitter,
-            flag: flag.clone(),
+            flag: flag.to_owned(),
         };
         Database::emit(keys_event).await?;
         Ok(listener.await?)
     }
 
-    pub async fn get_type(key: &String) -> anyhow::Result<String> {
+    pub async fn get_type(key: &Bytes) -> anyhow::Result<String> {
         let (emitter, listener) = oneshot::channel::<String>();
         let type_event = DatabaseEvent::Type {
             emitter,
-            key: key.clone(),
+            key: key.to_owned(),
         };
         Database::emit(type_event).await?;
         Ok(listener.await?)
     }
 
-    pub async fn get_last_stream_id(stream_key: &String) -> anyhow::Result<String> {
-        let (emitter, listener) = oneshot::channel::<String>();
+    pub async fn get_last_stream_id(stream_key: &Bytes) -> anyhow::Result<StreamId> {
+        let (emitter, listener) = oneshot::channel::<StreamId>();
         let event = DatabaseEvent::_GetLastStreamId {
             emitter,
-            stream_key: stream_key.clone(),
+            stream_key: stream_key.to_owned(),
         };
         Database::emit(event).await?;
         Ok(listener.await?)
     }
 
     pub async fn xadd(
-        stream_key: &String,
-        stream_id: &String,
-        key: &String,
-        value: &String,
-    ) -> anyhow::Result<String, String> {
-        let (emitter, listener) = oneshot::channel::<Result<String, String>>();
+        stream_key: &Bytes,
+        stream_id: &str,
+        fields: &[(Bytes, Bytes)],
+        options: &XAddOptions,
+    ) -> anyhow::Result<Option<String>, String> {
+        let (emitter, listener) = oneshot::channel::<Result<Option<String>, String>>();
         Database::emit(DatabaseEvent::XAdd {
             emitter,
-            stream_key: stream_key.clone(),
-            stream_id: stream_id.clone(),
-            key: key.clone(),
-            value: value.clone(),
+            stream_key: stream_key.to_owned(),
+            stream_id: stream_id.to_owned(),
+            fields: fields.to_vec(),
+            options: options.clone(),
         })
         .await
         .map_err(|e| e.to_string())?;
         listener.await.map_err(|e| e.to_string())?
     }
 
+    /// Entries after the given ID of each stream
     pub async fn xread(
-        filters: &Vec<(String, String)>,
-    ) -> anyhow::Result<Vec<(String, Vec<StreamDbValueType>)>> {
-        let (tx, rx) = oneshot::channel::<Vec<(String, Vec<StreamDbValueType>)>>();
-        Database::emit(DatabaseEvent::XRead {
-            emitter: tx,
-            filters: filters.clone(),
+        filters: &[(Bytes, StreamId)],
+    ) -> anyhow::Result<Vec<(Bytes, Vec<StreamDbValueType>)>> {
+        Database::request(|emitter| XRead {
+            emitter,
+            filters: filters.to_vec(),
         })
         .await
-        .context(fdbg!("Something went wrong when sending read event"))?;
-        let db_value = rx.await.context(fdbg!("Unable to receive"))?;
-        Ok(db_value)
     }
 
     pub async fn xrange(
-        stream_key: &String,
-        start: &String,
-        end: &String,
+        stream_key: &Bytes,
+        range: &StreamRange,
     ) -> anyhow::Result<Vec<StreamDbValueType>> {
-        let (emitter, listener) = oneshot::channel::<Vec<StreamDbValueType>>();
-        Database::emit(DatabaseEvent::XRange {
+        Database::request(|emitter| XRange {
             emitter,
-            stream_key: stream_key.clone(),
-            start: start.clone(),
-            end: end.clone(),
+            stream_key: stream_key.to_owned(),
+            range: range.to_owned(),
         })
-        .await?;
-        Ok(listener.await?)
+        .await
     }
 
     pub async fn was_last_command_set() -> anyhow::Result<bool> {
@@ -155,25 +198,55 @@ impl Database {
         Ok(listener.await?)
     }
 
+    /// Emits the event built by `event` and waits for the actor to answer on its emitter
+    async fn request<T>(
+        event: impl FnOnce(oneshot::Sender<Result<T, DbError>>) -> DatabaseEvent,
+    ) -> anyhow::Result<T> {
+        let (emitter, listener) = oneshot::channel::<Result<T, DbError>>();
+        Database::emit(event(emitter)).await?;
+        Ok(listener.await??)
+    }
+
     pub async fn emit(event: DatabaseEvent) -> anyhow::Result<()> {
         let Some(emitter) = LISTENER.get() else {
             panic!("DatabaseEventEmitter not initialized");
         };
-        emitter.send(event).await?;
+        emitter.send((Database::selected_db(), event)).await?;
         Ok(())
     }
 
-    async fn _setup_db_event_listener(mut receiver: mpsc::Receiver<DatabaseEvent>) {
-        let mut db = Database { db: HashMap::new() };
+    async fn _setup_db_event_listener(mut receiver: mpsc::Receiver<(usize, DatabaseEvent)>) {
+        let databases = AppConfig::get_databases();
+        let mut db = Database {
+            db: ScanMap::default(),
+            volatile_keys: expire::SampledKeys::default(),
+            selected: 0,
+            keyspaces: (0..databases).map(|_| Keyspace::default()).collect(),
+            list_waiters: VecDeque::new(),
+            memory: memory::Memory::new(databases),
+        };
         let mut last_command_was_set = false;
-        while let Some(cmd) = receiver.recv().await {
+        let mut expire_cycle = tokio::time::interval(expire::ACTIVE_EXPIRE_INTERVAL);
+        loop {
+            let (index, cmd) = tokio::select! {
+                cmd = receiver.recv() => match cmd {
+                    Some(cmd) => cmd,
+                    None => break,
+                },
+                _ = expire_cycle.tick() => {
+                    db._active_expire_cycle();
+                    continue;
+                }
+            };
+            db._select(index);
             match cmd {
-                Set { key, value, flags } => {
-                    let value = match value.parse::<i64>() {
-                        Ok(i) => DbValueType::Integer(i),
-                        Err(_) => DbValueType::String(value),
-                    };
-                    db._set(&key, value, Some(&flags));
+                Set {
+                    emitter,
+                    key,
+                    value,
+                    options,
+                } => {
+                    let _ = emitter.send(db._set_with_options(&key, value, options));
                     // TODO: Better way to set this command
                     last_command_was_set = true;
                 }
@@ -184,21 +257,6 @@ impl Database {
                         .expect("Unable to send value back to caller");
                     last_command_was_set = false;
                 }
-                Incr { key, emitter } => {
-                    match db._incr(&key) {
-                        Ok(v) => {
-                            emitter
-                                .send(Ok(v))
-                                .expect("Unable to send value back to caller");
-                            last_command_was_set = false;
-                        }
-                        _ => emitter
-                            .send(Err(
-                                "ERR value is not an integer or out of range".to_string()
-                            ))
-                            .unwrap(),
-                    };
-                }
                 WasLastCommandSet { emitter } => {
                     emitter
                         .send(last_command_was_set)
@@ -206,14 +264,8 @@ impl Database {
                     last_command_was_set = false;
                 }
                 Keys { emitter, flag } => {
-                    tracing::debug!("Getting keys with flag: {}", flag);
-                    if flag != "*" {
-                        emitter
-                            .send(vec![])
-                            .expect("Unable to send keys back to caller");
-                        continue;
-                    }
-                    let keys = db._keys();
+                    tracing::debug!("Getting keys with flag: {:?}", flag);
+                    let keys = db._keys(&flag);
                     emitter
                         .send(keys)
                         .expect("Unable to send keys back to caller");
@@ -230,25 +282,35 @@ impl Database {
                     emitter,
                     stream_key,
                     stream_id,
-                    key,
-                    value,
+                    fields,
+                    options,
                 } => {
-                    let r = db._set_stream(&stream_key, &stream_id, &key, &value);
+                    debug!(?stream_key, ?stream_id, ?fields, "XAdd -- ");
+                    let r = db._set_stream(&stream_key, &stream_id, fields, &options);
                     emitter
                         .send(r)
                         .expect("Unable to send stream key back to caller");
-                    debug!(?stream_key, ?stream_id, ?key, ?value, "XAdd -- ");
                     last_command_was_set = true;
                 }
+                XTrim { emitter, key, trim } => {
+                    let _ = emitter.send(db._xtrim(&key, &trim));
+                    last_command_was_set = true;
+                }
+                XDel { emitter, key, ids } => {
+                    let _ = emitter.send(db._xdel(&key, &ids));
+                    last_command_was_set = true;
+                }
+                XLen { emitter, key } => {
+                    let _ = emitter.send(db._xlen(&key));
+                    last_command_was_set = false;
+                }
                 XRange {
                     emitter,
                     stream_key,
-                    start,
-                    end,
+                    range,
                 } => {
                     last_command_was_set = false;
-                    let value = db._get_stream_range(&stream_key, start, end);
-                    let _ = emitter.send(value);
+                    let _ = emitter.send(db._get_stream_range(&stream_key, &range));
                 }
                 _GetLastStreamId {
                     emitter,
@@ -260,308 +322,1136 @@ impl Database {
                 }
                 XRead { emitter, filters } => {
                     last_command_was_set = false;
-                    let mut result: Vec<(String, Vec<StreamDbValueType>)> = vec![];
-
                     debug!(?filters, "THIS IS ON XREAD");
-
-                    filters.iter().for_each(|(stream_key, stream_id)| {
-                        let (ms, sq) = stream_id.split_once("-").unwrap();
-                        let mut seq = sq.parse::<usize>().unwrap();
-                        seq += 1;
-                        let new_stream_id = format!("{}-{}", ms, seq.to_string());
-                        debug!(
-                            ?stream_key,
-                            ?stream_id,
-                            ?new_stream_id,
-                            "Getting stream range"
-                        );
-                        let value = db._get_stream_range(
-                            stream_key,
-                            new_stream_id.clone(),
-                            "+".to_string(),
-                        );
-                        result.push((stream_key.clone(), value));
-                    });
+                    let result = filters
+                        .into_iter()
+                        .map(|(stream_key, stream_id)| {
+                            // Nothing comes after the largest ID
+                            let Some(start) = next_stream_id(stream_id) else {
+                                return Ok((stream_key, vec![]));
+                            };
+                            let range = StreamRange {
+                                start,
+                                end: MAX_STREAM_ID,
+                                count: None,
+                                rev: false,
+                            };
+                            let value = db._get_stream_range(&stream_key, &range)?;
+                            Ok((stream_key, value))
+                        })
+                        .collect::<Result<Vec<_>, DbError>>();
                     debug!(?result, "XRead -- ");
                     let _ = emitter.send(result);
                 }
-            }
-        }
-    }
-
-    fn _get_stream_range(
-        &self,
-        stream_key: &String,
-        start: String,
-        end: String,
-    ) -> Vec<StreamDbValueType> {
-        let (start_ms, start_sq, end_ms, end_sq) = get_start_end_ms_seq(&start, &end);
-        match self.db.get(stream_key) {
-            None => vec![],
-            Some(value) => {
-                let DbValueType::Stream(stream) = &value.value else {
-                    return vec![];
-                };
-                let value = stream
-                    .iter()
-                    .filter(|value| {
-                        value.stream_id_ms_part >= start_ms && value.stream_id_ms_part <= end_ms
-                    })
-                    .filter(|value| {
-                        value.stream_id_seq_part >= start_sq && value.stream_id_seq_part <= end_sq
-                    })
-                    .cloned()
-                    .collect::<Vec<_>>();
-                value
-            }
-        }
-    }
-
-    fn _keys(&self) -> Vec<String> {
-        let value = self.db.keys().map(|k| k.to_owned()).collect();
-        value
-    }
-
-    fn _set_stream(
-        &mut self,
-        stream_key: &String,
-        stream_id: &String,
-        key: &String,
-        value: &String,
-    ) -> Result<String, String> {
-        info!("Setting stream: {} with value: {}", stream_key, value);
-        let (ms_part, seq_part) = self._get_stream_id(stream_key, stream_id)?;
-        match self.db.get_mut(stream_key) {
-            None => {
-                // Need to refactor so this duplicate code is used only once.
-                self.db.insert(
-                    stream_key.to_owned(),
-                    DatabaseValue {
-                        value: DbValueType::Stream(vec![StreamDbValueType {
-                            stream_id_ms_part: ms_part,
-                            stream_id_seq_part: seq_part,
-                            key: key.to_owned(),
-                            value: value.to_owned(),
-                        }]),
-                        exp_time: None,
-                    },
-                );
-            }
-            Some(db_value) => match db_value.value {
-                DbValueType::Stream(ref mut stream) => {
-                    stream.push(StreamDbValueType {
-                        stream_id_ms_part: ms_part,
-                        stream_id_seq_part: seq_part,
-                        key: key.to_owned(),
-                        value: value.to_owned(),
-                    });
-                }
-                _ => {
-                    self.db.insert(
-                        stream_key.to_owned(),
-                        DatabaseValue {
-                            value: DbValueType::Stream(vec![StreamDbValueType {
-                                stream_id_ms_part: ms_part,
-                                stream_id_seq_part: seq_part,
-                                key: key.to_owned(),
-                                value: value.to_owned(),
-                            }]),
-                            exp_time: None,
-                        },
-                    );
-                }
-            },
-        }
-
-        Ok(format!("{ms_part}-{seq_part}"))
-    }
-
-    fn _set(&mut self, key: &String, value: DbValueType, flags: Option<&HashMap<String, String>>) {
-        info!("Setting key: {key} with value: {value:?}");
-        let exp_time = match flags {
-            None => None,
-            Some(flags) => flags
-                .get("px")
-                .map(|v| Instant::now() + Duration::from_millis(v.parse::<u64>().unwrap())),
-        };
-        let value = value.to_owned();
-        let key = key.to_owned();
-        self.db.insert(key, DatabaseValue { value, exp_time });
-    }
-
-    fn _get_type(&mut self, key: &String) -> &str {
-        let value = self.db.get(key);
-        match value {
-            None => "none",
-            Some(kv) => match kv.value {
-                DbValueType::String(_) => "string",
-                DbValueType::Integer(_) => "integer",
-                DbValueType::Stream(_) => "stream",
-            },
-        }
-    }
-
-    fn _get_latest_stream_id(&mut self, stream_key: &String) -> String {
-        let stream = self
-            .db
-            .get(stream_key)
-            .and_then(|stream_v| match &stream_v.value {
-                DbValueType::Stream(stream) => stream.last(),
-                _ => None,
-            });
-        let stream_id = match stream {
-            None => "0-0".to_string(),
-            Some(stream) => format!("{}-{}", stream.stream_id_ms_part, stream.stream_id_seq_part),
-        };
-        stream_id
-    }
-    fn _incr(&mut self, key: &String) -> Result<DbValueType, DbError> {
-        let value = self._get(key);
-        let Some(value) = value else {
-            self.db.insert(
-                key.to_owned(),
-                DatabaseValue {
-                    value: DbValueType::Integer(1),
-                    exp_time: None,
-                },
-            );
-            return Ok(DbValueType::Integer(1));
-        };
-
-        match value {
-            DbValueType::Integer(v) => {
-                let v = v + 1;
-                self._set(key, DbValueType::Integer(v), None); // setting flag to None, probably shouldn't do this
-                Ok(DbValueType::Integer(v))
-            }
-            DbValueType::String(v) => match v.parse::<i64>() {
-                Ok(v) => {
-                    let v = v + 1;
-                    self._set(key, DbValueType::String(v.to_string()), None); // setting flag to None, probably shouldn't do this
-                    Ok(DbValueType::String(v.to_string()))
-                }
-                Err(_) => Err(DbError::UnableToPerformAction(
-                    "ERR value is not an integer or out of range".to_string(),
-                )),
-            },
-            DbValueType::Stream(_) => Err(DbError::UnableToPerformAction(
-                "ERR value is not an integer or out of range".to_string(),
-            )),
-        }
-
-    }
-
-    fn _get(&mut self, key: &String) -> Option<DbValueType> {
-        info!("Getting value for key: {}", key);
-        let value = self.db.get(key);
-        let Some(db_value) = value else {
-            return None;
-        };
-        let value = match &db_value.value {
-            DbValueType::Stream(..) => unimplemented!("GET not supported for streams"),
-            e => e.clone(),
-            // DbValueType::String(e) => e.clone(),
-        };
-        let Some(exp_time) = db_value.exp_time else {
-            return Some(value);
-        };
-        if exp_time <= Instant::now() {
-            self.db.remove(key);
-            return None;
-        }
-        Some(value)
-    }
-
-    fn _get_stream_id(
-        &mut self,
-        stream_key: &String,
-        stream_id: &String,
-    ) -> Result<(u128, usize), String> {
-        if stream_id == "*" {
-            // if ms_part is * then we need to dynamically generate stream_id
-            let ms_part = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_millis();
-            return Ok((ms_part, 0));
-        }
-        let Some((ms_part, seq_part)) = stream_id.split_once("-") else {
-            debug!("ERR The ID specified in XADD must be greater than 0-0");
-            return Err("ERR The ID specified in XADD must be greater than 0-0".to_string());
-        };
-
-        let ms_part = ms_part.parse::<u128>().unwrap();
-        let default_seq_part = if ms_part == 0 { 1 } else { 0 };
-        let db_stream = self
-            .db
-            .get(stream_key)
-            .and_then(|stream_v| match &stream_v.value {
-                DbValueType::Stream(stream) => {
-                    let stream_id = stream
-                        .iter()
-                        .filter(|stream| stream.stream_id_ms_part == ms_part)
-                        .map(|stream| (stream.stream_id_ms_part, stream.stream_id_seq_part))
-                        .collect::<Vec<(u128, usize)>>();
-                    let last = stream_id.last().copied();
-                    last
+                HSet {
+                    emitter,
+                    key,
+                    fields,
+                } => {
+                    let _ = emitter.send(db._hset(&key, fields));
+                    last_command_was_set = true;
                 }
-                _ => None,
-            });
-        let seq_part = match seq_part {
-            "*" => match db_stream {
-                None => default_seq_part,
-                Some((_ms_part, last_seq_part)) => last_seq_part + 1,
-            },
-            _ => seq_part.parse::<usize>().unwrap(),
-        };
-        let last = self
-            .db
-            .get(stream_key)
-            .and_then(|stream_v| match &stream_v.value {
-                DbValueType::Stream(stream) => stream.last(),
-                _ => None,
-            });
-        let (last_ms, last_seq) = last
-            .map(|stream| (stream.stream_id_ms_part, stream.stream_id_seq_part))
-            .unwrap_or((0, 0));
-
-        if ms_part == 0 && seq_part <= 0 {
-            debug!("ERR The ID specified in XADD must be greater than 0-0");
-            return Err("ERR The ID specified in XADD must be greater than 0-0".to_string());
-        }
-        if ms_part < last_ms {
-            return Err(
-                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
-                    .to_string(),
-            );
-        }
-        if ms_part == last_ms && seq_part <= last_seq {
-            return Err(
-                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
-                    .to_string(),
-            );
-        }
-        Ok((ms_part, seq_part))
-    }
-}
-fn get_start_end_ms_seq(start: &String, end: &String) -> (u128, usize, u128, usize) {
-    let min_usize = usize::MIN.to_string();
-    let max_u128 = u128::MAX.to_string();
-    let max_usize = usize::MAX.to_string();
-
-    let (start_ms, start_sq) = if start == "-" {
-        (min_usize.as_str(), min_usize.as_str())
-    } else {
-        start.split_once("-").unwrap()
-    };
-    let (last_ms, last_sq) = if end == "+" {
-        (max_u128.as_str(), max_usize.as_str())
-    } else {
-        end.split_once("-").unwrap()
-    };
-    let start_ms = start_ms.parse::<u128>().unwrap_or(0);
-    let start_sq = start_sq.parse::<usize>().unwrap_or(0);
-    let end_ms = last_ms.parse::<u128>().unwrap_or(0);
-    let end_sq = last_sq.parse::<usize>().unwrap_or(usize::MAX);
-    (start_ms, start_sq, end_ms, end_sq)
+                HSetNx {
+                    emitter,
+                    key,
+                    field,
+                    value,
+                } => {
+                    let _ = emitter.send(db._hsetnx(&key, &field, &value));
+                    last_command_was_set = true;
+                }
+                HGet {
+                    emitter,
+                    key,
+                    field,
+                } => {
+                    let _ = emitter.send(db._hget(&key, &field));
+                    last_command_was_set = false;
+                }
+                HMGet {
+                    emitter,
+                    key,
+                    fields,
+                } => {
+                    let _ = emitter.send(db._hmget(&key, &fields));
+                    last_command_was_set = false;
+                }
+                HGetAll { emitter, key } => {
+                    let _ = emitter.send(db._hgetall(&key));
+                    last_command_was_set = false;
+                }
+                HDel {
+                    emitter,
+                    key,
+                    fields,
+                } => {
+                    let _ = emitter.send(db._hdel(&key, &fields));
+                    last_command_was_set = true;
+                }
+                HExists {
+                    emitter,
+                    key,
+                    field,
+                } => {
+                    let _ = emitter.send(db._hexists(&key, &field));
+                    last_command_was_set = false;
+                }
+                HLen { emitter, key } => {
+                    let _ = emitter.send(db._hlen(&key));
+                    last_command_was_set = false;
+                }
+                HKeys { emitter, key } => {
+                    let _ = emitter.send(db._hkeys(&key));
+                    last_command_was_set = false;
+                }
+                HVals { emitter, key } => {
+                    let _ = emitter.send(db._hvals(&key));
+                    last_command_was_set = false;
+                }
+                HIncrBy {
+                    emitter,
+                    key,
+                    field,
+                    increment,
+                } => {
+                    let _ = emitter.send(db._hincrby(&key, &field, increment));
+                    last_command_was_set = true;
+                }
+                HIncrByFloat {
+                    emitter,
+                    key,
+                    field,
+                    increment,
+                } => {
+                    let _ = emitter.send(db._hincrbyfloat(&key, &field, increment));
+                    last_command_was_set = true;
+                }
+                HStrLen {
+                    emitter,
+                    key,
+                    field,
+                } => {
+                    let _ = emitter.send(db._hstrlen(&key, &field));
+                    last_command_was_set = false;
+                }
+                HRandField {
+                    emitter,
+                    key,
+                    count,
+                } => {
+                    let _ = emitter.send(db._hrandfield(&key, count));
+                    last_command_was_set = false;
+                }
+                Push {
+                    emitter,
+                    key,
+                    elements,
+                    end,
+                    only_if_exists,
+                } => {
+                    let _ = emitter.send(db._push(&key, elements, end, only_if_exists));
+                    db._serve_list_waiters(&key);
+                    last_command_was_set = true;
+                }
+                Pop {
+                    emitter,
+                    key,
+                    end,
+                    count,
+                } => {
+                    let _ = emitter.send(db._pop(&key, end, count));
+                    last_command_was_set = true;
+                }
+                LRange {
+                    emitter,
+                    key,
+                    start,
+                    stop,
+                } => {
+                    let _ = emitter.send(db._lrange(&key, start, stop));
+                    last_command_was_set = false;
+                }
+                LIndex {
+                    emitter,
+                    key,
+                    index,
+                } => {
+                    let _ = emitter.send(db._lindex(&key, index));
+                    last_command_was_set = false;
+                }
+                LLen { emitter, key } => {
+                    let _ = emitter.send(db._llen(&key));
+                    last_command_was_set = false;
+                }
+                LSet {
+                    emitter,
+                    key,
+                    index,
+                    element,
+                } => {
+                    let _ = emitter.send(db._lset(&key, index, element));
+                    last_command_was_set = true;
+                }
+                LInsert {
+                    emitter,
+                    key,
+                    before,
+                    pivot,
+                    element,
+                } => {
+                    let _ = emitter.send(db._linsert(&key, before, &pivot, element));
+                    last_command_was_set = true;
+                }
+                LRem {
+                    emitter,
+                    key,
+                    count,
+                    element,
+                } => {
+                    let _ = emitter.send(db._lrem(&key, count, &element));
+                    last_command_was_set = true;
+                }
+                LTrim {
+                    emitter,
+                    key,
+                    start,
+                    stop,
+                } => {
+                    let _ = emitter.send(db._ltrim(&key, start, stop));
+                    last_command_was_set = true;
+                }
+                LPos {
+                    emitter,
+                    key,
+                    element,
+                    rank,
+                    count,
+                    max_len,
+                } => {
+                    let _ = emitter.send(db._lpos(&key, &element, rank, count, max_len));
+                    last_command_was_set = false;
+                }
+                LMove {
+                    emitter,
+                    source,
+                    destination,
+                    from,
+                    to,
+                } => {
+                    let _ = emitter.send(db._lmove(&source, &destination, from, to));
+                    db._serve_list_waiters(&destination);
+                    last_command_was_set = true;
+                }
+                BlockingPop {
+                    emitter,
+                    keys,
+                    op,
+                    block,
+                } => {
+                    db._block_on_lists(keys, op, emitter, block);
+                    last_command_was_set = true;
+                }
+                SAdd {
+                    emitter,
+                    key,
+                    members,
+                } => {
+                    let _ = emitter.send(db._sadd(&key, members));
+                    last_command_was_set = true;
+                }
+                SRem {
+                    emitter,
+                    key,
+                    members,
+                } => {
+                    let _ = emitter.send(db._srem(&key, &members));
+                    last_command_was_set = true;
+                }
+                SMembers { emitter, key } => {
+                    let _ = emitter.send(db._smembers(&key));
+                    last_command_was_set = false;
+                }
+                SMIsMember {
+                    emitter,
+                    key,
+                    members,
+                } => {
+                    let _ = emitter.send(db._smismember(&key, &members));
+                    last_command_was_set = false;
+                }
+                SCard { emitter, key } => {
+                    let _ = emitter.send(db._scard(&key));
+                    last_command_was_set = false;
+                }
+                SPop {
+                    emitter,
+                    key,
+                    count,
+                } => {
+                    let _ = emitter.send(db._spop(&key, count));
+                    last_command_was_set = true;
+                }
+                SRandMember {
+                    emitter,
+                    key,
+                    count,
+                } => {
+                    let _ = emitter.send(db._srandmember(&key, count));
+                    last_command_was_set = false;
+                }
+                SMove {
+                    emitter,
+                    source,
+                    destination,
+                    member,
+                } => {
+                    let _ = emitter.send(db._smove(&source, &destination, &member));
+                    last_command_was_set = true;
+                }
+                SetOperation { emitter, op, keys } => {
+                    let _ = emitter.send(db._set_op(op, &keys));
+                    last_command_was_set = false;
+                }
+                SetOperationStore {
+                    emitter,
+                    op,
+                    destination,
+                    keys,
+                } => {
+                    let _ = emitter.send(db._set_op_store(op, &destination, &keys));
+                    last_command_was_set = true;
+                }
+                SInterCard {
+                    emitter,
+                    keys,
+                    limit,
+                } => {
+                    let _ = emitter.send(db._sintercard(&keys, limit));
+                    last_command_was_set = false;
+                }
+                ZAdd {
+                    emitter,
+                    key,
+                    members,
+                    options,
+                } => {
+                    let _ = emitter.send(db._zadd(&key, members, options));
+                    last_command_was_set = true;
+                }
+                ZIncrBy {
+                    emitter,
+                    key,
+                    member,
+                    increment,
+                    options,
+                } => {
+                    let _ = emitter.send(db._zincrby(&key, member, increment, options));
+                    last_command_was_set = true;
+                }
+                ZRem {
+                    emitter,
+                    key,
+                    members,
+                } => {
+                    let _ = emitter.send(db._zrem(&key, &members));
+                    last_command_was_set = true;
+                }
+                ZMScore {
+                    emitter,
+                    key,
+                    members,
+                } => {
+                    let _ = emitter.send(db._zmscore(&key, &members));
+                    last_command_was_set = false;
+                }
+                ZRank {
+                    emitter,
+                    key,
+                    member,
+                    rev,
+                } => {
+                    let _ = emitter.send(db._zrank(&key, &member, rev));
+                    last_command_was_set = false;
+                }
+                ZCard { emitter, key } => {
+                    let _ = emitter.send(db._zcard(&key));
+                    last_command_was_set = false;
+                }
+                ZCount {
+                    emitter,
+                    key,
+                    min,
+                    max,
+                } => {
+                    let _ = emitter.send(db._zcount(&key, min, max));
+                    last_command_was_set = false;
+                }
+                ZRange { emitter, key, spec } => {
+                    let _ = emitter.send(db._zrange(&key, &spec));
+                    last_command_was_set = false;
+                }
+                ZRangeStore {
+                    emitter,
+                    destination,
+                    source,
+                    spec,
+                } => {
+                    let _ = emitter.send(db._zrangestore(&destination, &source, &spec));
+                    last_command_was_set = true;
+                }
+                ZPop {
+                    emitter,
+                    key,
+                    count,
+                    max,
+                } => {
+                    let _ = emitter.send(db._zpop(&key, count, max));
+                    last_command_was_set = true;
+                }
+                ZSetOperationStore {
+                    emitter,
+                    op,
+                    destination,
+                    keys,
+                    weights,
+                    aggregate,
+                } => {
+                    let _ = emitter.send(db._zset_op_store(
+                        op,
+                        &destination,
+                        &keys,
+                        &weights,
+                        aggregate,
+                    ));
+                    last_command_was_set = true;
+                }
+                GeoSearch {
+                    emitter,
+                    key,
+                    query,
+                } => {
+                    let _ = emitter.send(db._geosearch(&key, &query));
+                    last_command_was_set = false;
+                }
+                GeoSearchStore {
+                    emitter,
+                    destination,
+                    source,
+                    query,
+                    store_dist,
+                } => {
+                    let _ =
+                        emitter.send(db._geosearchstore(&destination, &source, &query, store_dist));
+                    last_command_was_set = true;
+                }
+                PfAdd {
+                    emitter,
+                    key,
+                    elements,
+                } => {
+                    let _ = emitter.send(db._pfadd(&key, &elements));
+                    last_command_was_set = true;
+                }
+                PfCount { emitter, keys } => {
+                    let _ = emitter.send(db._pfcount(&keys));
+                    last_command_was_set = false;
+                }
+                PfMerge {
+                    emitter,
+                    destination,
+                    keys,
+                } => {
+                    let _ = emitter.send(db._pfmerge(&destination, &keys));
+                    last_command_was_set = true;
+                }
+                SetBit {
+                    emitter,
+                    key,
+                    offset,
+                    value,
+                } => {
+                    let _ = emitter.send(db._setbit(&key, offset, value));
+                    last_command_was_set = true;
+                }
+                GetBit {
+                    emitter,
+                    key,
+                    offset,
+                } => {
+                    let _ = emitter.send(db._getbit(&key, offset));
+                    last_command_was_set = false;
+                }
+                BitCount {
+                    emitter,
+                    key,
+                    range,
+                } => {
+                    let _ = emitter.send(db._bitcount(&key, range));
+                    last_command_was_set = false;
+                }
+                BitPos {
+                    emitter,
+                    key,
+                    bit,
+                    range,
+                } => {
+                    let _ = emitter.send(db._bitpos(&key, bit, range));
+                    last_command_was_set = false;
+                }
+                BitOp {
+                    emitter,
+                    op,
+                    destination,
+                    keys,
+                } => {
+                    let _ = emitter.send(db._bitop(op, &destination, &keys));
+                    last_command_was_set = true;
+                }
+                BitField { emitter, key, ops } => {
+                    let _ = emitter.send(db._bitfield(&key, &ops));
+                    last_command_was_set = true;
+                }
+                Append {
+                    emitter,
+                    key,
+                    value,
+                } => {
+                    let _ = emitter.send(db._append(&key, &value));
+                    last_command_was_set = true;
+                }
+                StrLen { emitter, key } => {
+                    let _ = emitter.send(db._strlen(&key));
+                    last_command_was_set = false;
+                }
+                GetRange {
+                    emitter,
+                    key,
+                    start,
+                    end,
+                } => {
+                    let _ = emitter.send(db._getrange(&key, start, end));
+                    last_command_was_set = false;
+                }
+                SetRange {
+                    emitter,
+                    key,
+                    offset,
+                    value,
+                } => {
+                    let _ = emitter.send(db._setrange(&key, offset, &value));
+                    last_command_was_set = true;
+                }
+                MGet { emitter, keys } => {
+                    let _ = emitter.send(db._mget(&keys));
+                    last_command_was_set = false;
+                }
+                MSet {
+                    emitter,
+                    pairs,
+                    only_new,
+                } => {
+                    let _ = emitter.send(db._mset(&pairs, only_new));
+                    last_command_was_set = true;
+                }
+                GetDel { emitter, key } => {
+                    let _ = emitter.send(db._getdel(&key));
+                    last_command_was_set = true;
+                }
+                GetEx {
+                    emitter,
+                    key,
+                    expiry,
+                } => {
+                    let _ = emitter.send(db._getex(&key, expiry));
+                    last_command_was_set = true;
+                }
+                Lcs {
+                    emitter,
+                    key1,
+                    key2,
+                } => {
+                    let _ = emitter.send(db._lcs(&key1, &key2));
+                    last_command_was_set = false;
+                }
+                IncrBy {
+                    emitter,
+                    key,
+                    increment,
+                } => {
+                    let _ = emitter.send(db._incrby(&key, increment));
+                    last_command_was_set = true;
+                }
+                IncrByFloat {
+                    emitter,
+                    key,
+                    increment,
+                } => {
+                    let _ = emitter.send(db._incrbyfloat(&key, increment));
+                    last_command_was_set = true;
+                }
+                Del {
+                    emitter,
+                    keys,
+                    lazy,
+                } => {
+                    let _ = emitter.send(db._del(&keys, lazy));
+                    last_command_was_set = true;
+                }
+                Exists { emitter, keys } => {
+                    let _ = emitter.send(db._exists(&keys));
+                    last_command_was_set = false;
+                }
+                Rename {
+                    emitter,
+                    key,
+                    new_key,
+                    only_new,
+                } => {
+                    let _ = emitter.send(db._rename(&key, &new_key, only_new));
+                    db._serve_list_waiters(&new_key);
+                    last_command_was_set = true;
+                }
+                Copy {
+                    emitter,
+                    source,
+                    destination,
+                    db: index,
+                    replace,
+                } => {
+                    let copied = db._copy(&source, &destination, index, replace);
+                    let landed = matches!(copied, Ok(true));
+                    let _ = emitter.send(copied);
+                    if landed {
+                        db._serve_list_waiters_in(index.unwrap_or(db.selected), &destination);
+                    }
+                    last_command_was_set = true;
+                }
+                Sort {
+                    emitter,
+                    key,
+                    options,
+                } => {
+                    let _ = emitter.send(db._sort(&key, &options));
+                    last_command_was_set = false;
+                }
+                SortStore {
+                    emitter,
+                    destination,
+                    key,
+                    options,
+                } => {
+                    let _ = emitter.send(db._sort_store(&destination, &key, &options));
+                    db._serve_list_waiters(&destination);
+                    last_command_was_set = true;
+                }
+                Dump { emitter, key } => {
+                    let _ = emitter.send(db._dump(&key));
+                    last_command_was_set = false;
+                }
+                Restore {
+                    emitter,
+                    key,
+                    value,
+                    options,
+                } => {
+                    let _ = emitter.send(db._restore(&key, value, options));
+                    db._serve_list_waiters(&key);
+                    last_command_was_set = true;
+                }
+                Touch { emitter, keys } => {
+                    let _ = emitter.send(db._touch(&keys));
+                    last_command_was_set = false;
+                }
+                RandomKey { emitter } => {
+                    let _ = emitter.send(db._random_key());
+                    last_command_was_set = false;
+                }
+                DbSize { emitter } => {
+                    let _ = emitter.send(db._dbsize());
+                    last_command_was_set = false;
+                }
+                Flush { emitter, all, lazy } => {
+                    let _ = emitter.send(db._flush(all, lazy));
+                    last_command_was_set = true;
+                }
+                Move {
+                    emitter,
+                    key,
+                    db: index,
+                } => {
+                    let moved = db._move(&key, index);
+                    let landed = matches!(moved, Ok(true));
+                    let _ = emitter.send(moved);
+                    if landed {
+                        db._serve_list_waiters_in(index, &key);
+                    }
+                    last_command_was_set = true;
+                }
+                SwapDb {
+                    emitter,
+                    first,
+                    second,
+                } => {
+                    let _ = emitter.send(db._swapdb(first, second));
+                    last_command_was_set = true;
+                }
+                Expire {
+                    emitter,
+                    key,
+                    expiry,
+                    options,
+                } => {
+                    let _ = emitter.send(db._expire(&key, expiry, options));
+                    last_command_was_set = true;
+                }
+                ExpireTime { emitter, key } => {
+                    let _ = emitter.send(db._expire_time(&key));
+                    last_command_was_set = false;
+                }
+                Persist { emitter, key } => {
+                    let _ = emitter.send(db._persist(&key));
+                    last_command_was_set = true;
+                }
+                Scan {
+                    emitter,
+                    cursor,
+                    options,
+                } => {
+                    let _ = emitter.send(db._scan(cursor, &options));
+                    last_command_was_set = false;
+                }
+                HScan {
+                    emitter,
+                    key,
+                    cursor,
+                    options,
+                } => {
+                    let _ = emitter.send(db._hscan(&key, cursor, &options));
+                    last_command_was_set = false;
+                }
+                SScan {
+                    emitter,
+                    key,
+                    cursor,
+                    options,
+                } => {
+                    let _ = emitter.send(db._sscan(&key, cursor, &options));
+                    last_command_was_set = false;
+                }
+                ZScan {
+                    emitter,
+                    key,
+                    cursor,
+                    options,
+                } => {
+                    let _ = emitter.send(db._zscan(&key, cursor, &options));
+                    last_command_was_set = false;
+                }
+                FreeMemory { emitter } => {
+                    let _ = emitter.send(db._free_memory());
+                }
+                MemoryUsage {
+                    emitter,
+                    key,
+                    samples,
+                } => {
+                    let _ = emitter.send(db._memory_usage(&key, samples));
+                    last_command_was_set = false;
+                }
+                MemoryStats { emitter } => {
+                    let _ = emitter.send(db._memory_stats());
+                    last_command_was_set = false;
+                }
+                Object { emitter, key } => {
+                    let _ = emitter.send(db._object(&key));
+                    last_command_was_set = false;
+                }
+            }
+            db._account_memory();
+        }
+    }
+
+    /// Parks the keys of the selected database and brings in those of `index`
+    fn _select(&mut self, index: usize) {
+        if index == self.selected {
+            return;
+        }
+        self._swap_selected();
+        self.selected = index;
+        self._swap_selected();
+    }
+
+    /// Swaps the keys in `db` with the slot of the selected database, which parks them when
+    /// they are live and brings them back when they are parked
+    fn _swap_selected(&mut self) {
+        let keyspace = &mut self.keyspaces[self.selected];
+        std::mem::swap(&mut self.db, &mut keyspace.db);
+        std::mem::swap(&mut self.volatile_keys, &mut keyspace.volatile_keys);
+    }
+
+    /// Keys of database `index`, whether it is the selected one or not
+    fn _keyspace(
+        &mut self,
+        index: usize,
+    ) -> (&mut ScanMap<DatabaseValue>, &mut expire::SampledKeys) {
+        match index == self.selected {
+            true => (&mut self.db, &mut self.volatile_keys),
+            false => {
+                let keyspace = &mut self.keyspaces[index];
+                (&mut keyspace.db, &mut keyspace.volatile_keys)
+            }
+        }
+    }
+
+    fn _get_stream_range(
+        &mut self,
+        stream_key: &Bytes,
+        range: &StreamRange,
+    ) -> Result<Vec<StreamDbValueType>, DbError> {
+        let Some(stream) = self._get_stream(stream_key)? else {
+            return Ok(vec![]);
+        };
+        let entries = stream.range(range.start, range.end);
+        let count = range.count.unwrap_or(usize::MAX);
+        let value = match range.rev {
+            true => entries.rev().take(count).cloned().collect(),
+            false => entries.take(count).cloned().collect(),
+        };
+        Ok(value)
+    }
+
+    fn _keys(&self, pattern: &[u8]) -> Vec<Bytes> {
+        let now = now_ms();
+        let value = self
+            .db
+            .iter()
+            .filter(|(_, db_value)| !db_value.is_expired(now))
+            .filter(|(key, _)| glob::matches(pattern, key))
+            .map(|(k, _)| k.to_owned())
+            .collect();
+        value
+    }
+
+    /// Appends an entry to the stream, then trims it. Nothing is added when the stream does not
+    /// exist and `options` say not to create it.
+    fn _set_stream(
+        &mut self,
+        stream_key: &Bytes,
+        stream_id: &str,
+        fields: Vec<(Bytes, Bytes)>,
+        options: &XAddOptions,
+    ) -> Result<Option<String>, String> {
+        info!("Setting stream: {:?} with fields: {:?}", stream_key, fields);
+        let last_id = match self._get_stream(stream_key).map_err(|e| e.to_string())? {
+            None if options.no_mkstream => return Ok(None),
+            None => (0, 0),
+            Some(stream) => stream.last_id(),
+        };
+        let (ms_part, seq_part) = get_stream_id(stream_id, last_id)?;
+        let entry = StreamDbValueType {
+            stream_id_ms_part: ms_part,
+            stream_id_seq_part: seq_part,
+            fields,
+        };
+        let stream = self
+            ._get_or_create_stream(stream_key)
+            .map_err(|e| e.to_string())?;
+        stream.push(entry);
+        if let Some(trim) = &options.trim {
+            stream.trim(trim);
+        }
+        Ok(Some(format!("{ms_part}-{seq_part}")))
+    }
+
+    fn _set(&mut self, key: &Bytes, value: DbValueType, exp_time: Option<u64>) {
+        info!("Setting key: {key:?} with value: {value:?}");
+        self._lookup_key(key);
+        if exp_time.is_some() {
+            self.volatile_keys.insert(key);
+        }
+        self.db
+            .insert(key.to_owned(), DatabaseValue::new(value, exp_time));
+    }
+
+    fn _get_type(&mut self, key: &Bytes) -> &str {
+        self._lookup_key(key);
+        let value = self.db.get(key);
+        match value {
+            None => "none",
+            Some(kv) => kv.value.type_name(),
+        }
+    }
+
+    fn _get_latest_stream_id(&mut self, stream_key: &Bytes) -> StreamId {
+        self._lookup_key(stream_key);
+        self.db
+            .get(stream_key)
+            .and_then(|stream_v| match &stream_v.value {
+                DbValueType::Stream(stream) => Some(stream.last_id()),
+                _ => None,
+            })
+            .unwrap_or((0, 0))
+    }
+
+    fn _get(&mut self, key: &Bytes) -> Result<Option<DbValueType>, DbError> {
+        info!("Getting value for key: {:?}", key);
+        self._lookup_key(key);
+        let Some(db_value) = self.db.get(key) else {
+            return Ok(None);
+        };
+        match &db_value.value {
+            e @ (DbValueType::Integer(_) | DbValueType::String(_)) => Ok(Some(e.clone())),
+            _ => Err(DbError::WrongType),
+        }
+    }
+
+    /// String value of `key` as bytes, with integers rendered the way GET shows them
+    fn _get_string(&mut self, key: &Bytes) -> Result<Option<Bytes>, DbError> {
+        let value = match self._get(key)? {
+            None => None,
+            Some(DbValueType::String(value)) => Some(value),
+            Some(DbValueType::Integer(value)) => Some(Bytes::from(value.to_string())),
+            Some(_) => return Err(DbError::WrongType),
+        };
+        Ok(value)
+    }
+
+    /// Replaces the value of `key` with a string, keeping the expiry of an existing key
+    fn _store_string(&mut self, key: &Bytes, value: Bytes) {
+        self._store_value(key, DbValueType::String(value));
+    }
+
+    /// Replaces the value of `key`, keeping the expiry of an existing key
+    fn _store_value(&mut self, key: &Bytes, value: DbValueType) {
+        self._lookup_key(key);
+        match self.db.get_mut(key) {
+            Some(existing) => existing.value = value,
+            None => {
+                self.db
+                    .insert(key.to_owned(), DatabaseValue::new(value, None));
+            }
+        }
+    }
+
+    /// Commands go through here before they read or write `key`. An expired key is dropped,
+    /// otherwise the access is recorded for the eviction policies. Either way the key gets
+    /// charged again against maxmemory once the command is done.
+    fn _lookup_key(&mut self, key: &Bytes) {
+        self._track_key(self.selected, key);
+        let now = now_ms();
+        match self.db.get_mut(key) {
+            Some(db_value) if db_value.is_expired(now) => {
+                self.db.remove(key);
+            }
+            Some(db_value) => self.memory.record_access(db_value, now),
+            None => {}
+        }
+    }
+}
+
+/// ID for a new entry of a stream that last handed out `last_id`. `*` picks the current time
+/// and `<ms>-*` the next sequence number for that millisecond.
+fn get_stream_id(stream_id: &str, last_id: StreamId) -> Result<StreamId, String> {
+    let (last_ms, last_seq) = last_id;
+    let next_seq = |ms_part: u128| match ms_part == last_ms {
+        true => last_seq.checked_add(1).map(|seq| (ms_part, seq)),
+        false => Some((ms_part, 0)),
+    };
+    let id = if stream_id == "*" {
+        next_seq((now_ms() as u128).max(last_ms))
+    } else if let Some(ms_part) = stream_id.strip_suffix("-*") {
+        let Ok(ms_part) = ms_part.parse::<u128>() else {
+            return Err(INVALID_STREAM_ID.to_string());
+        };
+        next_seq(ms_part)
+    } else {
+        let Some(id) = parse_stream_id(stream_id, 0) else {
+            return Err(INVALID_STREAM_ID.to_string());
+        };
+        Some(id)
+    };
+    match id {
+        Some((0, 0)) => Err("ERR The ID specified in XADD must be greater than 0-0".to_string()),
+        Some(id) if id > last_id => Ok(id),
+        _ => Err(
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                .to_string(),
+        ),
+    }
+}
+
+pub(crate) const INVALID_STREAM_ID: &str =
+    "ERR Invalid stream ID specified as stream command argument";
+
+/// Parses `<ms>-<seq>`, or just `<ms>` with `missing_seq` as its sequence number
+pub(crate) fn parse_stream_id(value: &str, missing_seq: usize) -> Option<StreamId> {
+    match value.split_once('-') {
+        Some((ms_part, seq_part)) => Some((ms_part.parse().ok()?, seq_part.parse().ok()?)),
+        None => Some((value.parse().ok()?, missing_seq)),
+    }
+}
+
+/// The ID right after `id`, None past the largest one
+pub(crate) fn next_stream_id((ms_part, seq_part): StreamId) -> Option<StreamId> {
+    match seq_part.checked_add(1) {
+        Some(seq_part) => Some((ms_part, seq_part)),
+        None => Some((ms_part.checked_add(1)?, 0)),
+    }
+}
+
+/// The ID right before `id`, None before 0-0
+pub(crate) fn prev_stream_id((ms_part, seq_part): StreamId) -> Option<StreamId> {
+    match seq_part.checked_sub(1) {
+        Some(seq_part) => Some((ms_part, seq_part)),
+        None => Some((ms_part.checked_sub(1)?, usize::MAX)),
+    }
+}
+
+/// Picks random items the way HRANDFIELD and SRANDMEMBER do: a single item without a count,
+/// distinct items for a positive count and possibly repeated items for a negative one
+pub(crate) fn random_sample<I>(items: I, count: Option<i64>) -> Vec<I::Item>
+where
+    I: ExactSizeIterator,
+    I::Item: Clone,
+{
+    let mut rng = rand::thread_rng();
+    match count {
+        None => items.choose(&mut rng).into_iter().collect(),
+        Some(count) if count >= 0 => {
+            // choose_multiple reserves room for as many items as asked for
+            let count = usize::try_from(count)
+                .unwrap_or(usize::MAX)
+                .min(items.len());
+            items.choose_multiple(&mut rng, count)
+        }
+        Some(count) => {
+            let items = items.collect::<Vec<_>>();
+            if items.is_empty() {
+                return vec![];
+            }
+            (0..count.unsigned_abs())
+                .map(|_| items[rng.gen_range(0..items.len())].clone())
+                .collect()
+        }
+    }
+}
+
+/// String values that look like integers are kept as such
+fn string_value(value: Bytes) -> DbValueType {
+    match parse_canonical_integer(&value) {
+        Some(i) => DbValueType::Integer(i),
+        None => DbValueType::String(value),
+    }
+}
+
+/// When a key with the given expiry stops being visible, `None` meaning never
+fn expiry_time(expiry: Expiry) -> Option<u64> {
+    match expiry {
+        Expiry::In(duration) => Some(now_ms().saturating_add(duration.as_millis() as u64)),
+        Expiry::At(unix_ms) => Some(unix_ms),
+        Expiry::Persist => None,
+    }
+}
+
+/// Current Unix time in milliseconds, the clock key expiry is measured against
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Integers are only stored as such when GET would give back the exact same bytes, so values
+/// like `007` or `+1` keep their spelling
+fn parse_canonical_integer(value: &[u8]) -> Option<i64> {
+    let integer = parse_i64(value)?;
+    (integer.to_string().as_bytes() == value).then_some(integer)
+}
+
+pub(crate) fn parse_i64(value: &[u8]) -> Option<i64> {
+    std::str::from_utf8(value).ok()?.parse::<i64>().ok()
+}
+
+/// Formats a float reply the way Redis does, dropping the fraction for whole numbers
+pub(crate) fn format_float(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e17 {
+        return format!("{}", value as i64);
+    }
+    value.to_string()
+}
+
+/// Parses a float the way Redis does, accepting `inf`/`-inf` but never `nan`
+pub(crate) fn parse_float(value: &[u8]) -> Option<f64> {
+    let value = std::str::from_utf8(value).ok()?.parse::<f64>().ok()?;
+    if value.is_nan() {
+        return None;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_stream_id_reads_both_parts() {
+        assert_eq!(
+            parse_stream_id("1526919030474-55", 0),
+            Some((1526919030474, 55))
+        );
+        assert_eq!(parse_stream_id("0-0", 7), Some((0, 0)));
+    }
+
+    #[test]
+    fn parse_stream_id_fills_in_a_missing_sequence() {
+        assert_eq!(parse_stream_id("5", 0), Some((5, 0)));
+        assert_eq!(parse_stream_id("5", usize::MAX), Some((5, usize::MAX)));
+    }
+
+    #[test]
+    fn parse_stream_id_rejects_malformed_ids() {
+        for id in ["", "-", "1-", "-1", "a-1", "1-b", "1-2-3", "-1-0", "1.5"] {
+            assert_eq!(parse_stream_id(id, 0), None, "{id:?}");
+        }
+    }
+
+    #[test]
+    fn get_stream_id_takes_explicit_ids_above_the_last_one() {
+        assert_eq!(get_stream_id("1-1", (0, 0)), Ok((1, 1)));
+        assert_eq!(get_stream_id("5", (4, 9)), Ok((5, 0)));
+        assert!(get_stream_id("1-1", (1, 1)).is_err());
+        assert!(get_stream_id("1-0", (1, 1)).is_err());
+        assert!(get_stream_id("0-0", (0, 0)).is_err());
+        assert_eq!(
+            get_stream_id("x", (0, 0)),
+            Err(INVALID_STREAM_ID.to_string())
+        );
+    }
+
+    #[test]
+    fn get_stream_id_generates_sequence_numbers() {
+        assert_eq!(get_stream_id("5-*", (0, 0)), Ok((5, 0)));
+        assert_eq!(get_stream_id("5-*", (5, 3)), Ok((5, 4)));
+        assert_eq!(get_stream_id("0-*", (0, 0)), Ok((0, 1)));
+        assert!(get_stream_id("4-*", (5, 3)).is_err());
+        assert!(get_stream_id("5-*", (5, usize::MAX)).is_err());
+    }
+
+    #[test]
+    fn get_stream_id_generates_ids_past_the_last_one() {
+        let (ms_part, _) = get_stream_id("*", (0, 0)).unwrap();
+        assert!(ms_part > 0);
+        let future = (u64::MAX as u128, 1);
+        assert_eq!(get_stream_id("*", future), Ok((u64::MAX as u128, 2)));
+    }
+
+    #[test]
+    fn next_and_prev_stream_ids_carry_over_the_sequence() {
+        assert_eq!(next_stream_id((1, 5)), Some((1, 6)));
+        assert_eq!(next_stream_id((1, usize::MAX)), Some((2, 0)));
+        assert_eq!(next_stream_id(MAX_STREAM_ID), None);
+        assert_eq!(prev_stream_id((1, 5)), Some((1, 4)));
+        assert_eq!(prev_stream_id((2, 0)), Some((1, usize::MAX)));
+        assert_eq!(prev_stream_id((0, 0)), None);
+    }
 }
@@ -0,0 +1,288 @@
+//! Stateless cursors for SCAN, HSCAN, SSCAN and ZSCAN.
+//!
+//! Redis walks its hash table buckets in reverse binary order so a cursor survives rehashing.
+//! Our maps don't expose their buckets, so the names of a scannable collection are also kept
+//! ordered by a fixed hash of the name and the cursor tells which hash to carry on from. That
+//! order does not depend on how big the table is, so every element present for the whole
+//! iteration is returned, while elements added or removed in between may or may not be.
+
+use std::{
+    borrow::Borrow,
+    collections::{hash_map, hash_set, BTreeSet, HashMap, HashSet},
+    hash::Hash,
+    mem::size_of,
+    ops::Deref,
+};
+
+use bytes::Bytes;
+
+pub type ScanMap<V> = Scannable<HashMap<Bytes, V>>;
+pub type ScanSet = Scannable<HashSet<Bytes>>;
+
+/// A map or set of names that also keeps the names in scan order, so a cursor seeks straight
+/// to its batch instead of walking every element. Reads go through to the collection; writes
+/// have to go through here to keep the order in step.
+#[derive(Clone, Debug, Default)]
+pub struct Scannable<C> {
+    items: C,
+    order: BTreeSet<(u64, Bytes)>,
+}
+
+impl<C> Deref for Scannable<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.items
+    }
+}
+
+impl<C> Scannable<C> {
+    /// Bytes the scan order takes
+    pub fn overhead(&self) -> usize {
+        self.order.len() * size_of::<(u64, Bytes)>()
+    }
+
+    /// Picks the next batch of at least `count` names after `cursor`, returning the cursor to
+    /// continue from, 0 once the iteration is complete. Names sharing a hash always come back
+    /// in the same batch so none of them can be skipped.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&Bytes>) {
+        // Cursors are positions shifted by one so that 0 can both start and end an iteration
+        let start = cursor.saturating_sub(1);
+        let count = count.max(1);
+        let mut batch = vec![];
+        let mut last = None;
+        for (position, name) in self.order.range((start, Bytes::new())..) {
+            if batch.len() >= count && last != Some(*position) {
+                return (position + 1, batch);
+            }
+            batch.push(name);
+            last = Some(*position);
+        }
+        (0, batch)
+    }
+}
+
+impl<V> ScanMap<V> {
+    /// Returns the value `name` had, if any
+    pub fn insert(&mut self, name: Bytes, value: V) -> Option<V> {
+        if !self.items.contains_key(&name) {
+            self.order.insert((position(&name), name.clone()));
+        }
+        self.items.insert(name, value)
+    }
+
+    pub fn remove<Q>(&mut self, name: &Q) -> Option<V>
+    where
+        Bytes: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (name, value) = self.items.remove_entry(name)?;
+        self.order.remove(&(position(&name), name));
+        Some(value)
+    }
+
+    pub fn get_mut<Q>(&mut self, name: &Q) -> Option<&mut V>
+    where
+        Bytes: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.items.get_mut(name)
+    }
+
+    pub fn get_or_insert_with(&mut self, name: &Bytes, value: impl FnOnce() -> V) -> &mut V {
+        if !self.items.contains_key(name) {
+            self.insert(name.to_owned(), value());
+        }
+        self.items.get_mut(name).expect("name was just inserted")
+    }
+
+    pub fn values_mut(&mut self) -> hash_map::ValuesMut<'_, Bytes, V> {
+        self.items.values_mut()
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.order.clear();
+    }
+}
+
+impl ScanSet {
+    /// Returns whether `name` is new to the set
+    pub fn insert(&mut self, name: Bytes) -> bool {
+        if self.items.contains(&name) {
+            return false;
+        }
+        self.order.insert((position(&name), name.clone()));
+        self.items.insert(name)
+    }
+
+    pub fn remove<Q>(&mut self, name: &Q) -> bool
+    where
+        Bytes: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let Some(name) = self.items.take(name) else {
+            return false;
+        };
+        self.order.remove(&(position(&name), name))
+    }
+}
+
+impl<V> FromIterator<(Bytes, V)> for ScanMap<V> {
+    fn from_iter<I: IntoIterator<Item = (Bytes, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<V> Extend<(Bytes, V)> for ScanMap<V> {
+    fn extend<I: IntoIterator<Item = (Bytes, V)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.insert(name, value);
+        }
+    }
+}
+
+impl FromIterator<Bytes> for ScanSet {
+    fn from_iter<I: IntoIterator<Item = Bytes>>(iter: I) -> Self {
+        let mut set = Self::default();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<Bytes> for ScanSet {
+    fn extend<I: IntoIterator<Item = Bytes>>(&mut self, iter: I) {
+        for name in iter {
+            self.insert(name);
+        }
+    }
+}
+
+impl<V> IntoIterator for ScanMap<V> {
+    type Item = (Bytes, V);
+    type IntoIter = hash_map::IntoIter<Bytes, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl IntoIterator for ScanSet {
+    type Item = Bytes;
+    type IntoIter = hash_set::IntoIter<Bytes>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'a, C> IntoIterator for &'a Scannable<C>
+where
+    &'a C: IntoIterator,
+{
+    type Item = <&'a C as IntoIterator>::Item;
+    type IntoIter = <&'a C as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+/// 64 bit FNV-1a, dropping the top bit so a position plus one still fits a cursor
+fn position(name: &[u8]) -> u64 {
+    let hash = name.iter().fold(0xcbf29ce484222325_u64, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash >> 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(count: usize) -> Vec<Bytes> {
+        (0..count)
+            .map(|i| Bytes::from(format!("name:{i}")))
+            .collect()
+    }
+
+    /// Follows the cursor until the iteration completes, collecting every name seen
+    fn scan_all<C>(items: &Scannable<C>, count: usize) -> Vec<Bytes> {
+        let (mut cursor, mut seen) = (0, vec![]);
+        loop {
+            let (next, batch) = items.scan(cursor, count);
+            seen.extend(batch.into_iter().cloned());
+            if next == 0 {
+                return seen;
+            }
+            cursor = next;
+        }
+    }
+
+    #[test]
+    fn scan_returns_every_name_once() {
+        let set = names(1000).into_iter().collect::<ScanSet>();
+        let mut seen = scan_all(&set, 7);
+        seen.sort();
+        let mut expected = names(1000);
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn scan_batches_hold_at_least_count_names() {
+        let map = names(100)
+            .into_iter()
+            .map(|name| (name, ()))
+            .collect::<ScanMap<()>>();
+        let (cursor, batch) = map.scan(0, 10);
+        assert_ne!(cursor, 0);
+        assert!(batch.len() >= 10);
+        let (cursor, batch) = map.scan(0, 1000);
+        assert_eq!(cursor, 0);
+        assert_eq!(batch.len(), 100);
+    }
+
+    #[test]
+    fn scan_of_empty_collection_completes_at_once() {
+        assert_eq!(ScanSet::default().scan(0, 10), (0, vec![]));
+    }
+
+    #[test]
+    fn scan_keeps_names_present_throughout_when_others_change() {
+        let mut set = names(200).into_iter().collect::<ScanSet>();
+        let (mut cursor, mut seen) = (0, vec![]);
+        let mut round = 0;
+        loop {
+            let (next, batch) = set.scan(cursor, 5);
+            seen.extend(batch.into_iter().cloned());
+            // Churn names outside the first hundred between batches
+            set.remove(&Bytes::from(format!("name:{}", 100 + round)));
+            set.insert(Bytes::from(format!("new:{round}")));
+            round += 1;
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        for name in &names(100) {
+            assert!(seen.contains(name), "{name:?} was skipped");
+        }
+    }
+
+    #[test]
+    fn writes_keep_scan_order_in_step() {
+        let mut map = ScanMap::default();
+        assert_eq!(map.insert(Bytes::from("a"), 1), None);
+        assert_eq!(map.insert(Bytes::from("a"), 2), Some(1));
+        *map.get_or_insert_with(&Bytes::from("b"), || 0) += 3;
+        assert_eq!(map.remove(b"a".as_slice()), Some(2));
+        assert_eq!(map.remove(b"a".as_slice()), None);
+        assert_eq!(scan_all(&map, 1), vec![Bytes::from("b")]);
+        assert_eq!(map[b"b".as_slice()], 3);
+        map.clear();
+        assert_eq!(map.scan(0, 10), (0, vec![]));
+    }
+}
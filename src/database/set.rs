@@ -0,0 +1,368 @@
+use std::collections::HashSet;
+
+use bytes::Bytes;
+use rand::seq::IteratorRandom;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{DatabaseValue, DbError, DbValueType, ScanOptions, SetOp};
+use super::scan::ScanSet;
+use super::{random_sample, Database};
+
+impl Database {
+    pub async fn sadd(key: &Bytes, members: &[Bytes]) -> anyhow::Result<i64> {
+        Database::request(|emitter| SAdd {
+            emitter,
+            key: key.to_owned(),
+            members: members.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn srem(key: &Bytes, members: &[Bytes]) -> anyhow::Result<i64> {
+        Database::request(|emitter| SRem {
+            emitter,
+            key: key.to_owned(),
+            members: members.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn smembers(key: &Bytes) -> anyhow::Result<Vec<Bytes>> {
+        Database::request(|emitter| SMembers {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn smismember(key: &Bytes, members: &[Bytes]) -> anyhow::Result<Vec<bool>> {
+        Database::request(|emitter| SMIsMember {
+            emitter,
+            key: key.to_owned(),
+            members: members.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn scard(key: &Bytes) -> anyhow::Result<i64> {
+        Database::request(|emitter| SCard {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn spop(key: &Bytes, count: usize) -> anyhow::Result<Vec<Bytes>> {
+        Database::request(|emitter| SPop {
+            emitter,
+            key: key.to_owned(),
+            count,
+        })
+        .await
+    }
+
+    pub async fn srandmember(key: &Bytes, count: Option<i64>) -> anyhow::Result<Vec<Bytes>> {
+        Database::request(|emitter| SRandMember {
+            emitter,
+            key: key.to_owned(),
+            count,
+        })
+        .await
+    }
+
+    pub async fn smove(
+        source: &Bytes,
+        destination: &Bytes,
+        member: &Bytes,
+    ) -> anyhow::Result<bool> {
+        Database::request(|emitter| SMove {
+            emitter,
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            member: member.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn set_op(op: SetOp, keys: &[Bytes]) -> anyhow::Result<Vec<Bytes>> {
+        Database::request(|emitter| SetOperation {
+            emitter,
+            op,
+            keys: keys.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn set_op_store(
+        op: SetOp,
+        destination: &Bytes,
+        keys: &[Bytes],
+    ) -> anyhow::Result<i64> {
+        Database::request(|emitter| SetOperationStore {
+            emitter,
+            op,
+            destination: destination.to_owned(),
+            keys: keys.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn sintercard(keys: &[Bytes], limit: usize) -> anyhow::Result<i64> {
+        Database::request(|emitter| SInterCard {
+            emitter,
+            keys: keys.to_vec(),
+            limit,
+        })
+        .await
+    }
+
+    pub async fn sscan(
+        key: &Bytes,
+        cursor: u64,
+        options: &ScanOptions,
+    ) -> anyhow::Result<(u64, Vec<Bytes>)> {
+        Database::request(|emitter| SScan {
+            emitter,
+            key: key.to_owned(),
+            cursor,
+            options: options.to_owned(),
+        })
+        .await
+    }
+
+    pub(super) fn _sadd(&mut self, key: &Bytes, members: Vec<Bytes>) -> Result<i64, DbError> {
+        let set = self._get_or_create_set(key)?;
+        let added = members
+            .into_iter()
+            .filter(|member| set.insert(member.clone()))
+            .count();
+        Ok(added as i64)
+    }
+
+    pub(super) fn _srem(&mut self, key: &Bytes, members: &[Bytes]) -> Result<i64, DbError> {
+        let Some(set) = self._get_set(key)? else {
+            return Ok(0);
+        };
+        let removed = members.iter().filter(|member| set.remove(*member)).count();
+        self._remove_if_empty_set(key);
+        Ok(removed as i64)
+    }
+
+    pub(super) fn _smembers(&mut self, key: &Bytes) -> Result<Vec<Bytes>, DbError> {
+        let members = match self._get_set(key)? {
+            None => vec![],
+            Some(set) => set.iter().cloned().collect(),
+        };
+        Ok(members)
+    }
+
+    pub(super) fn _smismember(
+        &mut self,
+        key: &Bytes,
+        members: &[Bytes],
+    ) -> Result<Vec<bool>, DbError> {
+        let set = self._get_set(key)?;
+        let found = members
+            .iter()
+            .map(|member| set.as_ref().is_some_and(|set| set.contains(member)))
+            .collect();
+        Ok(found)
+    }
+
+    pub(super) fn _scard(&mut self, key: &Bytes) -> Result<i64, DbError> {
+        let len = self._get_set(key)?.map(|set| set.len()).unwrap_or(0);
+        Ok(len as i64)
+    }
+
+    pub(super) fn _spop(&mut self, key: &Bytes, count: usize) -> Result<Vec<Bytes>, DbError> {
+        let Some(set) = self._get_set(key)? else {
+            return Ok(vec![]);
+        };
+        let count = count.min(set.len());
+        let popped = set
+            .iter()
+            .cloned()
+            .choose_multiple(&mut rand::thread_rng(), count);
+        popped.iter().for_each(|member| {
+            set.remove(member);
+        });
+        self._remove_if_empty_set(key);
+        Ok(popped)
+    }
+
+    pub(super) fn _srandmember(
+        &mut self,
+        key: &Bytes,
+        count: Option<i64>,
+    ) -> Result<Vec<Bytes>, DbError> {
+        let members = match self._get_set(key)? {
+            None => vec![],
+            Some(set) => random_sample(set.iter(), count)
+                .into_iter()
+                .cloned()
+                .collect(),
+        };
+        Ok(members)
+    }
+
+    pub(super) fn _smove(
+        &mut self,
+        source: &Bytes,
+        destination: &Bytes,
+        member: &Bytes,
+    ) -> Result<bool, DbError> {
+        // Both keys are type checked before anything is moved
+        self._get_set(destination)?;
+        let Some(set) = self._get_set(source)? else {
+            return Ok(false);
+        };
+        if !set.contains(member) {
+            return Ok(false);
+        }
+        if source == destination {
+            return Ok(true);
+        }
+        set.remove(member);
+        self._remove_if_empty_set(source);
+        self._get_or_create_set(destination)?
+            .insert(member.to_owned());
+        Ok(true)
+    }
+
+    pub(super) fn _set_op(&mut self, op: SetOp, keys: &[Bytes]) -> Result<Vec<Bytes>, DbError> {
+        let result = self._compute_set_op(op, keys, usize::MAX)?;
+        Ok(result.into_iter().collect())
+    }
+
+    pub(super) fn _set_op_store(
+        &mut self,
+        op: SetOp,
+        destination: &Bytes,
+        keys: &[Bytes],
+    ) -> Result<i64, DbError> {
+        let result = self._compute_set_op(op, keys, usize::MAX)?;
+        let len = result.len();
+        self._lookup_key(destination);
+        self.db.remove(destination);
+        if !result.is_empty() {
+            self.db.insert(
+                destination.to_owned(),
+                DatabaseValue::new(DbValueType::Set(result.into_iter().collect()), None),
+            );
+        }
+        Ok(len as i64)
+    }
+
+    pub(super) fn _sintercard(&mut self, keys: &[Bytes], limit: usize) -> Result<i64, DbError> {
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        let result = self._compute_set_op(SetOp::Inter, keys, limit)?;
+        Ok(result.len() as i64)
+    }
+
+    /// Computes `op` over `keys`, treating missing keys as empty sets. Intersections stop once
+    /// `limit` members are found.
+    fn _compute_set_op(
+        &mut self,
+        op: SetOp,
+        keys: &[Bytes],
+        limit: usize,
+    ) -> Result<HashSet<Bytes>, DbError> {
+        keys.iter().for_each(|key| self._lookup_key(key));
+        let sets = keys
+            .iter()
+            .map(|key| match self.db.get(key) {
+                None => Ok(None),
+                Some(DatabaseValue {
+                    value: DbValueType::Set(set),
+                    ..
+                }) => Ok(Some(set)),
+                Some(_) => Err(DbError::WrongType),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = match op {
+            SetOp::Union => sets.into_iter().flatten().flatten().cloned().collect(),
+            SetOp::Diff => {
+                let mut sets = sets.into_iter();
+                let Some(Some(first)) = sets.next() else {
+                    return Ok(HashSet::new());
+                };
+                let others = sets.flatten().collect::<Vec<_>>();
+                first
+                    .iter()
+                    .filter(|member| !others.iter().any(|set| set.contains(*member)))
+                    .cloned()
+                    .collect()
+            }
+            SetOp::Inter => {
+                let Some(mut sets) = sets.into_iter().collect::<Option<Vec<_>>>() else {
+                    return Ok(HashSet::new());
+                };
+                // Walk the smallest set and probe the others
+                sets.sort_by_key(|set| set.len());
+                let Some((smallest, others)) = sets.split_first() else {
+                    return Ok(HashSet::new());
+                };
+                smallest
+                    .iter()
+                    .filter(|member| others.iter().all(|set| set.contains(*member)))
+                    .take(limit)
+                    .cloned()
+                    .collect()
+            }
+        };
+        Ok(result)
+    }
+
+    pub(super) fn _sscan(
+        &mut self,
+        key: &Bytes,
+        cursor: u64,
+        options: &ScanOptions,
+    ) -> Result<(u64, Vec<Bytes>), DbError> {
+        let Some(set) = self._get_set(key)? else {
+            return Ok((0, vec![]));
+        };
+        let (cursor, members) = set.scan(cursor, options.count);
+        let members = members
+            .into_iter()
+            .filter(|member| options.matches(member))
+            .cloned()
+            .collect();
+        Ok((cursor, members))
+    }
+
+    fn _get_set(&mut self, key: &Bytes) -> Result<Option<&mut ScanSet>, DbError> {
+        self._lookup_key(key);
+        match self.db.get_mut(key) {
+            None => Ok(None),
+            Some(DatabaseValue {
+                value: DbValueType::Set(set),
+                ..
+            }) => Ok(Some(set)),
+            Some(_) => Err(DbError::WrongType),
+        }
+    }
+
+    fn _get_or_create_set(&mut self, key: &Bytes) -> Result<&mut ScanSet, DbError> {
+        self._lookup_key(key);
+        let db_value = self.db.get_or_insert_with(key, || {
+            DatabaseValue::new(DbValueType::Set(ScanSet::default()), None)
+        });
+        match &mut db_value.value {
+            DbValueType::Set(set) => Ok(set),
+            _ => Err(DbError::WrongType),
+        }
+    }
+
+    fn _remove_if_empty_set(&mut self, key: &Bytes) {
+        if let Some(DatabaseValue {
+            value: DbValueType::Set(set),
+            ..
+        }) = self.db.get(key)
+        {
+            if set.is_empty() {
+                self.db.remove(key);
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::{cmp::Ordering, collections::VecDeque};
+
+use bytes::Bytes;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{DatabaseValue, DbError, DbValueType, SortOptions};
+use super::{parse_float, Database};
+
+/// What elements are compared by, worked out once per element before sorting
+enum SortKey {
+    Score(f64),
+    /// Missing values sort first
+    Text(Option<Bytes>),
+}
+
+impl Database {
+    pub async fn sort(key: &Bytes, options: &SortOptions) -> anyhow::Result<Vec<Option<Bytes>>> {
+        Database::request(|emitter| Sort {
+            emitter,
+            key: key.to_owned(),
+            options: options.clone(),
+        })
+        .await
+    }
+
+    /// SORT with STORE, returning how many elements were stored
+    pub async fn sort_store(
+        destination: &Bytes,
+        key: &Bytes,
+        options: &SortOptions,
+    ) -> anyhow::Result<i64> {
+        Database::request(|emitter| SortStore {
+            emitter,
+            destination: destination.to_owned(),
+            key: key.to_owned(),
+            options: options.clone(),
+        })
+        .await
+    }
+
+    pub(super) fn _sort(
+        &mut self,
+        key: &Bytes,
+        options: &SortOptions,
+    ) -> Result<Vec<Option<Bytes>>, DbError> {
+        self._sort_elements(key, options, false)
+    }
+
+    /// Missing GET values are stored as empty strings, and an empty result deletes
+    /// `destination`
+    pub(super) fn _sort_store(
+        &mut self,
+        destination: &Bytes,
+        key: &Bytes,
+        options: &SortOptions,
+    ) -> Result<i64, DbError> {
+        let list = self
+            ._sort_elements(key, options, true)?
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect::<VecDeque<_>>();
+        let len = list.len();
+        self._lookup_key(destination);
+        self.db.remove(destination);
+        if !list.is_empty() {
+            self.db.insert(
+                destination.to_owned(),
+                DatabaseValue::new(DbValueType::List(list), None),
+            );
+        }
+        Ok(len as i64)
+    }
+
+    fn _sort_elements(
+        &mut self,
+        key: &Bytes,
+        options: &SortOptions,
+        store: bool,
+    ) -> Result<Vec<Option<Bytes>>, DbError> {
+        self._lookup_key(key);
+        let (mut elements, is_set) = match self.db.get(key).map(|db_value| &db_value.value) {
+            None => (vec![], false),
+            Some(DbValueType::List(list)) => (list.iter().cloned().collect::<Vec<_>>(), false),
+            Some(DbValueType::Set(set)) => (set.iter().cloned().collect(), true),
+            Some(DbValueType::SortedSet(zset)) => (
+                zset.iter(false).map(|(member, _)| member.clone()).collect(),
+                false,
+            ),
+            Some(_) => return Err(DbError::WrongType),
+        };
+        // A BY pattern without a `*` looks up the same key for every element, which leaves
+        // the elements in the order the collection keeps them
+        let mut by = options.by.as_ref();
+        let mut alpha = options.alpha;
+        let mut dont_sort = by.is_some_and(|by| !by.contains(&b'*'));
+        // Sets have no order of their own, so what gets stored is sorted by the elements
+        // themselves to come out the same every time
+        if dont_sort && is_set && store {
+            (dont_sort, alpha, by) = (false, true, None);
+        }
+        if dont_sort {
+            if options.desc {
+                elements.reverse();
+            }
+        } else {
+            let mut keyed = elements
+                .into_iter()
+                .map(|element| {
+                    let value = match by {
+                        Some(by) => self._lookup_pattern(by, &element),
+                        None => Some(element.clone()),
+                    };
+                    let sort_key = match (alpha, value) {
+                        (true, value) => SortKey::Text(value),
+                        (false, None) => SortKey::Score(0.0),
+                        (false, Some(value)) => match parse_float(&value) {
+                            Some(score) => SortKey::Score(score),
+                            None => {
+                                return Err(DbError::UnableToPerformAction(
+                                    "ERR One or more scores can't be converted into double"
+                                        .to_string(),
+                                ))
+                            }
+                        },
+                    };
+                    Ok((sort_key, element))
+                })
+                .collect::<Result<Vec<_>, DbError>>()?;
+            keyed.sort_by(|(a, a_element), (b, b_element)| {
+                let ordering = match (a, b) {
+                    // Equal scores fall back to the elements so the order is always the same
+                    (SortKey::Score(a), SortKey::Score(b)) => a
+                        .partial_cmp(b)
+                        .unwrap_or(Ordering::Equal)
+                        .then_with(|| a_element.cmp(b_element)),
+                    (SortKey::Text(a), SortKey::Text(b)) => a.cmp(b),
+                    _ => Ordering::Equal,
+                };
+                match options.desc {
+                    true => ordering.reverse(),
+                    false => ordering,
+                }
+            });
+            elements = keyed.into_iter().map(|(_, element)| element).collect();
+        }
+        let (offset, count) = options.limit.unwrap_or((0, -1));
+        let count = usize::try_from(count).unwrap_or(usize::MAX);
+        let elements = elements
+            .into_iter()
+            .skip(offset.max(0) as usize)
+            .take(count)
+            .collect::<Vec<_>>();
+        if options.get.is_empty() {
+            return Ok(elements.into_iter().map(Some).collect());
+        }
+        let mut values = Vec::with_capacity(elements.len() * options.get.len());
+        for element in &elements {
+            for pattern in &options.get {
+                values.push(self._lookup_pattern(pattern, element));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Value a BY or GET pattern points at for `element`. The first `*` is replaced by the
+    /// element to get a key name, whose string value is used, unless the pattern ends in
+    /// `->field` in which case it is that field of a hash. `#` stands for the element itself.
+    fn _lookup_pattern(&mut self, pattern: &[u8], element: &Bytes) -> Option<Bytes> {
+        if pattern == b"#" {
+            return Some(element.clone());
+        }
+        let star = pattern.iter().position(|&c| c == b'*')?;
+        // Only an arrow after the `*` with a field name following it counts
+        let arrow = pattern
+            .windows(2)
+            .position(|window| window == b"->")
+            .filter(|&arrow| arrow > star && arrow + 2 < pattern.len());
+        let key_pattern = &pattern[..arrow.unwrap_or(pattern.len())];
+        let key = Bytes::from([&key_pattern[..star], element, &key_pattern[star + 1..]].concat());
+        self._lookup_key(&key);
+        match (&self.db.get(&key)?.value, arrow) {
+            (DbValueType::String(value), None) => Some(value.clone()),
+            (DbValueType::Integer(value), None) => Some(Bytes::from(value.to_string())),
+            (DbValueType::Hash(hash), Some(arrow)) => hash.get(&pattern[arrow + 2..]).cloned(),
+            _ => None,
+        }
+    }
+}
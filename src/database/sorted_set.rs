@@ -0,0 +1,641 @@
+use std::mem::size_of;
+
+use bytes::Bytes;
+use rand::Rng;
+
+use super::db_event::{LexBound, ScoreBound};
+use super::scan::ScanMap;
+
+const MAX_LEVEL: usize = 32;
+/// Chance of a node reaching the next level up
+const LEVEL_PROBABILITY: f64 = 0.25;
+/// Marks a missing link between nodes
+const NIL: usize = usize::MAX;
+/// The head node always sits in the first slot and never holds a member
+const HEAD: usize = 0;
+
+/// Members ordered by `(score, member)`. Scores are looked up by member in constant time and
+/// the skip list keeps rank lookups and range seeks logarithmic.
+#[derive(Clone, Debug, Default)]
+pub struct SortedSet {
+    scores: ScanMap<f64>,
+    list: SkipList,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Bytes the set takes besides its members, assuming nodes have the expected 4/3 levels
+    pub fn overhead(&self) -> usize {
+        self.scores.capacity() * size_of::<(Bytes, f64)>()
+            + self.scores.overhead()
+            + self.list.nodes.capacity() * (size_of::<Node>() + size_of::<Link>() * 4 / 3)
+    }
+
+    /// The next batch of members for ZSCAN, see `Scannable::scan`
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, f64)>) {
+        let (cursor, members) = self.scores.scan(cursor, count);
+        let members = members
+            .into_iter()
+            .map(|member| (member, self.scores[member]))
+            .collect();
+        (cursor, members)
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Inserts `member` or moves it to its new score, returning whether it is a new member
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        // Keeps -0.0 and 0.0 from ordering differently
+        let score = score + 0.0;
+        match self.scores.get(&member).copied() {
+            Some(old) if old == score => false,
+            Some(old) => {
+                self.list.remove(&member, old);
+                self.list.insert(member.clone(), score);
+                self.scores.insert(member, score);
+                false
+            }
+            None => {
+                self.list.insert(member.clone(), score);
+                self.scores.insert(member, score);
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.list.remove(member, score);
+        Some(score)
+    }
+
+    /// Zero based position of `member` in ascending order
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        self.list.rank(member, score)
+    }
+
+    pub fn iter(&self, rev: bool) -> Iter<'_> {
+        let start = if rev {
+            self.list.tail
+        } else {
+            self.list.first()
+        };
+        self.list.iter_from(start, rev)
+    }
+
+    /// Members between the zero based ranks `start` and `stop` inclusive, where ranks count
+    /// from the highest score when `rev` is set
+    pub fn range_by_rank(
+        &self,
+        start: usize,
+        stop: usize,
+        rev: bool,
+    ) -> impl Iterator<Item = (&Bytes, f64)> {
+        let start_rank = match rev {
+            true => self.len().checked_sub(start + 1),
+            false => Some(start),
+        };
+        let start_node = start_rank
+            .and_then(|rank| self.list.by_rank(rank))
+            .unwrap_or(NIL);
+        self.list
+            .iter_from(start_node, rev)
+            .take((stop + 1).saturating_sub(start))
+    }
+
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+        rev: bool,
+    ) -> impl Iterator<Item = (&Bytes, f64)> {
+        let start = match rev {
+            true => self.list.last_where(|node| !max.is_after_max(node.score)),
+            false => self.list.first_where(|node| min.is_before_min(node.score)),
+        };
+        let start = start.map(|(node, _)| node).unwrap_or(NIL);
+        self.list
+            .iter_from(start, rev)
+            .take_while(move |(_, score)| match rev {
+                true => !min.is_before_min(*score),
+                false => !max.is_after_max(*score),
+            })
+    }
+
+    /// Lexicographic ranges assume every member shares the same score, like Redis does
+    pub fn range_by_lex<'a>(
+        &'a self,
+        min: &'a LexBound,
+        max: &'a LexBound,
+        rev: bool,
+    ) -> impl Iterator<Item = (&'a Bytes, f64)> {
+        let start = match rev {
+            true => self.list.last_where(|node| !max.is_after_max(&node.member)),
+            false => self
+                .list
+                .first_where(|node| min.is_before_min(&node.member)),
+        };
+        let start = start.map(|(node, _)| node).unwrap_or(NIL);
+        self.list
+            .iter_from(start, rev)
+            .take_while(move |(member, _)| match rev {
+                true => !min.is_before_min(member),
+                false => !max.is_after_max(member),
+            })
+    }
+
+    pub fn count_by_score(&self, min: ScoreBound, max: ScoreBound) -> usize {
+        let first = self.list.first_where(|node| min.is_before_min(node.score));
+        let last = self.list.last_where(|node| !max.is_after_max(node.score));
+        match (first, last) {
+            (Some((_, first)), Some((_, last))) if last >= first => last - first + 1,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Link {
+    forward: usize,
+    /// Number of level zero steps this link skips over
+    span: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    backward: usize,
+    levels: Vec<Link>,
+}
+
+impl Node {
+    fn precedes(&self, member: &[u8], score: f64) -> bool {
+        self.score < score || (self.score == score && self.member.as_ref() < member)
+    }
+}
+
+/// Skip list with spans on every link, as in Redis, so ranks are tracked while seeking. Nodes
+/// live in an arena and link to each other by index.
+#[derive(Clone, Debug)]
+struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: usize,
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            member: Bytes::new(),
+            score: 0.0,
+            backward: NIL,
+            levels: vec![
+                Link {
+                    forward: NIL,
+                    span: 0
+                };
+                MAX_LEVEL
+            ],
+        };
+        SkipList {
+            nodes: vec![head],
+            free: vec![],
+            tail: NIL,
+            level: 1,
+            len: 0,
+        }
+    }
+}
+
+impl SkipList {
+    fn first(&self) -> usize {
+        self.nodes[HEAD].levels[0].forward
+    }
+
+    fn iter_from(&self, start: usize, rev: bool) -> Iter<'_> {
+        Iter {
+            list: self,
+            cursor: start,
+            rev,
+        }
+    }
+
+    /// Finds the last node before the insertion point of `(score, member)` on every level
+    fn predecessors(&self, member: &[u8], score: f64) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+            loop {
+                let Link { forward, span } = self.nodes[x].levels[i];
+                if forward == NIL || !self.nodes[forward].precedes(member, score) {
+                    break;
+                }
+                rank[i] += span;
+                x = forward;
+            }
+            update[i] = x;
+        }
+        (update, rank)
+    }
+
+    fn insert(&mut self, member: Bytes, score: f64) {
+        let (mut update, mut rank) = self.predecessors(&member, score);
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+        let node = Node {
+            member,
+            score,
+            backward: if update[0] == HEAD { NIL } else { update[0] },
+            levels: vec![
+                Link {
+                    forward: NIL,
+                    span: 0
+                };
+                level
+            ],
+        };
+        let x = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        for i in 0..level {
+            let prev = self.nodes[update[i]].levels[i];
+            let skipped = rank[0] - rank[i];
+            self.nodes[x].levels[i] = Link {
+                forward: prev.forward,
+                span: prev.span - skipped,
+            };
+            self.nodes[update[i]].levels[i] = Link {
+                forward: x,
+                span: skipped + 1,
+            };
+        }
+        for (i, prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*prev].levels[i].span += 1;
+        }
+        match self.nodes[x].levels[0].forward {
+            NIL => self.tail = x,
+            next => self.nodes[next].backward = x,
+        }
+        self.len += 1;
+    }
+
+    fn remove(&mut self, member: &[u8], score: f64) -> bool {
+        let (update, _) = self.predecessors(member, score);
+        let x = self.nodes[update[0]].levels[0].forward;
+        if x == NIL || self.nodes[x].score != score || self.nodes[x].member != member {
+            return false;
+        }
+        for (i, prev) in update.iter().enumerate().take(self.level) {
+            let removed = self.nodes[x].levels.get(i).copied();
+            let link = &mut self.nodes[*prev].levels[i];
+            match removed {
+                Some(removed) if link.forward == x => {
+                    // Links past the tail may have a span of 0, so add before subtracting
+                    link.span = link.span + removed.span - 1;
+                    link.forward = removed.forward;
+                }
+                _ => link.span -= 1,
+            }
+        }
+        let backward = self.nodes[x].backward;
+        match self.nodes[x].levels[0].forward {
+            NIL => self.tail = backward,
+            next => self.nodes[next].backward = backward,
+        }
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].forward == NIL {
+            self.level -= 1;
+        }
+        // Drop the member now rather than when the slot gets reused
+        self.nodes[x].member = Bytes::new();
+        self.nodes[x].levels = vec![];
+        self.free.push(x);
+        self.len -= 1;
+        true
+    }
+
+    fn rank(&self, member: &[u8], score: f64) -> Option<usize> {
+        let (node, rank) = self.first_where(|node| node.precedes(member, score))?;
+        (self.nodes[node].member == member).then_some(rank)
+    }
+
+    /// Node at the zero based `rank`
+    fn by_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let Link { forward, span } = self.nodes[x].levels[i];
+                if forward == NIL || traversed + span > target {
+                    break;
+                }
+                traversed += span;
+                x = forward;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// First node, with its rank, for which `before` no longer holds. `before` must hold for a
+    /// prefix of the list.
+    fn first_where(&self, before: impl Fn(&Node) -> bool) -> Option<(usize, usize)> {
+        let (x, traversed) = self.seek(before);
+        match self.nodes[x].levels[0].forward {
+            NIL => None,
+            first => Some((first, traversed)),
+        }
+    }
+
+    /// Last node, with its rank, for which `within` holds. `within` must hold for a prefix of
+    /// the list.
+    fn last_where(&self, within: impl Fn(&Node) -> bool) -> Option<(usize, usize)> {
+        let (x, traversed) = self.seek(within);
+        (x != HEAD).then(|| (x, traversed - 1))
+    }
+
+    /// Walks to the last node matching `predicate`, returning it with its one based rank
+    fn seek(&self, predicate: impl Fn(&Node) -> bool) -> (usize, usize) {
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            loop {
+                let Link { forward, span } = self.nodes[x].levels[i];
+                if forward == NIL || !predicate(&self.nodes[forward]) {
+                    break;
+                }
+                traversed += span;
+                x = forward;
+            }
+        }
+        (x, traversed)
+    }
+}
+
+fn random_level() -> usize {
+    let mut rng = rand::thread_rng();
+    let mut level = 1;
+    while level < MAX_LEVEL && rng.gen_bool(LEVEL_PROBABILITY) {
+        level += 1;
+    }
+    level
+}
+
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    cursor: usize,
+    rev: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Bytes, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.cursor == NIL {
+            return None;
+        }
+        let node = &self.list.nodes[self.cursor];
+        self.cursor = match self.rev {
+            true => node.backward,
+            false => node.levels[0].forward,
+        };
+        Some((&node.member, node.score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    /// Integer scores keep the oracle ordered the way the skip list orders `(score, member)`
+    type Oracle = BTreeSet<(i64, Bytes)>;
+
+    fn member(i: usize) -> Bytes {
+        Bytes::from(format!("m{i:03}"))
+    }
+
+    /// Builds a set and its oracle from a fixed seed, with plenty of tied scores
+    fn random_set(seed: u64, ops: usize) -> (SortedSet, Oracle) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (mut zset, mut oracle) = (SortedSet::default(), Oracle::new());
+        for _ in 0..ops {
+            let name = member(rng.gen_range(0..150));
+            let old = zset.score(&name).map(|score| (score as i64, name.clone()));
+            if let Some(old) = &old {
+                oracle.remove(old);
+            }
+            if rng.gen_bool(0.3) {
+                assert_eq!(zset.remove(&name).is_some(), old.is_some());
+                continue;
+            }
+            let score = rng.gen_range(-20..20);
+            assert_eq!(zset.insert(name.clone(), score as f64), old.is_none());
+            oracle.insert((score, name));
+        }
+        (zset, oracle)
+    }
+
+    fn members<'a>(items: impl Iterator<Item = (&'a Bytes, f64)>) -> Vec<(i64, Bytes)> {
+        items
+            .map(|(member, score)| (score as i64, member.clone()))
+            .collect()
+    }
+
+    fn assert_matches(zset: &SortedSet, oracle: &Oracle) {
+        assert_eq!(zset.len(), oracle.len());
+        assert_eq!(
+            members(zset.iter(false)),
+            oracle.iter().cloned().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            members(zset.iter(true)),
+            oracle.iter().rev().cloned().collect::<Vec<_>>()
+        );
+        for (rank, (score, name)) in oracle.iter().enumerate() {
+            assert_eq!(zset.score(name), Some(*score as f64));
+            assert_eq!(zset.rank(name), Some(rank));
+            let node = zset.list.by_rank(rank).expect("rank is in range");
+            assert_eq!(zset.list.nodes[node].member, name);
+        }
+        assert_eq!(zset.list.by_rank(oracle.len()), None);
+    }
+
+    #[test]
+    fn inserts_updates_and_removes_keep_order_and_ranks() {
+        for seed in 0..20 {
+            let (zset, oracle) = random_set(seed, 500);
+            assert_matches(&zset, &oracle);
+        }
+    }
+
+    #[test]
+    fn removing_everything_empties_the_list() {
+        let (mut zset, oracle) = random_set(7, 300);
+        for (_, name) in &oracle {
+            assert!(zset.remove(name).is_some());
+        }
+        assert!(zset.is_empty());
+        assert_eq!(zset.rank(b"m000"), None);
+        assert_eq!(zset.iter(false).next(), None);
+        assert_eq!(zset.iter(true).next(), None);
+        assert_eq!(zset.list.level, 1);
+        // Freed slots are reused
+        zset.insert(member(0), 1.0);
+        assert_matches(&zset, &Oracle::from([(1, member(0))]));
+    }
+
+    #[test]
+    fn rank_ranges_count_from_either_end() {
+        let (zset, oracle) = random_set(3, 400);
+        let ascending = oracle.iter().cloned().collect::<Vec<_>>();
+        let descending = oracle.iter().rev().cloned().collect::<Vec<_>>();
+        for (start, stop) in [
+            (0_usize, 0_usize),
+            (0, 9),
+            (5, 20),
+            (30, 1000),
+            (1000, 2000),
+        ] {
+            let expected = |items: &[(i64, Bytes)]| {
+                items
+                    .iter()
+                    .skip(start)
+                    .take((stop + 1).saturating_sub(start))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                members(zset.range_by_rank(start, stop, false)),
+                expected(&ascending)
+            );
+            assert_eq!(
+                members(zset.range_by_rank(start, stop, true)),
+                expected(&descending)
+            );
+        }
+    }
+
+    #[test]
+    fn score_ranges_honour_inclusive_and_exclusive_bounds() {
+        let (zset, oracle) = random_set(11, 400);
+        let bounds = |value: f64| [ScoreBound::Inclusive(value), ScoreBound::Exclusive(value)];
+        for (low, high) in [
+            (-5.0, 5.0),
+            (0.0, 0.0),
+            (-100.0, 100.0),
+            (3.0, -3.0),
+            (19.0, 50.0),
+        ] {
+            for min in bounds(low) {
+                for max in bounds(high) {
+                    let expected = oracle
+                        .iter()
+                        .filter(|(score, _)| {
+                            let score = *score as f64;
+                            !min.is_before_min(score) && !max.is_after_max(score)
+                        })
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    assert_eq!(members(zset.range_by_score(min, max, false)), expected);
+                    let reversed = expected.iter().rev().cloned().collect::<Vec<_>>();
+                    assert_eq!(members(zset.range_by_score(min, max, true)), reversed);
+                    assert_eq!(zset.count_by_score(min, max), expected.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lex_ranges_honour_open_and_closed_bounds() {
+        let mut zset = SortedSet::default();
+        let names = ["a", "b", "c", "d", "e"];
+        for name in names {
+            zset.insert(Bytes::from(name), 0.0);
+        }
+        let lex = |bound: &str| match bound.split_at(1) {
+            ("[", value) => LexBound::Inclusive(Bytes::from(value.to_owned())),
+            ("(", value) => LexBound::Exclusive(Bytes::from(value.to_owned())),
+            ("-", _) => LexBound::Min,
+            _ => LexBound::Max,
+        };
+        for (min, max, expected) in [
+            ("-", "+", "abcde"),
+            ("[b", "[d", "bcd"),
+            ("(b", "(d", "c"),
+            ("(b", "[bb", ""),
+            ("[0", "(c", "ab"),
+            ("[d", "[zz", "de"),
+            ("+", "-", ""),
+            ("[c", "[a", ""),
+        ] {
+            let (min, max) = (lex(min), lex(max));
+            let found = |rev| {
+                zset.range_by_lex(&min, &max, rev)
+                    .map(|(member, _)| String::from_utf8_lossy(member).into_owned())
+                    .collect::<String>()
+            };
+            assert_eq!(found(false), expected);
+            assert_eq!(found(true), expected.chars().rev().collect::<String>());
+        }
+    }
+
+    #[test]
+    fn seeks_find_the_edges_of_a_prefix() {
+        let mut zset = SortedSet::default();
+        for (i, score) in [1.0, 2.0, 2.0, 3.0].into_iter().enumerate() {
+            zset.insert(member(i), score);
+        }
+        let list = &zset.list;
+        let found = |found: Option<(usize, usize)>| {
+            found.map(|(node, rank)| (list.nodes[node].member.clone(), rank))
+        };
+        assert_eq!(
+            found(list.first_where(|node| node.score < 2.0)),
+            Some((member(1), 1))
+        );
+        assert_eq!(
+            found(list.last_where(|node| node.score <= 2.0)),
+            Some((member(2), 2))
+        );
+        // Nothing left past the prefix, or no prefix at all
+        assert_eq!(found(list.first_where(|node| node.score < 10.0)), None);
+        assert_eq!(found(list.first_where(|_| false)), Some((member(0), 0)));
+        assert_eq!(found(list.last_where(|_| false)), None);
+        assert_eq!(found(list.last_where(|_| true)), Some((member(3), 3)));
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::{btree_map, BTreeMap},
+    mem::size_of,
+};
+
+use bytes::Bytes;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{
+    DatabaseValue, DbError, DbValueType, StreamDbValueType, StreamId, StreamTrim, TrimStrategy,
+};
+use super::Database;
+
+/// Entries in one node of a Redis stream, Redis' default stream-node-max-entries. Approximate
+/// trimming only removes whole nodes.
+pub(super) const NODE_MAX_ENTRIES: usize = 100;
+
+/// Entries of a stream indexed by ID, so lookups and range seeks are logarithmic. The last ID
+/// handed out is kept apart from the entries, so IDs never go backwards once the newest
+/// entries are deleted.
+#[derive(Clone, Debug, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, StreamDbValueType>,
+    last_id: StreamId,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Bytes the stream takes besides the fields of its entries
+    pub fn overhead(&self) -> usize {
+        self.len() * (size_of::<StreamId>() + size_of::<StreamDbValueType>())
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// Makes the stream look like it already handed out `id`, as a restored stream may have
+    pub fn raise_last_id(&mut self, id: StreamId) {
+        self.last_id = self.last_id.max(id);
+    }
+
+    pub fn iter(&self) -> btree_map::Values<'_, StreamId, StreamDbValueType> {
+        self.entries.values()
+    }
+
+    /// Entries with IDs from `start` to `end`, both included
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl DoubleEndedIterator<Item = &StreamDbValueType> {
+        // Ranges that end before they start make BTreeMap::range panic
+        (start <= end)
+            .then(|| self.entries.range(start..=end))
+            .into_iter()
+            .flatten()
+            .map(|(_, entry)| entry)
+    }
+
+    /// Appends `entry`, whose ID must be above every ID handed out so far
+    pub fn push(&mut self, entry: StreamDbValueType) {
+        self.last_id = entry.id();
+        self.entries.insert(self.last_id, entry);
+    }
+
+    pub fn remove(&mut self, id: StreamId) -> bool {
+        self.entries.remove(&id).is_some()
+    }
+
+    /// Drops the oldest entries the way XTRIM does, returning how many were dropped
+    pub fn trim(&mut self, trim: &StreamTrim) -> usize {
+        let excess = match trim.strategy {
+            TrimStrategy::MaxLen(max_len) => self.len().saturating_sub(max_len),
+            TrimStrategy::MinId(min_id) => self.entries.range(..min_id).count(),
+        };
+        let mut count = match trim.limit {
+            0 => excess,
+            limit => excess.min(limit),
+        };
+        if trim.approximate {
+            count -= count % NODE_MAX_ENTRIES;
+        }
+        for _ in 0..count {
+            self.entries.pop_first();
+        }
+        count
+    }
+}
+
+impl StreamDbValueType {
+    pub fn id(&self) -> StreamId {
+        (self.stream_id_ms_part, self.stream_id_seq_part)
+    }
+}
+
+impl Database {
+    pub async fn xtrim(key: &Bytes, trim: &StreamTrim) -> anyhow::Result<i64> {
+        Database::request(|emitter| XTrim {
+            emitter,
+            key: key.to_owned(),
+            trim: trim.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn xdel(key: &Bytes, ids: &[StreamId]) -> anyhow::Result<i64> {
+        Database::request(|emitter| XDel {
+            emitter,
+            key: key.to_owned(),
+            ids: ids.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn xlen(key: &Bytes) -> anyhow::Result<i64> {
+        Database::request(|emitter| XLen {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    /// Trimming leaves the key in place even when no entries are left
+    pub(super) fn _xtrim(&mut self, key: &Bytes, trim: &StreamTrim) -> Result<i64, DbError> {
+        match self._get_stream(key)? {
+            None => Ok(0),
+            Some(stream) => Ok(stream.trim(trim) as i64),
+        }
+    }
+
+    pub(super) fn _xdel(&mut self, key: &Bytes, ids: &[StreamId]) -> Result<i64, DbError> {
+        let Some(stream) = self._get_stream(key)? else {
+            return Ok(0);
+        };
+        Ok(ids.iter().filter(|&&id| stream.remove(id)).count() as i64)
+    }
+
+    pub(super) fn _xlen(&mut self, key: &Bytes) -> Result<i64, DbError> {
+        Ok(self
+            ._get_stream(key)?
+            .map_or(0, |stream| stream.len() as i64))
+    }
+
+    pub(super) fn _get_stream(&mut self, key: &Bytes) -> Result<Option<&mut Stream>, DbError> {
+        self._lookup_key(key);
+        match self.db.get_mut(key) {
+            None => Ok(None),
+            Some(DatabaseValue {
+                value: DbValueType::Stream(stream),
+                ..
+            }) => Ok(Some(stream)),
+            Some(_) => Err(DbError::WrongType),
+        }
+    }
+
+    pub(super) fn _get_or_create_stream(&mut self, key: &Bytes) -> Result<&mut Stream, DbError> {
+        self._lookup_key(key);
+        let db_value = self.db.get_or_insert_with(key, || {
+            DatabaseValue::new(DbValueType::Stream(Stream::default()), None)
+        });
+        match &mut db_value.value {
+            DbValueType::Stream(stream) => Ok(stream),
+            _ => Err(DbError::WrongType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(ids: impl IntoIterator<Item = StreamId>) -> Stream {
+        let mut stream = Stream::default();
+        for (ms_part, seq_part) in ids {
+            stream.push(StreamDbValueType {
+                stream_id_ms_part: ms_part,
+                stream_id_seq_part: seq_part,
+                fields: vec![(Bytes::from("field"), Bytes::from("value"))],
+            });
+        }
+        stream
+    }
+
+    fn ids(stream: &Stream) -> Vec<StreamId> {
+        stream.iter().map(StreamDbValueType::id).collect()
+    }
+
+    fn trim(strategy: TrimStrategy, approximate: bool, limit: usize) -> StreamTrim {
+        StreamTrim {
+            strategy,
+            approximate,
+            limit,
+        }
+    }
+
+    #[test]
+    fn trim_by_max_len_drops_the_oldest_entries() {
+        let mut stream = stream((1..=5).map(|ms| (ms, 0)));
+        assert_eq!(stream.trim(&trim(TrimStrategy::MaxLen(2), false, 0)), 3);
+        assert_eq!(ids(&stream), vec![(4, 0), (5, 0)]);
+        assert_eq!(stream.trim(&trim(TrimStrategy::MaxLen(10), false, 0)), 0);
+        assert_eq!(stream.len(), 2);
+    }
+
+    #[test]
+    fn trim_by_min_id_drops_lower_ids() {
+        let mut stream = stream([(1, 0), (1, 1), (2, 0), (3, 0)]);
+        assert_eq!(stream.trim(&trim(TrimStrategy::MinId((1, 1)), false, 0)), 1);
+        assert_eq!(ids(&stream), vec![(1, 1), (2, 0), (3, 0)]);
+        assert_eq!(stream.trim(&trim(TrimStrategy::MinId((9, 0)), false, 0)), 3);
+        assert_eq!(stream.len(), 0);
+    }
+
+    #[test]
+    fn trim_stops_at_the_limit() {
+        let mut stream = stream((1..=5).map(|ms| (ms, 0)));
+        assert_eq!(stream.trim(&trim(TrimStrategy::MaxLen(0), false, 2)), 2);
+        assert_eq!(stream.len(), 3);
+    }
+
+    #[test]
+    fn approximate_trim_only_drops_whole_nodes() {
+        let len = NODE_MAX_ENTRIES * 2 + 50;
+        let mut stream = stream((1..=len as u128).map(|ms| (ms, 0)));
+        let dropped = stream.trim(&trim(TrimStrategy::MaxLen(10), true, 0));
+        assert_eq!(dropped, NODE_MAX_ENTRIES * 2);
+        assert_eq!(stream.len(), 50);
+        assert_eq!(stream.trim(&trim(TrimStrategy::MaxLen(10), true, 0)), 0);
+    }
+
+    #[test]
+    fn range_includes_both_ends() {
+        let stream = stream([(1, 0), (1, 1), (2, 0), (3, 0)]);
+        let range = |start, end| {
+            stream
+                .range(start, end)
+                .map(StreamDbValueType::id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(range((1, 1), (2, 0)), vec![(1, 1), (2, 0)]);
+        assert_eq!(range((0, 0), (1, usize::MAX)), vec![(1, 0), (1, 1)]);
+        assert_eq!(range((2, 1), (2, 9)), vec![]);
+        assert_eq!(range((3, 0), (3, 0)), vec![(3, 0)]);
+    }
+
+    #[test]
+    fn range_reads_backwards() {
+        let stream = stream([(1, 0), (2, 0), (3, 0)]);
+        let ids = stream
+            .range((0, 0), (2, 0))
+            .rev()
+            .map(StreamDbValueType::id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![(2, 0), (1, 0)]);
+    }
+
+    #[test]
+    fn range_ending_before_it_starts_is_empty() {
+        let stream = stream([(1, 0), (2, 0)]);
+        assert_eq!(stream.range((2, 0), (1, 0)).count(), 0);
+    }
+
+    #[test]
+    fn last_id_survives_trimming_and_deletes() {
+        let mut stream = stream([(1, 0), (2, 0)]);
+        assert!(stream.remove((2, 0)));
+        assert!(!stream.remove((2, 0)));
+        stream.trim(&trim(TrimStrategy::MaxLen(0), false, 0));
+        assert_eq!(stream.len(), 0);
+        assert_eq!(stream.last_id(), (2, 0));
+        stream.raise_last_id((1, 0));
+        assert_eq!(stream.last_id(), (2, 0));
+        stream.raise_last_id((3, 5));
+        assert_eq!(stream.last_id(), (3, 5));
+    }
+}
@@ -0,0 +1,350 @@
+use bytes::Bytes;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{DbError, DbValueType, Expiry, LcsMatch, SetOptions, Subsequence};
+use super::list::normalize_range;
+use super::{expiry_time, format_float, parse_float, parse_i64, string_value, Database};
+
+impl Database {
+    pub async fn append(key: &Bytes, value: &Bytes) -> anyhow::Result<i64> {
+        Database::request(|emitter| Append {
+            emitter,
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn strlen(key: &Bytes) -> anyhow::Result<i64> {
+        Database::request(|emitter| StrLen {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn getrange(key: &Bytes, start: i64, end: i64) -> anyhow::Result<Bytes> {
+        Database::request(|emitter| GetRange {
+            emitter,
+            key: key.to_owned(),
+            start,
+            end,
+        })
+        .await
+    }
+
+    pub async fn setrange(key: &Bytes, offset: usize, value: &Bytes) -> anyhow::Result<i64> {
+        Database::request(|emitter| SetRange {
+            emitter,
+            key: key.to_owned(),
+            offset,
+            value: value.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn mget(keys: &[Bytes]) -> anyhow::Result<Vec<Option<Bytes>>> {
+        Database::request(|emitter| MGet {
+            emitter,
+            keys: keys.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn mset(pairs: &[(Bytes, Bytes)], only_new: bool) -> anyhow::Result<bool> {
+        Database::request(|emitter| MSet {
+            emitter,
+            pairs: pairs.to_vec(),
+            only_new,
+        })
+        .await
+    }
+
+    pub async fn getdel(key: &Bytes) -> anyhow::Result<Option<Bytes>> {
+        Database::request(|emitter| GetDel {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn getex(key: &Bytes, expiry: Option<Expiry>) -> anyhow::Result<Option<Bytes>> {
+        Database::request(|emitter| GetEx {
+            emitter,
+            key: key.to_owned(),
+            expiry,
+        })
+        .await
+    }
+
+    pub async fn lcs(key1: &Bytes, key2: &Bytes) -> anyhow::Result<Subsequence> {
+        Database::request(|emitter| Lcs {
+            emitter,
+            key1: key1.to_owned(),
+            key2: key2.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn incrby(key: &Bytes, increment: i64) -> anyhow::Result<i64> {
+        Database::request(|emitter| IncrBy {
+            emitter,
+            key: key.to_owned(),
+            increment,
+        })
+        .await
+    }
+
+    pub async fn incrbyfloat(key: &Bytes, increment: f64) -> anyhow::Result<Bytes> {
+        Database::request(|emitter| IncrByFloat {
+            emitter,
+            key: key.to_owned(),
+            increment,
+        })
+        .await
+    }
+
+    /// SET with its options. With GET, a previous value that is not a string fails the command
+    /// before anything is written.
+    pub(super) fn _set_with_options(
+        &mut self,
+        key: &Bytes,
+        value: Bytes,
+        options: SetOptions,
+    ) -> Result<(bool, Option<Bytes>), DbError> {
+        self._lookup_key(key);
+        let previous = match options.get {
+            true => self._get_string(key)?,
+            false => None,
+        };
+        let exists = self.db.contains_key(key);
+        if (options.nx && exists) || (options.xx && !exists) {
+            return Ok((false, previous));
+        }
+        let exp_time = match (options.expiry, options.keep_ttl) {
+            (Some(expiry), _) => expiry_time(expiry),
+            (None, true) => self.db.get(key).and_then(|db_value| db_value.exp_time),
+            (None, false) => None,
+        };
+        self._set(key, string_value(value), exp_time);
+        Ok((true, previous))
+    }
+
+    pub(super) fn _append(&mut self, key: &Bytes, value: &Bytes) -> Result<i64, DbError> {
+        let mut bytes = self._get_string(key)?.unwrap_or_default().to_vec();
+        bytes.extend_from_slice(value);
+        let len = bytes.len();
+        self._store_string(key, Bytes::from(bytes));
+        Ok(len as i64)
+    }
+
+    pub(super) fn _strlen(&mut self, key: &Bytes) -> Result<i64, DbError> {
+        let len = self._get_string(key)?.map_or(0, |value| value.len());
+        Ok(len as i64)
+    }
+
+    pub(super) fn _getrange(
+        &mut self,
+        key: &Bytes,
+        start: i64,
+        end: i64,
+    ) -> Result<Bytes, DbError> {
+        let bytes = self._get_string(key)?.unwrap_or_default();
+        let range = match normalize_range(start, end, bytes.len()) {
+            Some((first, last)) => bytes.slice(first..=last),
+            None => Bytes::new(),
+        };
+        Ok(range)
+    }
+
+    /// Overwrites part of the string starting at `offset`, padding with zero bytes as needed.
+    /// An empty `value` never creates the key.
+    pub(super) fn _setrange(
+        &mut self,
+        key: &Bytes,
+        offset: usize,
+        value: &Bytes,
+    ) -> Result<i64, DbError> {
+        let current = self._get_string(key)?;
+        if value.is_empty() {
+            return Ok(current.map_or(0, |current| current.len()) as i64);
+        }
+        let mut bytes = current.unwrap_or_default().to_vec();
+        let end = offset + value.len();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[offset..end].copy_from_slice(value);
+        let len = bytes.len();
+        self._store_string(key, Bytes::from(bytes));
+        Ok(len as i64)
+    }
+
+    /// Keys that are missing or hold another type come back as `None` instead of failing
+    pub(super) fn _mget(&mut self, keys: &[Bytes]) -> Result<Vec<Option<Bytes>>, DbError> {
+        let values = keys
+            .iter()
+            .map(|key| self._get_string(key).ok().flatten())
+            .collect();
+        Ok(values)
+    }
+
+    /// Sets every pair in one go, clearing any expiry. With `only_new` nothing is written when
+    /// any of the keys already exists.
+    pub(super) fn _mset(
+        &mut self,
+        pairs: &[(Bytes, Bytes)],
+        only_new: bool,
+    ) -> Result<bool, DbError> {
+        if only_new {
+            for (key, _) in pairs {
+                self._lookup_key(key);
+                if self.db.contains_key(key) {
+                    return Ok(false);
+                }
+            }
+        }
+        for (key, value) in pairs {
+            self._set(key, string_value(value.to_owned()), None);
+        }
+        Ok(true)
+    }
+
+    pub(super) fn _getdel(&mut self, key: &Bytes) -> Result<Option<Bytes>, DbError> {
+        let value = self._get_string(key)?;
+        if value.is_some() {
+            self.db.remove(key);
+        }
+        Ok(value)
+    }
+
+    pub(super) fn _getex(
+        &mut self,
+        key: &Bytes,
+        expiry: Option<Expiry>,
+    ) -> Result<Option<Bytes>, DbError> {
+        let value = self._get_string(key)?;
+        if let (Some(expiry), Some(db_value)) = (expiry, self.db.get_mut(key)) {
+            db_value.exp_time = expiry_time(expiry);
+            if db_value.exp_time.is_some() {
+                self.volatile_keys.insert(key);
+            }
+        }
+        Ok(value)
+    }
+
+    /// Missing keys count as empty strings
+    pub(super) fn _lcs(&mut self, key1: &Bytes, key2: &Bytes) -> Result<Subsequence, DbError> {
+        let (Ok(first), Ok(second)) = (self._get_string(key1), self._get_string(key2)) else {
+            return Err(DbError::UnableToPerformAction(
+                "ERR The specified keys must contain string values".to_string(),
+            ));
+        };
+        let (first, second) = (first.unwrap_or_default(), second.unwrap_or_default());
+        let table = (first.len() + 1)
+            .checked_mul(second.len() + 1)
+            .and_then(|cells| cells.checked_mul(std::mem::size_of::<u32>()));
+        if table.map_or(true, |table| table > MAX_LCS_TABLE) {
+            return Err(DbError::UnableToPerformAction(
+                "ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len"
+                    .to_string(),
+            ));
+        }
+        Ok(longest_common_subsequence(&first, &second))
+    }
+
+    /// Adds to the integer stored at `key`, a missing key counting as 0. The expiry is kept.
+    pub(super) fn _incrby(&mut self, key: &Bytes, increment: i64) -> Result<i64, DbError> {
+        let current = match self._get_string(key)? {
+            None => 0,
+            Some(value) => parse_i64(&value).ok_or_else(|| {
+                DbError::UnableToPerformAction(
+                    "ERR value is not an integer or out of range".to_string(),
+                )
+            })?,
+        };
+        let Some(value) = current.checked_add(increment) else {
+            return Err(DbError::UnableToPerformAction(
+                "ERR increment or decrement would overflow".to_string(),
+            ));
+        };
+        self._store_value(key, DbValueType::Integer(value));
+        Ok(value)
+    }
+
+    pub(super) fn _incrbyfloat(&mut self, key: &Bytes, increment: f64) -> Result<Bytes, DbError> {
+        let current = match self._get_string(key)? {
+            None => 0.0,
+            Some(value) => parse_float(&value).ok_or_else(|| {
+                DbError::UnableToPerformAction("ERR value is not a valid float".to_string())
+            })?,
+        };
+        let value = current + increment;
+        if !value.is_finite() {
+            return Err(DbError::UnableToPerformAction(
+                "ERR increment would produce NaN or Infinity".to_string(),
+            ));
+        }
+        let value = Bytes::from(format_float(value));
+        self._store_value(key, string_value(value.clone()));
+        Ok(value)
+    }
+}
+
+/// Bytes the LCS table may take, the same limit as a bulk string
+const MAX_LCS_TABLE: usize = 512 * 1024 * 1024;
+
+/// Classic dynamic programming LCS. Walking the table back from the end of both strings
+/// yields the subsequence in reverse, and every step that leaves a run of consecutive matches
+/// closes that run.
+fn longest_common_subsequence(a: &[u8], b: &[u8]) -> Subsequence {
+    let width = b.len() + 1;
+    // lengths[i * width + j] is the LCS length of a[..i] and b[..j]
+    let mut lengths = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            lengths[i * width + j] = match a[i - 1] == b[j - 1] {
+                true => lengths[(i - 1) * width + j - 1] + 1,
+                false => lengths[(i - 1) * width + j].max(lengths[i * width + j - 1]),
+            };
+        }
+    }
+
+    let mut sequence = Vec::with_capacity(lengths[a.len() * width + b.len()] as usize);
+    let mut matches = vec![];
+    let mut run: Option<LcsMatch> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        if a[i - 1] == b[j - 1] {
+            sequence.push(a[i - 1]);
+            run = match run {
+                Some(current) if current.first.0 == i && current.second.0 == j => Some(LcsMatch {
+                    first: (i - 1, current.first.1),
+                    second: (j - 1, current.second.1),
+                }),
+                current => {
+                    matches.extend(current);
+                    Some(LcsMatch {
+                        first: (i - 1, i - 1),
+                        second: (j - 1, j - 1),
+                    })
+                }
+            };
+            i -= 1;
+            j -= 1;
+            continue;
+        }
+        if lengths[(i - 1) * width + j] > lengths[i * width + j - 1] {
+            i -= 1;
+        } else {
+            j -= 1;
+        }
+        matches.extend(run.take());
+    }
+    matches.extend(run);
+    sequence.reverse();
+    Subsequence {
+        sequence: Bytes::from(sequence),
+        matches,
+    }
+}
@@ -0,0 +1,580 @@
+use std::collections::{HashMap, HashSet};
+
+use bytes::Bytes;
+
+use super::db_event::DatabaseEvent::*;
+use super::db_event::{
+    Aggregate, DatabaseValue, DbError, DbValueType, ScanOptions, ScoreBound, SetOp, ZAddOptions,
+    ZRangeBy, ZRangeSpec,
+};
+use super::list::normalize_range;
+use super::sorted_set::SortedSet;
+use super::Database;
+
+/// What a single ZADD update did to a member
+enum ZAddOutcome {
+    Added(f64),
+    Updated(f64),
+    Unchanged(f64),
+    Skipped,
+}
+
+/// A ZUNIONSTORE/ZINTERSTORE/ZDIFFSTORE input, where plain set members all score 1
+enum ZSource<'a> {
+    Sorted(&'a SortedSet),
+    Set(&'a HashSet<Bytes>),
+}
+
+impl Database {
+    pub async fn zadd(
+        key: &Bytes,
+        members: &[(f64, Bytes)],
+        options: ZAddOptions,
+    ) -> anyhow::Result<i64> {
+        Database::request(|emitter| ZAdd {
+            emitter,
+            key: key.to_owned(),
+            members: members.to_vec(),
+            options,
+        })
+        .await
+    }
+
+    pub async fn zincrby(
+        key: &Bytes,
+        member: &Bytes,
+        increment: f64,
+        options: ZAddOptions,
+    ) -> anyhow::Result<Option<f64>> {
+        Database::request(|emitter| ZIncrBy {
+            emitter,
+            key: key.to_owned(),
+            member: member.to_owned(),
+            increment,
+            options,
+        })
+        .await
+    }
+
+    pub async fn zrem(key: &Bytes, members: &[Bytes]) -> anyhow::Result<i64> {
+        Database::request(|emitter| ZRem {
+            emitter,
+            key: key.to_owned(),
+            members: members.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn zmscore(key: &Bytes, members: &[Bytes]) -> anyhow::Result<Vec<Option<f64>>> {
+        Database::request(|emitter| ZMScore {
+            emitter,
+            key: key.to_owned(),
+            members: members.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn zrank(
+        key: &Bytes,
+        member: &Bytes,
+        rev: bool,
+    ) -> anyhow::Result<Option<(i64, f64)>> {
+        Database::request(|emitter| ZRank {
+            emitter,
+            key: key.to_owned(),
+            member: member.to_owned(),
+            rev,
+        })
+        .await
+    }
+
+    pub async fn zcard(key: &Bytes) -> anyhow::Result<i64> {
+        Database::request(|emitter| ZCard {
+            emitter,
+            key: key.to_owned(),
+        })
+        .await
+    }
+
+    pub async fn zcount(key: &Bytes, min: ScoreBound, max: ScoreBound) -> anyhow::Result<i64> {
+        Database::request(|emitter| ZCount {
+            emitter,
+            key: key.to_owned(),
+            min,
+            max,
+        })
+        .await
+    }
+
+    pub async fn zrange(key: &Bytes, spec: &ZRangeSpec) -> anyhow::Result<Vec<(Bytes, f64)>> {
+        Database::request(|emitter| ZRange {
+            emitter,
+            key: key.to_owned(),
+            spec: spec.clone(),
+        })
+        .await
+    }
+
+    pub async fn zrangestore(
+        destination: &Bytes,
+        source: &Bytes,
+        spec: &ZRangeSpec,
+    ) -> anyhow::Result<i64> {
+        Database::request(|emitter| ZRangeStore {
+            emitter,
+            destination: destination.to_owned(),
+            source: source.to_owned(),
+            spec: spec.clone(),
+        })
+        .await
+    }
+
+    pub async fn zpop(key: &Bytes, count: usize, max: bool) -> anyhow::Result<Vec<(Bytes, f64)>> {
+        Database::request(|emitter| ZPop {
+            emitter,
+            key: key.to_owned(),
+            count,
+            max,
+        })
+        .await
+    }
+
+    pub async fn zset_op_store(
+        op: SetOp,
+        destination: &Bytes,
+        keys: &[Bytes],
+        weights: &[f64],
+        aggregate: Aggregate,
+    ) -> anyhow::Result<i64> {
+        Database::request(|emitter| ZSetOperationStore {
+            emitter,
+            op,
+            destination: destination.to_owned(),
+            keys: keys.to_vec(),
+            weights: weights.to_vec(),
+            aggregate,
+        })
+        .await
+    }
+
+    pub async fn zscan(
+        key: &Bytes,
+        cursor: u64,
+        options: &ScanOptions,
+    ) -> anyhow::Result<(u64, Vec<(Bytes, f64)>)> {
+        Database::request(|emitter| ZScan {
+            emitter,
+            key: key.to_owned(),
+            cursor,
+            options: options.to_owned(),
+        })
+        .await
+    }
+
+    pub(super) fn _zadd(
+        &mut self,
+        key: &Bytes,
+        members: Vec<(f64, Bytes)>,
+        options: ZAddOptions,
+    ) -> Result<i64, DbError> {
+        let zset = self._get_or_create_zset(key)?;
+        let mut count = 0;
+        for (score, member) in members {
+            match zadd_member(zset, member, score, false, options)? {
+                ZAddOutcome::Added(_) => count += 1,
+                ZAddOutcome::Updated(_) if options.ch => count += 1,
+                _ => {}
+            }
+        }
+        // XX on a missing key must not leave an empty sorted set behind
+        self._remove_if_empty_zset(key);
+        Ok(count)
+    }
+
+    pub(super) fn _zincrby(
+        &mut self,
+        key: &Bytes,
+        member: Bytes,
+        increment: f64,
+        options: ZAddOptions,
+    ) -> Result<Option<f64>, DbError> {
+        let zset = self._get_or_create_zset(key)?;
+        let outcome = zadd_member(zset, member, increment, true, options);
+        self._remove_if_empty_zset(key);
+        let score = match outcome? {
+            ZAddOutcome::Added(score)
+            | ZAddOutcome::Updated(score)
+            | ZAddOutcome::Unchanged(score) => Some(score),
+            ZAddOutcome::Skipped => None,
+        };
+        Ok(score)
+    }
+
+    pub(super) fn _zrem(&mut self, key: &Bytes, members: &[Bytes]) -> Result<i64, DbError> {
+        let Some(zset) = self._get_zset(key)? else {
+            return Ok(0);
+        };
+        let removed = members
+            .iter()
+            .filter(|member| zset.remove(member).is_some())
+            .count();
+        self._remove_if_empty_zset(key);
+        Ok(removed as i64)
+    }
+
+    pub(super) fn _zmscore(
+        &mut self,
+        key: &Bytes,
+        members: &[Bytes],
+    ) -> Result<Vec<Option<f64>>, DbError> {
+        let zset = self._get_zset(key)?;
+        let scores = members
+            .iter()
+            .map(|member| zset.as_ref().and_then(|zset| zset.score(member)))
+            .collect();
+        Ok(scores)
+    }
+
+    pub(super) fn _zrank(
+        &mut self,
+        key: &Bytes,
+        member: &Bytes,
+        rev: bool,
+    ) -> Result<Option<(i64, f64)>, DbError> {
+        let rank = self._get_zset(key)?.and_then(|zset| {
+            let rank = zset.rank(member)?;
+            let rank = if rev { zset.len() - 1 - rank } else { rank };
+            Some((rank as i64, zset.score(member)?))
+        });
+        Ok(rank)
+    }
+
+    pub(super) fn _zcard(&mut self, key: &Bytes) -> Result<i64, DbError> {
+        let len = self._get_zset(key)?.map(|zset| zset.len()).unwrap_or(0);
+        Ok(len as i64)
+    }
+
+    pub(super) fn _zcount(
+        &mut self,
+        key: &Bytes,
+        min: ScoreBound,
+        max: ScoreBound,
+    ) -> Result<i64, DbError> {
+        let count = self
+            ._get_zset(key)?
+            .map(|zset| zset.count_by_score(min, max))
+            .unwrap_or(0);
+        Ok(count as i64)
+    }
+
+    pub(super) fn _zrange(
+        &mut self,
+        key: &Bytes,
+        spec: &ZRangeSpec,
+    ) -> Result<Vec<(Bytes, f64)>, DbError> {
+        let range = match self._get_zset(key)? {
+            None => vec![],
+            Some(zset) => select_range(zset, spec),
+        };
+        Ok(range)
+    }
+
+    pub(super) fn _zrangestore(
+        &mut self,
+        destination: &Bytes,
+        source: &Bytes,
+        spec: &ZRangeSpec,
+    ) -> Result<i64, DbError> {
+        let range = self._zrange(source, spec)?;
+        let len = range.len();
+        self._store_zset(destination, range);
+        Ok(len as i64)
+    }
+
+    pub(super) fn _zpop(
+        &mut self,
+        key: &Bytes,
+        count: usize,
+        max: bool,
+    ) -> Result<Vec<(Bytes, f64)>, DbError> {
+        let Some(zset) = self._get_zset(key)? else {
+            return Ok(vec![]);
+        };
+        let popped = zset
+            .iter(max)
+            .take(count)
+            .map(|(member, score)| (member.to_owned(), score))
+            .collect::<Vec<_>>();
+        popped.iter().for_each(|(member, _)| {
+            zset.remove(member);
+        });
+        self._remove_if_empty_zset(key);
+        Ok(popped)
+    }
+
+    pub(super) fn _zset_op_store(
+        &mut self,
+        op: SetOp,
+        destination: &Bytes,
+        keys: &[Bytes],
+        weights: &[f64],
+        aggregate: Aggregate,
+    ) -> Result<i64, DbError> {
+        keys.iter().for_each(|key| self._lookup_key(key));
+        let sources = keys
+            .iter()
+            .map(|key| match self.db.get(key) {
+                None => Ok(None),
+                Some(DatabaseValue {
+                    value: DbValueType::SortedSet(zset),
+                    ..
+                }) => Ok(Some(ZSource::Sorted(zset))),
+                Some(DatabaseValue {
+                    value: DbValueType::Set(set),
+                    ..
+                }) => Ok(Some(ZSource::Set(set))),
+                Some(_) => Err(DbError::WrongType),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let weight = |i: usize, score: f64| {
+            let weighted = score * weights.get(i).copied().unwrap_or(1.0);
+            // inf * 0 has no meaningful score, Redis settles on 0
+            if weighted.is_nan() {
+                0.0
+            } else {
+                weighted
+            }
+        };
+        let mut result: HashMap<Bytes, f64> = HashMap::new();
+        match op {
+            SetOp::Union => {
+                for (i, source) in sources.iter().enumerate() {
+                    let Some(source) = source else { continue };
+                    for (member, score) in source.iter() {
+                        let score = weight(i, score);
+                        result
+                            .entry(member.to_owned())
+                            .and_modify(|current| *current = combine(aggregate, *current, score))
+                            .or_insert(score);
+                    }
+                }
+            }
+            SetOp::Inter => {
+                if let Some(sources) = sources
+                    .iter()
+                    .map(Option::as_ref)
+                    .collect::<Option<Vec<_>>>()
+                {
+                    let smallest = sources.iter().min_by_key(|source| source.len());
+                    for (member, _) in smallest.into_iter().flat_map(|source| source.iter()) {
+                        let scores = sources
+                            .iter()
+                            .enumerate()
+                            .map(|(i, source)| Some(weight(i, source.score(member)?)))
+                            .collect::<Option<Vec<_>>>();
+                        let Some(scores) = scores else { continue };
+                        let score = scores
+                            .into_iter()
+                            .reduce(|current, score| combine(aggregate, current, score))
+                            .unwrap_or(0.0);
+                        result.insert(member.to_owned(), score);
+                    }
+                }
+            }
+            SetOp::Diff => {
+                if let Some(Some(first)) = sources.first() {
+                    for (member, score) in first.iter() {
+                        let elsewhere = sources[1..]
+                            .iter()
+                            .flatten()
+                            .any(|source| source.score(member).is_some());
+                        if !elsewhere {
+                            result.insert(member.to_owned(), score);
+                        }
+                    }
+                }
+            }
+        }
+        let len = result.len();
+        self._store_zset(destination, result);
+        Ok(len as i64)
+    }
+
+    /// Replaces `key` with a sorted set of `members`, deleting it when there are none
+    pub(super) fn _store_zset(
+        &mut self,
+        key: &Bytes,
+        members: impl IntoIterator<Item = (Bytes, f64)>,
+    ) {
+        let mut zset = SortedSet::default();
+        for (member, score) in members {
+            zset.insert(member, score);
+        }
+        self._lookup_key(key);
+        self.db.remove(key);
+        if !zset.is_empty() {
+            self.db.insert(
+                key.to_owned(),
+                DatabaseValue::new(DbValueType::SortedSet(zset), None),
+            );
+        }
+    }
+
+    pub(super) fn _zscan(
+        &mut self,
+        key: &Bytes,
+        cursor: u64,
+        options: &ScanOptions,
+    ) -> Result<(u64, Vec<(Bytes, f64)>), DbError> {
+        let Some(zset) = self._get_zset(key)? else {
+            return Ok((0, vec![]));
+        };
+        let (cursor, batch) = zset.scan(cursor, options.count);
+        let members = batch
+            .into_iter()
+            .filter(|(member, _)| options.matches(member))
+            .map(|(member, score)| (member.clone(), score))
+            .collect();
+        Ok((cursor, members))
+    }
+
+    pub(super) fn _get_zset(&mut self, key: &Bytes) -> Result<Option<&mut SortedSet>, DbError> {
+        self._lookup_key(key);
+        match self.db.get_mut(key) {
+            None => Ok(None),
+            Some(DatabaseValue {
+                value: DbValueType::SortedSet(zset),
+                ..
+            }) => Ok(Some(zset)),
+            Some(_) => Err(DbError::WrongType),
+        }
+    }
+
+    fn _get_or_create_zset(&mut self, key: &Bytes) -> Result<&mut SortedSet, DbError> {
+        self._lookup_key(key);
+        let db_value = self.db.get_or_insert_with(key, || {
+            DatabaseValue::new(DbValueType::SortedSet(SortedSet::default()), None)
+        });
+        match &mut db_value.value {
+            DbValueType::SortedSet(zset) => Ok(zset),
+            _ => Err(DbError::WrongType),
+        }
+    }
+
+    fn _remove_if_empty_zset(&mut self, key: &Bytes) {
+        if let Some(DatabaseValue {
+            value: DbValueType::SortedSet(zset),
+            ..
+        }) = self.db.get(key)
+        {
+            if zset.is_empty() {
+                self.db.remove(key);
+            }
+        }
+    }
+}
+
+/// Applies one ZADD/ZINCRBY update. With `incr` the score is added to the current one.
+fn zadd_member(
+    zset: &mut SortedSet,
+    member: Bytes,
+    score: f64,
+    incr: bool,
+    options: ZAddOptions,
+) -> Result<ZAddOutcome, DbError> {
+    let Some(current) = zset.score(&member) else {
+        if options.xx {
+            return Ok(ZAddOutcome::Skipped);
+        }
+        zset.insert(member, score);
+        return Ok(ZAddOutcome::Added(score));
+    };
+    if options.nx {
+        return Ok(ZAddOutcome::Skipped);
+    }
+    let score = if incr { current + score } else { score };
+    if score.is_nan() {
+        return Err(DbError::UnableToPerformAction(
+            "ERR resulting score is not a number (NaN)".to_string(),
+        ));
+    }
+    if (options.gt && score <= current) || (options.lt && score >= current) {
+        return Ok(ZAddOutcome::Skipped);
+    }
+    if score == current {
+        return Ok(ZAddOutcome::Unchanged(score));
+    }
+    zset.insert(member, score);
+    Ok(ZAddOutcome::Updated(score))
+}
+
+fn select_range(zset: &SortedSet, spec: &ZRangeSpec) -> Vec<(Bytes, f64)> {
+    let (offset, count) = match spec.limit {
+        None => (0, usize::MAX),
+        Some((offset, _)) if offset < 0 => return vec![],
+        Some((offset, count)) => (
+            offset as usize,
+            usize::try_from(count).unwrap_or(usize::MAX),
+        ),
+    };
+    let owned = |(member, score): (&Bytes, f64)| (member.to_owned(), score);
+    match &spec.by {
+        ZRangeBy::Rank(start, stop) => match normalize_range(*start, *stop, zset.len()) {
+            None => vec![],
+            Some((start, stop)) => zset
+                .range_by_rank(start, stop, spec.rev)
+                .map(owned)
+                .collect(),
+        },
+        ZRangeBy::Score(min, max) => zset
+            .range_by_score(*min, *max, spec.rev)
+            .skip(offset)
+            .take(count)
+            .map(owned)
+            .collect(),
+        ZRangeBy::Lex(min, max) => zset
+            .range_by_lex(min, max, spec.rev)
+            .skip(offset)
+            .take(count)
+            .map(owned)
+            .collect(),
+    }
+}
+
+fn combine(aggregate: Aggregate, current: f64, score: f64) -> f64 {
+    match aggregate {
+        Aggregate::Sum => {
+            let sum = current + score;
+            if sum.is_nan() {
+                0.0
+            } else {
+                sum
+            }
+        }
+        Aggregate::Min => current.min(score),
+        Aggregate::Max => current.max(score),
+    }
+}
+
+impl ZSource<'_> {
+    fn len(&self) -> usize {
+        match self {
+            ZSource::Sorted(zset) => zset.len(),
+            ZSource::Set(set) => set.len(),
+        }
+    }
+
+    fn score(&self, member: &Bytes) -> Option<f64> {
+        match self {
+            ZSource::Sorted(zset) => zset.score(member),
+            ZSource::Set(set) => set.contains(member).then_some(1.0),
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&Bytes, f64)> + '_> {
+        match self {
+            ZSource::Sorted(zset) => Box::new(zset.iter(false)),
+            ZSource::Set(set) => Box::new(set.iter().map(|member| (member, 1.0))),
+        }
+    }
+}
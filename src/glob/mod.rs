@@ -0,0 +1,127 @@
+//! Redis style glob patterns, as used by KEYS and the other commands that filter by pattern.
+//!
+//! * `*` matches any number of bytes, `?` exactly one
+//! * `[abc]` matches one of the listed bytes, `[^abc]` any other byte and `[a-z]` a range
+//! * `\` makes the next byte literal, both outside and inside brackets
+//!
+//! Matching works on raw bytes, so keys do not have to be valid UTF-8.
+
+/// Whether `string` matches `pattern` as a whole
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to resume after the last `*` when the bytes that follow it stop matching: the
+    // pattern right past the star, and the string byte the star swallowed last
+    let mut star: Option<(usize, usize)> = None;
+    while s < string.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, s));
+            continue;
+        }
+        if let Some(next) = match_one(pattern, p, string[s]) {
+            p = next;
+            s += 1;
+            continue;
+        }
+        let Some((star_p, star_s)) = star else {
+            return false;
+        };
+        p = star_p;
+        s = star_s + 1;
+        star = Some((star_p, s));
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches the single byte `c` against the token at `pattern[p]`, returning where the next token
+/// starts. The caller deals with `*`.
+fn match_one(pattern: &[u8], p: usize, c: u8) -> Option<usize> {
+    match *pattern.get(p)? {
+        b'?' => Some(p + 1),
+        b'[' => match_class(pattern, p, c),
+        // A trailing backslash stands for itself
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        literal => (literal == c).then_some(p + 1),
+    }
+}
+
+/// `pattern[p]` opens a class. A class that is never closed runs to the end of the pattern.
+fn match_class(pattern: &[u8], p: usize, c: u8) -> Option<usize> {
+    let mut i = p + 1;
+    let negate = pattern.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    loop {
+        match pattern.get(i) {
+            None => break,
+            Some(b']') => {
+                i += 1;
+                break;
+            }
+            Some(b'\\') if i + 1 < pattern.len() => {
+                matched |= pattern[i + 1] == c;
+                i += 2;
+            }
+            Some(&start) if pattern.get(i + 1) == Some(&b'-') && i + 2 < pattern.len() => {
+                let end = pattern[i + 2];
+                matched |= (start.min(end)..=start.max(end)).contains(&c);
+                i += 3;
+            }
+            Some(&literal) => {
+                matched |= literal == c;
+                i += 1;
+            }
+        }
+    }
+    (matched != negate).then_some(i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches(b"h?llo", b"hello"));
+        assert!(!matches(b"h?llo", b"hllo"));
+        assert!(matches(b"h*llo", b"hllo"));
+        assert!(matches(b"h*llo", b"heeeello"));
+        assert!(matches(b"*", b""));
+        assert!(matches(b"a*b*c", b"aXbYbZc"));
+        assert!(!matches(b"a*b*c", b"aXbYbZ"));
+        assert!(matches(b"*llo**", b"hello"));
+        assert!(!matches(b"", b"a"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches(b"h[ae]llo", b"hallo"));
+        assert!(!matches(b"h[ae]llo", b"hillo"));
+        assert!(matches(b"h[^e]llo", b"hallo"));
+        assert!(!matches(b"h[^e]llo", b"hello"));
+        assert!(matches(b"h[a-c]llo", b"hbllo"));
+        assert!(!matches(b"h[a-c]llo", b"hdllo"));
+        // Ranges work either way round
+        assert!(matches(b"h[c-a]llo", b"hbllo"));
+        assert!(matches(b"[\\]]", b"]"));
+        // An unclosed class runs to the end of the pattern
+        assert!(matches(b"h[ab", b"hb"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches(b"a\\*b", b"a*b"));
+        assert!(!matches(b"a\\*b", b"axb"));
+        assert!(matches(b"a\\?", b"a?"));
+        // A trailing backslash stands for itself
+        assert!(matches(b"a\\", b"a\\"));
+    }
+
+    #[test]
+    fn raw_bytes() {
+        assert!(matches(b"\xff*", b"\xff\xfe"));
+        assert!(matches(b"?", b"\x80"));
+    }
+}
@@ -9,6 +9,7 @@ pub(crate) mod app_config;
 pub(crate) mod cmd_parser;
 pub(crate) mod cmd_processor;
 pub(crate) mod database;
+pub(crate) mod glob;
 pub(crate) mod log;
 pub(crate) mod rds_file;
 pub(crate) mod replication;
@@ -22,8 +23,8 @@ pub const NEW_LINE: u8 = b'\n';
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     setup_log()?;
-    Database::new();
-    parse_rdb_file().await?;
+    Database::setup();
+    Database::scoped(parse_rdb_file()).await?;
     ReplicationEvent::setup();
     Slave::setup().await?;
     Server::start().await?;
@@ -1,13 +1,22 @@
-use std::{collections::HashMap, time::SystemTime};
+use std::time::SystemTime;
 
 use anyhow::{bail, Context};
+use bytes::Bytes;
 use tokio::{
     fs::File,
     io::{AsyncBufRead, AsyncReadExt, BufReader},
 };
 use tracing::debug;
 
-use crate::{app_config::AppConfig, binary, database::Database, fdbg};
+use crate::{
+    app_config::AppConfig,
+    binary,
+    database::{
+        db_event::{Expiry, SetOptions},
+        lzf_decompress, Database,
+    },
+    fdbg,
+};
 
 pub(crate) async fn parse_rdb_file() -> anyhow::Result<()> {
     let dir = AppConfig::get_rds_dir();
@@ -19,7 +28,7 @@ pub(crate) async fn parse_rdb_file() -> anyhow::Result<()> {
 
     let file_header = read_string_encoded(&mut reader, 5).await?;
     let version = read_string_encoded(&mut reader, 4).await?;
-    tracing::debug!("{} {}", file_header, version);
+    tracing::debug!("{:?} {:?}", file_header, version);
     loop {
         let op_code = read_bytes(&mut reader, 1)
             .await
@@ -31,27 +40,29 @@ pub(crate) async fn parse_rdb_file() -> anyhow::Result<()> {
             }
             0xFA => {
                 // Auxiliary fields
-                let key = read_string_encoded_key(&mut reader).await?;
-                let value = read_length_encoded_key_as_string(&mut reader).await?;
-                debug!("AUX {key} = {value}");
+                let key = read_string(&mut reader).await?;
+                let value = read_string(&mut reader).await?;
+                debug!("AUX {key:?} = {}", String::from_utf8_lossy(&value));
             }
             0xFE => {
-                let value = read_length(&mut reader, 1).await?;
-                debug!("Database selector = {value}")
-            }
-            // "expiry time in seconds", followed by 4 byte unsigned int
-            0xFD => {
-                let time = read_length(&mut reader, 4).await?;
-                debug!("Reader time seconds = {time}");
-                // Not implemented for now
-                // let (key, value) = read_key_value(&mut reader, op_code[0]).await?;
-                // rdb_map.insert(key, value);
+                let value = read_length(&mut reader).await?;
+                debug!("Database selector = {value}");
+                if value >= AppConfig::get_databases() {
+                    bail!(
+                        "RDB file has keys for database {value}, but there are only {}",
+                        AppConfig::get_databases()
+                    );
+                }
+                // Keys that follow are loaded into this database
+                Database::select(value);
             }
+            // "expiry time in seconds", followed by 4 byte unsigned int, or
             // "expiry time in ms", followed by 8 byte unsigned long
-            0xFC => {
-                let time = read_bytes(&mut reader, 8).await?;
-                let time: [u8; 8] = time[..8].try_into().unwrap();
-                let exp_time = u64::from_le_bytes(time) as u128;
+            0xFD | 0xFC => {
+                let exp_time = match op_code[0] {
+                    0xFD => u32::from_le_bytes(read_array(&mut reader).await?) as u128 * 1000,
+                    _ => u64::from_le_bytes(read_array(&mut reader).await?) as u128,
+                };
                 let now = SystemTime::now()
                     .duration_since(SystemTime::UNIX_EPOCH)
                     .unwrap();
@@ -61,38 +72,43 @@ pub(crate) async fn parse_rdb_file() -> anyhow::Result<()> {
                     .context(fdbg!("Unable to read value type"))?;
                 let (key, value) = read_key_value(&mut reader, value_type[0]).await?;
                 if exp_time > now_millis {
-                    let diff = exp_time - now_millis;
-                    let mut map: HashMap<String, String> = HashMap::new();
-                    map.insert("px".to_string(), diff.to_string());
-                    Database::set(&key, &value, &map).await?;
+                    let options = SetOptions {
+                        expiry: Some(Expiry::At(exp_time as u64)),
+                        ..SetOptions::default()
+                    };
+                    Database::set(&key, &value, options).await?;
                 }
             }
             0xFB => {
                 debug!("Resize DB OP code found");
-                let size_of_corresponding_hash_table = read_length(&mut reader, 1).await?;
-                let size_of_corresponding_expire_hash_table = read_length(&mut reader, 1).await?;
+                let size_of_corresponding_hash_table = read_length(&mut reader).await?;
+                let size_of_corresponding_expire_hash_table = read_length(&mut reader).await?;
                 debug!("Size of corresponding hash table = {size_of_corresponding_hash_table}");
                 debug!("Size of corresponding exprire hash table = {size_of_corresponding_expire_hash_table}");
             }
             // Not special character, Try to read the data
             _ => {
                 let (key, value) = read_key_value(&mut reader, op_code[0]).await?;
-                Database::set(&key, &value, &HashMap::new()).await?;
+                Database::set(&key, &value, SetOptions::default()).await?;
             }
         }
     }
     Ok(())
 }
 
-async fn read_key_value<R>(reader: &mut R, value_type: u8) -> anyhow::Result<(String, String)>
+async fn read_key_value<R>(reader: &mut R, value_type: u8) -> anyhow::Result<(Bytes, Bytes)>
 where
     R: AsyncBufRead + AsyncReadExt + Unpin,
 {
     let value = match value_type {
         // 0 = String Encoding
         0 => {
-            let key = read_string_encoded_key(reader).await?;
-            let value = read_string_encoded_key(reader).await?;
+            let key = read_string(reader)
+                .await
+                .context(fdbg!("Unable to read key"))?;
+            let value = read_string(reader)
+                .await
+                .context(fdbg!("Unable to read value"))?;
             (key, value)
         }
         // Other encoding not imported yet
@@ -101,85 +117,104 @@ where
     Ok(value)
 }
 
-async fn read_length_encoded_key_as_string<R>(reader: &mut R) -> anyhow::Result<String>
+/// Reads a length, or the format of a specially encoded string when the flag is set
+async fn read_length_or_encoding<R>(reader: &mut R) -> anyhow::Result<(u64, bool)>
 where
     R: AsyncBufRead + AsyncReadExt + Unpin,
 {
-    let value = read_length(reader, 1)
+    let [byte] = read_array(reader)
         .await
-        .context(fdbg!("Unable to read value as string"))?;
-    // debug!("Value = {value}, {value:x}, {value:0>8b}");
-    let msb = binary!(@msb; value as u8, 2);
-    let value = match msb {
+        .context(fdbg!("Unable to read length"))?;
+    let value = match binary!(@msb; byte, 2) {
         // The next 6 bits represent the length
-        0b00 => {
-            let lsb = binary!(@lsb; value as u8, 6);
-            read_string_encoded(reader, lsb as usize).await?
-        }
+        0b00 => (binary!(@lsb; byte, 6) as u64, false),
         // Read one additional byte. The combined 14 bits represent the length
-        0b01 => unimplemented!("0b01 not implemented yet"),
-        // Discard the remaining 6 bits. The next 4 bytes from the stream represent the length
-        0b10 => unimplemented!("0b10 not implemented yet"),
-        // The next object is encoded in a special format. The remaining 6 bits indicate the format. May be used to store numbers or Strings, see String Encoding
-        0b11 => {
-            let lsb = binary!(@lsb; value as u8, 6);
-            let bytes_to_read_for_length = match lsb {
-                // 0 indicates that an 8 bit integer follows
-                0 => 1,
-                // 1 indicates that a 16 bit integer follows
-                1 => 2,
-                // 2 indicates that a 32 bit integer follows
-                2 => 4,
-                _ => unimplemented!("Unknown value"),
-            };
-            let length = read_length(reader, bytes_to_read_for_length)
-                .await
-                .context(fdbg!("Unable to read length of value"))?;
-            format!("{}", length)
+        0b01 => {
+            let [next] = read_array(reader).await?;
+            ((binary!(@lsb; byte, 6) as u64) << 8 | next as u64, false)
         }
-        _ => unimplemented!("Unknown value"),
+        // Discard the remaining 6 bits. The next 4 or 8 bytes represent the length, big endian
+        0b10 => match byte {
+            0x80 => (u32::from_be_bytes(read_array(reader).await?) as u64, false),
+            0x81 => (u64::from_be_bytes(read_array(reader).await?), false),
+            _ => bail!("Unknown length encoding {byte:#x}"),
+        },
+        // The next object is encoded in a special format. The remaining 6 bits indicate the format
+        _ => (binary!(@lsb; byte, 6) as u64, true),
     };
-
-    Ok(format!("{value}"))
+    Ok(value)
 }
 
-async fn read_string_encoded_key<R>(reader: &mut R) -> anyhow::Result<String>
+async fn read_length<R>(reader: &mut R) -> anyhow::Result<usize>
 where
     R: AsyncBufRead + AsyncReadExt + Unpin,
 {
-    let key_length = read_length(reader, 1)
-        .await
-        .context(fdbg!("Unable to read key_length of key"))?;
-    // debug!("Key length = {key_length}, {key_length:x}");
-    let key = read_string_encoded(reader, key_length)
-        .await
-        .context(fdbg!("Unable to read key as string"))?;
-    Ok(key)
+    match read_length_or_encoding(reader).await? {
+        (length, false) => usize::try_from(length).context(fdbg!("Length {length} is too big")),
+        (format, true) => bail!("Expected a length, found string format {format}"),
+    }
 }
 
-async fn read_length<R>(reader: &mut R, n: usize) -> anyhow::Result<usize>
+/// Reads a string, which may have been stored as an integer or compressed with LZF
+async fn read_string<R>(reader: &mut R) -> anyhow::Result<Bytes>
 where
     R: AsyncBufRead + AsyncReadExt + Unpin,
 {
-    let buf = read_bytes(reader, n).await?;
-    let length = buf[0];
-    return Ok(length as usize);
+    let value = match read_length_or_encoding(reader).await? {
+        (length, false) => {
+            let length = usize::try_from(length).context(fdbg!("Length {length} is too big"))?;
+            read_string_encoded(reader, length).await?
+        }
+        // 0 indicates that an 8 bit integer follows
+        (0, true) => int_text(i8::from_le_bytes(read_array(reader).await?) as i64),
+        // 1 indicates that a 16 bit integer follows
+        (1, true) => int_text(i16::from_le_bytes(read_array(reader).await?) as i64),
+        // 2 indicates that a 32 bit integer follows
+        (2, true) => int_text(i32::from_le_bytes(read_array(reader).await?) as i64),
+        // 3 indicates that a compressed string follows
+        (3, true) => {
+            let compressed_length = read_length(reader).await?;
+            let length = read_length(reader).await?;
+            let compressed = read_bytes(reader, compressed_length).await?;
+            lzf_decompress(&compressed, length)
+                .context(fdbg!("Unable to decompress string"))?
+                .into()
+        }
+        (format, true) => bail!("Unknown string format {format}"),
+    };
+    Ok(value)
+}
+
+fn int_text(value: i64) -> Bytes {
+    Bytes::from(value.to_string())
 }
 
-async fn read_string_encoded<R>(reader: &mut R, n: usize) -> anyhow::Result<String>
+async fn read_string_encoded<R>(reader: &mut R, n: usize) -> anyhow::Result<Bytes>
 where
     R: AsyncBufRead + AsyncReadExt + Unpin,
 {
     let buf = read_bytes(reader, n).await?;
-    let content = String::from_utf8(buf)?;
-    return Ok(content);
+    Ok(Bytes::from(buf))
 }
 
 async fn read_bytes<R>(reader: &mut R, n: usize) -> anyhow::Result<Vec<u8>>
 where
     R: AsyncBufRead + AsyncReadExt + Unpin,
 {
-    let mut buf = vec![0; n];
+    // Lengths come from the file, so only grow the buffer as far as the file goes
+    let mut buf = vec![];
+    (&mut *reader).take(n as u64).read_to_end(&mut buf).await?;
+    if buf.len() < n {
+        bail!("Unexpected end of RDB file");
+    }
+    Ok(buf)
+}
+
+async fn read_array<R, const N: usize>(reader: &mut R) -> anyhow::Result<[u8; N]>
+where
+    R: AsyncBufRead + AsyncReadExt + Unpin,
+{
+    let mut buf = [0; N];
     reader.read_exact(&mut buf).await?;
-    return Ok(buf);
+    Ok(buf)
 }
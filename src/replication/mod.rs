@@ -3,10 +3,12 @@ use std::collections::HashMap;
 use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
+use bytes::Bytes;
 use tokio::io::BufReader;
 use tokio::sync::Mutex;
 use tokio::{net::TcpStream, sync::oneshot};
 
+use crate::database::db_event::{Expiry, SetOptions};
 use crate::resp_type::RESPType;
 use tokio::{io::AsyncWriteExt, sync::mpsc};
 
@@ -20,9 +22,11 @@ pub enum ReplicationEvent {
         stream: TcpStream,
     },
     Set {
-        key: String,
-        value: String,
-        flags: HashMap<String, String>,
+        /// Database the key was written to
+        db: usize,
+        key: Bytes,
+        value: Bytes,
+        options: SetOptions,
     },
     GetNumOfReplicas {
         resp: oneshot::Sender<usize>,
@@ -36,7 +40,7 @@ type ReplicationEventEmitter = mpsc::Sender<ReplicationEvent>;
 
 impl ReplicationEvent {
     pub async fn emit(self) -> anyhow::Result<()> {
-        let emitter = EMITTER.get_or_init(|| ReplicationEvent::setup());
+        let emitter = EMITTER.get_or_init(ReplicationEvent::setup);
         emitter.send(self).await?;
         Ok(())
     }
@@ -46,23 +50,54 @@ impl ReplicationEvent {
         EMITTER.get_or_init(|| tx.clone());
         tokio::spawn(async move {
             let mut streams_map: HashMap<String, Arc<Mutex<TcpStream>>> = HashMap::new();
+            // Database the replicas have selected, `None` when they may not agree
+            let mut replicas_db = Some(0);
             while let Some(cmd) = rx.recv().await {
                 match cmd {
                     SaveStream { host, port, stream } => {
                         let key = format!("{host}:{port}");
                         streams_map.insert(key, Arc::new(Mutex::new(stream)));
+                        // A new replica starts out on database 0
+                        if replicas_db != Some(0) {
+                            replicas_db = None;
+                        }
                     }
                     Set {
+                        db,
                         key,
                         value,
-                        flags: _flags,
+                        options,
                     } => {
-                        let msg = RESPType::Array(vec![
-                            RESPType::BulkString("SET".to_string()),
-                            RESPType::BulkString(key.clone()),
-                            RESPType::BulkString(value.clone()),
-                        ]);
-                        for (_, v) in streams_map.borrow_mut() {
+                        if replicas_db != Some(db) {
+                            let select = RESPType::Array(vec![
+                                RESPType::BulkString("SELECT".into()),
+                                RESPType::BulkString(db.to_string().into()),
+                            ]);
+                            for v in streams_map.borrow_mut().values_mut() {
+                                let mut stream = v.lock().await;
+                                let _ = stream.write_all(&select.as_bytes()).await;
+                            }
+                            replicas_db = Some(db);
+                        }
+                        let mut args = vec![Bytes::from("SET"), key, value];
+                        // Only writes that went through get here, so NX/XX and GET are moot
+                        match options.expiry {
+                            Some(Expiry::In(duration)) => {
+                                args.push("PX".into());
+                                args.push(duration.as_millis().to_string().into());
+                            }
+                            Some(Expiry::At(unix_ms)) => {
+                                args.push("PXAT".into());
+                                args.push(unix_ms.to_string().into());
+                            }
+                            Some(Expiry::Persist) | None => {}
+                        }
+                        if options.keep_ttl {
+                            args.push("KEEPTTL".into());
+                        }
+                        let msg =
+                            RESPType::Array(args.into_iter().map(RESPType::BulkString).collect());
+                        for v in streams_map.borrow_mut().values_mut() {
                             let mut stream = v.lock().await;
                             let _ = stream.write_all(&msg.as_bytes()).await;
                             stream.flush().await.unwrap();
@@ -89,14 +124,14 @@ async fn get_ack(
 ) -> anyhow::Result<()> {
     let mut acks_received = 0;
 
-    for (_, stream) in streams_map {
+    for stream in streams_map.values() {
         let mut cl_stream = Arc::clone(stream);
         let resp = resp.clone();
         tokio::spawn(async move {
             let req = RESPType::Array(vec![
-                RESPType::BulkString("REPLCONF".to_string()),
-                RESPType::BulkString("GETACK".to_string()),
-                RESPType::BulkString("*".to_string()),
+                RESPType::BulkString("REPLCONF".into()),
+                RESPType::BulkString("GETACK".into()),
+                RESPType::BulkString("*".into()),
             ]);
             let mut stream = cl_stream.borrow_mut().lock().await;
             let (reader, mut writer) = stream.split();
@@ -1,3 +1,5 @@
+use bytes::Bytes;
+
 use crate::{LINE_ENDING, NEW_LINE};
 
 pub(crate) mod parser;
@@ -7,14 +9,15 @@ use RESPType::*;
 #[derive(Debug, Clone)]
 pub enum RESPType {
     Array(Vec<RESPType>),
-    BulkString(String),
+    BulkString(Bytes),
     NullBulkString,
-    RDB(Vec<u8>),
+    NullArray,
+    Rdb(Vec<u8>),
     SimpleString(String),
     Integer(i64),
     Error(String),
     CustomNewLine,
-    EOF,
+    Eof,
 }
 
 impl RESPType {
@@ -29,11 +32,11 @@ impl RESPType {
                 }
                 result
             }
-            BulkString(string) => {
+            BulkString(bytes) => {
                 let mut result = vec![b'$'];
-                result.extend(string.len().to_string().as_bytes());
+                result.extend(bytes.len().to_string().as_bytes());
                 result.extend(LINE_ENDING.as_bytes().to_vec());
-                result.extend(string.as_bytes());
+                result.extend(bytes);
                 result.extend(LINE_ENDING.as_bytes().to_vec());
                 result
             }
@@ -42,13 +45,18 @@ impl RESPType {
                 result.extend(LINE_ENDING.as_bytes().to_vec());
                 result
             }
+            NullArray => {
+                let mut result = vec![b'*', b'-', b'1'];
+                result.extend(LINE_ENDING.as_bytes().to_vec());
+                result
+            }
             SimpleString(string) => {
                 let mut result = vec![b'+'];
                 result.extend(string.as_bytes());
                 result.extend(LINE_ENDING.as_bytes().to_vec());
                 result
             }
-            RDB(data) => {
+            Rdb(data) => {
                 let mut result = vec![b'$'];
                 result.extend(data.len().to_string().as_bytes());
                 result.extend(LINE_ENDING.as_bytes().to_vec());
@@ -67,7 +75,7 @@ impl RESPType {
                 result.extend(LINE_ENDING.as_bytes().to_vec());
                 result
             }
-            CustomNewLine | RESPType::EOF => {
+            CustomNewLine | RESPType::Eof => {
                 let result = vec![NEW_LINE];
                 result
             }
@@ -1,4 +1,5 @@
 use anyhow::{bail, Context};
+use bytes::Bytes;
 use tokio::{
     io::{AsyncBufReadExt, AsyncReadExt, BufReader},
     net::tcp::ReadHalf,
@@ -26,7 +27,7 @@ impl RESPType {
             .unwrap_or(0);
         if count == 0 {
             debug!("GOT EOF");
-            return Ok(RESPType::EOF);
+            return Ok(RESPType::Eof);
         }
         // debug!("DataType identifying ASCII:({})", buf[0]);
         let request_resp_type = match buf[0] {
@@ -50,21 +51,17 @@ impl RESPType {
         Ok(request_resp_type)
     }
 
-    pub(crate) async fn read_custom_command<'a>(
-        reader: &'a mut Reader<'_>,
-    ) -> anyhow::Result<RESPType> {
+    pub(crate) async fn read_custom_command(reader: &mut Reader<'_>) -> anyhow::Result<RESPType> {
         let mut buf = String::new();
         reader.read_line(&mut buf).await?;
         let items = buf
             .split(" ")
-            .map(|x| RESPType::BulkString(x.to_string()))
+            .map(|x| RESPType::BulkString(Bytes::copy_from_slice(x.as_bytes())))
             .collect::<Vec<RESPType>>();
         Ok(RESPType::Array(items))
     }
 
-    pub(crate) async fn read_simple_string<'a>(
-        reader: &'a mut Reader<'_>,
-    ) -> anyhow::Result<RESPType> {
+    pub(crate) async fn read_simple_string(reader: &mut Reader<'_>) -> anyhow::Result<RESPType> {
         let mut buf = vec![];
         reader.read_until(NEW_LINE, &mut buf).await?;
         let content = std::str::from_utf8(&buf[..buf.len() - LINE_ENDING.len()])
@@ -72,8 +69,8 @@ impl RESPType {
         Ok(RESPType::SimpleString(content.to_string()))
     }
 
-    pub async fn read_bulk_string<'a>(
-        reader: &'a mut BufReader<ReadHalf<'_>>,
+    pub async fn read_bulk_string(
+        reader: &mut BufReader<ReadHalf<'_>>,
     ) -> anyhow::Result<RESPType> {
         let length = RESPType::read_count(reader).await?;
         let mut buf = vec![0; length + LINE_ENDING.len()];
@@ -81,13 +78,11 @@ impl RESPType {
             .read_exact(&mut buf)
             .await
             .context(fdbg!("Unable to read string from reader"))?;
-        let string = std::str::from_utf8(&buf[..length])
-            .context(fdbg!("Unable to convert bytes to string"))?
-            .to_string();
-        Ok(RESPType::BulkString(string))
+        buf.truncate(length);
+        Ok(RESPType::BulkString(Bytes::from(buf)))
     }
 
-    pub async fn read_count<'a>(reader: &'a mut BufReader<ReadHalf<'_>>) -> anyhow::Result<usize> {
+    pub async fn read_count(reader: &mut BufReader<ReadHalf<'_>>) -> anyhow::Result<usize> {
         let mut buf = vec![];
         let read_count = reader
             .read_until(NEW_LINE, &mut buf)
@@ -102,7 +97,7 @@ impl RESPType {
         Ok(length)
     }
 
-    pub async fn parse_rdb_file<'a>(reader: &'a mut BufReader<ReadHalf<'_>>) -> anyhow::Result<()> {
+    pub async fn parse_rdb_file(reader: &mut BufReader<ReadHalf<'_>>) -> anyhow::Result<()> {
         let mut buf = [0; 1];
         reader.read_exact(&mut buf).await?;
         debug!("RESP first byte {}", buf[0]);
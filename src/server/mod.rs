@@ -2,14 +2,14 @@ use std::net::SocketAddr;
 
 use anyhow::Context;
 use tokio::{
-    io::{AsyncWriteExt, BufReader},
-    net::{TcpListener, TcpStream},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::ReadHalf, TcpListener, TcpStream},
 };
 use tracing::debug;
 
 use crate::cmd_processor::server_cmd_processor::send_rds_file;
 use crate::{
-    app_config::AppConfig, cmd_parser::server_command::ServerCommand, fdbg,
+    app_config::AppConfig, cmd_parser::server_command::ServerCommand, database::Database, fdbg,
     replication::ReplicationEvent, resp_type::RESPType,
 };
 
@@ -21,11 +21,11 @@ impl Server {
         loop {
             let (stream, addr) = listener.accept().await?;
             debug!("Got a request from: {:?}", addr);
-            tokio::spawn(async move {
+            tokio::spawn(Database::scoped(async move {
                 Self::handle_stream(stream, addr)
                     .await
                     .expect("Connection was disconnected with an error")
-            });
+            }));
         }
     }
     async fn handle_stream(mut stream: TcpStream, addr: SocketAddr) -> anyhow::Result<()> {
@@ -34,7 +34,15 @@ impl Server {
         let mut tx_stack: Vec<Vec<ServerCommand>> = vec![];
         loop {
             let resp_type = RESPType::parse(&mut reader).await?;
-            let client_cmd = ServerCommand::from(&resp_type)?;
+            let client_cmd = match ServerCommand::from(&resp_type) {
+                Ok(client_cmd) => client_cmd,
+                Err(err) => {
+                    let resp = RESPType::Error(err.to_string());
+                    writer.write_all(&resp.as_bytes()).await?;
+                    writer.flush().await?;
+                    continue;
+                }
+            };
 
             let Some(client_cmd) = queue_if_transaction_active(client_cmd, &mut tx_stack).await
             else {
@@ -45,11 +53,19 @@ impl Server {
                 continue;
             };
 
-            if let Some(resp) = client_cmd
-                .process_client_cmd(&mut tx_stack)
-                .await
-                .context(fdbg!("Unable to write to client stream"))?
-            {
+            let resp = if client_cmd.is_blocking() {
+                tokio::select! {
+                    resp = client_cmd.process_client_cmd(&mut tx_stack) => resp,
+                    _ = wait_for_disconnect(&mut reader) => {
+                        // Dropping the command future cancels whatever it was blocked on
+                        debug!("Client disconnected while blocked");
+                        break;
+                    }
+                }
+            } else {
+                client_cmd.process_client_cmd(&mut tx_stack).await
+            };
+            if let Some(resp) = resp.context(fdbg!("Unable to write to client stream"))? {
                 writer.write_all(&resp.as_bytes()).await?;
                 writer.flush().await?;
             }
@@ -74,9 +90,18 @@ impl Server {
     }
 }
 
+/// Resolves once the client closes its side of the connection. Data sent in the meantime is
+/// only peeked at, so pipelined commands are still there for the next parse.
+async fn wait_for_disconnect(reader: &mut BufReader<ReadHalf<'_>>) {
+    match reader.fill_buf().await {
+        Ok([]) | Err(_) => (),
+        Ok(_) => std::future::pending().await,
+    }
+}
+
 async fn queue_if_transaction_active(
     cmd: ServerCommand,
-    tx_stack: &mut Vec<Vec<ServerCommand>>,
+    tx_stack: &mut [Vec<ServerCommand>],
 ) -> Option<ServerCommand> {
     use ServerCommand::*;
     if matches!(cmd, Exec) || matches!(cmd, Multi) || matches!(cmd, Discard) || tx_stack.is_empty()
@@ -5,11 +5,12 @@ use tokio::{
         TcpStream,
     },
 };
-use tracing::{debug, debug_span};
+use tracing::{debug, debug_span, error};
 
 use crate::{
     app_config::AppConfig,
     cmd_parser::{server_command::ServerCommand, slave_command::SlaveCommand},
+    database::Database,
     resp_type::RESPType,
 };
 
@@ -24,7 +25,7 @@ impl Slave {
             panic!("Replica should have --replicaof args");
         };
         let mut stream = TcpStream::connect(format!("{host}:{port}")).await?;
-        tokio::spawn(async move {
+        tokio::spawn(Database::scoped(async move {
             let (reader, mut writer) = stream.split();
             let mut reader = BufReader::new(reader);
             handshake(&mut writer, &mut reader).await;
@@ -36,15 +37,18 @@ impl Slave {
                 let resp_type = RESPType::parse(&mut reader).await.unwrap();
                 let client_cmd = ServerCommand::from(&resp_type).unwrap();
                 let slave_cmd = SlaveCommand::from(&client_cmd).unwrap();
-                slave_cmd
+                if let Err(e) = slave_cmd
                     .process_slave_cmd(&mut writer, bytes_received)
                     .await
-                    .unwrap();
+                {
+                    error!("Stopped replicating from master: {e:#}");
+                    break;
+                }
                 writer.flush().await.unwrap();
                 bytes_received += resp_type.as_bytes().len();
                 debug!("Bytes received: {bytes_received}");
             }
-        });
+        }));
         Ok(())
     }
 }
@@ -55,9 +59,9 @@ async fn receive_rdb_file(reader: &mut BufReader<ReadHalf<'_>>) {
         .expect("Should be able to parse RDB file");
 }
 
-async fn handshake<'a>(writer: &mut WriteHalf<'_>, reader: &mut BufReader<ReadHalf<'_>>) {
+async fn handshake(writer: &mut WriteHalf<'_>, reader: &mut BufReader<ReadHalf<'_>>) {
     // PING
-    let ping = RESPType::Array(vec![RESPType::BulkString("PING".to_string())]);
+    let ping = RESPType::Array(vec![RESPType::BulkString("PING".into())]);
     writer
         .write_all(&ping.as_bytes())
         .await
@@ -69,9 +73,9 @@ async fn handshake<'a>(writer: &mut WriteHalf<'_>, reader: &mut BufReader<ReadHa
     // REPL CONF
     let port = AppConfig::get_port();
     let repl_conf_listening_port = RESPType::Array(vec![
-        RESPType::BulkString("REPLCONF".to_string()),
-        RESPType::BulkString("listening-port".to_string()),
-        RESPType::BulkString(format!("{port}")),
+        RESPType::BulkString("REPLCONF".into()),
+        RESPType::BulkString("listening-port".into()),
+        RESPType::BulkString(format!("{port}").into()),
     ]);
     writer
         .write_all(&repl_conf_listening_port.as_bytes())
@@ -87,9 +91,9 @@ async fn handshake<'a>(writer: &mut WriteHalf<'_>, reader: &mut BufReader<ReadHa
 
     // REPL capa psync2
     let repl_conf_capa_psync2 = RESPType::Array(vec![
-        RESPType::BulkString("REPLCONF".to_string()),
-        RESPType::BulkString("capa".to_string()),
-        RESPType::BulkString("psync2".to_string()),
+        RESPType::BulkString("REPLCONF".into()),
+        RESPType::BulkString("capa".into()),
+        RESPType::BulkString("psync2".into()),
     ]);
     writer
         .write_all(&repl_conf_capa_psync2.as_bytes())
@@ -105,9 +109,9 @@ async fn handshake<'a>(writer: &mut WriteHalf<'_>, reader: &mut BufReader<ReadHa
 
     // PSYNC
     let repl_conf_capa_psync2 = RESPType::Array(vec![
-        RESPType::BulkString("PSYNC".to_string()),
-        RESPType::BulkString("?".to_string()),
-        RESPType::BulkString("-1".to_string()),
+        RESPType::BulkString("PSYNC".into()),
+        RESPType::BulkString("?".into()),
+        RESPType::BulkString("-1".into()),
     ]);
     writer
         .write_all(&repl_conf_capa_psync2.as_bytes())
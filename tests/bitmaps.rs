@@ -0,0 +1,95 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn bitpos_and_bitcount_take_bit_ranges() {
+    let server = Server::start();
+    let mut client = server.connect();
+    // "\x00\xff\xf0"
+    client.cmd(&[
+        "BITFIELD", "key", "SET", "u8", "8", "255", "SET", "u8", "16", "240",
+    ]);
+    assert_eq!(client.cmd(&["BITPOS", "key", "1", "0"]), ":8\r\n");
+    assert_eq!(client.cmd(&["BITPOS", "key", "1", "2"]), ":16\r\n");
+    assert_eq!(
+        client.cmd(&["BITPOS", "key", "1", "2", "-1", "BYTE"]),
+        ":16\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["BITPOS", "key", "1", "7", "15", "BIT"]),
+        ":8\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["BITPOS", "key", "1", "7", "-3", "BIT"]),
+        ":8\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["BITPOS", "key", "0", "8", "15", "BIT"]),
+        ":-1\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["BITPOS", "key", "0", "9", "-1", "BIT"]),
+        ":20\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["BITPOS", "key", "1", "21", "-1", "BIT"]),
+        ":-1\r\n"
+    );
+    client.cmd(&["SET", "key", "foobar"]);
+    assert_eq!(client.cmd(&["BITCOUNT", "key"]), ":26\r\n");
+    assert_eq!(client.cmd(&["BITCOUNT", "key", "1", "1", "BYTE"]), ":6\r\n");
+    assert_eq!(
+        client.cmd(&["BITCOUNT", "key", "5", "30", "BIT"]),
+        ":17\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["BITCOUNT", "key", "-8", "-1", "BIT"]),
+        ":4\r\n"
+    );
+    assert_eq!(client.cmd(&["BITCOUNT", "key", "30", "5", "BIT"]), ":0\r\n");
+}
+
+#[test]
+fn bitfield_overflow_applies_to_later_writes() {
+    let server = Server::start();
+    let mut client = server.connect();
+    let mut incr_four_times = |key, overflow| {
+        let incr = [
+            "BITFIELD", key, "OVERFLOW", overflow, "INCRBY", "u2", "102", "1",
+        ];
+        (0..4).map(|_| client.cmd(&incr)).collect::<String>()
+    };
+    assert_eq!(
+        incr_four_times("wrap", "WRAP"),
+        "*1\r\n:1\r\n*1\r\n:2\r\n*1\r\n:3\r\n*1\r\n:0\r\n"
+    );
+    assert_eq!(
+        incr_four_times("sat", "SAT"),
+        "*1\r\n:1\r\n*1\r\n:2\r\n*1\r\n:3\r\n*1\r\n:3\r\n"
+    );
+    let fail = [
+        "OVERFLOW", "FAIL", "INCRBY", "u2", "102", "1", "GET", "u2", "102",
+    ];
+    assert_eq!(
+        client.cmd(&[&["BITFIELD", "sat"][..], &fail].concat()),
+        "*2\r\n$-1\r\n:3\r\n"
+    );
+    let max = i64::MAX.to_string();
+    client.cmd(&["BITFIELD", "i64", "SET", "i64", "0", &max]);
+    let min = ":-9223372036854775808\r\n";
+    let incr = |by| {
+        [
+            "BITFIELD", "i64", "OVERFLOW", "SAT", "INCRBY", "i64", "0", by,
+        ]
+    };
+    assert_eq!(
+        client.cmd(&["BITFIELD", "i64", "INCRBY", "i64", "0", "1"]),
+        format!("*1\r\n{min}")
+    );
+    assert_eq!(client.cmd(&incr("-1")), format!("*1\r\n{min}"));
+    assert_eq!(
+        client.cmd(&incr("-9223372036854775808")),
+        format!("*1\r\n{min}")
+    );
+}
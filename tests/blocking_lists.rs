@@ -0,0 +1,121 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn blpop_timeout_replies_with_null_array() {
+    let server = Server::start();
+    let mut client = server.connect();
+    assert_eq!(client.cmd(&["BLPOP", "missing", "0.05"]), "*-1\r\n");
+    assert_eq!(client.cmd(&["BRPOP", "missing", "0.05"]), "*-1\r\n");
+}
+
+#[test]
+fn blmpop_timeout_replies_with_null_array() {
+    let server = Server::start();
+    let mut client = server.connect();
+    assert_eq!(
+        client.cmd(&["BLMPOP", "0.05", "1", "missing", "LEFT"]),
+        "*-1\r\n"
+    );
+}
+
+#[test]
+fn blmove_timeout_replies_with_null_bulk_string() {
+    let server = Server::start();
+    let mut client = server.connect();
+    assert_eq!(
+        client.cmd(&["BLMOVE", "missing", "dest", "LEFT", "RIGHT", "0.05"]),
+        "$-1\r\n"
+    );
+}
+
+#[test]
+fn blpop_is_served_by_a_later_push() {
+    let server = Server::start();
+    let mut blocked = server.connect();
+    let mut pusher = server.connect();
+    blocked.send(&["BLPOP", "queue", "5"]);
+    // Give the first client time to block before the push comes in
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    assert_eq!(pusher.cmd(&["RPUSH", "queue", "job"]), ":1\r\n");
+    assert_eq!(blocked.reply(), "*2\r\n$5\r\nqueue\r\n$3\r\njob\r\n");
+}
+
+#[test]
+fn out_of_range_arguments_are_refused() {
+    let server = Server::start();
+    let mut client = server.connect();
+    assert_eq!(
+        client.cmd(&["BLPOP", "queue", "1e300"]),
+        "-ERR timeout is out of range\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["BLMPOP", "0", "18446744073709551615", "queue"]),
+        "-ERR numkeys should be greater than 0\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["BLMPOP", "0", "18446744073709551613", "queue"]),
+        "-ERR syntax error\r\n"
+    );
+    // The connection is still usable afterwards
+    assert_eq!(client.cmd(&["PING"]), "+PONG\r\n");
+}
+
+#[test]
+fn blpop_is_served_by_a_move_or_copy_from_another_database() {
+    let server = Server::start();
+    let mut blocked = server.connect();
+    let mut mover = server.connect();
+    assert_eq!(blocked.cmd(&["SELECT", "1"]), "+OK\r\n");
+    blocked.send(&["BLPOP", "queue", "5"]);
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    mover.cmd(&["RPUSH", "queue", "moved"]);
+    assert_eq!(mover.cmd(&["MOVE", "queue", "1"]), ":1\r\n");
+    assert_eq!(blocked.reply(), "*2\r\n$5\r\nqueue\r\n$5\r\nmoved\r\n");
+
+    blocked.send(&["BLPOP", "queue", "5"]);
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    mover.cmd(&["RPUSH", "source", "copied"]);
+    assert_eq!(mover.cmd(&["COPY", "source", "queue", "DB", "1"]), ":1\r\n");
+    assert_eq!(blocked.reply(), "*2\r\n$5\r\nqueue\r\n$6\r\ncopied\r\n");
+}
+
+#[test]
+fn blocked_clients_are_served_in_the_order_they_blocked() {
+    let server = Server::start();
+    let mut pusher = server.connect();
+    let mut blocked = (0..3).map(|_| server.connect()).collect::<Vec<_>>();
+    for client in &mut blocked {
+        client.send(&["BLPOP", "queue", "5"]);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    assert_eq!(pusher.cmd(&["RPUSH", "queue", "a", "b", "c"]), ":3\r\n");
+    for (client, element) in blocked.iter_mut().zip(["a", "b", "c"]) {
+        assert_eq!(
+            client.reply(),
+            format!("*2\r\n$5\r\nqueue\r\n$1\r\n{element}\r\n")
+        );
+    }
+    assert_eq!(pusher.cmd(&["LLEN", "queue"]), ":0\r\n");
+}
+
+#[test]
+fn disconnected_clients_stop_waiting() {
+    let server = Server::start();
+    let mut pusher = server.connect();
+    let mut gone = server.connect();
+    gone.send(&["BLPOP", "queue", "0"]);
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    let mut blocked = server.connect();
+    blocked.send(&["BLPOP", "queue", "0"]);
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    drop(gone);
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    // The element goes to the client still waiting, not the one that left before it
+    assert_eq!(pusher.cmd(&["RPUSH", "queue", "first"]), ":1\r\n");
+    assert_eq!(blocked.reply(), "*2\r\n$5\r\nqueue\r\n$5\r\nfirst\r\n");
+    // With nobody left waiting, pushes stay in the list
+    assert_eq!(pusher.cmd(&["RPUSH", "queue", "second"]), ":1\r\n");
+    assert_eq!(pusher.cmd(&["LLEN", "queue"]), ":1\r\n");
+}
@@ -0,0 +1,111 @@
+// Each test binary builds its own copy of this module and only uses part of it
+#![allow(dead_code)]
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// The server binary running on a port of its own, killed once dropped
+pub struct Server {
+    child: Child,
+    port: u16,
+}
+
+impl Server {
+    pub fn start() -> Server {
+        Server::start_with(&[])
+    }
+
+    /// Starts the server with extra command line arguments
+    pub fn start_with(args: &[&str]) -> Server {
+        // Let the OS pick a free port, then hand it over to the server
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("free port")
+            .port();
+        let child = Command::new(env!("CARGO_BIN_EXE_redis-starter-rust"))
+            .args(["--port", &port.to_string()])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("server starts");
+        Server { child, port }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Connects a new client, waiting for the server to start listening
+    pub fn connect(&self) -> Client {
+        for _ in 0..100 {
+            if let Ok(stream) = TcpStream::connect(("127.0.0.1", self.port)) {
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .expect("read timeout");
+                return Client {
+                    reader: BufReader::new(stream.try_clone().expect("stream clone")),
+                    stream,
+                };
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("server did not start listening on port {}", self.port);
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    /// Sends `args` as a command and returns the raw reply
+    pub fn cmd(&mut self, args: &[&str]) -> String {
+        self.send(args);
+        self.reply()
+    }
+
+    pub fn send(&mut self, args: &[&str]) {
+        let mut request = format!("*{}\r\n", args.len());
+        for arg in args {
+            request.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+        }
+        self.stream
+            .write_all(request.as_bytes())
+            .expect("request sent");
+    }
+
+    /// Reads one whole reply, nested arrays included
+    pub fn reply(&mut self) -> String {
+        let mut line = String::new();
+        self.reader.read_line(&mut line).expect("reply line");
+        let len = line[1..].trim_end().parse::<i64>().unwrap_or(-1);
+        match line.as_bytes().first() {
+            Some(b'$') if len >= 0 => {
+                let mut data = vec![0; len as usize + 2];
+                self.reader.read_exact(&mut data).expect("bulk string");
+                line.push_str(&String::from_utf8_lossy(&data));
+            }
+            Some(b'*') => {
+                for _ in 0..len {
+                    line.push_str(&self.reply());
+                }
+            }
+            _ => {}
+        }
+        line
+    }
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn malformed_commands_report_wrong_number_of_arguments() {
+    let server = Server::start();
+    let mut client = server.connect();
+    assert_eq!(
+        client.cmd(&["GET"]),
+        "-ERR wrong number of arguments for 'get' command\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["HSET", "hash", "field"]),
+        "-ERR wrong number of arguments for 'hset' command\r\n"
+    );
+    // Errors of the arguments themselves reach the client as they are
+    assert_eq!(
+        client.cmd(&["SET", "key", "value", "EX", "soon"]),
+        "-ERR value is not an integer or out of range\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["WAIT", "some", "0"]),
+        "-ERR value is not an integer or out of range\r\n"
+    );
+}
@@ -0,0 +1,20 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn hrandfield_caps_huge_counts_at_the_hash_size() {
+    let server = Server::start();
+    let mut client = server.connect();
+    client.cmd(&["HSET", "h", "f", "v"]);
+    assert_eq!(
+        client.cmd(&["HRANDFIELD", "h", "9223372036854775807"]),
+        "*1\r\n$1\r\nf\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["HRANDFIELD", "h", "-2", "WITHVALUES"]),
+        "*4\r\n$1\r\nf\r\n$1\r\nv\r\n$1\r\nf\r\n$1\r\nv\r\n"
+    );
+    // The database is still serving commands
+    assert_eq!(client.cmd(&["HGET", "h", "f"]), "$1\r\nv\r\n");
+}
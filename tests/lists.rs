@@ -0,0 +1,14 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn pop_of_missing_key_replies_null_of_the_requested_shape() {
+    let server = Server::start();
+    let mut client = server.connect();
+    assert_eq!(client.cmd(&["LPOP", "missing"]), "$-1\r\n");
+    assert_eq!(client.cmd(&["LPOP", "missing", "2"]), "*-1\r\n");
+    assert_eq!(client.cmd(&["RPOP", "missing", "2"]), "*-1\r\n");
+    client.cmd(&["RPUSH", "list", "a"]);
+    assert_eq!(client.cmd(&["RPOP", "list", "2"]), "*1\r\n$1\r\na\r\n");
+}
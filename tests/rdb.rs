@@ -0,0 +1,67 @@
+mod common;
+
+use std::fs;
+
+use common::Server;
+
+fn string(out: &mut Vec<u8>, value: &[u8]) {
+    let len = value.len();
+    match len {
+        0..=63 => out.push(len as u8),
+        64..=16383 => out.extend_from_slice(&[0x40 | (len >> 8) as u8, len as u8]),
+        _ => {
+            out.push(0x80);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(value);
+}
+
+#[test]
+fn loads_every_length_and_integer_encoding() {
+    let mut rdb = b"REDIS0011".to_vec();
+    // Auxiliary field with an 8 bit integer value
+    rdb.push(0xFA);
+    string(&mut rdb, b"redis-bits");
+    rdb.extend_from_slice(&[0xC0, 64]);
+    rdb.extend_from_slice(&[0xFE, 0x00, 0xFB, 0x05, 0x01]);
+    for (key, len) in [("short", 3), ("medium", 300), ("long", 20000)] {
+        rdb.push(0x00);
+        string(&mut rdb, key.as_bytes());
+        string(&mut rdb, &vec![b'x'; len]);
+    }
+    rdb.push(0x00);
+    string(&mut rdb, b"int16");
+    rdb.extend_from_slice(&[0xC1, 0xE8, 0x03]);
+    rdb.push(0x00);
+    string(&mut rdb, b"int32");
+    rdb.extend_from_slice(&[0xC2]);
+    rdb.extend_from_slice(&(-70000_i32).to_le_bytes());
+    // Expiry in seconds, far in the future
+    rdb.push(0xFD);
+    rdb.extend_from_slice(&4_000_000_000_u32.to_le_bytes());
+    rdb.push(0x00);
+    string(&mut rdb, b"volatile");
+    string(&mut rdb, b"v");
+    rdb.push(0xFF);
+    rdb.extend_from_slice(&[0; 8]);
+
+    let dir = std::env::temp_dir().join(format!("rdb-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("temp dir");
+    fs::write(dir.join("dump.rdb"), &rdb).expect("rdb written");
+    let server = Server::start_with(&[
+        "--dir",
+        dir.to_str().expect("utf-8 path"),
+        "--dbfilename",
+        "dump.rdb",
+    ]);
+    let mut client = server.connect();
+    assert_eq!(client.cmd(&["STRLEN", "short"]), ":3\r\n");
+    assert_eq!(client.cmd(&["STRLEN", "medium"]), ":300\r\n");
+    assert_eq!(client.cmd(&["STRLEN", "long"]), ":20000\r\n");
+    assert_eq!(client.cmd(&["GET", "int16"]), "$4\r\n1000\r\n");
+    assert_eq!(client.cmd(&["GET", "int32"]), "$6\r\n-70000\r\n");
+    assert_eq!(client.cmd(&["GET", "volatile"]), "$1\r\nv\r\n");
+    assert_eq!(client.cmd(&["DBSIZE"]), ":6\r\n");
+    let _ = fs::remove_dir_all(dir);
+}
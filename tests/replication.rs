@@ -0,0 +1,26 @@
+mod common;
+
+use std::{thread, time::Duration};
+
+use common::Server;
+
+#[test]
+fn replica_stops_following_a_master_selecting_a_missing_database() {
+    let master = Server::start();
+    let mut client = master.connect();
+    let replicaof = format!("127.0.0.1 {}", master.port());
+    let replica = Server::start_with(&["--databases", "2", "--replicaof", &replicaof]);
+    let mut replica_client = replica.connect();
+    // Let the replica finish its handshake
+    thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(client.cmd(&["SET", "before", "1"]), "+OK\r\n");
+    assert_eq!(client.cmd(&["SELECT", "5"]), "+OK\r\n");
+    assert_eq!(client.cmd(&["SET", "far", "1"]), "+OK\r\n");
+    thread::sleep(Duration::from_millis(300));
+
+    // The replica kept what it got before and is still serving commands
+    assert_eq!(replica_client.cmd(&["GET", "before"]), "$1\r\n1\r\n");
+    assert_eq!(replica_client.cmd(&["DBSIZE"]), ":1\r\n");
+    assert_eq!(replica_client.cmd(&["SET", "local", "1"]), "+OK\r\n");
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn huge_counts_are_capped_at_the_set_size() {
+    let server = Server::start();
+    let mut client = server.connect();
+    client.cmd(&["SADD", "s", "a"]);
+    assert_eq!(
+        client.cmd(&["SRANDMEMBER", "s", "9223372036854775807"]),
+        "*1\r\n$1\r\na\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["SPOP", "s", "18446744073709551615"]),
+        "*1\r\n$1\r\na\r\n"
+    );
+    // The database is still serving commands
+    assert_eq!(client.cmd(&["SCARD", "s"]), ":0\r\n");
+}
+
+#[test]
+fn negative_counts_repeat_members() {
+    let server = Server::start();
+    let mut client = server.connect();
+    client.cmd(&["SADD", "s", "a"]);
+    assert_eq!(
+        client.cmd(&["SRANDMEMBER", "s", "-3"]),
+        "*3\r\n$1\r\na\r\n$1\r\na\r\n$1\r\na\r\n"
+    );
+    assert_eq!(client.cmd(&["SRANDMEMBER", "missing", "-3"]), "*0\r\n");
+}
+
+#[test]
+fn sintercard_refuses_out_of_range_numkeys() {
+    let server = Server::start();
+    let mut client = server.connect();
+    assert_eq!(
+        client.cmd(&["SINTERCARD", "18446744073709551615", "a"]),
+        "-ERR numkeys should be greater than 0\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["SINTERCARD", "18446744073709551614", "a"]),
+        "-ERR Number of keys can't be greater than number of args\r\n"
+    );
+    assert_eq!(client.cmd(&["PING"]), "+PONG\r\n");
+}
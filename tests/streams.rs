@@ -0,0 +1,25 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn range_ends_cannot_be_excluded() {
+    let server = Server::start();
+    let mut client = server.connect();
+    client.cmd(&["XADD", "stream", "1-1", "field", "value"]);
+    for (start, end, error) in [
+        ("(-", "+", "start"),
+        ("(+", "+", "start"),
+        ("-", "(+", "end"),
+        ("-", "(-", "end"),
+    ] {
+        assert_eq!(
+            client.cmd(&["XRANGE", "stream", start, end]),
+            format!("-ERR invalid {error} ID for the interval\r\n")
+        );
+    }
+    assert_eq!(
+        client.cmd(&["XRANGE", "stream", "(1-0", "+"]),
+        "*1\r\n*2\r\n$3\r\n1-1\r\n*2\r\n$5\r\nfield\r\n$5\r\nvalue\r\n"
+    );
+}
@@ -0,0 +1,18 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn lcs_refuses_tables_past_the_bulk_string_limit() {
+    let server = Server::start();
+    let mut client = server.connect();
+    let value = "x".repeat(12000);
+    client.cmd(&["SET", "first", &value]);
+    client.cmd(&["SET", "second", &value]);
+    assert_eq!(
+        client.cmd(&["LCS", "first", "second", "LEN"]),
+        "-ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len\r\n"
+    );
+    client.cmd(&["SET", "second", "xx"]);
+    assert_eq!(client.cmd(&["LCS", "first", "second", "LEN"]), ":2\r\n");
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn exec_replies_to_each_queued_command() {
+    let server = Server::start();
+    let mut client = server.connect();
+    assert_eq!(client.cmd(&["MULTI"]), "+OK\r\n");
+    assert_eq!(client.cmd(&["SET", "counter", "1"]), "+QUEUED\r\n");
+    assert_eq!(client.cmd(&["INCR", "counter"]), "+QUEUED\r\n");
+    assert_eq!(client.cmd(&["EXEC"]), "*2\r\n+OK\r\n:2\r\n");
+    assert_eq!(client.cmd(&["EXEC"]), "-ERR EXEC without MULTI\r\n");
+}
+
+#[test]
+fn blocking_pops_in_exec_time_out_at_once_on_empty_lists() {
+    let server = Server::start();
+    let mut client = server.connect();
+    client.cmd(&["MULTI"]);
+    client.cmd(&["BLPOP", "missing", "0"]);
+    client.cmd(&["BRPOP", "missing", "0"]);
+    client.cmd(&["BLMPOP", "0", "1", "missing", "LEFT"]);
+    client.cmd(&["BLMOVE", "missing", "dest", "LEFT", "RIGHT", "0"]);
+    assert_eq!(client.cmd(&["EXEC"]), "*4\r\n*-1\r\n*-1\r\n*-1\r\n$-1\r\n");
+    // The connection is still usable afterwards
+    assert_eq!(client.cmd(&["PING"]), "+PONG\r\n");
+}
+
+#[test]
+fn blocking_pops_in_exec_pop_what_is_there() {
+    let server = Server::start();
+    let mut client = server.connect();
+    client.cmd(&["RPUSH", "list", "a", "b", "c"]);
+    client.cmd(&["MULTI"]);
+    client.cmd(&["BLPOP", "list", "0"]);
+    client.cmd(&["BLMOVE", "list", "other", "RIGHT", "LEFT", "0"]);
+    client.cmd(&["BLMPOP", "0", "2", "missing", "other", "LEFT", "COUNT", "5"]);
+    assert_eq!(
+        client.cmd(&["EXEC"]),
+        "*3\r\n\
+         *2\r\n$4\r\nlist\r\n$1\r\na\r\n\
+         $1\r\nc\r\n\
+         *2\r\n$5\r\nother\r\n*1\r\n$1\r\nc\r\n"
+    );
+    assert_eq!(
+        client.cmd(&["LRANGE", "list", "0", "-1"]),
+        "*1\r\n$1\r\nb\r\n"
+    );
+}
+
+#[test]
+fn blocking_xread_in_exec_does_not_wait() {
+    let server = Server::start();
+    let mut client = server.connect();
+    client.cmd(&["XADD", "stream", "1-1", "field", "value"]);
+    client.cmd(&["MULTI"]);
+    client.cmd(&["XREAD", "BLOCK", "0", "STREAMS", "stream", "$"]);
+    client.cmd(&["XREAD", "BLOCK", "0", "STREAMS", "stream", "0"]);
+    assert_eq!(
+        client.cmd(&["EXEC"]),
+        "*2\r\n\
+         $-1\r\n\
+         *1\r\n*2\r\n$6\r\nstream\r\n*1\r\n*2\r\n$3\r\n1-1\r\n*2\r\n$5\r\nfield\r\n$5\r\nvalue\r\n"
+    );
+}
+
+#[test]
+fn relative_expire_in_exec_counts_from_exec() {
+    let server = Server::start();
+    let mut client = server.connect();
+    client.cmd(&["SET", "key", "value"]);
+    client.cmd(&["MULTI"]);
+    client.cmd(&["PEXPIRE", "key", "10000"]);
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    assert_eq!(client.cmd(&["EXEC"]), "*1\r\n:1\r\n");
+    let ttl = client.cmd(&["PTTL", "key"]);
+    let ttl = ttl[1..].trim_end().parse::<i64>().expect("integer reply");
+    assert!(ttl > 9700, "TTL shrank to {ttl} while queued");
+}